@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE markets;
+
+DROP TYPE market_status;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TYPE market_status AS ENUM (
+    'REGULAR', 'CRASH'
+);
+
+-- the market state of a game, maintained by the instance updating the prices
+CREATE TABLE markets (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    status market_status NOT NULL DEFAULT 'REGULAR',
+    last_crash_at TIMESTAMP WITH TIME ZONE,
+    last_update_at TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE games
+DROP COLUMN max_slot_quantity,
+DROP COLUMN max_window_quantity,
+DROP COLUMN quantity_window;
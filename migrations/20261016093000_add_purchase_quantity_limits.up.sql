@@ -0,0 +1,5 @@
+-- Add up migration script here
+ALTER TABLE games
+ADD COLUMN max_slot_quantity INT DEFAULT NULL CHECK (max_slot_quantity > 0),
+ADD COLUMN max_window_quantity INT DEFAULT NULL CHECK (max_window_quantity > 0),
+ADD COLUMN quantity_window INT DEFAULT NULL CHECK (quantity_window > 0);
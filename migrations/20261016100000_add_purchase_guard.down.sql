@@ -0,0 +1,8 @@
+-- Add down migration script here
+DROP TABLE game_events;
+
+DROP TYPE game_event_type;
+
+ALTER TABLE games
+DROP COLUMN purchase_cooldown,
+DROP COLUMN throttle_suspicious_users;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+ALTER TABLE games
+ADD COLUMN purchase_cooldown INT DEFAULT NULL CHECK (purchase_cooldown > 0),
+ADD COLUMN throttle_suspicious_users BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TYPE game_event_type AS ENUM (
+    'SUSPICIOUS_PURCHASES'
+);
+
+-- noteworthy things that happened during a game, visible to the game owner
+CREATE TABLE game_events (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT REFERENCES users(id),
+    event_type game_event_type NOT NULL,
+    description VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX game_events_game_id_idx ON game_events (game_id, created_at);
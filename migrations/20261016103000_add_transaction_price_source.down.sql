@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP INDEX price_histories_slot_idx;
+
+ALTER TABLE transactions
+DROP COLUMN price_history_id,
+DROP COLUMN priced_at;
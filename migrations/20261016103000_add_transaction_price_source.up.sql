@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- keep track of the price tick a beverage was purchased at
+ALTER TABLE transactions
+ADD COLUMN price_history_id BIGINT DEFAULT NULL REFERENCES price_histories(id),
+ADD COLUMN priced_at TIMESTAMP WITH TIME ZONE DEFAULT NULL;
+
+CREATE INDEX price_histories_slot_idx ON price_histories (game_id, user_id, slot_no, created_at);
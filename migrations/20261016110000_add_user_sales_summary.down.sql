@@ -0,0 +1,6 @@
+-- Add down migration script here
+DROP TRIGGER count_user_sales ON transactions;
+
+DROP FUNCTION rustfuif_count_user_sales();
+
+DROP TABLE user_sales;
@@ -0,0 +1,49 @@
+-- Add up migration script here
+-- the amount of beverages each user purchased in a game
+-- this table is kept up to date by the triggers on the transactions table,
+-- so the stats endpoints don't have to aggregate all transactions on every request
+CREATE TABLE user_sales (
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    sales BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (game_id, user_id)
+);
+
+CREATE OR REPLACE FUNCTION rustfuif_count_user_sales() RETURNS trigger AS $$
+BEGIN
+    IF (TG_OP = 'INSERT') THEN
+        INSERT INTO user_sales (game_id, user_id, sales)
+        SELECT orders.game_id, orders.user_id, NEW.amount
+        FROM orders WHERE orders.id = NEW.order_id
+        ON CONFLICT (game_id, user_id) DO UPDATE
+        SET sales = user_sales.sales + EXCLUDED.sales;
+        RETURN NEW;
+    ELSIF (TG_OP = 'DELETE') THEN
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount
+        FROM orders
+        WHERE orders.id = OLD.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN OLD;
+    ELSE
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount + NEW.amount
+        FROM orders
+        WHERE orders.id = NEW.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN NEW;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_user_sales AFTER INSERT OR UPDATE OF amount OR DELETE ON transactions
+FOR EACH ROW EXECUTE PROCEDURE rustfuif_count_user_sales();
+
+-- initialize the summary with the existing transactions
+INSERT INTO user_sales (game_id, user_id, sales)
+SELECT orders.game_id, orders.user_id, SUM(transactions.amount)
+FROM transactions
+INNER JOIN orders ON orders.id = transactions.order_id
+GROUP BY orders.game_id, orders.user_id;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE muted_users;
+DROP TABLE game_moderation;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- the moderation state of a game, managed by the game owner over the websocket
+CREATE TABLE game_moderation (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    pinned_announcement TEXT NULL,
+    -- clients hide the activity that happened before this moment
+    ticker_cleared_at TIMESTAMP WITH TIME ZONE NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE muted_users (
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    muted_by BIGINT NOT NULL REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (game_id, user_id)
+);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE games
+DROP COLUMN drift_percentage,
+DROP COLUMN drift_interval;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- inflate or deflate all prices of a game by a percentage every `drift_interval` seconds
+ALTER TABLE games
+ADD COLUMN drift_percentage DOUBLE PRECISION NULL CHECK (drift_percentage > -100 AND drift_percentage <> 0),
+ADD COLUMN drift_interval INT NULL CHECK (drift_interval > 0);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE predictions;
+DROP TYPE prediction_direction;
+ALTER TABLE games DROP COLUMN predictions_enabled;
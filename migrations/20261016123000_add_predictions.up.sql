@@ -0,0 +1,25 @@
+-- Add up migration script here
+ALTER TABLE games
+ADD COLUMN predictions_enabled BOOL NOT NULL DEFAULT FALSE;
+
+CREATE TYPE prediction_direction AS ENUM ('RISE', 'FALL');
+
+-- players stake points on the price of one of their beverages rising or falling at the next price update
+CREATE TABLE predictions (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    slot_no SMALLINT NOT NULL,
+    direction prediction_direction NOT NULL,
+    stake BIGINT NOT NULL CHECK (stake > 0),
+    -- the price of the beverage when the prediction was made
+    price BIGINT NOT NULL,
+    resolved_price BIGINT NULL,
+    -- the points returned to the player, 0 when the prediction was wrong
+    payout BIGINT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMP WITH TIME ZONE NULL
+);
+
+-- only one open prediction per beverage
+CREATE UNIQUE INDEX predictions_open_idx ON predictions(game_id, user_id, slot_no) WHERE resolved_at IS NULL;
@@ -0,0 +1,36 @@
+-- Add down migration script here
+CREATE OR REPLACE FUNCTION rustfuif_count_user_sales() RETURNS trigger AS $$
+BEGIN
+    IF (TG_OP = 'INSERT') THEN
+        INSERT INTO user_sales (game_id, user_id, sales)
+        SELECT orders.game_id, orders.user_id, NEW.amount
+        FROM orders WHERE orders.id = NEW.order_id
+        ON CONFLICT (game_id, user_id) DO UPDATE
+        SET sales = user_sales.sales + EXCLUDED.sales;
+        RETURN NEW;
+    ELSIF (TG_OP = 'DELETE') THEN
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount
+        FROM orders
+        WHERE orders.id = OLD.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN OLD;
+    ELSE
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount + NEW.amount
+        FROM orders
+        WHERE orders.id = NEW.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN NEW;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER count_user_sales ON transactions;
+CREATE TRIGGER count_user_sales AFTER INSERT OR UPDATE OF amount OR DELETE ON transactions
+FOR EACH ROW EXECUTE PROCEDURE rustfuif_count_user_sales();
+
+ALTER TABLE user_sales DROP COLUMN spent;
+ALTER TABLE games DROP COLUMN points_budget;
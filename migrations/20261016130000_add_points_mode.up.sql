@@ -0,0 +1,54 @@
+-- Add up migration script here
+-- when set, the players spend points instead of real money and start with this budget
+ALTER TABLE games
+ADD COLUMN points_budget BIGINT NULL CHECK (points_budget > 0);
+
+-- the total price of the beverages each user purchased in a game
+ALTER TABLE user_sales
+ADD COLUMN spent BIGINT NOT NULL DEFAULT 0;
+
+CREATE OR REPLACE FUNCTION rustfuif_count_user_sales() RETURNS trigger AS $$
+BEGIN
+    IF (TG_OP = 'INSERT') THEN
+        INSERT INTO user_sales (game_id, user_id, sales, spent)
+        SELECT orders.game_id, orders.user_id, NEW.amount, NEW.amount * NEW.price
+        FROM orders WHERE orders.id = NEW.order_id
+        ON CONFLICT (game_id, user_id) DO UPDATE
+        SET sales = user_sales.sales + EXCLUDED.sales,
+            spent = user_sales.spent + EXCLUDED.spent;
+        RETURN NEW;
+    ELSIF (TG_OP = 'DELETE') THEN
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount,
+            spent = user_sales.spent - OLD.amount * OLD.price
+        FROM orders
+        WHERE orders.id = OLD.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN OLD;
+    ELSE
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount + NEW.amount,
+            spent = user_sales.spent - OLD.amount * OLD.price + NEW.amount * NEW.price
+        FROM orders
+        WHERE orders.id = NEW.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN NEW;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER count_user_sales ON transactions;
+CREATE TRIGGER count_user_sales AFTER INSERT OR UPDATE OF amount, price OR DELETE ON transactions
+FOR EACH ROW EXECUTE PROCEDURE rustfuif_count_user_sales();
+
+UPDATE user_sales
+SET spent = totals.spent
+FROM (
+    SELECT orders.game_id, orders.user_id, SUM(transactions.amount * transactions.price) as spent
+    FROM transactions
+    INNER JOIN orders ON orders.id = transactions.order_id
+    GROUP BY orders.game_id, orders.user_id
+) totals
+WHERE user_sales.game_id = totals.game_id AND user_sales.user_id = totals.user_id;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TYPE split_state AS ENUM ('PENDING', 'ACCEPTED', 'DECLINED', 'EXPIRED');
+
+-- the share of an order a co-payer is asked to pay
+-- the purchaser pays every share that isn't accepted
+CREATE TABLE order_splits (
+    id BIGSERIAL PRIMARY KEY,
+    order_id BIGINT NOT NULL REFERENCES orders(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    amount BIGINT NOT NULL CHECK (amount >= 0),
+    state split_state NOT NULL DEFAULT 'PENDING',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    responded_at TIMESTAMP WITH TIME ZONE NULL,
+    UNIQUE (order_id, user_id)
+);
+
+CREATE INDEX order_splits_user_idx ON order_splits(user_id);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS draft_invitations;
+DROP TABLE IF EXISTS draft_beverages;
+DROP TABLE IF EXISTS game_drafts;
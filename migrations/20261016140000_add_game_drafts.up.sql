@@ -0,0 +1,44 @@
+-- Add up migration script here
+-- a game that's still being set up, it becomes a real game once it's published
+CREATE TABLE game_drafts (
+    id BIGSERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR NOT NULL,
+    -- the schedule is only required when publishing
+    start_time TIMESTAMP WITH TIME ZONE NULL,
+    close_time TIMESTAMP WITH TIME ZONE NULL,
+    beverage_count SMALLINT NOT NULL,
+    max_slot_quantity INT NULL,
+    max_window_quantity INT NULL,
+    quantity_window INT NULL,
+    purchase_cooldown INT NULL,
+    throttle_suspicious_users BOOLEAN NOT NULL DEFAULT FALSE,
+    drift_percentage DOUBLE PRECISION NULL,
+    drift_interval INT NULL,
+    predictions_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    points_budget BIGINT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+SELECT rustfuif_manage_updated_at('game_drafts');
+
+CREATE INDEX game_drafts_owner_idx ON game_drafts(owner_id);
+
+CREATE TABLE draft_beverages (
+    draft_id BIGINT NOT NULL REFERENCES game_drafts(id),
+    slot_no SMALLINT NOT NULL CHECK (slot_no >= 0),
+    name VARCHAR NOT NULL,
+    image_url VARCHAR NULL,
+    min_price BIGINT NOT NULL,
+    max_price BIGINT NOT NULL,
+    starting_price BIGINT NOT NULL,
+    PRIMARY KEY (draft_id, slot_no)
+);
+
+-- the users who get invited when the draft is published
+CREATE TABLE draft_invitations (
+    draft_id BIGINT NOT NULL REFERENCES game_drafts(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    PRIMARY KEY (draft_id, user_id)
+);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS series_games;
+DROP TABLE IF EXISTS game_series;
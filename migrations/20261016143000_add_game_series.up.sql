@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- a game that repeats, the next occurrence is created when the latest one starts
+CREATE TABLE game_series (
+    id BIGSERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL REFERENCES users(id),
+    interval_days INT NOT NULL CHECK (interval_days > 0),
+    occurrences INT NOT NULL CHECK (occurrences > 0),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE series_games (
+    series_id BIGINT NOT NULL REFERENCES game_series(id),
+    game_id BIGINT NOT NULL UNIQUE REFERENCES games(id),
+    -- starts at 1 for the game the series was created with
+    occurrence INT NOT NULL CHECK (occurrence > 0),
+    PRIMARY KEY (series_id, occurrence)
+);
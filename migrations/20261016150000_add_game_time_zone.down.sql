@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE game_drafts DROP COLUMN IF EXISTS time_zone;
+ALTER TABLE games DROP COLUMN IF EXISTS time_zone;
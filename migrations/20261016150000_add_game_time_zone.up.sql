@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- the IANA time zone a game is played in, the times themselves stay in UTC
+ALTER TABLE games ADD COLUMN time_zone VARCHAR NOT NULL DEFAULT 'UTC';
+ALTER TABLE game_drafts ADD COLUMN time_zone VARCHAR NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- calendar apps can't log in, they fetch the calendar of a user with this secret token
+CREATE TABLE calendar_tokens (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id),
+    token VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
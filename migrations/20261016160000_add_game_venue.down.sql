@@ -0,0 +1,12 @@
+-- Add down migration script here
+ALTER TABLE game_drafts
+    DROP COLUMN IF EXISTS venue_name,
+    DROP COLUMN IF EXISTS venue_address,
+    DROP COLUMN IF EXISTS latitude,
+    DROP COLUMN IF EXISTS longitude;
+
+ALTER TABLE games
+    DROP COLUMN IF EXISTS venue_name,
+    DROP COLUMN IF EXISTS venue_address,
+    DROP COLUMN IF EXISTS latitude,
+    DROP COLUMN IF EXISTS longitude;
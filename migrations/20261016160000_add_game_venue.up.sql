@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- where a game is played, every field is optional
+ALTER TABLE games
+    ADD COLUMN venue_name VARCHAR NULL,
+    ADD COLUMN venue_address VARCHAR NULL,
+    ADD COLUMN latitude DOUBLE PRECISION NULL CHECK (latitude BETWEEN -90 AND 90),
+    ADD COLUMN longitude DOUBLE PRECISION NULL CHECK (longitude BETWEEN -180 AND 180),
+    ADD CHECK ((latitude IS NULL) = (longitude IS NULL));
+
+ALTER TABLE game_drafts
+    ADD COLUMN venue_name VARCHAR NULL,
+    ADD COLUMN venue_address VARCHAR NULL,
+    ADD COLUMN latitude DOUBLE PRECISION NULL,
+    ADD COLUMN longitude DOUBLE PRECISION NULL;
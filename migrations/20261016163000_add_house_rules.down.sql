@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS rules_acknowledgements;
+DROP TABLE IF EXISTS game_rules;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+-- the markdown rules of a game, players acknowledge them before their first purchase
+CREATE TABLE game_rules (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    body TEXT NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE rules_acknowledgements (
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    acknowledged_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (game_id, user_id)
+);
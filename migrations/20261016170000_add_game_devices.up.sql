@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- the kiosk screens that show the market of a game, they authenticate with their token
+CREATE TABLE game_devices (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    name VARCHAR NOT NULL,
+    token VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE,
+    revoked_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX game_devices_game_id_idx ON game_devices (game_id);
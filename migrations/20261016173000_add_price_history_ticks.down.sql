@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS price_histories_tick_idx;
+ALTER TABLE price_histories DROP COLUMN tick;
+ALTER TABLE markets DROP COLUMN tick;
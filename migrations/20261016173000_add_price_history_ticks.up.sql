@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- every price update of a game gets the next tick number, so clients can detect missed updates
+ALTER TABLE markets ADD COLUMN tick BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE price_histories ADD COLUMN tick BIGINT;
+
+-- the prices of an update were written in the same second
+UPDATE price_histories
+SET tick = ticks.tick
+FROM (
+    SELECT id, DENSE_RANK() OVER (PARTITION BY game_id ORDER BY date_trunc('second', created_at)) AS tick
+    FROM price_histories
+) AS ticks
+WHERE ticks.id = price_histories.id;
+
+UPDATE markets
+SET tick = COALESCE((SELECT MAX(tick) FROM price_histories WHERE price_histories.game_id = markets.game_id), 0);
+
+ALTER TABLE price_histories ALTER COLUMN tick SET NOT NULL;
+
+CREATE INDEX price_histories_tick_idx ON price_histories (game_id, tick);
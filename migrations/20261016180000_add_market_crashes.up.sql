@@ -0,0 +1,13 @@
+-- Add up migration script here
+-- every stock market crash, used to see whether crashes drive purchases
+CREATE TABLE market_crashes (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    crashed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX market_crashes_game_id_idx ON market_crashes (game_id, crashed_at);
+
+-- only the last crash of every game is known
+INSERT INTO market_crashes (game_id, crashed_at)
+SELECT game_id, last_crash_at FROM markets WHERE last_crash_at IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- the results of a finished game can be shared publicly with this secret token
+CREATE TABLE shared_results (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    token VARCHAR NOT NULL UNIQUE,
+    show_usernames BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
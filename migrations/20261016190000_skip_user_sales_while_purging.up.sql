@@ -0,0 +1,38 @@
+-- Add up migration script here
+-- the user sales aren't recounted when the transactions of an expired game are purged
+CREATE OR REPLACE FUNCTION rustfuif_count_user_sales() RETURNS trigger AS $$
+BEGIN
+    -- the retention job deletes old transactions, but the aggregates are kept
+    IF (current_setting('rustfuif.purging', true) = 'on') THEN
+        RETURN NULL;
+    END IF;
+
+    IF (TG_OP = 'INSERT') THEN
+        INSERT INTO user_sales (game_id, user_id, sales, spent)
+        SELECT orders.game_id, orders.user_id, NEW.amount, NEW.amount * NEW.price
+        FROM orders WHERE orders.id = NEW.order_id
+        ON CONFLICT (game_id, user_id) DO UPDATE
+        SET sales = user_sales.sales + EXCLUDED.sales,
+            spent = user_sales.spent + EXCLUDED.spent;
+        RETURN NEW;
+    ELSIF (TG_OP = 'DELETE') THEN
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount,
+            spent = user_sales.spent - OLD.amount * OLD.price
+        FROM orders
+        WHERE orders.id = OLD.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN OLD;
+    ELSE
+        UPDATE user_sales
+        SET sales = user_sales.sales - OLD.amount + NEW.amount,
+            spent = user_sales.spent - OLD.amount * OLD.price + NEW.amount * NEW.price
+        FROM orders
+        WHERE orders.id = NEW.order_id
+        AND user_sales.game_id = orders.game_id
+        AND user_sales.user_id = orders.user_id;
+        RETURN NEW;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
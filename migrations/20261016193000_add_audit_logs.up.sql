@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- the sensitive actions of the administrators, like exporting the database
+CREATE TABLE audit_logs (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    action VARCHAR NOT NULL,
+    details VARCHAR NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX audit_logs_created_at_idx ON audit_logs (created_at);
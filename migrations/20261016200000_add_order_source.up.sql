@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- where an order was placed, orders rung up on the till of the bar are imported
+ALTER TABLE orders
+ADD COLUMN source VARCHAR NOT NULL DEFAULT 'app' CHECK (source IN ('app', 'import'));
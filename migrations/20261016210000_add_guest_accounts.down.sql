@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS guests;
+DROP TABLE IF EXISTS guest_links;
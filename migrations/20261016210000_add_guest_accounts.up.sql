@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- the invite link of a game, people who open it join the game as a guest without registering
+CREATE TABLE guest_links (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    token VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- the users that joined a game through its invite link and haven't set a password yet
+CREATE TABLE guests (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id),
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX guests_game_id_idx ON guests(game_id);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS passkey_second_factors;
+DROP TABLE IF EXISTS passkey_challenges;
+DROP TABLE IF EXISTS passkeys;
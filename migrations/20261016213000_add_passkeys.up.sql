@@ -0,0 +1,29 @@
+-- Add up migration script here
+-- the WebAuthn credentials of the users
+CREATE TABLE passkeys (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR NOT NULL,
+    credential_id BYTEA NOT NULL UNIQUE,
+    -- the COSE encoded public key
+    public_key BYTEA NOT NULL,
+    sign_count BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMP WITH TIME ZONE NULL
+);
+
+CREATE INDEX passkeys_user_id_idx ON passkeys(user_id);
+
+-- the challenges of the running registrations and logins, they're used once
+CREATE TABLE passkey_challenges (
+    challenge VARCHAR PRIMARY KEY,
+    ceremony VARCHAR NOT NULL CHECK (ceremony IN ('registration', 'authentication')),
+    user_id BIGINT NULL REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- the users that need a passkey next to their password
+CREATE TABLE passkey_second_factors (
+    user_id BIGINT PRIMARY KEY REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,21 @@
+-- Add up migration script here
+-- the devices and addresses the users logged in from, a device is the user agent of the browser
+CREATE TABLE login_devices (
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    fingerprint VARCHAR NOT NULL,
+    user_agent VARCHAR NOT NULL,
+    ip_address VARCHAR NOT NULL,
+    first_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, fingerprint, ip_address)
+);
+
+-- the failed password logins of the last day
+CREATE TABLE login_failures (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    ip_address VARCHAR NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX login_failures_user_idx ON login_failures (user_id, created_at);
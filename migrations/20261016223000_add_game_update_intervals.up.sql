@@ -0,0 +1,7 @@
+-- Add up migration script here
+-- the price update interval a game owner picked, games without a row use the global interval
+CREATE TABLE game_update_intervals (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    seconds INT NOT NULL CHECK (seconds > 0),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
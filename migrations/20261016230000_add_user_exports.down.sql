@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS user_exports;
+DROP TYPE IF EXISTS export_status;
+DROP TYPE IF EXISTS export_format;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+CREATE TYPE export_format AS ENUM ('json', 'csv');
+CREATE TYPE export_status AS ENUM ('PENDING', 'READY', 'FAILED');
+
+-- an archive of everything that's stored about a user, generated in the background
+CREATE TABLE user_exports (
+    id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    format export_format NOT NULL,
+    status export_status NOT NULL DEFAULT 'PENDING',
+    content TEXT NULL,
+    error VARCHAR NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMP WITH TIME ZONE NULL
+);
+
+CREATE INDEX user_exports_user_idx ON user_exports(user_id);
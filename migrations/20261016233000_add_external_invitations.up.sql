@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- an invitation for someone without an account, registering with the token accepts it
+CREATE TABLE external_invitations (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    invited_by BIGINT NOT NULL REFERENCES users(id),
+    email VARCHAR NOT NULL,
+    token VARCHAR NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    -- the account that was created with the invitation
+    user_id BIGINT NULL REFERENCES users(id),
+    converted_at TIMESTAMP WITH TIME ZONE NULL,
+    UNIQUE (game_id, email)
+);
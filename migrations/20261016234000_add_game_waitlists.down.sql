@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS game_waitlist;
+DROP TABLE IF EXISTS game_capacities;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+-- the maximum amount of players of a game, games without a row have no limit
+CREATE TABLE game_capacities (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    max_players INT NOT NULL CHECK (max_players > 0),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- the users who accepted the invitation of a full game, in the order they joined
+CREATE TABLE game_waitlist (
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (game_id, user_id)
+);
+
+CREATE INDEX game_waitlist_user_idx ON game_waitlist(user_id);
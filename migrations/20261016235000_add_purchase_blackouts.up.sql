@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- windows in which a game doesn't accept purchases, like a quiz round
+CREATE TABLE purchase_blackouts (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    reason VARCHAR(100),
+    start_time TIMESTAMP WITH TIME ZONE NOT NULL,
+    end_time TIMESTAMP WITH TIME ZONE NOT NULL,
+    -- when the players were told the blackout started and ended
+    started_notified_at TIMESTAMP WITH TIME ZONE,
+    ended_notified_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    CHECK (end_time > start_time)
+);
+
+CREATE INDEX purchase_blackouts_game_idx ON purchase_blackouts(game_id, end_time);
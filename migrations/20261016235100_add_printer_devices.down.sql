@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE game_devices DROP COLUMN IF EXISTS kind;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- printer bridges receive the new orders of the game, displays only show the market
+ALTER TABLE game_devices
+ADD COLUMN kind VARCHAR NOT NULL DEFAULT 'display' CHECK (kind IN ('display', 'printer'));
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- the sales a kiosk queued while it was offline, by the id the kiosk generated
+-- so a batch that's sent again doesn't create the orders twice
+CREATE TABLE offline_sales (
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    client_id VARCHAR(36) NOT NULL,
+    order_id BIGINT NOT NULL REFERENCES orders(id),
+    synced_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, client_id)
+);
+
+CREATE INDEX offline_sales_order_idx ON offline_sales(order_id);
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE invitations DROP COLUMN IF EXISTS team_id;
+DROP TABLE IF EXISTS teams;
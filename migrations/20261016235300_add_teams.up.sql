@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- the teams of a game, the players compete as a team instead of on their own
+CREATE TABLE teams (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    name VARCHAR(50) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (game_id, name)
+);
+
+-- the team a player is in, players without a team still play on their own
+ALTER TABLE invitations
+ADD COLUMN team_id BIGINT REFERENCES teams(id);
+
+CREATE INDEX invitations_team_id_idx ON invitations(team_id);
@@ -0,0 +1,15 @@
+-- Add down migration script here
+-- the value of an enum type can't be dropped, so the type is rebuilt without it
+DELETE FROM game_events WHERE event_type = 'DRINK_LIMIT_EXCEEDED';
+ALTER TYPE game_event_type RENAME TO game_event_type_old;
+CREATE TYPE game_event_type AS ENUM (
+    'SUSPICIOUS_PURCHASES'
+);
+ALTER TABLE game_events
+ALTER COLUMN event_type TYPE game_event_type USING event_type::text::game_event_type;
+DROP TYPE game_event_type_old;
+
+DROP TABLE IF EXISTS game_drink_limits;
+
+ALTER TABLE draft_beverages DROP COLUMN IF EXISTS alcoholic;
+ALTER TABLE beverages DROP COLUMN IF EXISTS alcoholic;
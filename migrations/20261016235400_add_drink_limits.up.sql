@@ -0,0 +1,15 @@
+-- Add up migration script here
+ALTER TABLE beverages ADD COLUMN alcoholic BOOLEAN NOT NULL DEFAULT TRUE;
+ALTER TABLE draft_beverages ADD COLUMN alcoholic BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- the maximum amount of alcoholic drinks a player buys per hour, games without a row have no limit
+CREATE TABLE game_drink_limits (
+    game_id BIGINT PRIMARY KEY REFERENCES games(id),
+    max_per_hour INT NOT NULL CHECK (max_per_hour > 0),
+    -- the minutes a player can't buy alcoholic drinks after exceeding the limit, NULL only warns
+    cooldown INT CHECK (cooldown > 0),
+    notify_owner BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+ALTER TYPE game_event_type ADD VALUE 'DRINK_LIMIT_EXCEEDED';
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS game_template_beverages;
+DROP TABLE IF EXISTS game_templates;
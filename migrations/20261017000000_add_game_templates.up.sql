@@ -0,0 +1,21 @@
+-- Add up migration script here
+-- a beverage lineup that's reused for recurring events
+CREATE TABLE game_templates (
+    id BIGSERIAL PRIMARY KEY,
+    owner_id BIGINT NOT NULL REFERENCES users(id),
+    name VARCHAR(50) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (owner_id, name)
+);
+
+CREATE TABLE game_template_beverages (
+    template_id BIGINT NOT NULL REFERENCES game_templates(id),
+    slot_no SMALLINT NOT NULL CHECK (slot_no >= 0),
+    name VARCHAR NOT NULL,
+    image_url VARCHAR NULL,
+    min_price BIGINT NOT NULL,
+    max_price BIGINT NOT NULL,
+    starting_price BIGINT NOT NULL,
+    alcoholic BOOLEAN NOT NULL DEFAULT TRUE,
+    PRIMARY KEY (template_id, slot_no)
+);
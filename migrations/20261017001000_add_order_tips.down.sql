@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE orders DROP COLUMN IF EXISTS tip;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- the tip on top of the beverages of an order, in cents
+ALTER TABLE orders ADD COLUMN tip BIGINT NOT NULL DEFAULT 0 CHECK (tip >= 0);
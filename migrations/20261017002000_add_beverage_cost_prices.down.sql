@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE game_template_beverages DROP COLUMN IF EXISTS cost_price;
+ALTER TABLE draft_beverages DROP COLUMN IF EXISTS cost_price;
+ALTER TABLE beverages DROP COLUMN IF EXISTS cost_price;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- what a beverage costs the bar, in cents
+ALTER TABLE beverages ADD COLUMN cost_price BIGINT CHECK (cost_price >= 0);
+ALTER TABLE draft_beverages ADD COLUMN cost_price BIGINT CHECK (cost_price >= 0);
+ALTER TABLE game_template_beverages ADD COLUMN cost_price BIGINT CHECK (cost_price >= 0);
@@ -0,0 +1,15 @@
+-- Add up migration script here
+-- the sessions of the logged in users, the session cookie refers to one of them
+CREATE TABLE sessions (
+    id TEXT PRIMARY KEY,
+    -- the id before the last rotation, accepted for a moment so requests in flight don't fail
+    previous_id TEXT UNIQUE,
+    user_id BIGINT NOT NULL REFERENCES users(id),
+    user_agent TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    rotated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions(user_id);
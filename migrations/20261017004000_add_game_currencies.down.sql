@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE game_drafts DROP COLUMN IF EXISTS price_step;
+ALTER TABLE game_drafts DROP COLUMN IF EXISTS currency;
+ALTER TABLE games DROP COLUMN IF EXISTS price_step;
+ALTER TABLE games DROP COLUMN IF EXISTS currency;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- the prices are stored in the minor unit of the currency, and rounded to a multiple of the price step
+ALTER TABLE games
+ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'EUR',
+ADD COLUMN price_step BIGINT NOT NULL DEFAULT 10 CHECK (price_step > 0);
+
+ALTER TABLE game_drafts
+ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'EUR',
+ADD COLUMN price_step BIGINT NOT NULL DEFAULT 10 CHECK (price_step > 0);
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS slot_group_slots;
+DROP TABLE IF EXISTS slot_groups;
+DROP TYPE IF EXISTS price_algorithm;
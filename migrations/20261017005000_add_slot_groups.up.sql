@@ -0,0 +1,25 @@
+-- Add up migration script here
+-- how the price of a slot reacts to its sales
+CREATE TYPE price_algorithm AS ENUM ('LINEAR', 'EXPONENTIAL');
+
+-- slots of a game that are priced with their own parameters, to compare the parameters in a single game
+CREATE TABLE slot_groups (
+    id BIGSERIAL PRIMARY KEY,
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    name VARCHAR(50) NOT NULL,
+    algorithm price_algorithm NOT NULL DEFAULT 'LINEAR',
+    -- the percentage the price moves for every sale above or below the average
+    sensitivity DOUBLE PRECISION NOT NULL DEFAULT 5 CHECK (sensitivity > 0),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (game_id, name)
+);
+
+-- a slot is in one group at most
+CREATE TABLE slot_group_slots (
+    game_id BIGINT NOT NULL REFERENCES games(id),
+    slot_no SMALLINT NOT NULL CHECK (slot_no >= 0),
+    group_id BIGINT NOT NULL REFERENCES slot_groups(id),
+    PRIMARY KEY (game_id, slot_no)
+);
+
+CREATE INDEX slot_group_slots_group_id_idx ON slot_group_slots(group_id);
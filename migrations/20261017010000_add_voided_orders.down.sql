@@ -0,0 +1,26 @@
+-- Add down migration script here
+-- the voided orders count as sales again
+UPDATE sales_counts SET sales = sales_counts.sales - voided.amount
+FROM (
+    SELECT orders.game_id, transactions.slot_no, SUM(transactions.amount) AS amount
+    FROM transactions
+    INNER JOIN orders ON orders.id = transactions.order_id
+    WHERE orders.source = 'void'
+    GROUP BY orders.game_id, transactions.slot_no
+) voided
+WHERE sales_counts.game_id = voided.game_id AND sales_counts.slot_no = voided.slot_no;
+
+DELETE FROM transactions WHERE amount < 0;
+DELETE FROM orders WHERE source = 'void';
+
+ALTER TABLE transactions DROP CONSTRAINT purchase_amount_check;
+ALTER TABLE transactions ADD CONSTRAINT purchase_amount_check CHECK (amount > 0);
+
+ALTER TABLE orders DROP CONSTRAINT orders_tip_check;
+ALTER TABLE orders ADD CONSTRAINT orders_tip_check CHECK (tip >= 0);
+
+ALTER TABLE orders DROP CONSTRAINT IF EXISTS orders_void_check;
+ALTER TABLE orders DROP COLUMN IF EXISTS voids_order_id;
+
+ALTER TABLE orders DROP CONSTRAINT orders_source_check;
+ALTER TABLE orders ADD CONSTRAINT orders_source_check CHECK (source IN ('app', 'import'));
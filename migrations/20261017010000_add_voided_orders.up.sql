@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- a voided order is compensated by a void order with the negated amounts, the original order is kept
+ALTER TABLE orders DROP CONSTRAINT orders_source_check;
+ALTER TABLE orders ADD CONSTRAINT orders_source_check CHECK (source IN ('app', 'import', 'void'));
+
+-- an order can only be voided once
+ALTER TABLE orders ADD COLUMN voids_order_id BIGINT UNIQUE REFERENCES orders(id);
+ALTER TABLE orders ADD CONSTRAINT orders_void_check CHECK ((source = 'void') = (voids_order_id IS NOT NULL));
+
+-- the tip is refunded as well
+ALTER TABLE orders DROP CONSTRAINT orders_tip_check;
+ALTER TABLE orders ADD CONSTRAINT orders_tip_check CHECK (tip >= 0 OR source = 'void');
+
+-- the transactions of a void order have negative amounts
+ALTER TABLE transactions DROP CONSTRAINT purchase_amount_check;
+ALTER TABLE transactions ADD CONSTRAINT purchase_amount_check CHECK (amount <> 0);
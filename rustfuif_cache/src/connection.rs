@@ -0,0 +1,431 @@
+//! The commands the cache sends to its store
+use std::fmt;
+#[cfg(feature = "memory")]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "redis")]
+use deadpool_redis::{cmd, redis::RedisError};
+
+use crate::limits::TokenBucket;
+#[cfg(feature = "memory")]
+use crate::memory::Store;
+
+#[cfg(not(any(feature = "redis", feature = "memory")))]
+compile_error!("enable the `redis` or the `memory` feature to store the cache");
+
+/// Deletes a lock, unless it expired and another instance holds it now
+#[cfg(feature = "redis")]
+const RELEASE_LOCK: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+else
+    return 0
+end
+"#;
+
+/// Counts a hit in the window of `KEYS[1]`, and returns it with the hits of the previous window `KEYS[2]`
+#[cfg(feature = "redis")]
+const INCR_WINDOW: &str = r#"
+local current = redis.call("INCR", KEYS[1])
+if current == 1 then
+    redis.call("PEXPIRE", KEYS[1], ARGV[1])
+end
+return {current, tonumber(redis.call("GET", KEYS[2])) or 0}
+"#;
+
+/// Refills the bucket since its last update and takes `ARGV[4]` tokens when there are enough
+///
+/// The time is passed by the instance, a script that writes can't read the clock of Redis.
+#[cfg(feature = "redis")]
+const TAKE_TOKENS: &str = r#"
+local capacity = tonumber(ARGV[1])
+local per_second = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local cost = tonumber(ARGV[4])
+
+local bucket = redis.call("HMGET", KEYS[1], "tokens", "updated_at")
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - updated_at) * per_second / 1000)
+
+local allowed = 0
+if tokens >= cost then
+    tokens = tokens - cost
+    allowed = 1
+end
+
+redis.call("HSET", KEYS[1], "tokens", tostring(tokens), "updated_at", now)
+redis.call("PEXPIRE", KEYS[1], math.ceil((capacity - tokens) * 1000 / per_second) + 1000)
+return {allowed, tostring(tokens)}
+"#;
+
+/// Moves an item from the list `KEYS[1]` to the list `KEYS[2]`, where it's stored as `ARGV[2]`
+#[cfg(feature = "redis")]
+const MOVE_ITEM: &str = r#"
+if redis.call("LREM", KEYS[1], 1, ARGV[1]) == 1 then
+    redis.call("LPUSH", KEYS[2], ARGV[2])
+    return 1
+end
+return 0
+"#;
+
+/// A failed command
+#[derive(Debug)]
+pub(crate) struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for Error {
+    fn from(error: String) -> Error {
+        Error(error)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl From<RedisError> for Error {
+    fn from(error: RedisError) -> Error {
+        Error(error.to_string())
+    }
+}
+
+/// Where the cache is stored
+pub(crate) enum Backend {
+    Disabled,
+    #[cfg(feature = "redis")]
+    Redis(deadpool_redis::Pool),
+    #[cfg(feature = "memory")]
+    Memory(Arc<Store>),
+}
+
+impl Backend {
+    pub(crate) fn is_enabled(&self) -> bool {
+        !matches!(self, Backend::Disabled)
+    }
+
+    #[tracing::instrument(name = "cache::connection", skip(self))]
+    pub(crate) async fn connection(&self) -> Option<Connection> {
+        match self {
+            Backend::Disabled => None,
+            #[cfg(feature = "redis")]
+            Backend::Redis(pool) => match pool.get().await {
+                Ok(connection) => Some(Connection::Redis(connection)),
+                Err(err) => {
+                    error!("unable to get cache connection: {}", err);
+                    None
+                }
+            },
+            #[cfg(feature = "memory")]
+            Backend::Memory(store) => Some(Connection::Memory(store.clone())),
+        }
+    }
+}
+
+pub(crate) enum Connection {
+    #[cfg(feature = "redis")]
+    Redis(deadpool_redis::Connection),
+    #[cfg(feature = "memory")]
+    Memory(Arc<Store>),
+}
+
+impl Connection {
+    pub(crate) async fn get(&mut self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("GET").arg(key).query_async(conn).await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.get(key),
+        }
+    }
+
+    pub(crate) async fn set(
+        &mut self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("SETEX")
+                .arg(key)
+                .arg(ttl.as_secs())
+                .arg(value)
+                .execute_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => {
+                store.set(key, value, ttl);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) async fn delete(&mut self, key: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("DEL").arg(key).execute_async(conn).await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => {
+                store.delete(key);
+                Ok(())
+            }
+        }
+    }
+
+    /// increment a counter, a new counter expires after `ttl`
+    pub(crate) async fn increment(&mut self, key: &str, ttl: Duration) -> Result<u64, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => {
+                let count: u64 = cmd("INCR").arg(key).query_async(conn).await?;
+
+                if count == 1 {
+                    let res = cmd("EXPIRE")
+                        .arg(key)
+                        .arg(ttl.as_secs())
+                        .execute_async(conn)
+                        .await;
+
+                    if let Err(err) = res {
+                        error!("unable to set the expiry of {}: {}", key, err);
+                    }
+                }
+
+                Ok(count)
+            }
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.increment(key, ttl),
+        }
+    }
+
+    pub(crate) async fn counter(&mut self, key: &str) -> Result<Option<u64>, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("GET").arg(key).query_async(conn).await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.counter(key),
+        }
+    }
+
+    /// set the value unless the key exists, returns false when it exists
+    pub(crate) async fn set_nx(
+        &mut self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => {
+                let res: Option<String> = cmd("SET")
+                    .arg(key)
+                    .arg(value)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl.as_millis() as u64)
+                    .query_async(conn)
+                    .await?;
+
+                Ok(res.is_some())
+            }
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => Ok(store.set_nx(key, value, ttl)),
+        }
+    }
+
+    /// delete the key when it still holds the value
+    pub(crate) async fn delete_if(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("EVAL")
+                .arg(RELEASE_LOCK)
+                .arg(1)
+                .arg(key)
+                .arg(value)
+                .execute_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => {
+                store.delete_if(key, value);
+                Ok(())
+            }
+        }
+    }
+
+    /// count a hit in the current window, returns it with the hits of the previous window
+    pub(crate) async fn incr_window(
+        &mut self,
+        current: &str,
+        previous: &str,
+        ttl: Duration,
+    ) -> Result<(u64, u64), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("EVAL")
+                .arg(INCR_WINDOW)
+                .arg(2)
+                .arg(current)
+                .arg(previous)
+                .arg(ttl.as_millis() as u64)
+                .query_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => Ok((
+                store.increment(current, ttl)?,
+                store.counter(previous)?.unwrap_or_default(),
+            )),
+        }
+    }
+
+    pub(crate) async fn counters(
+        &mut self,
+        current: &str,
+        previous: &str,
+    ) -> Result<(Option<u64>, Option<u64>), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("MGET")
+                .arg(current)
+                .arg(previous)
+                .query_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => Ok((store.counter(current)?, store.counter(previous)?)),
+        }
+    }
+
+    /// take tokens from a bucket, returns whether they were taken and the tokens left
+    pub(crate) async fn take_tokens(
+        &mut self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: Duration,
+        cost: u32,
+    ) -> Result<(bool, f64), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => {
+                let (allowed, tokens): (bool, String) = cmd("EVAL")
+                    .arg(TAKE_TOKENS)
+                    .arg(1)
+                    .arg(key)
+                    .arg(bucket.capacity)
+                    .arg(bucket.per_second)
+                    .arg(now.as_millis() as u64)
+                    .arg(cost)
+                    .query_async(conn)
+                    .await?;
+
+                Ok((allowed, tokens.parse().unwrap_or_default()))
+            }
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.take_tokens(key, bucket, now, cost),
+        }
+    }
+
+    /// add an item to the head of a list
+    pub(crate) async fn push(&mut self, key: &str, item: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => {
+                Ok(cmd("LPUSH").arg(key).arg(item).execute_async(conn).await?)
+            }
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.push(key, item),
+        }
+    }
+
+    /// Move the tail of a list to the head of another list
+    ///
+    /// Redis waits up to `timeout` for an item, the memory store returns right away.
+    #[cfg_attr(not(feature = "redis"), allow(unused_variables))]
+    pub(crate) async fn pop_push(
+        &mut self,
+        from: &str,
+        to: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => match timeout {
+                Some(timeout) => Ok(cmd("BRPOPLPUSH")
+                    .arg(from)
+                    .arg(to)
+                    .arg(timeout.as_secs().max(1))
+                    .query_async(conn)
+                    .await?),
+                None => Ok(cmd("RPOPLPUSH").arg(from).arg(to).query_async(conn).await?),
+            },
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.pop_push(from, to),
+        }
+    }
+
+    /// remove the first occurrence of an item from a list
+    pub(crate) async fn remove(&mut self, key: &str, item: &str) -> Result<(), Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("LREM")
+                .arg(key)
+                .arg(1)
+                .arg(item)
+                .execute_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.remove(key, item).map(|_| ()),
+        }
+    }
+
+    /// move the item `raw` from a list to another list where it's stored as `item`,
+    /// returns false when it isn't in the first list
+    pub(crate) async fn move_item(
+        &mut self,
+        from: &str,
+        to: &str,
+        raw: &str,
+        item: &str,
+    ) -> Result<bool, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("EVAL")
+                .arg(MOVE_ITEM)
+                .arg(2)
+                .arg(from)
+                .arg(to)
+                .arg(raw)
+                .arg(item)
+                .query_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.move_item(from, to, raw, item),
+        }
+    }
+
+    pub(crate) async fn len(&mut self, key: &str) -> Result<u64, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("LLEN").arg(key).query_async(conn).await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => Ok(store.range(key)?.len() as u64),
+        }
+    }
+
+    /// every item of a list, from the head to the tail
+    pub(crate) async fn range(&mut self, key: &str) -> Result<Vec<String>, Error> {
+        match self {
+            #[cfg(feature = "redis")]
+            Connection::Redis(conn) => Ok(cmd("LRANGE")
+                .arg(key)
+                .arg(0)
+                .arg(-1)
+                .query_async(conn)
+                .await?),
+            #[cfg(feature = "memory")]
+            Connection::Memory(store) => store.range(key),
+        }
+    }
+}
@@ -0,0 +1,632 @@
+//! A cache that never breaks the application
+//!
+//! Every error is logged and treated like a miss, so the application keeps working without its cache.
+//! Next to cached objects the cache offers counters, locks, rate limits and queues.
+//!
+//! The cache is stored in Redis with the `redis` feature, or in the memory of the process with the
+//! `memory` feature. The `stats` feature counts the hits and misses of every type.
+//!
+//! ```no_run
+//! # #[cfg(feature = "redis")]
+//! # async fn example() {
+//! let cache = rustfuif_cache::Cache::builder()
+//!     .redis_url("redis://localhost:6379")
+//!     .namespace("rustfuif")
+//!     .build();
+//!
+//! let visits = cache.increment("visits", std::time::Duration::from_secs(60)).await;
+//! # }
+//! ```
+#![warn(missing_debug_implementations, rust_2018_idioms, missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate serde_derive;
+
+use std::fmt::{self, Debug, Display};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+mod connection;
+mod limits;
+#[cfg(feature = "memory")]
+mod memory;
+mod queue;
+mod stats;
+
+use connection::{Backend, Connection};
+use stats::{Lookup, Stats};
+
+pub use limits::{Admission, TokenBucket, Window};
+pub use queue::{Delivery, Job, Queue, QueueReport};
+pub use stats::{DetailedStats, PrefixStats};
+
+/// Marks a gzip compressed value
+///
+/// JSON never starts with this byte, so the uncompressed values are stored as they are
+/// and the values stored before compression was added can still be read.
+const COMPRESSED: u8 = 0x00;
+
+/// Configures a cache, without a store the cache is disabled
+#[derive(Debug, Clone)]
+pub struct CacheBuilder {
+    #[cfg(feature = "redis")]
+    redis_url: Option<String>,
+    #[cfg(feature = "memory")]
+    memory: bool,
+    namespace: Option<String>,
+    ttl: Duration,
+    compress_above: usize,
+}
+
+impl Default for CacheBuilder {
+    fn default() -> Self {
+        CacheBuilder {
+            #[cfg(feature = "redis")]
+            redis_url: None,
+            #[cfg(feature = "memory")]
+            memory: false,
+            namespace: None,
+            ttl: Duration::from_secs(3600 * 12),
+            compress_above: 16 * 1024,
+        }
+    }
+}
+
+impl CacheBuilder {
+    /// Store the cache in Redis
+    #[cfg(feature = "redis")]
+    pub fn redis_url<S: Into<String>>(mut self, url: S) -> Self {
+        self.redis_url = Some(url.into());
+        self
+    }
+
+    /// Store the cache in the memory of the process, Redis is used instead when its url is set
+    #[cfg(feature = "memory")]
+    pub fn memory(mut self) -> Self {
+        self.memory = true;
+        self
+    }
+
+    /// Prefix every key, so caches of several applications can share a store
+    pub fn namespace<S: Into<String>>(mut self, namespace: S) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// How long the objects are cached by default
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Compress the objects that are larger than this amount of bytes when they're serialized
+    pub fn compress_above(mut self, bytes: usize) -> Self {
+        self.compress_above = bytes;
+        self
+    }
+
+    /// Create the cache, this ignores all errors to make sure the cache doesn't break the application
+    pub fn build(self) -> Cache {
+        Cache {
+            inner: Arc::new(Inner {
+                backend: RwLock::new(self.backend()),
+                stats: Stats::new(),
+                settings: self,
+            }),
+        }
+    }
+
+    fn backend(&self) -> Backend {
+        #[cfg(feature = "redis")]
+        {
+            if let Some(url) = &self.redis_url {
+                info!("creating cache pool");
+                let cfg = deadpool_redis::Config {
+                    url: Some(url.to_owned()),
+                    ..Default::default()
+                };
+
+                match cfg.create_pool() {
+                    Ok(pool) => return Backend::Redis(pool),
+                    Err(err) => error!("unable to initiate cache pool: {}", err),
+                };
+            }
+        }
+
+        #[cfg(feature = "memory")]
+        {
+            if self.memory {
+                return Backend::Memory(Arc::default());
+            }
+        }
+
+        Backend::Disabled
+    }
+}
+
+/// A handle to a cache, the clones share the store and the stats
+#[derive(Clone)]
+pub struct Cache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    settings: CacheBuilder,
+    backend: RwLock<Backend>,
+    stats: Stats,
+}
+
+impl Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("namespace", &self.inner.settings.namespace)
+            .finish()
+    }
+}
+
+/// Whether the cache can be used
+#[derive(Serialize, Debug)]
+pub struct CacheStatus {
+    /// is true when the cache has a store, like a valid redis url
+    enabled: bool,
+    /// is true when the cache is enabled and a connection can be retrieved
+    healthy: bool,
+}
+
+/// A type that's stored in the cache
+///
+/// Bump the version when the shape of the type changes. The objects cached by the previous deploy
+/// are ignored then, instead of failing to deserialize until they expire.
+pub trait CacheIdentifier: Sized {
+    /// the version of the shape of the type
+    const VERSION: u32 = 1;
+
+    /// Get the key for a certain cache entry
+    fn cache_key<Id: Display>(id: Id) -> String {
+        format!("{}.v{}.{}", prefix::<Self>(), Self::VERSION, id)
+    }
+}
+
+/// A lock shared by every instance, release it when the work is done or let it expire
+///
+/// Without a store there is no way to reach the other instances,
+/// so every instance gets the lock and does the work like before.
+#[derive(Debug)]
+pub struct Lock {
+    cache: Cache,
+    key: String,
+    /// only the instance that set the lock can release it
+    token: String,
+    /// false when the lock isn't stored
+    shared: bool,
+}
+
+impl Lock {
+    fn local(cache: Cache, key: String) -> Lock {
+        Lock {
+            cache,
+            key,
+            token: String::new(),
+            shared: false,
+        }
+    }
+
+    /// Release the lock, unless it expired and another instance holds it now
+    #[tracing::instrument(name = "Lock::release")]
+    pub async fn release(self) {
+        if !self.shared {
+            return;
+        }
+
+        let mut conn = match self.cache.connection().await {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if let Err(err) = conn.delete_if(&self.key, &self.token).await {
+            error!("unable to release the lock {}: {}", self.key, err);
+        }
+    }
+}
+
+/// The prefix of the keys of a type, the versions of a type share the prefix
+fn prefix<T>() -> &'static str {
+    std::any::type_name::<T>()
+}
+
+/// Compress the serialized object when it's larger than the threshold
+fn encode(json: Vec<u8>, threshold: usize) -> io::Result<Vec<u8>> {
+    if json.len() <= threshold {
+        return Ok(json);
+    }
+
+    let mut encoder = GzEncoder::new(vec![COMPRESSED], Compression::fast());
+    encoder.write_all(&json)?;
+    encoder.finish()
+}
+
+/// The serialized object of a cached value
+fn decode(value: Vec<u8>) -> io::Result<Vec<u8>> {
+    match value.split_first() {
+        Some((&COMPRESSED, compressed)) => {
+            let mut json = Vec::new();
+            GzDecoder::new(compressed).read_to_end(&mut json)?;
+            Ok(json)
+        }
+        _ => Ok(value),
+    }
+}
+
+impl Cache {
+    /// Configure a new cache
+    pub fn builder() -> CacheBuilder {
+        CacheBuilder::default()
+    }
+
+    /// returns true if the cache has a store
+    pub async fn is_enabled(&self) -> bool {
+        self.inner.backend.read().await.is_enabled()
+    }
+
+    pub(crate) async fn connection(&self) -> Option<Connection> {
+        self.inner.backend.read().await.connection().await
+    }
+
+    pub(crate) fn stats(&self) -> &Stats {
+        &self.inner.stats
+    }
+
+    /// the key in the namespace of the cache
+    pub(crate) fn key(&self, key: &str) -> String {
+        match &self.inner.settings.namespace {
+            Some(namespace) => format!("{}.{}", namespace, key),
+            None => key.to_string(),
+        }
+    }
+
+    /// Fetch a cached object
+    #[tracing::instrument(name = "cache::get")]
+    pub async fn get<T: CacheIdentifier + DeserializeOwned, Id: Display + Debug>(
+        &self,
+        id: Id,
+    ) -> Option<T> {
+        let mut conn = self.connection().await?;
+
+        let key = self.key(&T::cache_key(id));
+
+        match conn.get(&key).await {
+            Ok(Some(res)) => match decode(res)
+                .map_err(serde_json::Error::io)
+                .and_then(|json| serde_json::from_slice::<T>(&json))
+            {
+                Ok(object) => {
+                    self.stats().lookup(prefix::<T>(), Lookup::Hit);
+                    debug!("found {} in cache", &key);
+                    Some(object)
+                }
+                Err(err) => {
+                    warn!("unable to deserialize {} from cache: {}", &key, err);
+                    self.stats().lookup(prefix::<T>(), Lookup::Broken);
+                    None
+                }
+            },
+            Ok(None) => {
+                self.stats().lookup(prefix::<T>(), Lookup::Miss);
+                None
+            }
+            Err(err) => {
+                error!("unable to fetch {} from cache: {}", &key, err);
+                None
+            }
+        }
+    }
+
+    /// Store an object for the default duration
+    #[tracing::instrument(name = "cache::set", skip(object))]
+    pub async fn set<T: CacheIdentifier + Serialize, Id: Display + Debug>(
+        &self,
+        object: &T,
+        id: Id,
+    ) {
+        self.set_expiring(object, id, self.inner.settings.ttl).await
+    }
+
+    /// Store an object that expires after `ttl` instead of the default
+    #[tracing::instrument(name = "cache::set_expiring", skip(object))]
+    pub async fn set_expiring<T: CacheIdentifier + Serialize, Id: Display + Debug>(
+        &self,
+        object: &T,
+        id: Id,
+        ttl: Duration,
+    ) {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        let key = self.key(&T::cache_key(id));
+
+        let object_string = match serde_json::to_vec(object).and_then(|json| {
+            encode(json, self.inner.settings.compress_above).map_err(serde_json::Error::io)
+        }) {
+            Ok(res) => res,
+            Err(err) => {
+                error!("unable to serialize object for cache {}", err);
+                self.stats().serialization_error();
+                return;
+            }
+        };
+        let size = object_string.len();
+
+        match conn.set(&key, object_string, ttl).await {
+            Ok(()) => self.stats().stored(size),
+            Err(err) => error!("unable to store object in cache: {}", err),
+        }
+    }
+
+    /// Delete a cached value
+    #[tracing::instrument(name = "cache::delete")]
+    pub async fn delete(&self, cache_key: &str) {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if let Err(err) = conn.delete(&self.key(cache_key)).await {
+            error!("unable to delete object from cache: {}", err);
+        }
+    }
+
+    /// Increment a counter that expires `ttl` after it was created, returns the new count
+    #[tracing::instrument(name = "cache::increment")]
+    pub async fn increment(&self, key: &str, ttl: Duration) -> Option<u64> {
+        let mut conn = self.connection().await?;
+
+        match conn.increment(&self.key(key), ttl).await {
+            Ok(count) => Some(count),
+            Err(err) => {
+                error!("unable to increment {}: {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// the current value of a counter, zero when it doesn't exist
+    #[tracing::instrument(name = "cache::counter")]
+    pub async fn counter(&self, key: &str) -> Option<u64> {
+        let mut conn = self.connection().await?;
+
+        match conn.counter(&self.key(key)).await {
+            Ok(count) => Some(count.unwrap_or_default()),
+            Err(err) => {
+                error!("unable to fetch {}: {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// Try to take a lock that expires after `ttl`, returns nothing when another instance holds it
+    #[tracing::instrument(name = "cache::lock")]
+    pub async fn lock(&self, key: &str, ttl: Duration) -> Option<Lock> {
+        let key = self.key(&format!("lock.{}", key));
+
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return Some(Lock::local(self.clone(), key)),
+        };
+
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        match conn.set_nx(&key, &token, ttl).await {
+            Ok(true) => Some(Lock {
+                cache: self.clone(),
+                key,
+                token,
+                shared: true,
+            }),
+            Ok(false) => None,
+            Err(err) => {
+                error!("unable to take the lock {}: {}", key, err);
+                Some(Lock::local(self.clone(), key))
+            }
+        }
+    }
+
+    /// Count a hit in a sliding window, returns nothing when the hits can't be counted
+    #[tracing::instrument(name = "cache::incr_window")]
+    pub async fn incr_window(&self, key: &str, window: Duration) -> Option<Window> {
+        let mut conn = self.connection().await?;
+
+        let now = limits::now();
+        let (current, previous) = limits::window_keys(&self.key(key), window, now);
+
+        // the previous window is needed until the current one ends
+        match conn.incr_window(&current, &previous, 2 * window).await {
+            Ok((current, previous)) => Some(Window::new(current, previous, window, now)),
+            Err(err) => {
+                error!("unable to count {}: {}", current, err);
+                None
+            }
+        }
+    }
+
+    /// The hits of a sliding window, without counting one
+    #[tracing::instrument(name = "cache::window")]
+    pub async fn window(&self, key: &str, window: Duration) -> Option<Window> {
+        let mut conn = self.connection().await?;
+
+        let now = limits::now();
+        let (current, previous) = limits::window_keys(&self.key(key), window, now);
+
+        match conn.counters(&current, &previous).await {
+            Ok((current, previous)) => Some(Window::new(
+                current.unwrap_or_default(),
+                previous.unwrap_or_default(),
+                window,
+                now,
+            )),
+            Err(err) => {
+                error!("unable to fetch {}: {}", current, err);
+                None
+            }
+        }
+    }
+
+    /// Take `cost` tokens from a bucket, returns nothing when the bucket can't be reached
+    #[tracing::instrument(name = "cache::take_tokens")]
+    pub async fn take_tokens(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        cost: u32,
+    ) -> Option<Admission> {
+        let mut conn = self.connection().await?;
+
+        let key = self.key(&format!("bucket.{}", key));
+
+        match conn.take_tokens(&key, bucket, limits::now(), cost).await {
+            Ok((allowed, tokens)) => Some(Admission::new(bucket, allowed, tokens, cost)),
+            Err(err) => {
+                error!("unable to take tokens from {}: {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// A queue stored in this cache
+    pub fn queue(&self, name: &str) -> Queue {
+        Queue::new(self.clone(), name)
+    }
+
+    /// Stop using the store, everything is a miss until the cache is enabled again
+    pub async fn disable(&self) {
+        *self.inner.backend.write().await = Backend::Disabled;
+    }
+
+    /// Connect to the store again
+    pub async fn enable(&self) {
+        *self.inner.backend.write().await = self.inner.settings.backend();
+    }
+
+    /// Whether the cache is enabled and can be reached
+    pub async fn status(&self) -> CacheStatus {
+        let enabled = self.is_enabled().await;
+        let mut healthy = true;
+        if enabled {
+            healthy = self.connection().await.is_some();
+        }
+        CacheStatus { enabled, healthy }
+    }
+
+    /// The counters of the cache, with the hit ratio per key prefix
+    pub fn detailed_stats(&self) -> DetailedStats {
+        self.stats().snapshot()
+    }
+
+    /// the amount of objects that were found
+    pub fn hits(&self) -> usize {
+        self.stats().hits()
+    }
+
+    /// the amount of objects that weren't found
+    pub fn misses(&self) -> usize {
+        self.stats().misses()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compress_large_values() {
+        let small = br#"{"token":"abc"}"#.to_vec();
+        let encoded = encode(small.clone(), 1024).unwrap();
+        assert_eq!(encoded, small);
+        assert_eq!(decode(encoded).unwrap(), small);
+
+        let large = serde_json::to_vec(&vec!["https://duckduckgo.com/i/image.jpg"; 100]).unwrap();
+        let encoded = encode(large.clone(), 1024).unwrap();
+        assert_eq!(encoded[0], COMPRESSED);
+        assert!(encoded.len() < large.len());
+        assert_eq!(decode(encoded).unwrap(), large);
+    }
+
+    #[test]
+    fn versioned_keys() {
+        struct Analytics;
+        impl CacheIdentifier for Analytics {}
+
+        struct Images;
+        impl CacheIdentifier for Images {
+            const VERSION: u32 = 3;
+        }
+
+        assert!(Analytics::cache_key(5).ends_with("::Analytics.v1.5"));
+        assert!(Images::cache_key("cat").ends_with("::Images.v3.cat"));
+    }
+
+    #[cfg(feature = "memory")]
+    #[tokio::test]
+    async fn caches_share_a_store() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Token(String);
+        impl CacheIdentifier for Token {}
+
+        let cache = Cache::builder().memory().namespace("rustfuif").build();
+        let handle = cache.clone();
+        let other = Cache::builder().memory().namespace("other").build();
+
+        cache.set(&Token("abc".to_string()), 1).await;
+        assert_eq!(handle.get(1).await, Some(Token("abc".to_string())));
+        assert_eq!(other.get::<Token, _>(1).await, None);
+
+        let lock = cache.lock("prices", Duration::from_secs(60)).await.unwrap();
+        assert!(handle
+            .lock("prices", Duration::from_secs(60))
+            .await
+            .is_none());
+        lock.release().await;
+        assert!(handle
+            .lock("prices", Duration::from_secs(60))
+            .await
+            .is_some());
+
+        cache.disable().await;
+        assert_eq!(cache.get::<Token, _>(1).await, None);
+        assert!(cache
+            .lock("prices", Duration::from_secs(60))
+            .await
+            .is_some());
+        cache.enable().await;
+        assert!(cache.status().await.healthy);
+    }
+
+    #[tokio::test]
+    async fn disabled_without_a_store() {
+        let cache = Cache::builder().build();
+
+        assert!(!cache.is_enabled().await);
+        assert_eq!(
+            cache.increment("visits", Duration::from_secs(60)).await,
+            None
+        );
+        // every instance does the work when the others can't be reached
+        assert!(cache
+            .lock("prices", Duration::from_secs(60))
+            .await
+            .is_some());
+    }
+}
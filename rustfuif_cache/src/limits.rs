@@ -0,0 +1,134 @@
+//! Sliding windows and token buckets for rate limiting
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The hits of a sliding window
+///
+/// The hits are counted per fixed window, the windows start at the Unix epoch.
+/// The sliding count weighs the previous window by the part of it that still overlaps,
+/// which is close enough for rate limiting without storing every hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    /// the hits since the start of the current fixed window
+    pub current: u64,
+    /// the hits of the previous fixed window
+    pub previous: u64,
+    /// the part of the current fixed window that has passed, from 0 to 1
+    elapsed: f64,
+}
+
+impl Window {
+    pub(crate) fn new(current: u64, previous: u64, window: Duration, now: Duration) -> Window {
+        let window = window.as_millis().max(1);
+
+        Window {
+            current,
+            previous,
+            elapsed: (now.as_millis() % window) as f64 / window as f64,
+        }
+    }
+
+    /// the estimated hits in the last window
+    pub fn count(&self) -> u64 {
+        self.current + (self.previous as f64 * (1.0 - self.elapsed)).floor() as u64
+    }
+}
+
+/// A bucket of `capacity` tokens that refills at `per_second` tokens a second
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    /// the tokens in a full bucket, a new bucket is full
+    pub capacity: u32,
+    /// the tokens added every second
+    pub per_second: f64,
+}
+
+/// Whether tokens were taken from a bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Admission {
+    /// true when the tokens were taken
+    pub allowed: bool,
+    /// the whole tokens left in the bucket
+    pub remaining: u32,
+    /// the time until enough tokens are refilled, zero when the tokens were taken
+    pub retry_after: Duration,
+}
+
+impl Admission {
+    pub(crate) fn new(bucket: &TokenBucket, allowed: bool, tokens: f64, cost: u32) -> Admission {
+        let retry_after = if allowed || bucket.per_second <= 0.0 {
+            Duration::default()
+        } else {
+            Duration::from_secs_f64((f64::from(cost) - tokens).max(0.0) / bucket.per_second)
+        };
+
+        Admission {
+            allowed,
+            remaining: tokens.max(0.0).floor() as u32,
+            retry_after,
+        }
+    }
+}
+
+impl TokenBucket {
+    /// The tokens in the bucket after refilling it for `elapsed`
+    ///
+    /// The Redis script does the same, so both stores agree.
+    #[cfg(feature = "memory")]
+    pub(crate) fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        f64::from(self.capacity).min(tokens + elapsed.as_secs_f64() * self.per_second)
+    }
+}
+
+/// the time since the Unix epoch
+pub(crate) fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// the keys of the current and the previous fixed window
+pub(crate) fn window_keys(key: &str, window: Duration, now: Duration) -> (String, String) {
+    let index = now.as_millis() / window.as_millis().max(1);
+
+    (
+        format!("window.{}.{}", key, index),
+        format!("window.{}.{}", key, index.saturating_sub(1)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliding_window() {
+        let minute = Duration::from_secs(60);
+        let now = Duration::from_secs(60 * 1000 + 15);
+
+        let (current, previous) = window_keys("login.4", minute, now);
+        assert_eq!(current, "window.login.4.1000");
+        assert_eq!(previous, "window.login.4.999");
+
+        // a quarter of the current window has passed, so three quarters of the previous one count
+        let window = Window::new(10, 40, minute, now);
+        assert_eq!(window.count(), 40);
+        assert_eq!(Window::new(10, 0, minute, now).count(), 10);
+    }
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket {
+            capacity: 10,
+            per_second: 2.0,
+        };
+
+        let admission = Admission::new(&bucket, true, 6.5, 1);
+        assert_eq!(admission.remaining, 6);
+        assert_eq!(admission.retry_after, Duration::default());
+
+        let admission = Admission::new(&bucket, false, 0.5, 2);
+        assert!(!admission.allowed);
+        assert_eq!(admission.remaining, 0);
+        assert_eq!(admission.retry_after, Duration::from_millis(750));
+    }
+}
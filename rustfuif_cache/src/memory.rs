@@ -0,0 +1,344 @@
+//! A store in the memory of the process
+//!
+//! It behaves like the Redis commands the cache uses, but only the current process sees it.
+//! The expired keys are removed when they are touched.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::connection::Error;
+use crate::limits::TokenBucket;
+
+#[derive(Debug, Default)]
+pub(crate) struct Store {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    expires_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Bytes(Vec<u8>),
+    List(VecDeque<String>),
+    Bucket { tokens: f64, updated_at: Duration },
+}
+
+fn wrong_type(key: &str) -> Error {
+    Error::from(format!("{} holds the wrong kind of value", key))
+}
+
+impl Entry {
+    fn new(value: Value, ttl: Option<Duration>) -> Entry {
+        Entry {
+            value,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        }
+    }
+
+    fn counter(&self, key: &str) -> Result<u64, Error> {
+        match &self.value {
+            Value::Bytes(bytes) => std::str::from_utf8(bytes)
+                .ok()
+                .and_then(|count| count.parse().ok())
+                .ok_or_else(|| Error::from(format!("{} isn't a counter", key))),
+            _ => Err(wrong_type(key)),
+        }
+    }
+}
+
+impl Store {
+    /// the entries without the expired ones
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, Entry>> {
+        let mut entries = match self.entries.lock() {
+            Ok(entries) => entries,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let now = Instant::now();
+        entries
+            .retain(|_, entry| !matches!(entry.expires_at, Some(expires_at) if expires_at <= now));
+        entries
+    }
+
+    fn list<'a>(
+        entries: &'a mut HashMap<String, Entry>,
+        key: &str,
+    ) -> Result<&'a mut VecDeque<String>, Error> {
+        let entry = entries
+            .entry(key.to_string())
+            .or_insert_with(|| Entry::new(Value::List(VecDeque::new()), None));
+
+        match &mut entry.value {
+            Value::List(list) => Ok(list),
+            _ => Err(wrong_type(key)),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match self.entries().get(key).map(|entry| &entry.value) {
+            Some(Value::Bytes(bytes)) => Ok(Some(bytes.clone())),
+            Some(_) => Err(wrong_type(key)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn set(&self, key: &str, value: Vec<u8>, ttl: Duration) {
+        self.entries()
+            .insert(key.to_string(), Entry::new(Value::Bytes(value), Some(ttl)));
+    }
+
+    pub(crate) fn delete(&self, key: &str) {
+        self.entries().remove(key);
+    }
+
+    pub(crate) fn increment(&self, key: &str, ttl: Duration) -> Result<u64, Error> {
+        let mut entries = self.entries();
+
+        let count = match entries.get(key) {
+            Some(entry) => entry.counter(key)? + 1,
+            None => 1,
+        };
+        let expires_at = match entries.get(key) {
+            Some(entry) => entry.expires_at,
+            None => Some(Instant::now() + ttl),
+        };
+
+        entries.insert(
+            key.to_string(),
+            Entry {
+                value: Value::Bytes(count.to_string().into_bytes()),
+                expires_at,
+            },
+        );
+
+        Ok(count)
+    }
+
+    pub(crate) fn counter(&self, key: &str) -> Result<Option<u64>, Error> {
+        self.entries()
+            .get(key)
+            .map(|entry| entry.counter(key))
+            .transpose()
+    }
+
+    /// set the value unless the key exists, like `SET NX`
+    pub(crate) fn set_nx(&self, key: &str, value: &str, ttl: Duration) -> bool {
+        let mut entries = self.entries();
+        if entries.contains_key(key) {
+            return false;
+        }
+
+        entries.insert(
+            key.to_string(),
+            Entry::new(Value::Bytes(value.as_bytes().to_vec()), Some(ttl)),
+        );
+        true
+    }
+
+    /// delete the key when it still holds the value
+    pub(crate) fn delete_if(&self, key: &str, value: &str) {
+        let mut entries = self.entries();
+
+        if let Some(Value::Bytes(bytes)) = entries.get(key).map(|entry| &entry.value) {
+            if bytes.as_slice() == value.as_bytes() {
+                entries.remove(key);
+            }
+        }
+    }
+
+    pub(crate) fn take_tokens(
+        &self,
+        key: &str,
+        bucket: &TokenBucket,
+        now: Duration,
+        cost: u32,
+    ) -> Result<(bool, f64), Error> {
+        let mut entries = self.entries();
+
+        let tokens = match entries.get(key).map(|entry| &entry.value) {
+            Some(Value::Bucket { tokens, updated_at }) => {
+                bucket.refill(*tokens, now.checked_sub(*updated_at).unwrap_or_default())
+            }
+            Some(_) => return Err(wrong_type(key)),
+            None => f64::from(bucket.capacity),
+        };
+
+        let allowed = tokens >= f64::from(cost);
+        let tokens = if allowed {
+            tokens - f64::from(cost)
+        } else {
+            tokens
+        };
+
+        // the bucket expires once it's full again
+        let ttl = if bucket.per_second > 0.0 {
+            Duration::from_secs_f64((f64::from(bucket.capacity) - tokens) / bucket.per_second)
+                + Duration::from_secs(1)
+        } else {
+            Duration::from_secs(24 * 60 * 60)
+        };
+
+        entries.insert(
+            key.to_string(),
+            Entry::new(
+                Value::Bucket {
+                    tokens,
+                    updated_at: now,
+                },
+                Some(ttl),
+            ),
+        );
+
+        Ok((allowed, tokens))
+    }
+
+    /// add an item to the head of a list, like `LPUSH`
+    pub(crate) fn push(&self, key: &str, item: &str) -> Result<(), Error> {
+        Store::list(&mut self.entries(), key)?.push_front(item.to_string());
+        Ok(())
+    }
+
+    /// move the tail of a list to the head of another list, like `RPOPLPUSH`
+    pub(crate) fn pop_push(&self, from: &str, to: &str) -> Result<Option<String>, Error> {
+        let mut entries = self.entries();
+
+        let item = match Store::list(&mut entries, from)?.pop_back() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        Store::list(&mut entries, to)?.push_front(item.clone());
+
+        Ok(Some(item))
+    }
+
+    /// remove the first occurrence of an item from a list, like `LREM key 1 item`
+    pub(crate) fn remove(&self, key: &str, item: &str) -> Result<bool, Error> {
+        let mut entries = self.entries();
+        let list = Store::list(&mut entries, key)?;
+
+        match list.iter().position(|candidate| candidate == item) {
+            Some(position) => {
+                list.remove(position);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub(crate) fn move_item(
+        &self,
+        from: &str,
+        to: &str,
+        raw: &str,
+        item: &str,
+    ) -> Result<bool, Error> {
+        let mut entries = self.entries();
+
+        let list = Store::list(&mut entries, from)?;
+        let position = match list.iter().position(|candidate| candidate == raw) {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        list.remove(position);
+        Store::list(&mut entries, to)?.push_front(item.to_string());
+
+        Ok(true)
+    }
+
+    pub(crate) fn range(&self, key: &str) -> Result<Vec<String>, Error> {
+        Ok(Store::list(&mut self.entries(), key)?
+            .iter()
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters() {
+        let store = Store::default();
+
+        assert_eq!(store.counter("visits").unwrap(), None);
+        assert_eq!(
+            store.increment("visits", Duration::from_secs(60)).unwrap(),
+            1
+        );
+        assert_eq!(
+            store.increment("visits", Duration::from_secs(60)).unwrap(),
+            2
+        );
+        assert_eq!(store.counter("visits").unwrap(), Some(2));
+
+        store.increment("expired", Duration::from_secs(0)).unwrap();
+        assert_eq!(store.counter("expired").unwrap(), None);
+    }
+
+    #[test]
+    fn locks() {
+        let store = Store::default();
+
+        assert!(store.set_nx("lock", "first", Duration::from_secs(60)));
+        assert!(!store.set_nx("lock", "second", Duration::from_secs(60)));
+
+        // only the holder can release the lock
+        store.delete_if("lock", "second");
+        assert!(!store.set_nx("lock", "second", Duration::from_secs(60)));
+        store.delete_if("lock", "first");
+        assert!(store.set_nx("lock", "second", Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn token_buckets() {
+        let store = Store::default();
+        let bucket = TokenBucket {
+            capacity: 2,
+            per_second: 1.0,
+        };
+        let now = Duration::from_secs(1000);
+
+        assert_eq!(
+            store.take_tokens("api", &bucket, now, 1).unwrap(),
+            (true, 1.0)
+        );
+        assert_eq!(
+            store.take_tokens("api", &bucket, now, 1).unwrap(),
+            (true, 0.0)
+        );
+        assert_eq!(
+            store.take_tokens("api", &bucket, now, 1).unwrap(),
+            (false, 0.0)
+        );
+
+        let later = now + Duration::from_millis(1500);
+        assert_eq!(
+            store.take_tokens("api", &bucket, later, 1).unwrap(),
+            (true, 0.5)
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let store = Store::default();
+
+        store.push("queue", "a").unwrap();
+        store.push("queue", "b").unwrap();
+        assert_eq!(
+            store.pop_push("queue", "processing").unwrap(),
+            Some("a".to_string())
+        );
+        assert_eq!(store.range("queue").unwrap(), vec!["b"]);
+
+        assert!(store.move_item("processing", "dead", "a", "a2").unwrap());
+        assert!(!store.remove("processing", "a").unwrap());
+        assert_eq!(store.range("dead").unwrap(), vec!["a2"]);
+
+        assert!(store.get("queue").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+//! Reliable queues for background deliveries
+use std::time::Duration;
+
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::connection::{Connection, Error};
+use crate::Cache;
+
+/// A queue that keeps the items until they are acknowledged
+///
+/// A popped item moves to a processing list, and only leaves it when it's acknowledged or failed.
+/// The items that were being processed when an instance stopped are put back with `recover`,
+/// so an item is delivered at least once. An item that failed too often moves to the dead letters,
+/// where an administrator can inspect it and retry it.
+#[derive(Debug, Clone)]
+pub struct Queue {
+    cache: Cache,
+    name: String,
+}
+
+/// An item in a queue, with its delivery attempts
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Delivery<T> {
+    /// a random id, which identifies the item in the dead letters
+    pub id: String,
+    /// the failed attempts to process the item
+    pub attempts: u32,
+    /// the error of the last failed attempt
+    pub error: Option<String>,
+    /// the item that was pushed
+    pub payload: T,
+}
+
+/// An item that's being processed, acknowledge it or fail it
+#[derive(Debug)]
+pub struct Job<T> {
+    /// the item as it's stored in the processing list
+    raw: String,
+    /// the item with its attempts
+    pub delivery: Delivery<T>,
+}
+
+/// The items of a queue
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueReport {
+    /// the name of the queue
+    pub name: String,
+    /// the items waiting to be processed
+    pub pending: u64,
+    /// the items that are being processed
+    pub processing: u64,
+    /// the items that failed too often
+    pub dead_letters: Vec<Delivery<serde_json::Value>>,
+}
+
+impl Queue {
+    pub(crate) fn new(cache: Cache, name: &str) -> Queue {
+        Queue {
+            cache,
+            name: name.to_string(),
+        }
+    }
+
+    /// The name of the queue
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn pending(&self) -> String {
+        self.cache.key(&format!("queue.{}", self.name))
+    }
+
+    fn processing(&self) -> String {
+        self.cache.key(&format!("queue.{}.processing", self.name))
+    }
+
+    fn dead(&self) -> String {
+        self.cache.key(&format!("queue.{}.dead", self.name))
+    }
+
+    async fn connection(&self) -> Option<Connection> {
+        self.cache.connection().await
+    }
+
+    /// Add an item to the queue, returns false when it can't be queued
+    #[tracing::instrument(name = "Queue::push", skip(payload))]
+    pub async fn push<T: Serialize>(&self, payload: &T) -> bool {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return false,
+        };
+
+        let delivery = Delivery {
+            id: format!("{:032x}", rand::thread_rng().gen::<u128>()),
+            attempts: 0,
+            error: None,
+            payload,
+        };
+        let item = match serde_json::to_string(&delivery) {
+            Ok(item) => item,
+            Err(err) => {
+                error!("unable to serialize the item for {}: {}", self.name, err);
+                self.cache.stats().serialization_error();
+                return false;
+            }
+        };
+
+        match conn.push(&self.pending(), &item).await {
+            Ok(()) => true,
+            Err(err) => {
+                error!("unable to queue an item in {}: {}", self.name, err);
+                false
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for an item, returns nothing when the queue stayed empty
+    ///
+    /// A queue in memory doesn't wait, it returns nothing right away when it's empty.
+    #[tracing::instrument(name = "Queue::pop")]
+    pub async fn pop<T: DeserializeOwned>(&self, timeout: Duration) -> Option<Job<T>> {
+        let mut conn = self.connection().await?;
+
+        let raw = match conn
+            .pop_push(&self.pending(), &self.processing(), Some(timeout))
+            .await
+        {
+            Ok(raw) => raw?,
+            Err(err) => {
+                error!("unable to pop an item from {}: {}", self.name, err);
+                return None;
+            }
+        };
+
+        match serde_json::from_str(&raw) {
+            Ok(delivery) => Some(Job { raw, delivery }),
+            Err(err) => {
+                // the item can never be processed, keep it for an administrator
+                error!("unable to deserialize an item from {}: {}", self.name, err);
+                self.cache.stats().serialization_error();
+                self.move_item(&self.processing(), &self.dead(), &raw, &raw)
+                    .await;
+                None
+            }
+        }
+    }
+
+    /// The item is processed, remove it from the queue
+    #[tracing::instrument(name = "Queue::ack", skip(job))]
+    pub async fn ack<T>(&self, job: Job<T>) {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return,
+        };
+
+        if let Err(err) = conn.remove(&self.processing(), &job.raw).await {
+            error!(
+                "unable to acknowledge {} in {}: {}",
+                job.delivery.id, self.name, err
+            );
+        }
+    }
+
+    /// The item couldn't be processed, queue it again or move it to the dead letters
+    #[tracing::instrument(name = "Queue::fail", skip(job))]
+    pub async fn fail<T: Serialize>(&self, job: Job<T>, error: String, max_attempts: u32) {
+        let delivery = Delivery {
+            attempts: job.delivery.attempts + 1,
+            error: Some(error),
+            ..job.delivery
+        };
+
+        let item = match serde_json::to_string(&delivery) {
+            Ok(item) => item,
+            Err(err) => {
+                error!("unable to serialize the item for {}: {}", self.name, err);
+                return;
+            }
+        };
+
+        let target = if delivery.attempts < max_attempts {
+            self.pending()
+        } else {
+            warn!(
+                "{} failed {} times, moving it to the dead letters",
+                delivery.id, delivery.attempts
+            );
+            self.dead()
+        };
+
+        self.move_item(&self.processing(), &target, &job.raw, &item)
+            .await;
+    }
+
+    /// Put the items that were being processed back in the queue, returns how many were put back
+    #[tracing::instrument(name = "Queue::recover")]
+    pub async fn recover(&self) -> usize {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return 0,
+        };
+
+        let mut recovered = 0;
+        loop {
+            match conn
+                .pop_push(&self.processing(), &self.pending(), None)
+                .await
+            {
+                Ok(Some(_)) => recovered += 1,
+                Ok(None) => return recovered,
+                Err(err) => {
+                    error!("unable to recover the items of {}: {}", self.name, err);
+                    return recovered;
+                }
+            }
+        }
+    }
+
+    /// The length of the queue and its dead letters, returns nothing when the queue can't be reached
+    #[tracing::instrument(name = "Queue::report")]
+    pub async fn report(&self) -> Option<QueueReport> {
+        let mut conn = self.connection().await?;
+
+        let res: Result<(u64, u64, Vec<String>), Error> = async {
+            let pending = conn.len(&self.pending()).await?;
+            let processing = conn.len(&self.processing()).await?;
+            let dead = conn.range(&self.dead()).await?;
+
+            Ok((pending, processing, dead))
+        }
+        .await;
+
+        match res {
+            Ok((pending, processing, dead)) => Some(QueueReport {
+                name: self.name.clone(),
+                pending,
+                processing,
+                dead_letters: dead
+                    .iter()
+                    .filter_map(|item| serde_json::from_str(item).ok())
+                    .collect(),
+            }),
+            Err(err) => {
+                error!("unable to fetch the length of {}: {}", self.name, err);
+                None
+            }
+        }
+    }
+
+    /// Queue a dead letter again with its attempts reset, returns false when it doesn't exist
+    #[tracing::instrument(name = "Queue::retry")]
+    pub async fn retry(&self, id: &str) -> Option<bool> {
+        let mut conn = self.connection().await?;
+
+        let dead = match conn.range(&self.dead()).await {
+            Ok(dead) => dead,
+            Err(err) => {
+                error!("unable to fetch the dead letters of {}: {}", self.name, err);
+                return None;
+            }
+        };
+
+        for raw in dead {
+            let delivery = match serde_json::from_str::<Delivery<serde_json::Value>>(&raw) {
+                Ok(delivery) if delivery.id == id => delivery,
+                _ => continue,
+            };
+
+            let delivery = Delivery {
+                attempts: 0,
+                error: None,
+                ..delivery
+            };
+            let item = serde_json::to_string(&delivery).ok()?;
+
+            return Some(
+                self.move_item(&self.dead(), &self.pending(), &raw, &item)
+                    .await,
+            );
+        }
+
+        Some(false)
+    }
+
+    /// returns false when the item isn't in the source list anymore
+    async fn move_item(&self, from: &str, to: &str, raw: &str, item: &str) -> bool {
+        let mut conn = match self.connection().await {
+            Some(conn) => conn,
+            None => return false,
+        };
+
+        match conn.move_item(from, to, raw, item).await {
+            Ok(moved) => moved,
+            Err(err) => {
+                error!("unable to move an item of {} to {}: {}", self.name, to, err);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "memory"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_keys() {
+        let queue = Cache::builder()
+            .namespace("rustfuif")
+            .build()
+            .queue("webhooks");
+
+        assert_eq!(queue.pending(), "rustfuif.queue.webhooks");
+        assert_eq!(queue.processing(), "rustfuif.queue.webhooks.processing");
+        assert_eq!(queue.dead(), "rustfuif.queue.webhooks.dead");
+    }
+
+    #[tokio::test]
+    async fn dead_letters() {
+        let queue = Cache::builder().memory().build().queue("webhooks");
+        let timeout = Duration::from_secs(1);
+
+        assert!(queue.push(&"https://example.com").await);
+        let job: Job<String> = queue.pop(timeout).await.unwrap();
+        assert_eq!(job.delivery.payload, "https://example.com");
+        queue.fail(job, "timed out".to_string(), 2).await;
+
+        let job: Job<String> = queue.pop(timeout).await.unwrap();
+        assert_eq!(job.delivery.attempts, 1);
+        queue.fail(job, "timed out again".to_string(), 2).await;
+        assert!(queue.pop::<String>(timeout).await.is_none());
+
+        let report = queue.report().await.unwrap();
+        assert_eq!((report.pending, report.processing), (0, 0));
+        let dead = &report.dead_letters[0];
+        assert_eq!(dead.error.as_deref(), Some("timed out again"));
+
+        assert!(queue.retry(&dead.id).await.unwrap());
+        assert!(!queue.retry(&dead.id).await.unwrap());
+        let job: Job<String> = queue.pop(timeout).await.unwrap();
+        assert_eq!(job.delivery.attempts, 0);
+        queue.ack(job).await;
+
+        let report = queue.report().await.unwrap();
+        assert_eq!((report.pending, report.processing), (0, 0));
+        assert!(report.dead_letters.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recover_unfinished_items() {
+        let queue = Cache::builder().memory().build().queue("webhooks");
+
+        assert!(queue.push(&1).await);
+        assert!(queue.push(&2).await);
+        let _: Job<u32> = queue.pop(Duration::from_secs(1)).await.unwrap();
+
+        assert_eq!(queue.recover().await, 1);
+        assert_eq!(queue.report().await.unwrap().pending, 2);
+    }
+}
@@ -0,0 +1,186 @@
+//! The hits and misses of a cache
+//!
+//! Without the `stats` feature nothing is counted, and the reports stay empty.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub(crate) struct Stats {
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    /// objects that couldn't be serialized, or cached objects that couldn't be deserialized
+    serialization_errors: AtomicUsize,
+    /// the amount of objects stored in the cache
+    stored: AtomicUsize,
+    /// the total size in bytes of the objects stored in the cache
+    stored_bytes: AtomicUsize,
+    /// the hits and misses per key prefix, which is the type of the cached object
+    prefixes: Mutex<HashMap<&'static str, PrefixStats>>,
+}
+
+/// The lookups of the objects of one type
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrefixStats {
+    hits: usize,
+    misses: usize,
+    hit_ratio: f64,
+    /// cached objects that no longer match the type, these count as misses
+    deserialization_errors: usize,
+}
+
+/// The outcome of a cache lookup
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Lookup {
+    Hit,
+    Miss,
+    /// the cached object couldn't be deserialized
+    Broken,
+}
+
+/// The counters of a cache, with the hit ratio per key prefix
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DetailedStats {
+    hits: usize,
+    misses: usize,
+    serialization_errors: usize,
+    /// the average size in bytes of the stored objects, empty when nothing has been stored yet
+    average_size: Option<usize>,
+    prefixes: BTreeMap<&'static str, PrefixStats>,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        Stats {
+            hits: AtomicUsize::default(),
+            misses: AtomicUsize::default(),
+            serialization_errors: AtomicUsize::default(),
+            stored: AtomicUsize::default(),
+            stored_bytes: AtomicUsize::default(),
+            prefixes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn lookup(&self, prefix: &'static str, lookup: Lookup) {
+        if !cfg!(feature = "stats") {
+            return;
+        }
+
+        match lookup {
+            Lookup::Hit => self.hits.fetch_add(1, Ordering::Relaxed),
+            Lookup::Miss => self.misses.fetch_add(1, Ordering::Relaxed),
+            Lookup::Broken => {
+                self.serialization_error();
+                self.misses.fetch_add(1, Ordering::Relaxed)
+            }
+        };
+        self.record_prefix(prefix, lookup);
+    }
+
+    pub(crate) fn serialization_error(&self) {
+        if cfg!(feature = "stats") {
+            self.serialization_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn stored(&self, size: usize) {
+        if cfg!(feature = "stats") {
+            self.stored.fetch_add(1, Ordering::Relaxed);
+            self.stored_bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    fn record_prefix(&self, prefix: &'static str, lookup: Lookup) {
+        let mut prefixes = match self.prefixes.lock() {
+            Ok(prefixes) => prefixes,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let stats = prefixes.entry(prefix).or_default();
+        match lookup {
+            Lookup::Hit => stats.hits += 1,
+            Lookup::Miss => stats.misses += 1,
+            Lookup::Broken => {
+                stats.misses += 1;
+                stats.deserialization_errors += 1;
+            }
+        }
+    }
+
+    pub(crate) fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self) -> DetailedStats {
+        let stored = self.stored.load(Ordering::Relaxed);
+        let average_size = match stored {
+            0 => None,
+            _ => Some(self.stored_bytes.load(Ordering::Relaxed) / stored),
+        };
+
+        let prefixes = match self.prefixes.lock() {
+            Ok(prefixes) => prefixes,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        DetailedStats {
+            hits: self.hits(),
+            misses: self.misses(),
+            serialization_errors: self.serialization_errors.load(Ordering::Relaxed),
+            average_size,
+            prefixes: prefixes
+                .iter()
+                .map(|(prefix, stats)| {
+                    let total = stats.hits + stats.misses;
+                    let hit_ratio = match total {
+                        0 => 0.0,
+                        _ => stats.hits as f64 / total as f64,
+                    };
+
+                    (
+                        *prefix,
+                        PrefixStats {
+                            hit_ratio,
+                            ..*stats
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detailed_stats() {
+        let stats = Stats::new();
+        assert_eq!(stats.snapshot().average_size, None);
+
+        stats.record_prefix("rustfuif::ddg::Token", Lookup::Hit);
+        stats.record_prefix("rustfuif::ddg::Token", Lookup::Hit);
+        stats.record_prefix("rustfuif::ddg::Token", Lookup::Miss);
+        stats.record_prefix("rustfuif::ddg::Images", Lookup::Broken);
+        stats.stored.fetch_add(2, Ordering::Relaxed);
+        stats.stored_bytes.fetch_add(300, Ordering::Relaxed);
+
+        let detailed = stats.snapshot();
+        assert_eq!(detailed.average_size, Some(150));
+
+        let token = detailed.prefixes["rustfuif::ddg::Token"];
+        assert_eq!(token.hits, 2);
+        assert_eq!(token.misses, 1);
+        assert!((token.hit_ratio - 2.0 / 3.0).abs() < f64::EPSILON);
+        let images = detailed.prefixes["rustfuif::ddg::Images"];
+        assert_eq!(images.hit_ratio, 0.0);
+        assert_eq!(images.deserialization_errors, 1);
+    }
+}
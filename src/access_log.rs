@@ -0,0 +1,167 @@
+//! Structured access logs
+//!
+//! Every request is logged through tracing, so the logs are written as JSON when `LOG_FORMAT=json`.
+//! The request id is taken from the `X-Request-Id` header when the client sent one.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{ok, Ready};
+use futures::Future;
+use rand::Rng;
+
+use crate::users::User;
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The paths that aren't logged, like health checks
+const EXCLUDED_PATHS: [&str; 3] = ["/api/health", "/api/health/ready", "/stats"];
+
+/// How the logs are written to stdout
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Text
+    }
+}
+
+pub struct Middleware;
+
+impl Middleware {
+    pub fn default() -> Middleware {
+        Middleware
+    }
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AccessLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogMiddleware { service })
+    }
+}
+
+pub struct AccessLogMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for AccessLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let excluded = EXCLUDED_PATHS
+            .iter()
+            .any(|path| request.path().starts_with(path));
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|header| header.to_str().ok())
+            .map(String::from)
+            .unwrap_or_else(generate_request_id);
+
+        let fut = self.service.call(request);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            if let Ok(header) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), header);
+            }
+
+            if excluded {
+                return Ok(res);
+            }
+
+            let request = res.request();
+            // only the id of the user the session middleware loaded is logged
+            let user_id = request.extensions().get::<User>().map(|user| user.id);
+
+            tracing::info!(
+                target: "access_log",
+                method = %request.method(),
+                // the route template keeps the amount of distinct paths low, like `/api/games/{id}`
+                path = request.match_pattern().as_deref().unwrap_or_else(|| request.path()),
+                status = res.status().as_u16(),
+                latency_ms = started.elapsed().as_millis() as u64,
+                // user ids start at 1, anonymous requests are logged with user id 0
+                user_id = user_id.unwrap_or_default(),
+                request_id = request_id.as_str(),
+                "request handled"
+            );
+
+            Ok(res)
+        })
+    }
+}
+
+fn generate_request_id() -> String {
+    format!("{:032x}", rand::thread_rng().gen::<u128>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn request_ids() {
+        let mut srv = test::init_service(
+            App::new()
+                .wrap(Middleware::default())
+                .service(web::resource("/games/{id}").to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res =
+            test::call_service(&mut srv, TestRequest::with_uri("/games/1").to_request()).await;
+        let request_id = res.headers().get(REQUEST_ID_HEADER).unwrap();
+        assert_eq!(request_id.len(), 32);
+
+        let res = test::call_service(
+            &mut srv,
+            TestRequest::with_uri("/games/1")
+                .header(REQUEST_ID_HEADER, "some-request")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(
+            res.headers().get(REQUEST_ID_HEADER).unwrap(),
+            "some-request"
+        );
+    }
+}
@@ -0,0 +1,58 @@
+//! A trail of the sensitive actions of the administrators
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+/// the amount of entries returned by the audit log endpoint
+const MAX_ENTRIES: i64 = 100;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLog {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub action: String,
+    pub details: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditLog {
+    #[tracing::instrument(name = "AuditLog::record", skip(db))]
+    pub async fn record(
+        user_id: i64,
+        action: &str,
+        details: Option<&str>,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "INSERT INTO audit_logs (user_id, action, details) VALUES ($1, $2, $3)",
+            user_id,
+            action,
+            details
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// the most recent entries first
+    #[tracing::instrument(name = "AuditLog::recent", skip(db))]
+    pub async fn recent(db: &Pool<Postgres>) -> Result<Vec<AuditLog>, sqlx::Error> {
+        sqlx::query_as!(
+            AuditLog,
+            r#"
+            SELECT audit_logs.id, audit_logs.user_id, users.username, audit_logs.action,
+                audit_logs.details, audit_logs.created_at
+            FROM audit_logs
+            INNER JOIN users ON users.id = audit_logs.user_id
+            ORDER BY audit_logs.created_at DESC, audit_logs.id DESC
+            LIMIT $1
+            "#,
+            MAX_ENTRIES
+        )
+        .fetch_all(db)
+        .await
+    }
+}
@@ -1 +1,3 @@
+pub mod audit;
+pub mod backup;
 pub mod routes;
@@ -1,15 +1,26 @@
-use actix_identity::Identity;
-use actix_web::web::{Data, Json};
-use actix_web::{get, post, web};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse};
 
+use crate::admin::audit::AuditLog;
+use crate::admin::backup;
 use crate::auth;
+use crate::auth::Identity;
+use crate::cache::{CacheHandle, Queue};
 use crate::config::Config;
+use crate::errors::ServiceError;
 use crate::games::Game;
 use crate::market::MarketAgent;
+use crate::pool::PoolStatus;
+use crate::retention::RetentionReport;
 use crate::server::{Response, State};
-use crate::users::User;
+use crate::transactions::crashes::{CrashFilter, GameCrashes};
+use crate::users::{export, provisioning, User};
+use crate::webhooks;
 use crate::websocket::queries::{ActiveGames, ConnectedUsers};
 
+/// a day, a longer interval doesn't make sense for a party
+const MAX_UPDATE_INTERVAL: u64 = 24 * 60 * 60;
+
 #[get("/admin/games/count")]
 async fn game_count(state: Data<State>, id: Identity) -> Response {
     auth::verify_admin(&id)?;
@@ -28,6 +39,42 @@ async fn user_count(state: Data<State>, id: Identity) -> Response {
     http_ok_json!(count);
 }
 
+/// Create the users of a member list, as CSV or as JSON, and report the outcome of every row
+#[post("/admin/users/import")]
+async fn import_users(
+    req: HttpRequest,
+    body: String,
+    id: Identity,
+    state: Data<State>,
+) -> Response {
+    auth::verify_admin(&id)?;
+    let admin = auth::get_user(&id)?;
+
+    if !state.auth.manages_passwords() {
+        forbidden!("the accounts are managed by the identity provider");
+    }
+
+    let members = match req.content_type() {
+        "text/csv" => provisioning::parse_csv(&body)?,
+        _ => provisioning::parse_json(&body)?,
+    };
+    let results = provisioning::create(members, &state.db).await?;
+
+    let created = results
+        .iter()
+        .filter(|result| result.status == provisioning::RowStatus::Created)
+        .count();
+    AuditLog::record(
+        admin.id,
+        "users.import",
+        Some(&format!("{} of {} users created", created, results.len())),
+        &state.db,
+    )
+    .await?;
+
+    http_ok_json!(results);
+}
+
 #[get("/admin/websockets/connected-users")]
 async fn connected_users(id: Identity, state: Data<State>) -> Response {
     auth::verify_admin(&id)?;
@@ -59,32 +106,85 @@ async fn active_games(id: Identity, state: Data<State>) -> Response {
 }
 
 #[get("/admin/server/cache")]
-async fn cache_status(id: Identity) -> Response {
+async fn cache_status(id: Identity, state: Data<State>) -> Response {
     auth::verify_admin(&id)?;
 
-    http_ok_json!(crate::cache::Cache::status().await);
+    #[derive(Serialize)]
+    struct CacheReport {
+        #[serde(flatten)]
+        status: crate::cache::CacheStatus,
+        stats: crate::cache::DetailedStats,
+    }
+
+    http_ok_json!(CacheReport {
+        status: state.cache.status().await,
+        stats: state.cache.detailed_stats(),
+    });
 }
 
 #[post("/admin/server/cache/disable")]
-async fn disable_cache(id: Identity) -> Response {
+async fn disable_cache(id: Identity, state: Data<State>) -> Response {
     auth::verify_admin(&id)?;
 
-    crate::cache::Cache::disable_cache().await;
+    state.cache.disable().await;
 
-    http_ok_json!(crate::cache::Cache::status().await);
+    http_ok_json!(state.cache.status().await);
 }
 
 #[post("/admin/server/cache/enable")]
-async fn enable_cache(id: Identity) -> Response {
+async fn enable_cache(id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+
+    state.cache.enable().await;
+
+    http_ok_json!(state.cache.status().await);
+}
+
+/// the queues of the background deliveries
+fn queue(name: &str, cache: &CacheHandle) -> Result<Queue, ServiceError> {
+    match name {
+        webhooks::QUEUE | export::QUEUE => Ok(cache.queue(name)),
+        _ => Err(ServiceError::NotFound),
+    }
+}
+
+fn unreachable_queue() -> ServiceError {
+    ServiceError::BadGateway("the queues can't be reached".to_string())
+}
+
+/// The pending items of a queue and the items that failed too often
+#[get("/admin/queues/{name}")]
+async fn queue_report(name: Path<String>, id: Identity, state: Data<State>) -> Response {
     auth::verify_admin(&id)?;
 
-    crate::cache::Cache::enable_cache().await;
+    let report = queue(&name, &state.cache)?
+        .report()
+        .await
+        .ok_or_else(unreachable_queue)?;
+
+    http_ok_json!(report);
+}
+
+/// Queue a dead letter again
+#[post("/admin/queues/{name}/dead-letters/{id}/retry")]
+async fn retry_dead_letter(
+    path: Path<(String, String)>,
+    id: Identity,
+    state: Data<State>,
+) -> Response {
+    auth::verify_admin(&id)?;
+    let (name, item) = path.into_inner();
+
+    let queue = queue(&name, &state.cache)?;
+    if !queue.retry(&item).await.ok_or_else(unreachable_queue)? {
+        return Err(ServiceError::NotFound);
+    }
 
-    http_ok_json!(crate::cache::Cache::status().await);
+    http_ok_json!(queue.report().await);
 }
 
 #[get("/admin/server/stats")]
-async fn server_stats(id: Identity) -> Response {
+async fn server_stats(id: Identity, state: Data<State>) -> Response {
     auth::verify_admin(&id)?;
 
     #[derive(Serialize)]
@@ -93,30 +193,45 @@ async fn server_stats(id: Identity) -> Response {
         errors: usize,
         cache_hits: usize,
         cache_misses: usize,
+        login_failures: usize,
+        throttled_logins: usize,
     }
 
     http_ok_json!(Stats {
         requests: crate::stats::Stats::load_requests(),
         errors: crate::stats::Stats::load_errors(),
-        cache_hits: crate::cache::Stats::load_hits(),
-        cache_misses: crate::cache::Stats::load_misses(),
+        cache_hits: state.cache.hits(),
+        cache_misses: state.cache.misses(),
+        login_failures: crate::stats::Stats::load_login_failures(),
+        throttled_logins: crate::stats::Stats::load_throttled_logins(),
     });
 }
 
-#[get("/admin/server/database")]
-async fn database_stats(id: Identity, state: Data<State>) -> Response {
+/// The amount of server errors in the last minute
+#[get("/admin/server/error-rate")]
+async fn error_rate(id: Identity) -> Response {
     auth::verify_admin(&id)?;
 
     #[derive(Serialize)]
-    struct Stats {
-        active_db_connections: usize,
-        idle_db_connections: usize,
+    #[serde(rename_all = "camelCase")]
+    struct ErrorRate {
+        errors_per_minute: usize,
+        threshold: Option<usize>,
+        alerting: bool,
     }
 
-    http_ok_json!(Stats {
-        active_db_connections: state.db.size() as usize,
-        idle_db_connections: state.db.num_idle(),
-    })
+    http_ok_json!(ErrorRate {
+        errors_per_minute: crate::stats::Stats::load_error_rate(),
+        threshold: Config::error_budget().map(|budget| budget.threshold),
+        alerting: crate::stats::Stats::load_alerting(),
+    });
+}
+
+#[get("/admin/server/database")]
+async fn database_stats(id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+
+    http_ok_json!(PoolStatus::load(&state.db))
 }
 
 #[post("/admin/market/update-prices")]
@@ -147,17 +262,111 @@ async fn set_price_update_interval(id: Identity, seconds: Json<u64>) -> Response
     http_ok_json!(MarketAgent::interval().as_secs());
 }
 
+/// The shortest and longest price update interval a game owner can choose
+#[derive(Deserialize, Serialize)]
+struct IntervalBounds {
+    minimum: u64,
+    maximum: u64,
+}
+
+#[get("/admin/market/update-interval/bounds")]
+async fn get_update_interval_bounds(id: Identity) -> Response {
+    auth::verify_admin(&id)?;
+
+    let (minimum, maximum) = Config::update_interval_bounds();
+
+    http_ok_json!(IntervalBounds { minimum, maximum });
+}
+
+/// Change the bounds, the games with an interval outside the new bounds are kept within them
+#[post("/admin/market/update-interval/bounds")]
+async fn set_update_interval_bounds(id: Identity, bounds: Json<IntervalBounds>) -> Response {
+    auth::verify_admin(&id)?;
+
+    if bounds.minimum == 0 || bounds.minimum > bounds.maximum {
+        bad_request!("the minimum should be at least 1 second and at most the maximum");
+    }
+    if bounds.maximum > MAX_UPDATE_INTERVAL {
+        bad_request!(format!(
+            "the maximum can be at most {} seconds",
+            MAX_UPDATE_INTERVAL
+        ));
+    }
+
+    Config::set_update_interval_bounds(bounds.minimum, bounds.maximum);
+
+    http_ok_json!(bounds.into_inner());
+}
+
+/// Whether the stock market crashes of the recent games drove purchases
+#[get("/admin/market/crashes")]
+async fn crash_effects(filter: Query<CrashFilter>, id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+
+    let games = GameCrashes::recent(&filter, &state.db).await?;
+
+    http_ok_json!(games);
+}
+
+/// A dry run of the retention job, reports what would be deleted without deleting anything
+#[get("/admin/server/retention")]
+async fn retention_report(id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+
+    let report = RetentionReport::load(&state.db).await?;
+
+    http_ok_json!(report);
+}
+
+/// Stream a compressed export of the database, restore it with `pg_restore`
+#[post("/admin/server/backup")]
+async fn create_backup(id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+    let admin = auth::get_user(&id)?;
+
+    let file_name = backup::file_name();
+    let archive = backup::start()?;
+    AuditLog::record(admin.id, "server.backup", Some(&file_name), &state.db).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", file_name),
+        )
+        .streaming(archive))
+}
+
+#[get("/admin/server/audit-log")]
+async fn audit_log(id: Identity, state: Data<State>) -> Response {
+    auth::verify_admin(&id)?;
+
+    let entries = AuditLog::recent(&state.db).await?;
+
+    http_ok_json!(entries);
+}
+
 pub fn register(cfg: &mut web::ServiceConfig) {
     cfg.service(game_count);
     cfg.service(user_count);
+    cfg.service(import_users);
     cfg.service(connected_users);
     cfg.service(active_games);
     cfg.service(cache_status);
     cfg.service(disable_cache);
     cfg.service(enable_cache);
+    cfg.service(queue_report);
+    cfg.service(retry_dead_letter);
     cfg.service(server_stats);
+    cfg.service(error_rate);
     cfg.service(database_stats);
+    cfg.service(retention_report);
+    cfg.service(create_backup);
+    cfg.service(audit_log);
     cfg.service(update_prices);
     cfg.service(get_price_update_interval);
     cfg.service(set_price_update_interval);
+    cfg.service(get_update_interval_bounds);
+    cfg.service(set_update_interval_bounds);
+    cfg.service(crash_effects);
 }
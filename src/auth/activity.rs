@@ -0,0 +1,264 @@
+//! Unusual login activity
+//!
+//! Every login is stored with the device and the address it came from, a device is the user agent of the browser.
+//! A login from a new device or address of a user who logged in before is suspicious, and so are repeated
+//! failed logins. The user and the connected administrators are notified, so an account takeover during
+//! a game gets noticed. After too many failed logins the password logins of the account are throttled for the
+//! address they came from, so someone else can't lock the user out.
+
+use actix_web::http::header::USER_AGENT;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use ring::digest::{digest, SHA256};
+use sqlx::{Pool, Postgres};
+
+use crate::rate_limit;
+use crate::users::User;
+
+/// the window in seconds the failed logins are counted over
+const FAILURE_WINDOW: i32 = 15 * 60;
+/// the users are notified when this many logins failed in the window
+const FAILURE_THRESHOLD: i64 = 5;
+/// the password logins from an address are rejected when this many logins from it failed in the window
+const THROTTLE_THRESHOLD: i64 = 10;
+
+const MAX_USER_AGENT: usize = 255;
+
+/// Where a login came from
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub user_agent: String,
+    pub ip_address: String,
+}
+
+impl Client {
+    pub fn from_request(req: &HttpRequest) -> Client {
+        let user_agent = req
+            .headers()
+            .get(USER_AGENT)
+            .and_then(|agent| agent.to_str().ok())
+            .unwrap_or("unknown");
+
+        Client {
+            user_agent: user_agent.chars().take(MAX_USER_AGENT).collect(),
+            ip_address: rate_limit::client_address(req)
+                .map(|address| address.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+        }
+    }
+
+    fn fingerprint(&self) -> String {
+        digest(&SHA256, self.user_agent.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum LoginAnomaly {
+    NewDevice,
+    NewIpAddress,
+    RepeatedFailures,
+}
+
+/// A login that the user should know about
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspiciousLogin {
+    pub user_id: i64,
+    pub username: String,
+    pub anomaly: LoginAnomaly,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A device and address a user logged in from
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginDevice {
+    pub user_agent: String,
+    pub ip_address: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl LoginDevice {
+    #[tracing::instrument(name = "LoginDevice::find_by_user", skip(db))]
+    pub async fn find_by_user(
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<LoginDevice>, sqlx::Error> {
+        sqlx::query_as!(
+            LoginDevice,
+            r#"
+            SELECT user_agent, ip_address, first_seen_at, last_seen_at
+            FROM login_devices
+            WHERE user_id = $1
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+}
+
+/// Returns the seconds until the client can retry when the password logins of the user from its address are throttled
+///
+/// The logins are allowed again once the oldest of the last `THROTTLE_THRESHOLD` failures leaves the window.
+#[tracing::instrument(name = "activity::throttled", skip(db))]
+pub async fn throttled(
+    username: &str,
+    client: &Client,
+    db: &Pool<Postgres>,
+) -> Result<Option<u64>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT CEIL(EXTRACT(EPOCH FROM login_failures.created_at + make_interval(secs => $3) - NOW()))::BIGINT AS "retry_after!"
+        FROM login_failures
+        INNER JOIN users ON users.id = login_failures.user_id
+        WHERE users.username = $1 AND login_failures.ip_address = $2
+            AND login_failures.created_at > NOW() - make_interval(secs => $3)
+        ORDER BY login_failures.created_at DESC
+        OFFSET $4 LIMIT 1
+        "#,
+        username,
+        client.ip_address,
+        FAILURE_WINDOW as f64,
+        THROTTLE_THRESHOLD - 1
+    )
+    .fetch_optional(db)
+    .await?;
+
+    Ok(row.map(|row| row.retry_after.max(1) as u64))
+}
+
+/// Store a failed login, the users are notified once when the failures pass the threshold
+#[tracing::instrument(name = "activity::record_failure", skip(db))]
+pub async fn record_failure(
+    username: &str,
+    client: &Client,
+    db: &Pool<Postgres>,
+) -> Result<Option<SuspiciousLogin>, sqlx::Error> {
+    let user = match User::find_by_name(username, db).await {
+        Ok(user) => user,
+        Err(sqlx::Error::RowNotFound) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    sqlx::query!(
+        "DELETE FROM login_failures WHERE user_id = $1 AND created_at < NOW() - INTERVAL '1 day'",
+        user.id
+    )
+    .execute(db)
+    .await?;
+
+    let row = sqlx::query!(
+        r#"
+        WITH failure AS (
+            INSERT INTO login_failures (user_id, ip_address) VALUES ($1, $2)
+        )
+        SELECT COUNT(*) + 1 AS "failures!"
+        FROM login_failures
+        WHERE user_id = $1 AND created_at > NOW() - make_interval(secs => $3)
+        "#,
+        user.id,
+        client.ip_address,
+        FAILURE_WINDOW as f64
+    )
+    .fetch_one(db)
+    .await?;
+
+    if row.failures != FAILURE_THRESHOLD {
+        return Ok(None);
+    }
+
+    Ok(Some(SuspiciousLogin {
+        user_id: user.id,
+        username: user.username,
+        anomaly: LoginAnomaly::RepeatedFailures,
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+        created_at: Utc::now(),
+    }))
+}
+
+/// Store a successful login, and return it when it came from a new device or address
+#[tracing::instrument(name = "activity::record_success", skip(user, db))]
+pub async fn record_success(
+    user: &User,
+    client: &Client,
+    db: &Pool<Postgres>,
+) -> Result<Option<SuspiciousLogin>, sqlx::Error> {
+    let fingerprint = client.fingerprint();
+
+    let known = sqlx::query!(
+        r#"
+        SELECT
+            EXISTS (SELECT 1 FROM login_devices WHERE user_id = $1) AS "user!",
+            EXISTS (SELECT 1 FROM login_devices WHERE user_id = $1 AND fingerprint = $2) AS "device!",
+            EXISTS (SELECT 1 FROM login_devices WHERE user_id = $1 AND ip_address = $3) AS "ip_address!"
+        "#,
+        user.id,
+        fingerprint,
+        client.ip_address
+    )
+    .fetch_one(db)
+    .await?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO login_devices (user_id, fingerprint, user_agent, ip_address)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (user_id, fingerprint, ip_address) DO UPDATE SET last_seen_at = NOW()
+        "#,
+        user.id,
+        fingerprint,
+        client.user_agent,
+        client.ip_address
+    )
+    .execute(db)
+    .await?;
+
+    let anomaly = match (known.user, known.device, known.ip_address) {
+        // the first login of a user isn't unusual
+        (false, _, _) => return Ok(None),
+        (true, false, _) => LoginAnomaly::NewDevice,
+        (true, true, false) => LoginAnomaly::NewIpAddress,
+        (true, true, true) => return Ok(None),
+    };
+
+    Ok(Some(SuspiciousLogin {
+        user_id: user.id,
+        username: user.username.clone(),
+        anomaly,
+        ip_address: client.ip_address.clone(),
+        user_agent: client.user_agent.clone(),
+        created_at: Utc::now(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn client_of_request() {
+        let req = TestRequest::default()
+            .header("User-Agent", "Mozilla/5.0")
+            .header("X-Forwarded-For", "203.0.113.7")
+            .peer_addr("192.0.2.9:51234".parse().unwrap())
+            .to_http_request();
+        let client = Client::from_request(&req);
+
+        assert_eq!(client.user_agent, "Mozilla/5.0");
+        // without trusted proxies the forwarded address is ignored
+        assert_eq!(client.ip_address, "192.0.2.9");
+        assert_eq!(client.fingerprint().len(), 64);
+    }
+}
@@ -0,0 +1,220 @@
+//! Where the users log in
+//!
+//! The password backend checks the credentials against the `users` table.
+//! The OIDC backend asks an identity provider like Keycloak whether an access token is active,
+//! through token introspection, and maps the external identity to a user, creating it on the first login.
+//! LDAP directories can be used through the user federation of the identity provider.
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::auth::passkeys::Assertion;
+use crate::config::Config;
+use crate::errors::ServiceError;
+use crate::http::HttpClient;
+use crate::users::{Credentials, User};
+
+/// the provider name of the users created by the OIDC backend
+const OIDC_PROVIDER: &str = "oidc";
+
+/// The backend selected with `AUTH_BACKEND`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    Password,
+    Oidc,
+}
+
+impl Default for BackendKind {
+    fn default() -> Self {
+        BackendKind::Password
+    }
+}
+
+/// A login attempt, with a username and password or with an access token of the identity provider
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum Login {
+    Password {
+        #[serde(flatten)]
+        credentials: Credentials,
+        /// the users who turned on the second factor also sign in with a passkey
+        passkey: Option<Assertion>,
+    },
+    Token {
+        token: String,
+    },
+}
+
+impl std::fmt::Debug for Login {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Login::Password { credentials, .. } => credentials.fmt(f),
+            Login::Token { .. } => f.debug_struct("Token").field("token", &"redacted").finish(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+pub trait AuthBackend {
+    /// find the user who's logging in
+    async fn authenticate(&self, login: &Login, db: &Pool<Postgres>) -> Result<User, ServiceError>;
+
+    /// returns true when the users register and change their passwords in this application
+    fn manages_passwords(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordBackend;
+
+#[async_trait(?Send)]
+impl AuthBackend for PasswordBackend {
+    async fn authenticate(&self, login: &Login, db: &Pool<Postgres>) -> Result<User, ServiceError> {
+        let credentials = match login {
+            Login::Password { credentials, .. } => credentials,
+            Login::Token { .. } => bad_request!("log in with a username and password"),
+        };
+
+        let user = User::find_by_name(&credentials.username, db)
+            .await
+            .map_err(|error| match error {
+                sqlx::Error::RowNotFound => ServiceError::Unauthorized,
+                _ => error.into(),
+            })?;
+
+        user.verify_password(&credentials.password).await?;
+
+        Ok(user)
+    }
+
+    fn manages_passwords(&self) -> bool {
+        true
+    }
+}
+
+/// How the application authenticates with the identity provider
+#[derive(Debug, Clone)]
+pub struct OidcSettings {
+    pub introspection_url: String,
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+/// OAuth 2.0 token introspection, RFC 7662
+#[derive(Debug)]
+pub struct OidcBackend {
+    http: HttpClient,
+    settings: OidcSettings,
+}
+
+#[derive(Debug, Deserialize)]
+struct Introspection {
+    active: bool,
+    sub: Option<String>,
+    preferred_username: Option<String>,
+    username: Option<String>,
+}
+
+impl Introspection {
+    /// the username of a new user, the subject when the provider doesn't share a username
+    fn username(&self) -> Option<&str> {
+        self.preferred_username
+            .as_deref()
+            .or(self.username.as_deref())
+            .or(self.sub.as_deref())
+    }
+}
+
+#[async_trait(?Send)]
+impl AuthBackend for OidcBackend {
+    async fn authenticate(&self, login: &Login, db: &Pool<Postgres>) -> Result<User, ServiceError> {
+        let token = match login {
+            Login::Token { token } => token,
+            Login::Password { .. } => {
+                bad_request!("log in with an access token of the identity provider")
+            }
+        };
+
+        let response = self
+            .http
+            .send(
+                self.http
+                    .post(&self.settings.introspection_url)
+                    .basic_auth(&self.settings.client_id, Some(&self.settings.client_secret))
+                    .form(&[("token", token)]),
+            )
+            .await?;
+
+        if !response.status().is_success() {
+            error!(
+                "the token introspection responded with {}",
+                response.status()
+            );
+            return Err(ServiceError::BadGateway(
+                "unable to verify the access token".to_string(),
+            ));
+        }
+
+        let introspection: Introspection = response.json().await.map_err(|e| {
+            error!("invalid token introspection response: {}", e);
+            ServiceError::BadGateway("unable to verify the access token".to_string())
+        })?;
+
+        let (subject, username) = match (&introspection.sub, introspection.username()) {
+            (Some(subject), Some(username)) if introspection.active => (subject, username),
+            _ => return Err(ServiceError::Unauthorized),
+        };
+
+        match User::find_external(OIDC_PROVIDER, subject, db).await? {
+            Some(user) => Ok(user),
+            None => User::create_external(username, OIDC_PROVIDER, subject, db).await,
+        }
+    }
+}
+
+/// The backend configured with `AUTH_BACKEND`
+pub fn from_config(http: HttpClient) -> Arc<dyn AuthBackend + Send + Sync> {
+    match Config::auth_backend() {
+        BackendKind::Password => Arc::new(PasswordBackend),
+        BackendKind::Oidc => {
+            let settings = Config::oidc().expect(
+                "the oidc backend needs OIDC_INTROSPECTION_URL, OIDC_CLIENT_ID and OIDC_CLIENT_SECRET",
+            );
+
+            Arc::new(OidcBackend { http, settings })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_login() {
+        let login: Login =
+            serde_json::from_str(r#"{"username": "bart", "password": "secret"}"#).unwrap();
+        assert!(matches!(login, Login::Password { passkey: None, .. }));
+
+        let login: Login = serde_json::from_str(r#"{"token": "abc"}"#).unwrap();
+        assert!(matches!(login, Login::Token { .. }));
+    }
+
+    #[test]
+    fn introspection_username() {
+        let introspection: Introspection =
+            serde_json::from_str(r#"{"active": true, "sub": "f81d4fae", "username": "bart"}"#)
+                .unwrap();
+        assert_eq!(introspection.username(), Some("bart"));
+
+        let introspection: Introspection =
+            serde_json::from_str(r#"{"active": true, "sub": "f81d4fae"}"#).unwrap();
+        assert_eq!(introspection.username(), Some("f81d4fae"));
+
+        let introspection: Introspection = serde_json::from_str(r#"{"active": false}"#).unwrap();
+        assert!(!introspection.active);
+    }
+}
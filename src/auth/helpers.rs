@@ -1,17 +1,138 @@
-use actix_identity::Identity;
+use std::ops::Deref;
 
+use actix_web::dev::Payload;
+use actix_web::{Error, FromRequest, HttpRequest};
+use futures::future::{err, ok, Ready};
+use sqlx::{Pool, Postgres};
+
+use crate::auth::activity::Client;
+use crate::auth::sessions::Session;
 use crate::errors::ServiceError;
 use crate::users::User;
 
+/// The identity of a request, with the user of its session
+///
+/// The session cookie only refers to a session, `sessions::Middleware` loads the user of the session
+/// on every request. A revoked session or a demoted user takes effect on the next request.
+pub struct Identity {
+    identity: actix_identity::Identity,
+    request: HttpRequest,
+}
+
+impl Identity {
+    fn user(&self) -> Option<User> {
+        self.request.extensions().get::<User>().cloned()
+    }
+}
+
+impl Deref for Identity {
+    type Target = actix_identity::Identity;
+
+    fn deref(&self) -> &Self::Target {
+        &self.identity
+    }
+}
+
+impl FromRequest for Identity {
+    type Config = ();
+    type Error = Error;
+    type Future = Ready<Result<Identity, Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        match actix_identity::Identity::from_request(req, payload).into_inner() {
+            Ok(identity) => ok(Identity {
+                identity,
+                request: req.clone(),
+            }),
+            Err(e) => err(e),
+        }
+    }
+}
+
 pub fn get_user(id: &Identity) -> Result<User, ServiceError> {
-    let user_str = id.identity().ok_or(ServiceError::Unauthorized)?;
+    id.user().ok_or(ServiceError::Unauthorized)
+}
+
+/// The session cookie, the session and the game a kiosk client operates on
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionCookie<'a> {
+    session_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    active_game: Option<i64>,
+}
 
-    serde_json::from_str(&user_str).map_err(|e| {
-        error!("unable to deserialize user: {}", e);
+/// The session cookie as it's stored
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredSession {
+    session_id: Option<String>,
+    active_game: Option<i64>,
+}
+
+fn stored_session(id: &Identity) -> Result<StoredSession, ServiceError> {
+    let session_str = id.identity().ok_or(ServiceError::Unauthorized)?;
+
+    serde_json::from_str(&session_str).map_err(|e| {
+        error!("unable to deserialize the session: {}", e);
         ServiceError::Unauthorized
     })
 }
 
+/// Start a session for the user and store it in the session cookie, this resets the active game
+pub async fn remember(
+    id: &Identity,
+    user: &User,
+    req: &HttpRequest,
+    db: &Pool<Postgres>,
+) -> Result<(), ServiceError> {
+    let session = Session::create(user.id, &Client::from_request(req), db).await?;
+
+    store(
+        id,
+        SessionCookie {
+            session_id: &session.id,
+            active_game: None,
+        },
+    )
+}
+
+fn store(id: &Identity, session: SessionCookie<'_>) -> Result<(), ServiceError> {
+    let session_string = serde_json::to_string(&session).map_err(|e| {
+        error!("unable to serialize the session: {}", e);
+        ServiceError::InternalServerError
+    })?;
+
+    id.remember(session_string);
+
+    Ok(())
+}
+
+/// The game stored in the session with `set_active_game`
+pub fn active_game(id: &Identity) -> Result<Option<i64>, ServiceError> {
+    Ok(stored_session(id)?.active_game)
+}
+
+/// The id of the session in the database, see `auth::sessions`
+pub fn session_id(id: &Identity) -> Result<String, ServiceError> {
+    stored_session(id)?
+        .session_id
+        .ok_or(ServiceError::Unauthorized)
+}
+
+/// Store the game the user operates on in the session, `None` clears it
+pub fn set_active_game(id: &Identity, active_game: Option<i64>) -> Result<(), ServiceError> {
+    let session_id = session_id(id)?;
+
+    store(
+        id,
+        SessionCookie {
+            session_id: &session_id,
+            active_game,
+        },
+    )
+}
+
 pub fn verify_admin(id: &Identity) -> Result<(), ServiceError> {
     let user = get_user(id)?;
 
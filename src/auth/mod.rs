@@ -1,5 +1,10 @@
+pub mod activity;
+pub mod backends;
 mod helpers;
 mod models;
+pub mod passkeys;
+pub mod sessions;
+pub mod webauthn;
 
 pub mod routes;
 pub use helpers::*;
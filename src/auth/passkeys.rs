@@ -0,0 +1,470 @@
+//! Passkeys, WebAuthn credentials of the users
+//!
+//! A user registers passkeys after logging in. A passkey logs the user in without a password,
+//! or is required next to the password when the user turns on the second factor.
+//! Every registration and login starts with a challenge, which can be used once within 5 minutes.
+
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::{Done, Pool, Postgres};
+
+use crate::auth::webauthn::{self, Ceremony, RelyingParty};
+use crate::config::Config;
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::users::User;
+
+/// how long a challenge can be answered, in seconds
+const CHALLENGE_TIMEOUT: i64 = 5 * 60;
+
+const MAX_PASSKEY_NAME: usize = 50;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Passkey {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub credential_id: Vec<u8>,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    #[serde(skip)]
+    pub sign_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// The answer of the authenticator to a registration challenge, base64url encoded
+#[derive(Deserialize)]
+pub struct NewPasskey {
+    pub name: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// The answer of the authenticator to a login challenge, base64url encoded
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Assertion {
+    pub credential_id: String,
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+}
+
+impl std::fmt::Debug for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Assertion")
+            .field("credential_id", &self.credential_id)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+impl From<&Passkey> for CredentialDescriptor {
+    fn from(passkey: &Passkey) -> Self {
+        CredentialDescriptor {
+            kind: "public-key",
+            id: webauthn::encode(&passkey.credential_id),
+        }
+    }
+}
+
+/// The options of `navigator.credentials.create()`, the binary values are base64url encoded
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationOptions {
+    challenge: String,
+    rp: serde_json::Value,
+    user: serde_json::Value,
+    pub_key_cred_params: Vec<serde_json::Value>,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: serde_json::Value,
+    attestation: &'static str,
+    timeout: i64,
+}
+
+/// The options of `navigator.credentials.get()`, the binary values are base64url encoded
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginOptions {
+    challenge: String,
+    rp_id: String,
+    allow_credentials: Vec<CredentialDescriptor>,
+    user_verification: &'static str,
+    timeout: i64,
+}
+
+/// The users who asked for a login challenge
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    /// leave it empty to pick one of the passkeys stored on the device
+    pub username: Option<String>,
+}
+
+impl crate::validator::Validate<NewPasskey> for NewPasskey {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.name.trim().is_empty() {
+            bad_request!("the passkey name is too short");
+        }
+        if self.name.trim().len() > MAX_PASSKEY_NAME {
+            bad_request!("the passkey name is too long, maximum 50 characters");
+        }
+
+        Ok(())
+    }
+}
+
+/// the relying party, passkeys are disabled when it's not configured
+pub fn relying_party() -> Result<RelyingParty, ServiceError> {
+    Config::webauthn().ok_or_else(|| {
+        ServiceError::BadRequest("passkeys aren't enabled on this server".to_string())
+    })
+}
+
+async fn issue_challenge(
+    ceremony: Ceremony,
+    user_id: Option<i64>,
+    db: &Pool<Postgres>,
+) -> Result<String, sqlx::Error> {
+    let challenge = webauthn::challenge();
+
+    sqlx::query!(
+        "DELETE FROM passkey_challenges WHERE created_at < NOW() - make_interval(secs => $1)",
+        CHALLENGE_TIMEOUT as f64
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO passkey_challenges (challenge, ceremony, user_id) VALUES ($1, $2, $3)",
+        challenge,
+        ceremony.as_str(),
+        user_id
+    )
+    .execute(db)
+    .await?;
+
+    Ok(challenge)
+}
+
+/// Use up a challenge, and return the user it was issued for
+async fn consume_challenge(
+    challenge: &str,
+    ceremony: Ceremony,
+    db: &Pool<Postgres>,
+) -> Result<Option<i64>, ServiceError> {
+    let row = sqlx::query!(
+        r#"
+        DELETE FROM passkey_challenges
+        WHERE challenge = $1 AND ceremony = $2 AND created_at > NOW() - make_interval(secs => $3)
+        RETURNING user_id
+        "#,
+        challenge,
+        ceremony.as_str(),
+        CHALLENGE_TIMEOUT as f64
+    )
+    .fetch_optional(db)
+    .await?;
+
+    match row {
+        Some(row) => Ok(row.user_id),
+        None => Err(ServiceError::Unauthorized),
+    }
+}
+
+impl Passkey {
+    #[tracing::instrument(name = "Passkey::find_by_user", skip(db))]
+    pub async fn find_by_user(
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Passkey>, sqlx::Error> {
+        sqlx::query_as!(
+            Passkey,
+            "SELECT * FROM passkeys WHERE user_id = $1 ORDER BY created_at",
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    async fn find_by_credential(
+        credential_id: &[u8],
+        db: &Pool<Postgres>,
+    ) -> Result<Option<Passkey>, sqlx::Error> {
+        sqlx::query_as!(
+            Passkey,
+            "SELECT * FROM passkeys WHERE credential_id = $1",
+            credential_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Start the registration of a passkey
+    #[tracing::instrument(name = "Passkey::registration_options", skip(user, db))]
+    pub async fn registration_options(
+        user: &User,
+        db: &Pool<Postgres>,
+    ) -> Result<RegistrationOptions, ServiceError> {
+        let rp = relying_party()?;
+        let challenge = issue_challenge(Ceremony::Registration, Some(user.id), db).await?;
+        let passkeys = Passkey::find_by_user(user.id, db).await?;
+
+        Ok(RegistrationOptions {
+            challenge,
+            rp: json!({ "id": rp.id, "name": "rustfuif" }),
+            user: json!({
+                "id": webauthn::encode(&user.id.to_be_bytes()),
+                "name": user.username,
+                "displayName": user.username,
+            }),
+            pub_key_cred_params: webauthn::ALGORITHMS
+                .iter()
+                .map(|alg| json!({ "type": "public-key", "alg": alg }))
+                .collect(),
+            exclude_credentials: passkeys.iter().map(CredentialDescriptor::from).collect(),
+            authenticator_selection: json!({
+                "residentKey": "preferred",
+                "userVerification": "preferred",
+            }),
+            attestation: "none",
+            timeout: CHALLENGE_TIMEOUT * 1000,
+        })
+    }
+
+    /// Finish the registration of a passkey
+    #[tracing::instrument(name = "Passkey::register", skip(user, passkey, db))]
+    pub async fn register(
+        user: &User,
+        passkey: &NewPasskey,
+        db: &Pool<Postgres>,
+    ) -> Result<Passkey, ServiceError> {
+        let rp = relying_party()?;
+
+        let client_data = rp.client_data(
+            &webauthn::decode(&passkey.client_data_json)?,
+            Ceremony::Registration,
+        )?;
+        if consume_challenge(&client_data.challenge, Ceremony::Registration, db).await?
+            != Some(user.id)
+        {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let credential = rp.verify_registration(&webauthn::decode(&passkey.attestation_object)?)?;
+
+        let passkey = sqlx::query_as!(
+            Passkey,
+            r#"
+            INSERT INTO passkeys (user_id, name, credential_id, public_key, sign_count)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+            user.id,
+            passkey.name.trim(),
+            credential.id,
+            credential.public_key,
+            credential.sign_count as i64
+        )
+        .fetch_one(db)
+        .await
+        .map_err(|error| match ServiceError::from(error) {
+            ServiceError::Conflict(_) => {
+                ServiceError::Conflict("this passkey is already registered".to_string())
+            }
+            error => error,
+        })?;
+
+        Ok(passkey)
+    }
+
+    /// Delete a passkey, the second factor is turned off with the last passkey
+    #[tracing::instrument(name = "Passkey::delete", skip(db))]
+    pub async fn delete(id: i64, user_id: i64, db: &Pool<Postgres>) -> Result<(), ServiceError> {
+        let mut tx = db::begin(Operation::DeletePasskey, db).await?;
+
+        let deleted = sqlx::query!(
+            "DELETE FROM passkeys WHERE id = $1 AND user_id = $2",
+            id,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if deleted.rows_affected() == 0 {
+            return Err(ServiceError::NotFound);
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM passkey_second_factors
+            WHERE user_id = $1 AND NOT EXISTS (SELECT 1 FROM passkeys WHERE user_id = $1)
+            "#,
+            user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Start a login, for a specific user or for any user
+    ///
+    /// An unknown user gets the same options as a user without passkeys, so the usernames can't be enumerated.
+    #[tracing::instrument(name = "Passkey::login_options", skip(db))]
+    pub async fn login_options(
+        request: &LoginRequest,
+        db: &Pool<Postgres>,
+    ) -> Result<LoginOptions, ServiceError> {
+        let rp = relying_party()?;
+
+        let user = match &request.username {
+            Some(username) => match User::find_by_name(username, db).await {
+                Ok(user) => Some(user),
+                Err(sqlx::Error::RowNotFound) => None,
+                Err(e) => return Err(e.into()),
+            },
+            None => None,
+        };
+        let passkeys = match &user {
+            Some(user) => Passkey::find_by_user(user.id, db).await?,
+            None => Vec::new(),
+        };
+
+        let challenge =
+            issue_challenge(Ceremony::Authentication, user.map(|user| user.id), db).await?;
+
+        Ok(LoginOptions {
+            challenge,
+            rp_id: rp.id,
+            allow_credentials: passkeys.iter().map(CredentialDescriptor::from).collect(),
+            user_verification: "preferred",
+            timeout: CHALLENGE_TIMEOUT * 1000,
+        })
+    }
+
+    /// Verify a login, and return the user of the passkey
+    ///
+    /// The user has to be verified by the authenticator when the passkey replaces the password.
+    #[tracing::instrument(name = "Passkey::authenticate", skip(assertion, db))]
+    pub async fn authenticate(
+        assertion: &Assertion,
+        passwordless: bool,
+        db: &Pool<Postgres>,
+    ) -> Result<User, ServiceError> {
+        let rp = relying_party()?;
+
+        let client_data_json = webauthn::decode(&assertion.client_data_json)?;
+        let client_data = rp.client_data(&client_data_json, Ceremony::Authentication)?;
+        let challenge_user =
+            consume_challenge(&client_data.challenge, Ceremony::Authentication, db).await?;
+
+        let passkey = Passkey::find_by_credential(&webauthn::decode(&assertion.credential_id)?, db)
+            .await?
+            .ok_or(ServiceError::Unauthorized)?;
+        if matches!(challenge_user, Some(user_id) if user_id != passkey.user_id) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        let sign_count = rp.verify_assertion(
+            &client_data_json,
+            &webauthn::decode(&assertion.authenticator_data)?,
+            &webauthn::decode(&assertion.signature)?,
+            &passkey.public_key,
+            passkey.sign_count as u32,
+            passwordless,
+        )?;
+
+        // a concurrent login with the same counter means the assertion was replayed or the passkey was cloned
+        let updated = sqlx::query!(
+            "UPDATE passkeys SET sign_count = $1, last_used_at = NOW() WHERE id = $2 AND sign_count = $3",
+            sign_count as i64,
+            passkey.id,
+            passkey.sign_count
+        )
+        .execute(db)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(User::find(passkey.user_id, db).await?)
+    }
+
+    /// Returns true when the user needs a passkey next to the password
+    pub async fn second_factor(user_id: i64, db: &Pool<Postgres>) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT user_id FROM passkey_second_factors WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(row.is_some())
+    }
+
+    #[tracing::instrument(name = "Passkey::set_second_factor", skip(db))]
+    pub async fn set_second_factor(
+        user_id: i64,
+        enabled: bool,
+        db: &Pool<Postgres>,
+    ) -> Result<(), ServiceError> {
+        if !enabled {
+            sqlx::query!(
+                "DELETE FROM passkey_second_factors WHERE user_id = $1",
+                user_id
+            )
+            .execute(db)
+            .await?;
+
+            return Ok(());
+        }
+
+        if Passkey::find_by_user(user_id, db).await?.is_empty() {
+            bad_request!("register a passkey first");
+        }
+
+        sqlx::query!(
+            "INSERT INTO passkey_second_factors (user_id) VALUES ($1) ON CONFLICT DO NOTHING",
+            user_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Check the passkey of a password login, when the user turned on the second factor
+    pub async fn verify_second_factor(
+        user: &User,
+        assertion: Option<&Assertion>,
+        db: &Pool<Postgres>,
+    ) -> Result<(), ServiceError> {
+        if !Passkey::second_factor(user.id, db).await? {
+            return Ok(());
+        }
+
+        let assertion = match assertion {
+            Some(assertion) => assertion,
+            None => forbidden!("a passkey is required to log in"),
+        };
+
+        if Passkey::authenticate(assertion, false, db).await?.id != user.id {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(())
+    }
+}
@@ -1,49 +1,249 @@
 use crate::auth;
+use crate::auth::activity::{self, Client};
+use crate::auth::backends::Login;
+use crate::auth::passkeys::{Assertion, LoginRequest, NewPasskey, Passkey};
+use crate::auth::sessions::Session;
+use crate::auth::Identity;
 use crate::errors::ServiceError;
+use crate::events::DomainEvent;
+use crate::games::waitlist::Admission;
+use crate::guests::Guest;
+use crate::invitations::external::ExternalInvitation;
 use crate::server::{Response, State};
+use crate::stats::Stats;
 use crate::users::{Credentials, User};
 use crate::validator::Validator;
+use crate::websocket::server::GameId;
 
-use actix_identity::Identity;
-use actix_web::web::{Data, Json};
-use actix_web::{get, post, web, HttpResponse};
+use actix_web::web::{Data, Json, Path, Query};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 use serde_json::json;
 
+#[derive(Deserialize)]
+struct Registration {
+    /// the token of an external invitation, which gets accepted by the new account
+    invitation: Option<String>,
+}
+
+/// The new account, with its place on the waitlist when the game of its invitation is full
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NewAccount {
+    #[serde(flatten)]
+    user: User,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    waitlist_position: Option<i64>,
+}
+
 #[post("/register")]
-async fn create_account(credentials: Json<Validator<Credentials>>, state: Data<State>) -> Response {
-    let user = User::create(&mut credentials.into_inner().validate()?, &state.db).await?;
+async fn create_account(
+    credentials: Json<Validator<Credentials>>,
+    registration: Query<Registration>,
+    state: Data<State>,
+) -> Response {
+    if !state.auth.manages_passwords() {
+        forbidden!("the accounts are managed by the identity provider");
+    }
+
+    let mut credentials = credentials.into_inner().validate()?;
+    if let Some(token) = &registration.invitation {
+        ExternalInvitation::verify(token, &state.db).await?;
+    }
+
+    let user = User::create(&mut credentials, &state.db).await?;
+
+    let mut waitlist_position = None;
+    if let Some(token) = &registration.invitation {
+        match ExternalInvitation::convert(token, user.id, &state.db).await? {
+            Admission::Accepted(invitation) => {
+                state.events.publish(DomainEvent::InvitationResponded {
+                    game_id: GameId(invitation.game_id),
+                    user_id: user.id,
+                    accepted: true,
+                });
+            }
+            Admission::Waitlisted(position) => waitlist_position = Some(position),
+        }
+    }
+
+    http_created_json!(NewAccount {
+        user,
+        waitlist_position
+    });
+}
+
+/// Share the login with the user and the administrators when it's unusual
+async fn record_success(user: &User, client: &Client, state: &State) {
+    match activity::record_success(user, client, &state.db).await {
+        Ok(Some(suspicion)) => state
+            .events
+            .publish(DomainEvent::SuspiciousLogin(suspicion)),
+        Ok(None) => (),
+        Err(e) => error!("unable to record the login of {}: {}", user, e),
+    }
+}
+
+#[post("/login", wrap = "crate::rate_limit::LOGIN")]
+async fn login(login: Json<Login>, req: HttpRequest, id: Identity, state: Data<State>) -> Response {
+    let client = Client::from_request(&req);
+    let username = match &*login {
+        Login::Password { credentials, .. } => Some(credentials.username.as_str()),
+        Login::Token { .. } => None,
+    };
+
+    if let Some(username) = username {
+        if let Some(retry_after) = activity::throttled(username, &client, &state.db).await? {
+            Stats::add_throttled_login();
+            return Err(ServiceError::RateLimited(retry_after));
+        }
+    }
+
+    let user = match state.auth.authenticate(&login, &state.db).await {
+        Ok(user) => user,
+        Err(ServiceError::Unauthorized) => {
+            Stats::add_login_failure();
+            if let Some(username) = username {
+                match activity::record_failure(username, &client, &state.db).await {
+                    Ok(Some(suspicion)) => state
+                        .events
+                        .publish(DomainEvent::SuspiciousLogin(suspicion)),
+                    Ok(None) => (),
+                    Err(e) => error!("unable to record the failed login of {}: {}", username, e),
+                }
+            }
+            return Err(ServiceError::Unauthorized);
+        }
+        Err(e) => return Err(e),
+    };
+    if let Login::Password { passkey, .. } = &*login {
+        Passkey::verify_second_factor(&user, passkey.as_ref(), &state.db).await?;
+    }
+
+    auth::remember(&id, &user, &req, &state.db).await?;
+    record_success(&user, &client, &state).await;
+
+    http_ok_json!(user);
+}
+
+/// Start the registration of a passkey, answer with `navigator.credentials.create()`
+#[post("/passkeys/register/options")]
+async fn passkey_registration_options(id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
+    if !state.auth.manages_passwords() {
+        forbidden!("the accounts are managed by the identity provider");
+    }
+    Guest::forbid(user.id, &state.db).await?;
+
+    let options = Passkey::registration_options(&user, &state.db).await?;
+
+    http_ok_json!(options);
+}
+
+#[post("/passkeys/register")]
+async fn register_passkey(
+    passkey: Json<Validator<NewPasskey>>,
+    id: Identity,
+    state: Data<State>,
+) -> Response {
+    let user = auth::get_user(&id)?;
+    let passkey = passkey.into_inner().validate()?;
 
-    http_created_json!(user);
+    let passkey = Passkey::register(&user, &passkey, &state.db).await?;
+
+    http_created_json!(passkey);
+}
+
+#[get("/passkeys")]
+async fn passkeys(id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let passkeys = Passkey::find_by_user(user.id, &state.db).await?;
+
+    http_ok_json!(passkeys);
+}
+
+#[delete("/passkeys/{id}")]
+async fn delete_passkey(passkey_id: Path<i64>, id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
+
+    Passkey::delete(*passkey_id, user.id, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
-#[post("/login")]
-async fn login(credentials: Json<Credentials>, id: Identity, state: Data<State>) -> Response {
-    let user = User::find_by_name(&credentials.username, &state.db)
-        .await
-        .map_err(|error| match error {
-            sqlx::Error::RowNotFound => ServiceError::Unauthorized,
-            _ => error.into(),
-        })?;
+/// Require a passkey next to the password
+#[put("/passkeys/second-factor")]
+async fn set_second_factor(enabled: Json<bool>, id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
 
-    user.verify_password(credentials.password.as_bytes())?;
+    Passkey::set_second_factor(user.id, *enabled, &state.db).await?;
 
-    let user_string = serde_json::to_string(&user).map_err(|e| {
-        error!("unable to serialize the user struct: {}", e);
-        ServiceError::InternalServerError
-    })?;
+    http_ok_json!(*enabled);
+}
+
+/// Start a login with a passkey, answer with `navigator.credentials.get()`
+#[post("/passkeys/login/options")]
+async fn passkey_login_options(request: Json<LoginRequest>, state: Data<State>) -> Response {
+    if !state.auth.manages_passwords() {
+        forbidden!("log in through the identity provider");
+    }
 
-    id.remember(user_string);
+    let options = Passkey::login_options(&request, &state.db).await?;
+
+    http_ok_json!(options);
+}
+
+/// Log in with a passkey instead of a password
+#[post("/passkeys/login")]
+async fn passkey_login(
+    assertion: Json<Assertion>,
+    req: HttpRequest,
+    id: Identity,
+    state: Data<State>,
+) -> Response {
+    if !state.auth.manages_passwords() {
+        forbidden!("log in through the identity provider");
+    }
+
+    let user = Passkey::authenticate(&assertion, true, &state.db).await?;
+    auth::remember(&id, &user, &req, &state.db).await?;
+    record_success(&user, &Client::from_request(&req), &state).await;
 
     http_ok_json!(user);
 }
 
 #[post("/logout")]
-async fn logout(id: Identity) -> Response {
+async fn logout(id: Identity, state: Data<State>) -> Response {
+    if let Ok(session_id) = auth::session_id(&id) {
+        Session::revoke(&session_id, &state.db).await?;
+    }
     id.forget();
 
     Ok(HttpResponse::Ok().json(json!({ "message": "Successfully signed out" })))
 }
 
+/// The devices the user is logged in on
+#[get("/sessions")]
+async fn sessions(id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
+    let session_id = auth::session_id(&id)?;
+
+    let sessions = Session::find_by_user(user.id, &session_id, &state.db).await?;
+
+    http_ok_json!(sessions);
+}
+
+/// Log out on every device, including this one
+#[delete("/sessions")]
+async fn logout_everywhere(id: Identity, state: Data<State>) -> Response {
+    let user = auth::get_user(&id)?;
+
+    Session::revoke_all(user.id, &state.db).await?;
+    id.forget();
+
+    Ok(HttpResponse::Ok().json(json!({ "message": "Successfully signed out everywhere" })))
+}
+
 #[post("/change-password")]
 async fn change_password(
     password_change: Json<Validator<auth::PasswordChange>>,
@@ -51,12 +251,17 @@ async fn change_password(
     id: Identity,
 ) -> Response {
     let session = auth::get_user(&id)?;
+    if !state.auth.manages_passwords() {
+        forbidden!("the passwords are managed by the identity provider");
+    }
 
     let password_change = password_change.into_inner().validate()?;
 
     let mut user = User::find(session.id, &state.db).await?;
-    user.verify_password(password_change.old.as_bytes())?;
+    user.verify_password(&password_change.old).await?;
     user.update_password(password_change.new, &state.db).await?;
+    // whoever knew the old password is logged out
+    Session::revoke_others(user.id, &auth::session_id(&id)?, &state.db).await?;
 
     http_ok_json!("password succesfully updated")
 }
@@ -72,18 +277,27 @@ pub fn register(cfg: &mut web::ServiceConfig) {
     cfg.service(create_account);
     cfg.service(login);
     cfg.service(logout);
+    cfg.service(sessions);
+    cfg.service(logout_everywhere);
     cfg.service(change_password);
     cfg.service(verify_session);
+    cfg.service(passkey_registration_options);
+    cfg.service(register_passkey);
+    cfg.service(passkeys);
+    cfg.service(delete_passkey);
+    cfg.service(set_second_factor);
+    cfg.service(passkey_login_options);
+    cfg.service(passkey_login);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix_identity::Identity;
-    use actix_identity::{CookieIdentityPolicy, IdentityService};
+    use actix_identity::{CookieIdentityPolicy, IdentityService, RequestIdentity};
+    use actix_service::Service;
     use actix_web::http::StatusCode;
     use actix_web::test::{self, TestRequest};
-    use actix_web::{web, App, HttpResponse};
+    use actix_web::{web, App, HttpMessage, HttpResponse};
 
     const COOKIE_KEY_MASTER: [u8; 32] = [0; 32];
     const COOKIE_NAME: &str = "actix_auth";
@@ -92,6 +306,20 @@ mod tests {
     async fn test_identity() {
         let mut srv = test::init_service(
             App::new()
+                // instead of the session middleware, only the `current` session has a user
+                .wrap_fn(|req, srv| {
+                    if req.get_identity().as_deref() == Some("current") {
+                        req.extensions_mut().insert(User {
+                            id: 1,
+                            is_admin: true,
+                            username: "admin".to_string(),
+                            password: "admin".to_string(),
+                            created_at: None,
+                            updated_at: None,
+                        });
+                    }
+                    srv.call(req)
+                })
                 .wrap(IdentityService::new(
                     CookieIdentityPolicy::new(&COOKIE_KEY_MASTER)
                         .domain("localhost")
@@ -100,21 +328,12 @@ mod tests {
                         .secure(true),
                 ))
                 .service(verify_session)
-                .service(web::resource("/login").to(|id: Identity| {
-                    let user = User {
-                        id: 1,
-                        is_admin: true,
-                        username: "admin".to_string(),
-                        password: "admin".to_string(),
-                        created_at: None,
-                        updated_at: None,
-                    };
-
-                    let user_string = serde_json::to_string(&user).unwrap();
-
-                    id.remember(user_string);
-                    HttpResponse::Ok()
-                })),
+                .service(web::resource("/login/{session}").to(
+                    |id: Identity, session: Path<String>| {
+                        id.remember(session.into_inner());
+                        HttpResponse::Ok()
+                    },
+                )),
         )
         .await;
 
@@ -125,16 +344,25 @@ mod tests {
         .await;
         assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
 
-        let resp = test::call_service(&mut srv, TestRequest::with_uri("/login").to_request()).await;
-        let cookie = resp.response().cookies().next().unwrap().to_owned();
+        for (session, status) in &[
+            ("current", StatusCode::OK),
+            ("revoked", StatusCode::UNAUTHORIZED),
+        ] {
+            let resp = test::call_service(
+                &mut srv,
+                TestRequest::with_uri(&format!("/login/{}", session)).to_request(),
+            )
+            .await;
+            let cookie = resp.response().cookies().next().unwrap().to_owned();
 
-        let resp = test::call_service(
-            &mut srv,
-            TestRequest::with_uri("/verify-session")
-                .cookie(cookie.clone())
-                .to_request(),
-        )
-        .await;
-        assert_eq!(resp.status(), StatusCode::OK);
+            let resp = test::call_service(
+                &mut srv,
+                TestRequest::with_uri("/verify-session")
+                    .cookie(cookie)
+                    .to_request(),
+            )
+            .await;
+            assert_eq!(resp.status(), *status);
+        }
     }
 }
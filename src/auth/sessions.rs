@@ -0,0 +1,489 @@
+//! Sessions of the logged in users
+//!
+//! The session cookie refers to a session in the database, so a session can be revoked before
+//! the cookie expires: when the user logs out, logs out everywhere or changes their password.
+//! The user is loaded with the session on every request, so the route handlers see the user
+//! as it's stored right now, not as it was when they logged in.
+//! The id of a session is rotated every day, so a copied cookie stops working
+//! once the browser of the user picked up the rotated one.
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_identity::{Identity, RequestIdentity};
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::web::Data;
+use actix_web::{Error, FromRequest, HttpRequest};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::{ok, Ready};
+use futures::Future;
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::auth::activity::Client;
+use crate::errors::ServiceError;
+use crate::server::State;
+use crate::users::User;
+
+/// the session id is replaced after this many hours
+const ROTATE_AFTER_HOURS: i64 = 24;
+/// the seconds the previous id of a rotated session is still accepted
+const GRACE_PERIOD_SECONDS: i64 = 60;
+/// a session ends after two weeks without requests, like the visit deadline of the cookie
+const IDLE_TIMEOUT_DAYS: i64 = 14;
+/// the last request of a session is only stored every few minutes
+const TOUCH_INTERVAL_MINUTES: i64 = 5;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    #[serde(skip)]
+    pub id: String,
+    pub user_id: i64,
+    pub user_agent: String,
+    pub ip_address: String,
+    pub created_at: DateTime<Utc>,
+    pub rotated_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    /// the session of the request
+    pub current: bool,
+}
+
+/// What to do with the session of a request
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Valid,
+    /// store the time of the request
+    Touch,
+    /// give the session a new id
+    Rotate,
+    Expired,
+}
+
+/// The part of the session cookie the middleware needs
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionCookie {
+    session_id: Option<String>,
+}
+
+fn new_id() -> String {
+    format!(
+        "{:032x}{:032x}",
+        rand::thread_rng().gen::<u128>(),
+        rand::thread_rng().gen::<u128>()
+    )
+}
+
+impl Session {
+    /// Start a session after a login, the idle sessions of the user are cleaned up
+    #[tracing::instrument(name = "Session::create", skip(db))]
+    pub async fn create(
+        user_id: i64,
+        client: &Client,
+        db: &Pool<Postgres>,
+    ) -> Result<Session, sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE user_id = $1 AND last_seen_at < NOW() - make_interval(days => $2::int)",
+            user_id,
+            IDLE_TIMEOUT_DAYS as i32
+        )
+        .execute(db)
+        .await?;
+
+        sqlx::query_as!(
+            Session,
+            r#"
+            INSERT INTO sessions (id, user_id, user_agent, ip_address)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, user_agent, ip_address,
+                created_at, rotated_at, last_seen_at, true AS "current!"
+            "#,
+            new_id(),
+            user_id,
+            client.user_agent,
+            client.ip_address
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// The session of a cookie with its user, a rotated session is found by its previous id as well
+    #[tracing::instrument(name = "Session::find", skip(session_id, db))]
+    pub async fn find(
+        session_id: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<(Session, User)>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT sessions.id, sessions.user_id, sessions.user_agent, sessions.ip_address,
+                sessions.created_at, sessions.rotated_at, sessions.last_seen_at,
+                users.username, users.password, users.is_admin,
+                users.created_at AS user_created_at, users.updated_at AS user_updated_at
+            FROM sessions
+            INNER JOIN users ON users.id = sessions.user_id
+            WHERE sessions.id = $1 OR sessions.previous_id = $1
+            "#,
+            session_id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(row.map(|row| {
+            let user = User {
+                id: row.user_id,
+                username: row.username,
+                password: row.password,
+                is_admin: row.is_admin,
+                created_at: row.user_created_at,
+                updated_at: row.user_updated_at,
+            };
+            let session = Session {
+                id: row.id,
+                user_id: row.user_id,
+                user_agent: row.user_agent,
+                ip_address: row.ip_address,
+                created_at: row.created_at,
+                rotated_at: row.rotated_at,
+                last_seen_at: row.last_seen_at,
+                current: true,
+            };
+
+            (session, user)
+        }))
+    }
+
+    /// The sessions of a user, the most recently used first
+    #[tracing::instrument(name = "Session::find_by_user", skip(current, db))]
+    pub async fn find_by_user(
+        user_id: i64,
+        current: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Session>, sqlx::Error> {
+        sqlx::query_as!(
+            Session,
+            r#"
+            SELECT id, user_id, user_agent, ip_address,
+                created_at, rotated_at, last_seen_at,
+                (id = $2 OR previous_id IS NOT DISTINCT FROM $2) AS "current!"
+            FROM sessions
+            WHERE user_id = $1 AND last_seen_at >= NOW() - make_interval(days => $3::int)
+            ORDER BY last_seen_at DESC
+            "#,
+            user_id,
+            current,
+            IDLE_TIMEOUT_DAYS as i32
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// End a session, like when the user logs out
+    #[tracing::instrument(name = "Session::revoke", skip(session_id, db))]
+    pub async fn revoke(session_id: &str, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM sessions WHERE id = $1 OR previous_id = $1",
+            session_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// End every session of the user, on every device
+    #[tracing::instrument(name = "Session::revoke_all", skip(db))]
+    pub async fn revoke_all(user_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// End every session of the user except the one of the request
+    #[tracing::instrument(name = "Session::revoke_others", skip(current, db))]
+    pub async fn revoke_others(
+        user_id: i64,
+        current: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            DELETE FROM sessions
+            WHERE user_id = $1 AND id <> $2 AND previous_id IS DISTINCT FROM $2
+            "#,
+            user_id,
+            current
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Give the session a new id, the current id becomes the previous one
+    ///
+    /// Returns `None` when a concurrent request already rotated the session.
+    #[tracing::instrument(name = "Session::rotate", skip(self, db))]
+    async fn rotate(&self, db: &Pool<Postgres>) -> Result<Option<String>, sqlx::Error> {
+        let rotated = sqlx::query!(
+            r#"
+            UPDATE sessions
+            SET previous_id = id, id = $1, rotated_at = NOW(), last_seen_at = NOW()
+            WHERE id = $2
+            RETURNING id
+            "#,
+            new_id(),
+            self.id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(rotated.map(|row| row.id))
+    }
+
+    #[tracing::instrument(name = "Session::touch", skip(self, db))]
+    async fn touch(&self, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE sessions SET last_seen_at = NOW() WHERE id = $1",
+            self.id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// `session_id` is the id in the cookie of the request, which can be the previous id
+    fn verdict(&self, session_id: &str, now: DateTime<Utc>) -> Verdict {
+        if now - self.last_seen_at > Duration::days(IDLE_TIMEOUT_DAYS) {
+            return Verdict::Expired;
+        }
+        if session_id != self.id {
+            if now - self.rotated_at > Duration::seconds(GRACE_PERIOD_SECONDS) {
+                return Verdict::Expired;
+            }
+            return Verdict::Valid;
+        }
+        if now - self.rotated_at > Duration::hours(ROTATE_AFTER_HOURS) {
+            return Verdict::Rotate;
+        }
+        if now - self.last_seen_at > Duration::minutes(TOUCH_INTERVAL_MINUTES) {
+            return Verdict::Touch;
+        }
+
+        Verdict::Valid
+    }
+}
+
+/// the session cookie with a different session id, the rest of the cookie is kept
+fn with_session_id(identity: &str, session_id: &str) -> Option<String> {
+    let mut cookie: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(identity).ok()?;
+    cookie.insert(
+        "sessionId".to_string(),
+        serde_json::Value::String(session_id.to_string()),
+    );
+
+    serde_json::to_string(&cookie).ok()
+}
+
+/// Forget the identity of a request whose session doesn't exist anymore, and rotate the old sessions
+///
+/// Returns the user of a valid session.
+async fn verify(
+    request: &HttpRequest,
+    identity: String,
+    db: &Pool<Postgres>,
+) -> Result<Option<User>, ServiceError> {
+    let id = Identity::from_request(request, &mut Payload::None)
+        .into_inner()
+        .map_err(|_| ServiceError::InternalServerError)?;
+
+    let cookie: SessionCookie = match serde_json::from_str(&identity) {
+        Ok(cookie) => cookie,
+        Err(_) => {
+            id.forget();
+            return Ok(None);
+        }
+    };
+    // cookies from before the sessions were stored don't have a session
+    let session_id = match cookie.session_id {
+        Some(session_id) => session_id,
+        None => {
+            id.forget();
+            return Ok(None);
+        }
+    };
+    let (session, user) = match Session::find(&session_id, db).await? {
+        Some(found) => found,
+        None => {
+            id.forget();
+            return Ok(None);
+        }
+    };
+
+    match session.verdict(&session_id, Utc::now()) {
+        Verdict::Valid => (),
+        Verdict::Touch => session.touch(db).await?,
+        // the cookie is kept when another request rotated the session first,
+        // its id stays valid as the previous id during the grace period
+        Verdict::Rotate => {
+            if let Some(rotated) = session.rotate(db).await? {
+                match with_session_id(&identity, &rotated) {
+                    Some(identity) => id.remember(identity),
+                    None => id.forget(),
+                }
+            }
+        }
+        Verdict::Expired => {
+            Session::revoke(&session_id, db).await?;
+            id.forget();
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(user))
+}
+
+/// Check the session of every request with a session cookie, and load its user
+///
+/// The identity middleware runs before this one, the user is stored in the extensions of the request.
+pub struct Middleware;
+
+impl Middleware {
+    pub fn default() -> Middleware {
+        Middleware
+    }
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct SessionMiddleware<S> {
+    // the session is checked before the request is passed on, so the service is shared with that future
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for SessionMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let identity = match request.get_identity() {
+            Some(identity) => identity,
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+        let db = match request.app_data::<Data<State>>() {
+            Some(state) => state.db.clone(),
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            // the identity can only be changed through the `HttpRequest` of the service request
+            let (http_request, payload) = request.into_parts();
+            match verify(&http_request, identity, &db).await {
+                Ok(Some(user)) => {
+                    http_request.extensions_mut().insert(user);
+                }
+                Ok(None) => (),
+                Err(e) => return Ok(ServiceResponse::from_err(e, http_request)),
+            }
+            let request = match ServiceRequest::from_parts(http_request, payload) {
+                Ok(request) => request,
+                Err((http_request, _)) => {
+                    error!("unable to rebuild the request after checking the session");
+                    return Ok(ServiceResponse::from_err(
+                        ServiceError::InternalServerError,
+                        http_request,
+                    ));
+                }
+            };
+
+            let response = service.borrow_mut().call(request);
+            response.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(rotated_hours_ago: i64, seen_minutes_ago: i64) -> Session {
+        let now = Utc::now();
+
+        Session {
+            id: String::from("current"),
+            user_id: 1,
+            user_agent: String::from("Mozilla/5.0"),
+            ip_address: String::from("127.0.0.1"),
+            created_at: now - Duration::days(3),
+            rotated_at: now - Duration::hours(rotated_hours_ago),
+            last_seen_at: now - Duration::minutes(seen_minutes_ago),
+            current: true,
+        }
+    }
+
+    #[test]
+    fn verdicts() {
+        let now = Utc::now();
+
+        assert_eq!(session(1, 1).verdict("current", now), Verdict::Valid);
+        assert_eq!(session(1, 10).verdict("current", now), Verdict::Touch);
+        assert_eq!(session(25, 1).verdict("current", now), Verdict::Rotate);
+        assert_eq!(
+            session(400, 15 * 24 * 60).verdict("current", now),
+            Verdict::Expired
+        );
+
+        // a request that was sent before the rotation
+        assert_eq!(session(0, 0).verdict("previous", now), Verdict::Valid);
+        assert_eq!(session(1, 1).verdict("previous", now), Verdict::Expired);
+    }
+
+    #[test]
+    fn rotated_cookie() {
+        let cookie = with_session_id(r#"{"sessionId":"old","activeGame":4}"#, "new")
+            .expect("the cookie should be rewritten");
+        let cookie: serde_json::Value = serde_json::from_str(&cookie).unwrap();
+
+        assert_eq!(cookie["sessionId"], "new");
+        assert_eq!(cookie["activeGame"], 4);
+        assert_eq!(with_session_id("not json", "new"), None);
+    }
+}
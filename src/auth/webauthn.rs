@@ -0,0 +1,669 @@
+//! The relying party side of the WebAuthn ceremonies
+//!
+//! Only what passkeys need is implemented: the attestation statement isn't verified, because it doesn't
+//! matter which authenticator made the passkey. Only ES256 keys on P-256 are accepted, every platform
+//! authenticator supports them, and anything else is rejected before a verifying key is built.
+//! The authenticator data and the public keys are CBOR encoded, so this contains a small CBOR decoder.
+use rand::Rng;
+use ring::digest::{digest, SHA256};
+use ring::signature::{self, UnparsedPublicKey};
+
+use crate::errors::ServiceError;
+
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// the maximum nesting of the CBOR values, the authenticators don't go deeper than 3
+const MAX_DEPTH: usize = 8;
+
+/// This server, as seen by the authenticators
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    /// the domain the passkeys are bound to, like `rustfuif.example.com`
+    pub id: String,
+    /// where the frontend is served, like `https://rustfuif.example.com`
+    pub origin: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Ceremony::Registration => "registration",
+            Ceremony::Authentication => "authentication",
+        }
+    }
+
+    /// the type in the client data
+    fn client_data_type(self) -> &'static str {
+        match self {
+            Ceremony::Registration => "webauthn.create",
+            Ceremony::Authentication => "webauthn.get",
+        }
+    }
+}
+
+/// What the browser signed, next to the authenticator data
+#[derive(Debug, Deserialize)]
+pub struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    pub challenge: String,
+    origin: String,
+}
+
+/// A passkey that was just created by an authenticator
+#[derive(Debug)]
+pub struct NewCredential {
+    pub id: Vec<u8>,
+    /// the COSE encoded public key
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+fn invalid(message: &str) -> ServiceError {
+    ServiceError::BadRequest(format!("invalid passkey response: {}", message))
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, ServiceError> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD).map_err(|_| invalid("not base64url"))
+}
+
+/// a new random challenge, base64url encoded like the browser returns it in the client data
+pub fn challenge() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    encode(&bytes)
+}
+
+impl RelyingParty {
+    /// Parse the client data and check that it was made for this ceremony on our origin
+    pub fn client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: Ceremony,
+    ) -> Result<ClientData, ServiceError> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|_| invalid("client data"))?;
+
+        if client_data.kind != ceremony.client_data_type() {
+            return Err(invalid("wrong ceremony"));
+        }
+        if client_data.origin != self.origin {
+            return Err(invalid("wrong origin"));
+        }
+
+        Ok(client_data)
+    }
+
+    /// Check a new passkey, the client data should already be checked
+    pub fn verify_registration(
+        &self,
+        attestation_object: &[u8],
+    ) -> Result<NewCredential, ServiceError> {
+        let (attestation, _) = Cbor::decode(attestation_object)?;
+        let auth_data = match attestation.get_text("authData") {
+            Some(Cbor::Bytes(auth_data)) => auth_data,
+            _ => return Err(invalid("missing authenticator data")),
+        };
+
+        let auth_data = AuthenticatorData::parse(auth_data)?;
+        self.check_authenticator_data(&auth_data, false)?;
+
+        let credential = auth_data
+            .credential
+            .ok_or_else(|| invalid("missing credential"))?;
+        // only store keys that can be used later on
+        PublicKey::parse(&credential.public_key)?;
+
+        Ok(NewCredential {
+            id: credential.id,
+            public_key: credential.public_key,
+            sign_count: auth_data.sign_count,
+        })
+    }
+
+    /// Check the signature of a login, and return the new signature counter of the passkey
+    pub fn verify_assertion(
+        &self,
+        client_data_json: &[u8],
+        authenticator_data: &[u8],
+        signature: &[u8],
+        public_key: &[u8],
+        sign_count: u32,
+        require_user_verification: bool,
+    ) -> Result<u32, ServiceError> {
+        let auth_data = AuthenticatorData::parse(authenticator_data)?;
+        self.check_authenticator_data(&auth_data, require_user_verification)?;
+
+        let mut message = authenticator_data.to_vec();
+        message.extend_from_slice(digest(&SHA256, client_data_json).as_ref());
+        if !PublicKey::parse(public_key)?.verify(&message, signature) {
+            return Err(ServiceError::Unauthorized);
+        }
+
+        // a counter that doesn't increase means the passkey was cloned,
+        // authenticators that don't count always send 0
+        if auth_data.sign_count != 0 && auth_data.sign_count <= sign_count {
+            warn!("the signature counter of a passkey went backwards");
+            return Err(ServiceError::Unauthorized);
+        }
+
+        Ok(auth_data.sign_count)
+    }
+
+    fn check_authenticator_data(
+        &self,
+        auth_data: &AuthenticatorData,
+        require_user_verification: bool,
+    ) -> Result<(), ServiceError> {
+        if auth_data.rp_id_hash != digest(&SHA256, self.id.as_bytes()).as_ref() {
+            return Err(invalid("made for another relying party"));
+        }
+        if auth_data.flags & USER_PRESENT == 0 {
+            return Err(invalid("the user wasn't present"));
+        }
+        if require_user_verification && auth_data.flags & USER_VERIFIED == 0 {
+            return Err(invalid("the user wasn't verified"));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct AttestedCredential {
+    id: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    credential: Option<AttestedCredential>,
+}
+
+impl AuthenticatorData {
+    fn parse(data: &[u8]) -> Result<AuthenticatorData, ServiceError> {
+        if data.len() < 37 {
+            return Err(invalid("authenticator data too short"));
+        }
+
+        let flags = data[32];
+        let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+        let credential = if flags & ATTESTED_CREDENTIAL != 0 {
+            // the AAGUID of the authenticator, followed by the length of the credential id
+            let rest = data
+                .get(37 + 16..)
+                .filter(|rest| rest.len() >= 2)
+                .ok_or_else(|| invalid("attested credential too short"))?;
+            let id_length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            let id = rest
+                .get(2..2 + id_length)
+                .ok_or_else(|| invalid("credential id too short"))?;
+
+            let key = &rest[2 + id_length..];
+            let (_, key_length) = Cbor::decode(key)?;
+
+            Some(AttestedCredential {
+                id: id.to_vec(),
+                public_key: key[..key_length].to_vec(),
+            })
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            rp_id_hash: data[..32].to_vec(),
+            flags,
+            sign_count,
+            credential,
+        })
+    }
+}
+
+/// The COSE labels and values of an ES256 key
+const KEY_TYPE: i64 = 1;
+const ALGORITHM: i64 = 3;
+const CURVE: i64 = -1;
+const EC2: i64 = 2;
+const ES256: i64 = -7;
+const P256: i64 = 1;
+
+/// the algorithms the authenticators can choose from
+pub const ALGORITHMS: [i64; 1] = [ES256];
+
+/// An ES256 public key, the uncompressed P-256 point
+#[derive(Debug)]
+struct PublicKey {
+    point: Vec<u8>,
+}
+
+impl PublicKey {
+    fn parse(cose: &[u8]) -> Result<PublicKey, ServiceError> {
+        let (key, _) = Cbor::decode(cose)?;
+        let integer = |label: i64| match key.get_int(label) {
+            Some(Cbor::Integer(value)) => Some(*value),
+            _ => None,
+        };
+        let bytes = |label: i64| match key.get_int(label) {
+            Some(Cbor::Bytes(bytes)) if bytes.len() == 32 => Ok(bytes.as_slice()),
+            _ => Err(invalid("not a P-256 key")),
+        };
+
+        if integer(KEY_TYPE) != Some(EC2) {
+            return Err(invalid("unsupported key type"));
+        }
+        if integer(ALGORITHM) != Some(ES256) {
+            return Err(invalid("unsupported key algorithm"));
+        }
+        if integer(CURVE) != Some(P256) {
+            return Err(invalid("unsupported curve"));
+        }
+
+        let mut point = vec![0x04];
+        point.extend_from_slice(bytes(-2)?);
+        point.extend_from_slice(bytes(-3)?);
+
+        Ok(PublicKey { point })
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, &self.point)
+            .verify(message, signature)
+            .is_ok()
+    }
+}
+
+/// A decoded CBOR value, only the definite length encodings are supported
+#[derive(Debug, Clone, PartialEq)]
+enum Cbor {
+    Integer(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Bool(bool),
+    Null,
+}
+
+impl Cbor {
+    /// Decode the first value, and return the amount of bytes it used
+    fn decode(data: &[u8]) -> Result<(Cbor, usize), ServiceError> {
+        let mut decoder = Decoder { data, position: 0 };
+        let value = decoder.value(0)?;
+
+        Ok((value, decoder.position))
+    }
+
+    fn get(&self, key: &Cbor) -> Option<&Cbor> {
+        match self {
+            Cbor::Map(entries) => entries
+                .iter()
+                .find(|(entry, _)| entry == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn get_text(&self, key: &str) -> Option<&Cbor> {
+        self.get(&Cbor::Text(key.to_string()))
+    }
+
+    fn get_int(&self, key: i64) -> Option<&Cbor> {
+        self.get(&Cbor::Integer(key))
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, length: u64) -> Result<&'a [u8], ServiceError> {
+        let remaining = self.data.len() - self.position;
+        if length > remaining as u64 {
+            return Err(invalid("truncated CBOR"));
+        }
+
+        let bytes = &self.data[self.position..self.position + length as usize];
+        self.position += length as usize;
+        Ok(bytes)
+    }
+
+    /// the argument of a data item, a length or the value of an integer
+    fn argument(&mut self, info: u8) -> Result<u64, ServiceError> {
+        let bytes = match info {
+            0..=23 => return Ok(info as u64),
+            24 => self.take(1)?,
+            25 => self.take(2)?,
+            26 => self.take(4)?,
+            27 => self.take(8)?,
+            _ => return Err(invalid("unsupported CBOR length")),
+        };
+
+        Ok(bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Cbor, ServiceError> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("CBOR nested too deep"));
+        }
+
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1F);
+        if major == 7 {
+            return match info {
+                20 => Ok(Cbor::Bool(false)),
+                21 => Ok(Cbor::Bool(true)),
+                22 => Ok(Cbor::Null),
+                _ => Err(invalid("unsupported CBOR value")),
+            };
+        }
+
+        let argument = self.argument(info)?;
+        let integer = || {
+            if argument > i64::MAX as u64 {
+                return Err(invalid("CBOR integer too large"));
+            }
+            Ok(argument as i64)
+        };
+
+        match major {
+            0 => Ok(Cbor::Integer(integer()?)),
+            1 => Ok(Cbor::Integer(-1 - integer()?)),
+            2 => Ok(Cbor::Bytes(self.take(argument)?.to_vec())),
+            3 => String::from_utf8(self.take(argument)?.to_vec())
+                .map(Cbor::Text)
+                .map_err(|_| invalid("CBOR text isn't UTF-8")),
+            4 => {
+                let mut items = Vec::new();
+                for _ in 0..argument {
+                    items.push(self.value(depth + 1)?);
+                }
+                Ok(Cbor::Array(items))
+            }
+            5 => {
+                let mut entries = Vec::new();
+                for _ in 0..argument {
+                    let key = self.value(depth + 1)?;
+                    entries.push((key, self.value(depth + 1)?));
+                }
+                Ok(Cbor::Map(entries))
+            }
+            // tags don't change the meaning of the values we use
+            _ => self.value(depth + 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+
+    // recorded from a software authenticator with a fixed P-256 key, for rustfuif.example.com:
+    // a registration with the "none" attestation and a login with it
+    const ATTESTATION_OBJECT: &str = "o2NmbXRkbm9uZWdhdHRTdG10oGhhdXRoRGF0YVkAlA4gbneoygpkDikzX474Gxus0LSeKQCbYyA5A__zqKPgRQAAAACtzgACNbzGCmSLCyXx8FUDABAQERITFBUWFxgZGhscHR4fpQECAyYgASFYIC4Zfo1fdBIG85_0JomJsFUlNkuoIrMTJCu75x3QphzAIlggpMownafLQAkJPFjtSVfK2XWqQbsltH43oWfysFflC_g";
+    const REGISTRATION_CLIENT_DATA: &str = "eyJ0eXBlIjoid2ViYXV0aG4uY3JlYXRlIiwiY2hhbGxlbmdlIjoicTFyRXZuZjhDazZra3ZLTDAtTmszdyIsIm9yaWdpbiI6Imh0dHBzOi8vcnVzdGZ1aWYuZXhhbXBsZS5jb20iLCJjcm9zc09yaWdpbiI6ZmFsc2V9";
+    const CREDENTIAL_ID: &str = "EBESExQVFhcYGRobHB0eHw";
+    const PUBLIC_KEY: &str = "pQECAyYgASFYIC4Zfo1fdBIG85_0JomJsFUlNkuoIrMTJCu75x3QphzAIlggpMownafLQAkJPFjtSVfK2XWqQbsltH43oWfysFflC_g";
+    const ASSERTION_CLIENT_DATA: &str = "eyJ0eXBlIjoid2ViYXV0aG4uZ2V0IiwiY2hhbGxlbmdlIjoiQXR4Qk1meWpjVENQWkcyd0FuRy1vdyIsIm9yaWdpbiI6Imh0dHBzOi8vcnVzdGZ1aWYuZXhhbXBsZS5jb20iLCJjcm9zc09yaWdpbiI6ZmFsc2V9";
+    const AUTHENTICATOR_DATA: &str = "DiBud6jKCmQOKTNfjvgbG6zQtJ4pAJtjIDkD__Ooo-AFAAAAAQ";
+    const SIGNATURE: &str = "MEYCIQCeR1UUONLJFhqKbzmmUgip2U1VcAEawJSRalqs43eFrgIhAKBY1iabeqGrvzu3AQmzL1VKN7MurZVHZdyje-ixd-1B";
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: "rustfuif.example.com".to_string(),
+            origin: "https://rustfuif.example.com".to_string(),
+        }
+    }
+
+    /// the COSE encoding of a P-256 key
+    fn cose_key(point: &[u8]) -> Vec<u8> {
+        let mut key = vec![0xA5, 0x01, 0x02, 0x03, 0x26, 0x20, 0x01, 0x21, 0x58, 0x20];
+        key.extend_from_slice(&point[1..33]);
+        key.extend_from_slice(&[0x22, 0x58, 0x20]);
+        key.extend_from_slice(&point[33..]);
+        key
+    }
+
+    fn authenticator_data(flags: u8, sign_count: u32) -> Vec<u8> {
+        let mut data = digest(&SHA256, b"rustfuif.example.com").as_ref().to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn decode_cbor() {
+        // {"fmt": "none", 1: -7, "list": [true, null, h'0102']}
+        let data = [
+            0xA3, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e', 0x01, 0x26, 0x64, b'l',
+            b'i', b's', b't', 0x83, 0xF5, 0xF6, 0x42, 0x01, 0x02, 0xFF,
+        ];
+        let (value, length) = Cbor::decode(&data).unwrap();
+
+        assert_eq!(length, data.len() - 1);
+        assert_eq!(value.get_text("fmt"), Some(&Cbor::Text("none".to_string())));
+        assert_eq!(value.get_int(1), Some(&Cbor::Integer(-7)));
+        assert_eq!(
+            value.get_text("list"),
+            Some(&Cbor::Array(vec![
+                Cbor::Bool(true),
+                Cbor::Null,
+                Cbor::Bytes(vec![1, 2])
+            ]))
+        );
+
+        assert!(Cbor::decode(&[0x5A, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(Cbor::decode(&[0x81; 64]).is_err());
+    }
+
+    #[test]
+    fn registration() {
+        let mut auth_data = authenticator_data(USER_PRESENT | ATTESTED_CREDENTIAL, 0);
+        auth_data.extend_from_slice(&[0; 16]);
+        auth_data.extend_from_slice(&[0x00, 0x02, 0xAB, 0xCD]);
+        auth_data.extend(cose_key(&[0x04; 65]));
+
+        // {"fmt": "none", "attStmt": {}, "authData": ...}
+        let mut attestation = vec![0xA3, 0x63, b'f', b'm', b't', 0x64, b'n', b'o', b'n', b'e'];
+        attestation.extend_from_slice(&[0x67, b'a', b't', b't', b'S', b't', b'm', b't', 0xA0]);
+        attestation.extend_from_slice(&[0x68, b'a', b'u', b't', b'h', b'D', b'a', b't', b'a']);
+        attestation.extend_from_slice(&[0x58, auth_data.len() as u8]);
+        attestation.extend(auth_data);
+
+        let credential = relying_party().verify_registration(&attestation).unwrap();
+        assert_eq!(credential.id, vec![0xAB, 0xCD]);
+        assert_eq!(credential.public_key, cose_key(&[0x04; 65]));
+    }
+
+    #[test]
+    fn assertion() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref()).unwrap();
+        let public_key = cose_key(key_pair.public_key().as_ref());
+
+        let client_data =
+            br#"{"type":"webauthn.get","challenge":"abc","origin":"https://rustfuif.example.com"}"#;
+        let auth_data = authenticator_data(USER_PRESENT | USER_VERIFIED, 5);
+        let mut message = auth_data.clone();
+        message.extend_from_slice(digest(&SHA256, client_data).as_ref());
+        let signature = key_pair.sign(&rng, &message).unwrap();
+
+        let rp = relying_party();
+        assert_eq!(
+            rp.client_data(client_data, Ceremony::Authentication)
+                .unwrap()
+                .challenge,
+            "abc"
+        );
+        assert!(rp.client_data(client_data, Ceremony::Registration).is_err());
+
+        let verify = |sign_count| {
+            rp.verify_assertion(
+                client_data,
+                &auth_data,
+                signature.as_ref(),
+                &public_key,
+                sign_count,
+                true,
+            )
+        };
+        assert_eq!(verify(4).unwrap(), 5);
+        // a cloned passkey
+        assert!(verify(5).is_err());
+
+        let other_client_data =
+            br#"{"type":"webauthn.get","challenge":"abd","origin":"https://rustfuif.example.com"}"#;
+        assert!(rp
+            .verify_assertion(
+                other_client_data,
+                &auth_data,
+                signature.as_ref(),
+                &public_key,
+                0,
+                true
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn recorded_registration() {
+        let rp = relying_party();
+
+        let client_data = rp
+            .client_data(
+                &decode(REGISTRATION_CLIENT_DATA).unwrap(),
+                Ceremony::Registration,
+            )
+            .unwrap();
+        assert_eq!(client_data.challenge, "q1rEvnf8Ck6kkvKL0-Nk3w");
+
+        let credential = rp
+            .verify_registration(&decode(ATTESTATION_OBJECT).unwrap())
+            .unwrap();
+        assert_eq!(encode(&credential.id), CREDENTIAL_ID);
+        assert_eq!(encode(&credential.public_key), PUBLIC_KEY);
+        assert_eq!(credential.sign_count, 0);
+
+        let other_rp = RelyingParty {
+            id: "example.com".to_string(),
+            origin: "https://example.com".to_string(),
+        };
+        assert!(other_rp
+            .verify_registration(&decode(ATTESTATION_OBJECT).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn recorded_assertion() {
+        let rp = relying_party();
+        let client_data = decode(ASSERTION_CLIENT_DATA).unwrap();
+        let auth_data = decode(AUTHENTICATOR_DATA).unwrap();
+        let public_key = decode(PUBLIC_KEY).unwrap();
+        let signature = decode(SIGNATURE).unwrap();
+
+        assert!(rp
+            .client_data(&client_data, Ceremony::Authentication)
+            .is_ok());
+        assert_eq!(
+            rp.verify_assertion(&client_data, &auth_data, &signature, &public_key, 0, true)
+                .unwrap(),
+            1
+        );
+
+        let mut tampered = signature.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(rp
+            .verify_assertion(&client_data, &auth_data, &tampered, &public_key, 0, true)
+            .is_err());
+
+        // signed by the authenticator without the user being there
+        let mut absent = auth_data.clone();
+        absent[32] &= !USER_PRESENT;
+        assert!(rp
+            .verify_assertion(&client_data, &absent, &signature, &public_key, 0, true)
+            .is_err());
+    }
+
+    #[test]
+    fn unsupported_keys() {
+        let key = decode(PUBLIC_KEY).unwrap();
+        assert!(PublicKey::parse(&key).is_ok());
+
+        // an OKP key, like Ed25519
+        let mut okp = key.clone();
+        okp[2] = 0x01;
+        // EdDSA
+        let mut eddsa = key.clone();
+        eddsa[4] = 0x27;
+        // P-384
+        let mut p384 = key.clone();
+        p384[6] = 0x02;
+        // without a curve
+        let mut curveless = vec![0xA4];
+        curveless.extend_from_slice(&key[1..5]);
+        curveless.extend_from_slice(&key[7..]);
+
+        for key in [okp, eddsa, p384, curveless].iter() {
+            assert!(PublicKey::parse(key).is_err());
+        }
+
+        // a valid key with a point of the wrong size
+        let mut short = key[..key.len() - 1].to_vec();
+        let y_header = short.len() - 32;
+        short[y_header] = 31;
+        assert!(PublicKey::parse(&short).is_err());
+    }
+
+    #[test]
+    fn malformed_cbor() {
+        let rp = relying_party();
+        let attestation = decode(ATTESTATION_OBJECT).unwrap();
+
+        for length in 0..attestation.len() {
+            assert!(rp.verify_registration(&attestation[..length]).is_err());
+        }
+
+        let auth_data = decode(AUTHENTICATOR_DATA).unwrap();
+        for length in 0..auth_data.len() {
+            assert!(AuthenticatorData::parse(&auth_data[..length]).is_err());
+        }
+
+        // an indefinite length map
+        assert!(Cbor::decode(&[0xBF, 0x63, b'f', b'm', b't', 0x60, 0xFF]).is_err());
+        // the authenticator data as text
+        let mut text = attestation.clone();
+        let position = text
+            .windows(3)
+            .position(|window| window == [0x59, 0x00, 0x94]);
+        text[position.unwrap()] = 0x79;
+        assert!(rp.verify_registration(&text).is_err());
+        // a byte string that claims to be longer than the data
+        assert!(Cbor::decode(&[0x5B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        // a negative integer that doesn't fit
+        assert!(Cbor::decode(&[0x3B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).is_err());
+        assert!(Cbor::decode(&[0xFF]).is_err());
+    }
+}
@@ -0,0 +1,15 @@
+//! Run CPU heavy work, like password hashing, on the blocking thread pool
+//!
+//! The async workers keep handling the other requests while the work is running.
+use actix_web::web;
+
+use crate::errors::ServiceError;
+
+/// run a blocking function on the thread pool and wait for the result
+pub async fn run<F, T>(f: F) -> Result<T, ServiceError>
+where
+    F: FnOnce() -> Result<T, ServiceError> + Send + 'static,
+    T: Send + 'static,
+{
+    Ok(web::block(f).await?)
+}
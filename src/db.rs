@@ -0,0 +1,132 @@
+//! Instrumented database transactions
+//!
+//! Every transaction gets a span tagged with the logical operation, the span stays open until the
+//! transaction is committed or rolled back, so the traces show how long the rows were locked.
+use std::ops::{Deref, DerefMut};
+
+use sqlx::{Pool, Postgres};
+use tracing::field;
+use tracing::Span;
+use tracing_futures::Instrument;
+
+/// The logical operation a transaction belongs to
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    CreateGame,
+    Purchase,
+    PriceUpdate,
+    ImportSales,
+    SyncSales,
+    VoidOrder,
+    SplitOrder,
+    RespondSplit,
+    CreateSeries,
+    MaterializeSeries,
+    SaveTemplate,
+    DeleteTemplate,
+    DeleteDraft,
+    SaveSlotGroup,
+    DeleteSlotGroup,
+    SaveCapacity,
+    AcceptInvitation,
+    LeaveGame,
+    ConvertInvitation,
+    DeleteTeam,
+    SavePrediction,
+    ResolvePredictions,
+    CreateUser,
+    DeletePasskey,
+    ExportUser,
+    JoinAsGuest,
+    PurgeGuest,
+    PurgeGame,
+}
+
+impl Operation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operation::CreateGame => "create_game",
+            Operation::Purchase => "purchase",
+            Operation::PriceUpdate => "price_update",
+            Operation::ImportSales => "import_sales",
+            Operation::SyncSales => "sync_sales",
+            Operation::VoidOrder => "void_order",
+            Operation::SplitOrder => "split_order",
+            Operation::RespondSplit => "respond_split",
+            Operation::CreateSeries => "create_series",
+            Operation::MaterializeSeries => "materialize_series",
+            Operation::SaveTemplate => "save_template",
+            Operation::DeleteTemplate => "delete_template",
+            Operation::DeleteDraft => "delete_draft",
+            Operation::SaveSlotGroup => "save_slot_group",
+            Operation::DeleteSlotGroup => "delete_slot_group",
+            Operation::SaveCapacity => "save_capacity",
+            Operation::AcceptInvitation => "accept_invitation",
+            Operation::LeaveGame => "leave_game",
+            Operation::ConvertInvitation => "convert_invitation",
+            Operation::DeleteTeam => "delete_team",
+            Operation::SavePrediction => "save_prediction",
+            Operation::ResolvePredictions => "resolve_predictions",
+            Operation::CreateUser => "create_user",
+            Operation::DeletePasskey => "delete_passkey",
+            Operation::ExportUser => "export_user",
+            Operation::JoinAsGuest => "join_as_guest",
+            Operation::PurgeGuest => "purge_guest",
+            Operation::PurgeGame => "purge_game",
+        }
+    }
+}
+
+/// A database transaction with its tracing span
+///
+/// Dereferences to the sqlx transaction, use `&mut *tx` to execute queries
+#[derive(Debug)]
+pub struct Transaction {
+    tx: sqlx::Transaction<'static, Postgres>,
+    span: Span,
+    rows: u64,
+}
+
+/// begin a transaction for a logical operation
+pub async fn begin(operation: Operation, db: &Pool<Postgres>) -> Result<Transaction, sqlx::Error> {
+    let span = tracing::info_span!(
+        "db.transaction",
+        operation = operation.as_str(),
+        rows = field::Empty
+    );
+
+    let tx = db
+        .begin()
+        .instrument(tracing::info_span!(parent: &span, "db.begin"))
+        .await?;
+
+    Ok(Transaction { tx, span, rows: 0 })
+}
+
+impl Transaction {
+    /// add the amount of rows read or written by a query to the span
+    pub fn record_rows(&mut self, rows: u64) {
+        self.rows += rows;
+        self.span.record("rows", &self.rows);
+    }
+
+    pub async fn commit(self) -> Result<(), sqlx::Error> {
+        let commit = tracing::info_span!(parent: &self.span, "db.commit");
+
+        self.tx.commit().instrument(commit).await
+    }
+}
+
+impl Deref for Transaction {
+    type Target = sqlx::Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for Transaction {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
@@ -1,44 +1,66 @@
 pub mod routes;
+mod wikimedia;
 
-use crate::cache::Cache;
+use crate::cache::{CacheHandle, CacheIdentifier};
 use crate::errors::ServiceError;
+use crate::http::HttpClient;
 
 use regex::Regex;
+use reqwest::StatusCode;
 
 const BASE_URI: &str = "https://duckduckgo.com";
 
+/// The maximum amount of images returned at once, DDG returns at most 100 results per page
+pub const MAX_LIMIT: usize = 100;
+
 #[derive(Debug)]
-pub struct Client {
+pub struct Client<'a> {
     token: Option<String>,
-    reqwest: reqwest::Client,
+    http: &'a HttpClient,
+    cache: &'a CacheHandle,
 }
 
-impl Client {
-    fn new() -> Self {
+impl<'a> Client<'a> {
+    fn new(http: &'a HttpClient, cache: &'a CacheHandle) -> Self {
         Client {
             token: None,
-            reqwest: reqwest::Client::new(),
+            http,
+            cache,
         }
     }
 
     /// fetch and set the duckduckgo request token
     /// This token is only valid for a specific request for a (currently unkown) amount of time
+    ///
+    /// The token is cached per query, set `refresh` to fetch a new one when the cached token expired
     #[tracing::instrument]
-    async fn acquire_token(&mut self, query: &str) -> Result<&Self, ServiceError> {
+    async fn acquire_token(&mut self, query: &str, refresh: bool) -> Result<&Self, ServiceError> {
+        let cache_key = query.trim().to_lowercase();
+
+        if !refresh {
+            if let Some(Token(token)) = self.cache.get(&cache_key).await {
+                self.token = Some(token);
+                return Ok(&*self);
+            }
+        }
+
         let resp = self
-            .reqwest
-            .get(BASE_URI)
-            .query(&[("q", query)])
-            .send()
+            .http
+            .send(self.http.get(BASE_URI).query(&[("q", query)]))
             .await?
             .text()
             .await?;
 
         match Client::find_token(&resp) {
-            Some(token) => self.token = Some(token),
+            Some(token) => {
+                self.cache.set(&Token(token.clone()), cache_key).await;
+                self.token = Some(token);
+            }
             None => {
                 error!("token not found in ddg request");
-                return Err(ServiceError::InternalServerError);
+                return Err(ServiceError::BadGateway(String::from(
+                    "unable to search images",
+                )));
             }
         }
 
@@ -59,47 +81,268 @@ impl Client {
             .map(|token| token.to_string())
     }
 
-    #[tracing::instrument]
-    pub async fn search_images(query: &str) -> Result<ImageResponse, ServiceError> {
-        if let Some(res) = Cache::get(query).await {
+    /// search images, skipping the first `offset` results and returning at most `limit` images
+    #[tracing::instrument(skip(http, cache))]
+    pub async fn search_images(
+        http: &HttpClient,
+        cache: &CacheHandle,
+        query: &str,
+        offset: usize,
+        limit: usize,
+        filter: &ImageFilter,
+    ) -> Result<ImageResponse, ServiceError> {
+        if limit == 0 || limit > MAX_LIMIT {
+            bad_request!(format!("the limit should be between 1 and {}", MAX_LIMIT));
+        }
+
+        let page = Client::new(http, cache)
+            .fetch_page(query, offset, filter)
+            .await?;
+
+        Ok(page.into_response(offset, limit, filter))
+    }
+
+    /// fetch the DDG result page starting at `offset`
+    async fn fetch_page(
+        mut self,
+        query: &str,
+        offset: usize,
+        filter: &ImageFilter,
+    ) -> Result<ImagePage, ServiceError> {
+        let cache_key = format!(
+            "{}.{}.{}.{}",
+            filter.safe_search.param(),
+            filter.layout.map(Layout::param).unwrap_or_default(),
+            offset,
+            query
+        );
+        if let Some(res) = self.cache.get(&cache_key).await {
             return Ok(res);
         }
 
-        let mut client = Client::new();
-        client.acquire_token(query).await?;
+        let res = match self.search_ddg(query, offset, filter).await {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("ddg image search failed, falling back to wikimedia: {}", e);
+                // the fallback results are not cached, so DDG is tried again next time
+                return wikimedia::search_images(self.http, query, offset)
+                    .await
+                    .map_err(|_| {
+                        ServiceError::BadGateway(String::from("image search is unavailable"))
+                    });
+            }
+        };
+
+        self.cache.set(&res, cache_key).await;
+
+        Ok(res)
+    }
+
+    /// search DDG, acquiring a new token and retrying once when the cached token is rejected
+    async fn search_ddg(
+        &mut self,
+        query: &str,
+        offset: usize,
+        filter: &ImageFilter,
+    ) -> Result<ImagePage, ServiceError> {
+        self.acquire_token(query, false).await?;
+        if let Some(page) = self.request_page(query, offset, filter).await? {
+            return Ok(page);
+        }
+
+        debug!("ddg token expired, retrying with a new token");
+        self.acquire_token(query, true).await?;
+        match self.request_page(query, offset, filter).await? {
+            Some(page) => Ok(page),
+            None => Err(ServiceError::BadGateway(String::from(
+                "ddg rejected a new token",
+            ))),
+        }
+    }
 
-        let res = client
-            .reqwest
+    /// request a page of images, returns `None` when DDG rejects the token
+    async fn request_page(
+        &self,
+        query: &str,
+        offset: usize,
+        filter: &ImageFilter,
+    ) -> Result<Option<ImagePage>, ServiceError> {
+        let token = self
+            .token
+            .as_deref()
+            .expect("By this point the DDG token should exist");
+
+        let request = self
+            .http
             .get(format!("{}/i.js", BASE_URI).as_str())
             .query(&[
                 ("l", "us-en"),
                 ("o", "json"),
-                (
-                    "vqd",
-                    client
-                        .token
-                        .expect("By this point the DDG token should exist")
-                        .as_str(),
-                ),
+                ("vqd", token),
                 ("q", query),
-            ])
-            .send()
-            .await?
-            .json::<ImageResponse>()
-            .await?;
+                ("s", offset.to_string().as_str()),
+                ("p", filter.safe_search.param()),
+                ("f", filter.provider_filters().as_str()),
+            ]);
+        let res = self.http.send(request).await?;
 
-        Cache::set(&res, res.query.clone()).await;
+        if res.status() == StatusCode::FORBIDDEN {
+            return Ok(None);
+        }
 
-        Ok(res)
+        Ok(Some(res.error_for_status()?.json::<ImagePage>().await?))
+    }
+}
+
+/// A DDG request token
+#[derive(Serialize, Deserialize)]
+struct Token(String);
+
+impl CacheIdentifier for Token {}
+
+/// A page of image results as returned by DDG
+#[derive(Serialize, Deserialize)]
+struct ImagePage {
+    query: String,
+    results: Vec<Image>,
+    /// the path to the next page, missing on the last page
+    #[serde(default)]
+    next: Option<String>,
+}
+
+impl CacheIdentifier for ImagePage {}
+
+impl ImagePage {
+    /// return at most `limit` images matching the filter
+    fn into_response(self, offset: usize, limit: usize, filter: &ImageFilter) -> ImageResponse {
+        let page_size = self.results.len();
+        let mut consumed = 0;
+        let mut results = Vec::new();
+
+        for image in self.results {
+            if results.len() == limit {
+                break;
+            }
+            consumed += 1;
+            if filter.matches(&image) {
+                results.push(image);
+            }
+        }
+
+        ImageResponse {
+            query: self.query,
+            offset,
+            next_offset: offset + consumed,
+            has_more: consumed < page_size || self.next.is_some(),
+            results,
+        }
     }
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ImageResponse {
     query: String,
+    offset: usize,
+    /// the offset of the next page, images that don't match the filters are skipped
+    next_offset: usize,
+    /// is true when more images can be fetched, starting at `next_offset`
+    has_more: bool,
     results: Vec<Image>,
 }
 
+/// How strict DDG should filter adult content
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SafeSearch {
+    Strict,
+    Moderate,
+    Off,
+}
+
+impl Default for SafeSearch {
+    fn default() -> Self {
+        SafeSearch::Strict
+    }
+}
+
+impl SafeSearch {
+    fn param(self) -> &'static str {
+        match self {
+            SafeSearch::Strict => "1",
+            SafeSearch::Moderate => "-1",
+            SafeSearch::Off => "-2",
+        }
+    }
+}
+
+/// The shape of an image
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum Layout {
+    Square,
+    Tall,
+    Wide,
+}
+
+impl Layout {
+    fn param(self) -> &'static str {
+        match self {
+            Layout::Square => "Square",
+            Layout::Tall => "Tall",
+            Layout::Wide => "Wide",
+        }
+    }
+
+    /// DDG's layout filter is not exact, so the results are checked again
+    fn matches(self, width: i32, height: i32) -> bool {
+        let ratio = f64::from(width) / f64::from(height);
+        match self {
+            Layout::Square => (0.8..=1.25).contains(&ratio),
+            Layout::Tall => ratio < 1.0,
+            Layout::Wide => ratio > 1.0,
+        }
+    }
+}
+
+/// Filters for the image search
+///
+/// The safe search level and layout are sent to DDG, the minimum dimensions are checked afterwards.
+#[derive(Debug, Default)]
+pub struct ImageFilter {
+    pub safe_search: SafeSearch,
+    pub min_width: Option<i32>,
+    pub min_height: Option<i32>,
+    pub layout: Option<Layout>,
+}
+
+impl ImageFilter {
+    /// the value of DDG's `f` parameter
+    fn provider_filters(&self) -> String {
+        match self.layout {
+            Some(layout) => format!(",,,layout:{},", layout.param()),
+            None => String::from(",,,,"),
+        }
+    }
+
+    fn matches(&self, image: &Image) -> bool {
+        if image.width <= 0 || image.height <= 0 {
+            return false;
+        }
+
+        let too_small =
+            |minimum: Option<i32>, size: i32| minimum.map(|min| size < min).unwrap_or(false);
+        let wrong_layout = self
+            .layout
+            .map(|layout| !layout.matches(image.width, image.height))
+            .unwrap_or(false);
+
+        !too_small(self.min_width, image.width)
+            && !too_small(self.min_height, image.height)
+            && !wrong_layout
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Image {
     width: i32,
@@ -121,6 +364,81 @@ impl From<reqwest::Error> for ServiceError {
 mod tests {
     use super::*;
 
+    fn image(width: i32, height: i32) -> Image {
+        Image {
+            width,
+            height,
+            url: String::from("https://example.com"),
+            source: String::from("Bing"),
+            title: String::from("duvel"),
+            image: String::from("https://example.com/duvel.jpg"),
+        }
+    }
+
+    fn page(results: usize, next: Option<&str>) -> ImagePage {
+        ImagePage {
+            query: String::from("duvel"),
+            results: (0..results)
+                .map(|i| Image {
+                    width: 100,
+                    height: 100,
+                    url: format!("https://example.com/{}", i),
+                    source: String::from("Bing"),
+                    title: String::from("duvel"),
+                    image: format!("https://example.com/{}.jpg", i),
+                })
+                .collect(),
+            next: next.map(String::from),
+        }
+    }
+
+    #[test]
+    fn paginate_images() {
+        let filter = ImageFilter::default();
+
+        let res = page(100, Some("i.js?q=duvel&s=100")).into_response(0, 20, &filter);
+        assert_eq!(res.results.len(), 20);
+        assert_eq!(res.next_offset, 20);
+        assert!(res.has_more);
+
+        let res = page(30, None).into_response(100, 50, &filter);
+        assert_eq!(res.results.len(), 30);
+        assert_eq!(res.offset, 100);
+        assert!(!res.has_more);
+
+        let res = page(60, None).into_response(100, 50, &filter);
+        assert!(res.has_more);
+    }
+
+    #[test]
+    fn filter_images() {
+        let filter = ImageFilter {
+            min_width: Some(200),
+            layout: Some(Layout::Square),
+            ..Default::default()
+        };
+
+        assert!(filter.matches(&image(300, 280)));
+        assert!(!filter.matches(&image(100, 100)));
+        assert!(!filter.matches(&image(600, 200)));
+        assert!(!filter.matches(&image(0, 0)));
+
+        let page = ImagePage {
+            query: String::from("duvel"),
+            results: vec![
+                image(50, 50),
+                image(300, 300),
+                image(10, 10),
+                image(400, 400),
+            ],
+            next: None,
+        };
+        let res = page.into_response(0, 1, &filter);
+        assert_eq!(res.results.len(), 1);
+        assert_eq!(res.next_offset, 2);
+        assert!(res.has_more);
+    }
+
     #[test]
     fn find_token() {
         let token = Client::find_token("nrj('/d.js?q=test&t=D&l=us-en&s=0&dl=en&ct=BE&ss_mkt=us&vqd=3-322225378556065850860803507288131703155-133178935652763664263271092398831973244&p_ent=&ex=-1&sp=0');");
@@ -1,20 +1,49 @@
 use crate::auth;
-use crate::ddg::Client;
-use crate::server::Response;
+use crate::auth::Identity;
+use crate::ddg::{Client, ImageFilter, Layout, SafeSearch, MAX_LIMIT};
+use crate::server::{Response, State};
 
-use actix_identity::Identity;
 use actix_web::{get, web};
 
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct Query {
     query: String,
+    #[serde(default)]
+    offset: usize,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    #[serde(default)]
+    safe_search: SafeSearch,
+    min_width: Option<i32>,
+    min_height: Option<i32>,
+    layout: Option<Layout>,
+}
+
+fn default_limit() -> usize {
+    MAX_LIMIT
 }
 
 #[get("/images")]
-async fn images(id: Identity, query: web::Query<Query>) -> Response {
+async fn images(id: Identity, query: web::Query<Query>, state: web::Data<State>) -> Response {
     auth::get_user(&id)?;
 
-    let res = Client::search_images(query.query.as_str()).await?;
+    let filter = ImageFilter {
+        safe_search: query.safe_search,
+        min_width: query.min_width,
+        min_height: query.min_height,
+        layout: query.layout,
+    };
+
+    let res = Client::search_images(
+        &state.http,
+        &state.cache,
+        query.query.as_str(),
+        query.offset,
+        query.limit,
+        &filter,
+    )
+    .await?;
 
     http_ok_json!(res)
 }
@@ -0,0 +1,126 @@
+//! Wikimedia Commons image search, used when DDG is unavailable
+use std::collections::HashMap;
+
+use crate::ddg::{Image, ImagePage};
+use crate::errors::ServiceError;
+use crate::http::HttpClient;
+
+const API_URI: &str = "https://commons.wikimedia.org/w/api.php";
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    #[serde(default, rename = "continue")]
+    next: Option<HashMap<String, serde_json::Value>>,
+    #[serde(default)]
+    query: Option<Pages>,
+}
+
+#[derive(Deserialize)]
+struct Pages {
+    pages: HashMap<String, Page>,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    index: usize,
+    title: String,
+    #[serde(default)]
+    imageinfo: Vec<ImageInfo>,
+}
+
+#[derive(Deserialize)]
+struct ImageInfo {
+    url: String,
+    descriptionurl: String,
+    width: i32,
+    height: i32,
+}
+
+impl SearchResponse {
+    fn into_page(self, query: &str) -> ImagePage {
+        let mut pages: Vec<Page> = self
+            .query
+            .map(|query| query.pages.into_values().collect())
+            .unwrap_or_default();
+        pages.sort_by_key(|page| page.index);
+
+        let results = pages
+            .into_iter()
+            .filter_map(|page| {
+                let title = page.title.trim_start_matches("File:").to_string();
+                page.imageinfo.into_iter().next().map(|info| Image {
+                    width: info.width,
+                    height: info.height,
+                    url: info.descriptionurl,
+                    source: String::from("Wikimedia Commons"),
+                    title,
+                    image: info.url,
+                })
+            })
+            .collect();
+
+        ImagePage {
+            query: query.to_string(),
+            results,
+            next: self.next.map(|_| String::from("continue")),
+        }
+    }
+}
+
+/// search the images on Wikimedia Commons
+///
+/// Commons has no safe search parameter, the size and layout filters are applied on the results
+#[tracing::instrument(skip(http))]
+pub(super) async fn search_images(
+    http: &HttpClient,
+    query: &str,
+    offset: usize,
+) -> Result<ImagePage, ServiceError> {
+    let request = http.get(API_URI).query(&[
+        ("action", "query"),
+        ("format", "json"),
+        ("generator", "search"),
+        ("gsrnamespace", "6"),
+        ("gsrsearch", format!("filetype:bitmap {}", query).as_str()),
+        ("gsrlimit", super::MAX_LIMIT.to_string().as_str()),
+        ("gsroffset", offset.to_string().as_str()),
+        ("prop", "imageinfo"),
+        ("iiprop", "url|size"),
+    ]);
+
+    let res = http
+        .send(request)
+        .await?
+        .error_for_status()?
+        .json::<SearchResponse>()
+        .await?;
+
+    Ok(res.into_page(query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_search_response() {
+        let res: SearchResponse = serde_json::from_str(
+            r#"{
+                "continue": {"gsroffset": 2, "continue": "gsroffset||"},
+                "query": {"pages": {
+                    "12": {"pageid": 12, "ns": 6, "title": "File:Duvel glass.jpg", "index": 2,
+                        "imageinfo": [{"url": "https://upload.wikimedia.org/duvel.jpg", "descriptionurl": "https://commons.wikimedia.org/wiki/File:Duvel_glass.jpg", "width": 800, "height": 1200}]},
+                    "7": {"pageid": 7, "ns": 6, "title": "File:Duvel.png", "index": 1,
+                        "imageinfo": [{"url": "https://upload.wikimedia.org/duvel.png", "descriptionurl": "https://commons.wikimedia.org/wiki/File:Duvel.png", "width": 400, "height": 400}]}
+                }}
+            }"#,
+        )
+        .unwrap();
+
+        let page = res.into_page("duvel");
+        assert_eq!(page.results.len(), 2);
+        assert_eq!(page.results[0].title, "Duvel.png");
+        assert_eq!(page.results[1].height, 1200);
+        assert!(page.next.is_some());
+    }
+}
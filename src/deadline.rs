@@ -0,0 +1,147 @@
+//! Request deadlines
+//!
+//! A handler that doesn't respond before the deadline is cancelled and the client gets a 503.
+//! Dropping the handler cancels the database queries and outbound HTTP requests it's waiting for,
+//! so requests don't pile up behind a database that hiccups. actix already drops the handler
+//! when the client disconnects. The deadline only covers the response head,
+//! streamed bodies and websocket connections can take as long as they need.
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use crate::errors::ServiceError;
+use crate::pool::RETRY_AFTER;
+
+/// the amount of requests that were cancelled, since the server started
+static EXCEEDED: AtomicUsize = AtomicUsize::new(0);
+
+/// the amount of requests that didn't respond before their deadline
+pub fn exceeded() -> usize {
+    EXCEEDED.load(Ordering::Relaxed)
+}
+
+/// Cancel the requests that take longer than `timeout`, requests don't have a deadline without a timeout
+pub struct Middleware {
+    timeout: Option<Duration>,
+}
+
+impl Middleware {
+    pub fn new(timeout: Option<Duration>) -> Middleware {
+        Middleware { timeout }
+    }
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = DeadlineMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(DeadlineMiddleware {
+            service,
+            timeout: self.timeout,
+        })
+    }
+}
+
+pub struct DeadlineMiddleware<S> {
+    service: S,
+    timeout: Option<Duration>,
+}
+
+impl<S, B> Service for DeadlineMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Box::pin(self.service.call(request)),
+        };
+
+        let (method, path) = (request.method().clone(), request.path().to_string());
+        let fut = self.service.call(request);
+
+        Box::pin(async move {
+            match actix_rt::time::timeout(timeout, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    EXCEEDED.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "{} {} was cancelled after {} seconds",
+                        method,
+                        path,
+                        timeout.as_secs()
+                    );
+
+                    // the request is owned by the cancelled handler, so actix turns the error into the response
+                    Err(ServiceError::ServiceUnavailable(RETRY_AFTER).into())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn cancelled_requests() {
+        let mut srv = test::init_service(
+            App::new()
+                .wrap(Middleware::new(Some(Duration::from_millis(50))))
+                .service(web::resource("/fast").to(HttpResponse::Ok))
+                .service(web::resource("/slow").to(|| async {
+                    actix_rt::time::delay_for(Duration::from_secs(5)).await;
+                    "ok"
+                })),
+        )
+        .await;
+
+        let resp = test::call_service(&mut srv, TestRequest::with_uri("/fast").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let before = exceeded();
+        let error = srv
+            .call(TestRequest::with_uri("/slow").to_request())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.as_response_error().error_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(exceeded(), before + 1);
+    }
+}
@@ -4,6 +4,9 @@ use derive_more::Display;
 use redis::RedisError;
 use std::convert::From;
 
+use crate::quota::Usage;
+use crate::transactions::Transaction;
+
 #[derive(Debug, Display)]
 pub enum ServiceError {
     #[display(fmt = "Internal Server Error")]
@@ -26,6 +29,37 @@ pub enum ServiceError {
 
     #[display(fmt = "Payload Too Large")]
     PayloadTooLarge,
+
+    #[display(fmt = "Bad Gateway: {}", _0)]
+    BadGateway(String),
+
+    /// The server is overloaded, contains the amount of seconds after which the client can retry
+    #[display(fmt = "Service Unavailable")]
+    ServiceUnavailable(u64),
+
+    /// The client sent too many requests in a short time, contains the amount of seconds after which it can retry
+    #[display(fmt = "Too Many Requests: rate limited")]
+    RateLimited(u64),
+
+    /// The user used up the daily request quota
+    #[display(fmt = "Too Many Requests: daily quota exceeded")]
+    QuotaExceeded(Usage),
+
+    /// An identical order was placed moments ago, contains the transactions of that order
+    #[display(fmt = "Conflict: duplicate order")]
+    DuplicateOrder(Vec<Transaction>),
+}
+
+#[derive(Serialize)]
+struct DuplicateOrderResponse<'a> {
+    message: &'a str,
+    order: &'a [Transaction],
+}
+
+#[derive(Serialize)]
+struct QuotaExceededResponse<'a> {
+    message: &'a str,
+    usage: &'a Usage,
 }
 
 // impl ResponseError trait allows to convert our errors into http responses with appropriate data
@@ -43,6 +77,29 @@ impl ResponseError for ServiceError {
             ServiceError::PayloadTooLarge => {
                 HttpResponse::PayloadTooLarge().json("Payload Too Large")
             }
+            ServiceError::BadGateway(ref message) => HttpResponse::BadGateway().json(message),
+            ServiceError::ServiceUnavailable(retry_after) => HttpResponse::ServiceUnavailable()
+                .header("Retry-After", retry_after.to_string())
+                .json("The server is busy, please try again later"),
+            ServiceError::RateLimited(retry_after) => HttpResponse::TooManyRequests()
+                .header("Retry-After", retry_after.to_string())
+                .json("too many requests, please slow down"),
+            ServiceError::QuotaExceeded(ref usage) => {
+                let mut response = HttpResponse::TooManyRequests()
+                    .header("Retry-After", usage.retry_after().to_string())
+                    .json(QuotaExceededResponse {
+                        message: "the daily request quota is exceeded",
+                        usage,
+                    });
+                usage.set_headers(response.headers_mut());
+                response
+            }
+            ServiceError::DuplicateOrder(ref order) => {
+                HttpResponse::Conflict().json(DuplicateOrderResponse {
+                    message: "an identical order was placed moments ago",
+                    order,
+                })
+            }
         }
     }
 }
@@ -109,6 +166,11 @@ impl From<sqlx::Error> for ServiceError {
                 err.downcast_ref::<sqlx::postgres::PgDatabaseError>().into()
             }
             sqlx::Error::RowNotFound => ServiceError::NotFound,
+            sqlx::Error::PoolTimedOut => {
+                warn!("timed out waiting for a database connection");
+                crate::pool::add_timeout();
+                ServiceError::ServiceUnavailable(crate::pool::RETRY_AFTER)
+            }
             _ => ServiceError::InternalServerError,
         }
     }
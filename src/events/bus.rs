@@ -0,0 +1,132 @@
+//! In-process event bus
+//!
+//! Producers publish what happened without knowing who is interested,
+//! consumers (like the websocket server) subscribe to the bus and pick the events they need.
+use tokio::sync::broadcast;
+
+use crate::auth::activity::SuspiciousLogin;
+use crate::games::blackouts::Blackout;
+use crate::games::Game;
+use crate::teams::TeamLeaderboard;
+use crate::transactions::drink_limits::DrinkWarning;
+use crate::transactions::splits::OrderSplit;
+use crate::websocket::server::{GameId, PriceUpdate, Sale, SuspiciousPurchases};
+
+/// The amount of events a slow subscriber can lag behind before it starts missing events
+const CAPACITY: usize = 1024;
+
+/// Something that happened in the application
+///
+/// Not every event has a subscriber yet
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub enum DomainEvent {
+    /// Someone purchased beverages
+    SaleCreated(Sale),
+    /// An order was voided, contains the compensating transactions
+    SaleVoided(Sale),
+    /// A player placed an order in the app, the bar prints a ticket for it
+    OrderPlaced {
+        game_id: GameId,
+        order_id: i64,
+    },
+    /// The prices of a game have been updated, possibly by another instance
+    PricesUpdated(PriceUpdate),
+    /// A user has been invited for a game
+    InvitationCreated {
+        game_id: GameId,
+        user_id: i64,
+    },
+    /// A user accepted or declined an invitation
+    InvitationResponded {
+        game_id: GameId,
+        user_id: i64,
+        accepted: bool,
+    },
+    GameCreated(Game),
+    GameUpdated(Game),
+    GameDeleted(GameId),
+    /// A user is buying a lot more than the other players
+    SuspiciousPurchases(SuspiciousPurchases),
+    /// The price predictions of a game have been resolved after a price update
+    PredictionsResolved(GameId),
+    /// A co-payer is asked to pay a share of an order
+    SplitRequested(OrderSplit),
+    /// A co-payer accepted or declined their share, or the request expired
+    SplitResolved(OrderSplit),
+    /// The game owner revoked a display device
+    DeviceRevoked {
+        game_id: GameId,
+        device_id: i64,
+    },
+    /// A user logged in from a new device or address, or failed to log in repeatedly
+    SuspiciousLogin(SuspiciousLogin),
+    /// A waitlisted user got a place in a full game, their invitation is accepted
+    WaitlistPromoted {
+        game_id: GameId,
+        user_id: i64,
+    },
+    /// A game stopped accepting purchases for a while
+    BlackoutStarted(Blackout),
+    /// A game accepts purchases again, or the owner cancelled the blackout
+    BlackoutEnded(Blackout),
+    /// The scoreboard of the teams of a game changed after a sale
+    TeamScoresUpdated(TeamLeaderboard),
+    /// A player bought more alcoholic drinks than the drink limit of the game
+    DrinkLimitExceeded(DrinkWarning),
+}
+
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<DomainEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+
+        EventBus { sender }
+    }
+
+    /// Share an event with all current subscribers
+    pub fn publish(&self, event: DomainEvent) {
+        if self.sender.send(event).is_err() {
+            debug!("an event was published without any subscribers");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DomainEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_rt::test]
+    async fn every_subscriber_receives_the_events() {
+        let bus = EventBus::new();
+
+        // publishing without subscribers shouldn't fail
+        bus.publish(DomainEvent::GameDeleted(GameId(0)));
+
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+
+        bus.publish(DomainEvent::GameDeleted(GameId(1)));
+
+        for receiver in [&mut first, &mut second].iter_mut() {
+            match receiver.recv().await {
+                Ok(DomainEvent::GameDeleted(GameId(1))) => (),
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+    }
+}
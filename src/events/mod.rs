@@ -0,0 +1,5 @@
+mod bus;
+mod models;
+pub mod routes;
+pub use bus::{DomainEvent, EventBus};
+pub use models::{EventType, GameEvent};
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+/// The kind of event that happened during a game
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "game_event_type", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventType {
+    /// A user is buying a lot more than the other players
+    SuspiciousPurchases,
+    /// A player bought more alcoholic drinks than the drink limit of the game
+    DrinkLimitExceeded,
+}
+
+/// An entry in the event log of a game
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameEvent {
+    pub id: i64,
+    pub game_id: i64,
+    pub user_id: Option<i64>,
+    pub event_type: EventType,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl GameEvent {
+    #[tracing::instrument(name = "GameEvent::create")]
+    pub async fn create(
+        game_id: i64,
+        user_id: Option<i64>,
+        event_type: EventType,
+        description: String,
+        db: &Pool<Postgres>,
+    ) -> Result<GameEvent, sqlx::Error> {
+        sqlx::query_as!(
+            GameEvent,
+            r#"
+            INSERT INTO game_events (game_id, user_id, event_type, description)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, game_id, user_id, event_type as "event_type: EventType", description, created_at
+            "#,
+            game_id,
+            user_id,
+            event_type as _,
+            description
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// returns the event log of a game, most recent events first
+    #[tracing::instrument(name = "GameEvent::find_by_game")]
+    pub async fn find_by_game(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<GameEvent>, sqlx::Error> {
+        sqlx::query_as!(
+            GameEvent,
+            r#"
+            SELECT id, game_id, user_id, event_type as "event_type: EventType", description, created_at
+            FROM game_events
+            WHERE game_id = $1
+            ORDER BY created_at DESC
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// returns true when a user has been flagged with this event type in the last `seconds`
+    #[tracing::instrument(name = "GameEvent::recently_flagged")]
+    pub async fn recently_flagged(
+        game_id: i64,
+        user_id: i64,
+        event_type: EventType,
+        seconds: i32,
+        db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ) -> Result<bool, sqlx::Error> {
+        let res = sqlx::query!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM game_events
+                WHERE game_id = $1
+                AND user_id = $2
+                AND event_type = $3
+                AND created_at > NOW() - make_interval(secs => $4::int)
+            ) as "flagged!"
+            "#,
+            game_id,
+            user_id,
+            event_type as _,
+            seconds
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(res.flagged)
+    }
+}
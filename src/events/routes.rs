@@ -0,0 +1,27 @@
+use actix_web::web::{Data, Path};
+use actix_web::{get, web};
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::events::GameEvent;
+use crate::games::Game;
+use crate::server::{self, State};
+
+/// show the event log of a game, only available for the game owner and administrators
+#[get("/games/{id}/events")]
+async fn find_by_game(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = Game::find_by_id(*game_id, &state.db).await?;
+    if game.owner_id != user.id && !user.is_admin {
+        forbidden!("Only game owners can view the event log");
+    }
+
+    let events = GameEvent::find_by_game(game.id, &state.db).await?;
+
+    http_ok_json!(events);
+}
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_by_game);
+}
@@ -0,0 +1,62 @@
+//! The game a client operates on
+//!
+//! Kiosk clients only ever operate on one game. They store it in the session once,
+//! and use `current` instead of the game id in the paths of the sales and stats endpoints.
+
+use std::convert::TryFrom;
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::errors::ServiceError;
+
+const CURRENT: &str = "current";
+
+/// The game in a path, a game id or `current` for the active game of the session
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub enum GameParam {
+    Current,
+    Id(i64),
+}
+
+impl TryFrom<String> for GameParam {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value == CURRENT {
+            return Ok(GameParam::Current);
+        }
+
+        value
+            .parse()
+            .map(GameParam::Id)
+            .map_err(|_| format!("expected a game id or `{}`", CURRENT))
+    }
+}
+
+impl GameParam {
+    /// the id of the game, the active game is read from the session
+    pub fn resolve(&self, id: &Identity) -> Result<i64, ServiceError> {
+        match self {
+            GameParam::Id(game_id) => Ok(*game_id),
+            GameParam::Current => auth::active_game(id)?.ok_or_else(|| {
+                ServiceError::BadRequest("there is no active game, select one first".to_string())
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_param() {
+        assert_eq!(
+            GameParam::try_from("current".to_string()),
+            Ok(GameParam::Current)
+        );
+        assert_eq!(GameParam::try_from("42".to_string()), Ok(GameParam::Id(42)));
+        assert!(GameParam::try_from("latest".to_string()).is_err());
+    }
+}
@@ -0,0 +1,278 @@
+//! Windows in which a game doesn't accept purchases, like during a quiz round
+//!
+//! The game owner plans a blackout ahead or starts one right away,
+//! purchases are rejected while it lasts and the market shows the current blackout.
+//! The scheduler tells the players in the game room when a blackout starts and ends.
+
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::cache::CacheHandle;
+use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+
+/// how often the scheduler looks for blackouts that started or ended
+const SCHEDULE_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+const MAX_REASON_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Blackout {
+    pub id: i64,
+    pub game_id: i64,
+    /// shown to the players, like "quiz round"
+    pub reason: Option<String>,
+    pub start_time: DateTime<Utc>,
+    pub end_time: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewBlackout {
+    pub reason: Option<String>,
+    /// empty starts the blackout right away
+    pub start_time: Option<DateTime<Utc>>,
+    pub end_time: DateTime<Utc>,
+}
+
+impl crate::validator::Validate<NewBlackout> for NewBlackout {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if let Some(reason) = &self.reason {
+            if reason.chars().count() > MAX_REASON_LENGTH {
+                bad_request!("the reason of a blackout can't be longer than 100 characters");
+            }
+        }
+
+        if self.end_time <= Utc::now() {
+            bad_request!("a blackout should end in the future");
+        }
+
+        if let Some(start_time) = self.start_time {
+            if start_time >= self.end_time {
+                bad_request!("a blackout should start before it ends");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl NewBlackout {
+    #[tracing::instrument(name = "NewBlackout::save", skip(db))]
+    pub async fn save(&self, game_id: i64, db: &Pool<Postgres>) -> Result<Blackout, sqlx::Error> {
+        let start_time = self.start_time.unwrap_or_else(Utc::now);
+
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            INSERT INTO purchase_blackouts (game_id, reason, start_time, end_time)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, game_id, reason, start_time, end_time, created_at
+            "#,
+            game_id,
+            self.reason,
+            start_time,
+            self.end_time
+        )
+        .fetch_one(db)
+        .await
+    }
+}
+
+impl Blackout {
+    /// the current and upcoming blackouts of a game, the first one first
+    #[tracing::instrument(name = "Blackout::find", skip(db))]
+    pub async fn find(game_id: i64, db: &Pool<Postgres>) -> Result<Vec<Blackout>, sqlx::Error> {
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            SELECT id, game_id, reason, start_time, end_time, created_at
+            FROM purchase_blackouts
+            WHERE game_id = $1 AND end_time > NOW()
+            ORDER BY start_time, id
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// the blackout the game is in right now, the one that lasts longest when they overlap
+    #[tracing::instrument(name = "Blackout::active", skip(db))]
+    pub async fn active(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<Blackout>, sqlx::Error> {
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            SELECT id, game_id, reason, start_time, end_time, created_at
+            FROM purchase_blackouts
+            WHERE game_id = $1 AND start_time <= NOW() AND end_time > NOW()
+            ORDER BY end_time DESC
+            LIMIT 1
+            "#,
+            game_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// the blackout the game was in at a moment, like when a kiosk queued a sale while it was offline
+    #[tracing::instrument(name = "Blackout::at", skip(db))]
+    pub async fn at(
+        game_id: i64,
+        at: DateTime<Utc>,
+        db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ) -> Result<Option<Blackout>, sqlx::Error> {
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            SELECT id, game_id, reason, start_time, end_time, created_at
+            FROM purchase_blackouts
+            WHERE game_id = $1 AND start_time <= $2 AND end_time > $2
+            ORDER BY end_time DESC
+            LIMIT 1
+            "#,
+            game_id,
+            at
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Remove a blackout of a game, returns it when it was going on
+    #[tracing::instrument(name = "Blackout::delete", skip(db))]
+    pub async fn delete(
+        game_id: i64,
+        blackout_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<Blackout>, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            DELETE FROM purchase_blackouts
+            WHERE id = $1 AND game_id = $2
+            RETURNING id, game_id, reason, start_time, end_time, created_at,
+                started_notified_at, ended_notified_at
+            "#,
+            blackout_id,
+            game_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        // the players were told it started, but not yet that it ended
+        if row.started_notified_at.is_none() || row.ended_notified_at.is_some() {
+            return Ok(None);
+        }
+
+        Ok(Some(Blackout {
+            id: row.id,
+            game_id: row.game_id,
+            reason: row.reason,
+            start_time: row.start_time,
+            end_time: row.end_time,
+            created_at: row.created_at,
+        }))
+    }
+
+    /// Claim the blackouts that started since the last round, so only one instance announces them
+    async fn claim_started(db: &Pool<Postgres>) -> Result<Vec<Blackout>, sqlx::Error> {
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            UPDATE purchase_blackouts SET started_notified_at = NOW()
+            WHERE start_time <= NOW() AND end_time > NOW() AND started_notified_at IS NULL
+            RETURNING id, game_id, reason, start_time, end_time, created_at
+            "#
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Claim the blackouts that ended since the last round
+    ///
+    /// A blackout shorter than the schedule interval only gets an end notification.
+    async fn claim_ended(db: &Pool<Postgres>) -> Result<Vec<Blackout>, sqlx::Error> {
+        sqlx::query_as!(
+            Blackout,
+            r#"
+            UPDATE purchase_blackouts
+            SET ended_notified_at = NOW(), started_notified_at = COALESCE(started_notified_at, NOW())
+            WHERE end_time <= NOW() AND ended_notified_at IS NULL
+            RETURNING id, game_id, reason, start_time, end_time, created_at
+            "#
+        )
+        .fetch_all(db)
+        .await
+    }
+}
+
+/// Tell the players when a blackout starts or ends
+pub fn schedule(db: Pool<Postgres>, events: EventBus, cache: CacheHandle) {
+    actix_rt::spawn(async move {
+        loop {
+            actix_rt::time::delay_for(SCHEDULE_INTERVAL).await;
+
+            match cache.lock("blackouts", SCHEDULE_INTERVAL).await {
+                Some(lock) => {
+                    announce(&db, &events).await;
+                    lock.release().await;
+                }
+                None => debug!("the blackouts are announced by another instance"),
+            }
+        }
+    });
+}
+
+async fn announce(db: &Pool<Postgres>, events: &EventBus) {
+    match Blackout::claim_started(db).await {
+        Ok(started) => started
+            .into_iter()
+            .for_each(|blackout| events.publish(DomainEvent::BlackoutStarted(blackout))),
+        Err(e) => error!("unable to find the blackouts that started: {}", e),
+    }
+
+    match Blackout::claim_ended(db).await {
+        Ok(ended) => ended
+            .into_iter()
+            .for_each(|blackout| events.publish(DomainEvent::BlackoutEnded(blackout))),
+        Err(e) => error!("unable to find the blackouts that ended: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Validate;
+    use chrono::Duration;
+
+    fn blackout(start: Option<i64>, end: i64) -> NewBlackout {
+        let now = Utc::now();
+
+        NewBlackout {
+            reason: Some(String::from("quiz round")),
+            start_time: start.map(|minutes| now + Duration::minutes(minutes)),
+            end_time: now + Duration::minutes(end),
+        }
+    }
+
+    #[test]
+    fn validation() {
+        assert!(blackout(None, 15).validate().is_ok());
+        assert!(blackout(Some(-5), 15).validate().is_ok());
+        assert!(blackout(Some(10), 15).validate().is_ok());
+
+        assert!(blackout(None, -1).validate().is_err());
+        assert!(blackout(Some(15), 15).validate().is_err());
+        assert!(blackout(Some(20), 15).validate().is_err());
+
+        let mut long = blackout(None, 15);
+        long.reason = Some("a".repeat(101));
+        assert!(long.validate().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! The currency of a game
+//!
+//! The prices are stored as integers in the minor unit of the currency, like cents for euros.
+//! The market rounds the prices to the price step of the game, so a bar that only takes
+//! coins of 50 cents doesn't end up with a price of 2.30.
+
+use crate::validator::Violations;
+
+pub const DEFAULT_CURRENCY: &str = "EUR";
+/// in the minor unit of the currency
+pub const DEFAULT_PRICE_STEP: i64 = 10;
+const MAX_PRICE_STEP: i64 = 10_000;
+
+/// the active ISO 4217 currency codes
+const CURRENCIES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD", "CAD",
+    "CDF", "CHF", "CLP", "CNY", "COP", "CRC", "CUP", "CVE", "CZK", "DJF", "DKK", "DOP", "DZD",
+    "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP", "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ",
+    "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS", "INR", "IQD", "IRR", "ISK", "JMD", "JOD",
+    "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW", "KWD", "KYD", "KZT", "LAK", "LBP", "LKR",
+    "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD", "MMK", "MNT", "MOP", "MRU", "MUR", "MVR",
+    "MWK", "MXN", "MYR", "MZN", "NAD", "NGN", "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN",
+    "PGK", "PHP", "PKR", "PLN", "PYG", "QAR", "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR",
+    "SDG", "SEK", "SGD", "SHP", "SLE", "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB",
+    "TJS", "TMT", "TND", "TOP", "TRY", "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "UYU", "UZS",
+    "VED", "VES", "VND", "VUV", "WST", "XAF", "XCD", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWL",
+    "BOV", "CHE", "CHW", "CLF", "COU", "CUC", "MXV", "SLL", "USN", "UYI", "UYW", "XCG", "XDR",
+    "XSU", "XUA", "ZWG",
+];
+
+pub fn default_currency() -> String {
+    DEFAULT_CURRENCY.to_string()
+}
+
+pub fn default_price_step() -> i64 {
+    DEFAULT_PRICE_STEP
+}
+
+/// true for an ISO 4217 code in capitals, like `EUR`
+pub fn is_known(currency: &str) -> bool {
+    CURRENCIES.contains(&currency)
+}
+
+/// round a price to the nearest multiple of the price step, halfway rounds up
+pub fn round(price: i64, price_step: i64) -> i64 {
+    let remainder = price.rem_euclid(price_step);
+    if remainder * 2 >= price_step {
+        price + (price_step - remainder)
+    } else {
+        price - remainder
+    }
+}
+
+/// The currency and the price step of a game
+pub struct Pricing<'a> {
+    pub currency: &'a str,
+    pub price_step: i64,
+}
+
+impl<'a> Pricing<'a> {
+    /// add the violations of the pricing
+    pub fn check(&self, violations: &mut Violations) {
+        violations.check(
+            is_known(self.currency),
+            "currency",
+            "unknown currency, use an ISO 4217 code like EUR",
+        );
+        violations.check(
+            self.price_step > 0 && self.price_step <= MAX_PRICE_STEP,
+            "priceStep",
+            format!("the price step should be between 1 and {}", MAX_PRICE_STEP),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding() {
+        assert_eq!(round(254, 10), 250);
+        assert_eq!(round(255, 10), 260);
+        assert_eq!(round(230, 50), 250);
+        assert_eq!(round(224, 50), 200);
+        assert_eq!(round(1234, 1), 1234);
+    }
+
+    #[test]
+    fn pricing() {
+        let valid = |currency, price_step| {
+            let mut violations = Violations::default();
+            Pricing {
+                currency,
+                price_step,
+            }
+            .check(&mut violations);
+            violations.into_inner().is_empty()
+        };
+
+        assert!(valid("EUR", 10));
+        assert!(valid("JPY", 50));
+
+        assert!(!valid("eur", 10));
+        assert!(!valid("XYZ", 10));
+        assert!(!valid("EUR", 0));
+        assert!(!valid("EUR", MAX_PRICE_STEP + 1));
+    }
+}
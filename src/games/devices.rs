@@ -0,0 +1,306 @@
+//! The kiosk screens of a game
+//!
+//! A tablet behind the bar shouldn't be logged in as the game owner, so the owner registers
+//! it as a device. The device token only gives access to the websocket and the display endpoints
+//! of that game, and the owner can revoke it when the tablet goes missing.
+//! A printer bridge is a device that also receives the new orders, so the bar gets paper tickets.
+
+use actix_web::web::Query;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::errors::ServiceError;
+use crate::users::User;
+
+/// the header that contains the device token
+pub const TOKEN_HEADER: &str = "X-Device-Token";
+
+const MAX_DEVICE_NAME: usize = 50;
+
+/// A registered display device
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Device {
+    pub id: i64,
+    pub game_id: i64,
+    pub name: String,
+    /// `display` or `printer`
+    pub kind: String,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDevice {
+    pub name: String,
+    #[serde(default)]
+    pub kind: DeviceKind,
+}
+
+/// What a device is used for
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceKind {
+    /// shows the market of the game
+    Display,
+    /// prints a ticket for every new order
+    Printer,
+}
+
+impl Default for DeviceKind {
+    fn default() -> Self {
+        DeviceKind::Display
+    }
+}
+
+impl DeviceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceKind::Display => "display",
+            DeviceKind::Printer => "printer",
+        }
+    }
+}
+
+/// A device with its token, the token is only shown when the device is registered
+#[derive(Debug, Serialize)]
+pub struct RegisteredDevice {
+    #[serde(flatten)]
+    pub device: Device,
+    pub token: String,
+}
+
+/// An authenticated device and the owner of its game
+#[derive(Debug, Clone)]
+pub struct DisplayDevice {
+    pub device: Device,
+    pub owner_id: i64,
+}
+
+/// Who's watching a display endpoint
+#[derive(Debug)]
+pub enum Viewer {
+    User(User),
+    Device(DisplayDevice),
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    device_token: String,
+}
+
+impl crate::validator::Validate<NewDevice> for NewDevice {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.name.trim().is_empty() {
+            bad_request!("the device name is too short");
+        }
+        if self.name.trim().len() > MAX_DEVICE_NAME {
+            bad_request!("the device name is too long, maximum 50 characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl Device {
+    #[tracing::instrument(name = "Device::register", skip(db))]
+    pub async fn register(
+        game_id: i64,
+        device: &NewDevice,
+        db: &Pool<Postgres>,
+    ) -> Result<RegisteredDevice, sqlx::Error> {
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        let device = sqlx::query_as!(
+            Device,
+            r#"
+            INSERT INTO game_devices (game_id, name, kind, token)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, game_id, name, kind, created_at, last_seen_at, revoked_at
+            "#,
+            game_id,
+            device.name.trim(),
+            device.kind.as_str(),
+            token
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(RegisteredDevice { device, token })
+    }
+
+    /// the devices of a game that haven't been revoked
+    #[tracing::instrument(name = "Device::find_active", skip(db))]
+    pub async fn find_active(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Device>, sqlx::Error> {
+        sqlx::query_as!(
+            Device,
+            r#"
+            SELECT id, game_id, name, kind, created_at, last_seen_at, revoked_at
+            FROM game_devices
+            WHERE game_id = $1 AND revoked_at IS NULL
+            ORDER BY last_seen_at DESC NULLS LAST, id
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// the token of a revoked device stops working immediately
+    #[tracing::instrument(name = "Device::revoke", skip(db))]
+    pub async fn revoke(
+        game_id: i64,
+        device_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Device, sqlx::Error> {
+        sqlx::query_as!(
+            Device,
+            r#"
+            UPDATE game_devices SET revoked_at = NOW()
+            WHERE id = $1 AND game_id = $2 AND revoked_at IS NULL
+            RETURNING id, game_id, name, kind, created_at, last_seen_at, revoked_at
+            "#,
+            device_id,
+            game_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Find the device of a token for a game, and remember when it was last seen
+    #[tracing::instrument(name = "Device::authenticate", skip(token, db))]
+    pub async fn authenticate(
+        game_id: i64,
+        token: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<DisplayDevice, ServiceError> {
+        let row = sqlx::query!(
+            r#"
+            UPDATE game_devices SET last_seen_at = NOW()
+            FROM games
+            WHERE game_devices.token = $1 AND game_devices.game_id = $2
+                AND game_devices.revoked_at IS NULL AND games.id = game_devices.game_id
+            RETURNING game_devices.id, game_devices.game_id, game_devices.name, game_devices.kind, game_devices.created_at,
+                game_devices.last_seen_at, game_devices.revoked_at, games.owner_id
+            "#,
+            token,
+            game_id
+        )
+        .fetch_one(db)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => ServiceError::Unauthorized,
+            e => e.into(),
+        })?;
+
+        Ok(DisplayDevice {
+            device: Device {
+                id: row.id,
+                game_id: row.game_id,
+                name: row.name,
+                kind: row.kind,
+                created_at: row.created_at,
+                last_seen_at: row.last_seen_at,
+                revoked_at: row.revoked_at,
+            },
+            owner_id: row.owner_id,
+        })
+    }
+}
+
+impl DisplayDevice {
+    pub fn is_printer(&self) -> bool {
+        self.device.kind == DeviceKind::Printer.as_str()
+    }
+
+    /// The device acts as a read-only version of the game owner, so it sees the owner's prices
+    pub fn as_user(&self) -> User {
+        User {
+            id: self.owner_id,
+            username: format!("{} (display)", self.device.name),
+            password: String::new(),
+            is_admin: false,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+}
+
+/// The device token of a request, from the header or the `device_token` query parameter
+///
+/// Browsers can't set headers on a websocket, so those pass the token in the url.
+pub fn token(req: &HttpRequest) -> Option<String> {
+    if let Some(token) = req.headers().get(TOKEN_HEADER) {
+        return token.to_str().ok().map(String::from);
+    }
+
+    Query::<TokenQuery>::from_query(req.query_string())
+        .ok()
+        .map(|query| query.into_inner().device_token)
+}
+
+impl Viewer {
+    /// a request with a device token is always handled as that device
+    pub async fn identify(
+        game_id: i64,
+        req: &HttpRequest,
+        id: &Identity,
+        db: &Pool<Postgres>,
+    ) -> Result<Viewer, ServiceError> {
+        match token(req) {
+            Some(token) => Ok(Viewer::Device(
+                Device::authenticate(game_id, &token, db).await?,
+            )),
+            None => Ok(Viewer::User(auth::get_user(id)?)),
+        }
+    }
+
+    /// the user whose beverages are shown
+    pub fn user_id(&self) -> i64 {
+        match self {
+            Viewer::User(user) => user.id,
+            Viewer::Device(device) => device.owner_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Validate;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn invalid_device_name() {
+        let device = |name: &str| NewDevice {
+            name: name.to_string(),
+            kind: DeviceKind::Display,
+        };
+
+        assert!(device("bar tablet").validate().is_ok());
+        assert!(device("  ").validate().is_err());
+        assert!(device(&"a".repeat(MAX_DEVICE_NAME + 1)).validate().is_err());
+    }
+
+    #[test]
+    fn device_token() {
+        let req = TestRequest::default()
+            .header(TOKEN_HEADER, "from-header")
+            .to_http_request();
+        assert_eq!(token(&req), Some(String::from("from-header")));
+
+        let req = TestRequest::with_uri("/ws/game/1?device_token=from-query").to_http_request();
+        assert_eq!(token(&req), Some(String::from("from-query")));
+
+        let req = TestRequest::with_uri("/ws/game/1").to_http_request();
+        assert_eq!(token(&req), None);
+    }
+}
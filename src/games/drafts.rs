@@ -0,0 +1,535 @@
+//! Games that are still being set up
+//!
+//! A draft doesn't need a schedule yet and its beverages and invitations are stored with the draft.
+//! Publishing it runs the full validation, creates the game and sends the invitations.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::models::{Beverage, CreateGame, Game};
+use crate::games::{currency, timezone};
+use crate::users::UserResponse;
+use crate::validator::{first_violation, Validate, Violation};
+
+/// the fields that are only validated when the draft gets published
+const SCHEDULE_FIELDS: [&str; 2] = ["startTime", "closeTime"];
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Draft {
+    pub id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    pub start_time: Option<DateTime<Utc>>,
+    pub close_time: Option<DateTime<Utc>>,
+    pub beverage_count: i16,
+    pub max_slot_quantity: Option<i32>,
+    pub max_window_quantity: Option<i32>,
+    pub quantity_window: Option<i32>,
+    pub purchase_cooldown: Option<i32>,
+    pub throttle_suspicious_users: bool,
+    pub drift_percentage: Option<f64>,
+    pub drift_interval: Option<i32>,
+    pub predictions_enabled: bool,
+    pub points_budget: Option<i64>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub time_zone: String,
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub currency: String,
+    pub price_step: i64,
+}
+
+/// The game settings of a draft, the schedule can be left empty
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftSettings {
+    pub name: String,
+    pub start_time: Option<DateTime<Utc>>,
+    pub close_time: Option<DateTime<Utc>>,
+    pub beverage_count: i16,
+    pub max_slot_quantity: Option<i32>,
+    pub max_window_quantity: Option<i32>,
+    pub quantity_window: Option<i32>,
+    pub purchase_cooldown: Option<i32>,
+    #[serde(default)]
+    pub throttle_suspicious_users: bool,
+    pub drift_percentage: Option<f64>,
+    pub drift_interval: Option<i32>,
+    #[serde(default)]
+    pub predictions_enabled: bool,
+    pub points_budget: Option<i64>,
+    #[serde(default = "timezone::default_time_zone")]
+    pub time_zone: String,
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    #[serde(default = "currency::default_currency")]
+    pub currency: String,
+    #[serde(default = "currency::default_price_step")]
+    pub price_step: i64,
+}
+
+/// A beverage that's created when the draft gets published
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftBeverage {
+    pub slot_no: i16,
+    pub name: String,
+    pub image_url: Option<String>,
+    pub min_price: i64,
+    pub max_price: i64,
+    pub starting_price: i64,
+    pub alcoholic: bool,
+    pub cost_price: Option<i64>,
+}
+
+/// A draft with everything that's staged for it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DraftResponse {
+    #[serde(flatten)]
+    pub draft: Draft,
+    pub beverages: Vec<DraftBeverage>,
+    pub invitations: Vec<UserResponse>,
+}
+
+/// The outcome of publishing a draft
+#[derive(Debug)]
+pub struct Published {
+    pub game: Game,
+    pub invited_users: Vec<i64>,
+}
+
+impl DraftSettings {
+    /// the game this draft would create, a missing schedule is filled in with placeholders
+    fn to_game(&self, owner_id: i64) -> CreateGame {
+        let start_time = self
+            .start_time
+            .unwrap_or_else(|| Utc::now() + Duration::days(1));
+
+        CreateGame {
+            name: self.name.clone(),
+            owner_id,
+            start_time,
+            close_time: self
+                .close_time
+                .unwrap_or_else(|| start_time + Duration::hours(1)),
+            beverage_count: self.beverage_count,
+            max_slot_quantity: self.max_slot_quantity,
+            max_window_quantity: self.max_window_quantity,
+            quantity_window: self.quantity_window,
+            purchase_cooldown: self.purchase_cooldown,
+            throttle_suspicious_users: self.throttle_suspicious_users,
+            drift_percentage: self.drift_percentage,
+            drift_interval: self.drift_interval,
+            predictions_enabled: self.predictions_enabled,
+            points_budget: self.points_budget,
+            time_zone: self.time_zone.clone(),
+            venue_name: self.venue_name.clone(),
+            venue_address: self.venue_address.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            currency: self.currency.clone(),
+            price_step: self.price_step,
+        }
+    }
+}
+
+impl Validate<DraftSettings> for DraftSettings {
+    fn validate(&self) -> Result<(), ServiceError> {
+        first_violation(self.violations())
+    }
+
+    /// the rules of a game, except for an incomplete schedule
+    fn violations(&self) -> Vec<Violation> {
+        let complete = self.start_time.is_some() && self.close_time.is_some();
+
+        self.to_game(0)
+            .violations()
+            .into_iter()
+            .filter(|violation| {
+                complete
+                    || !matches!(violation.field, Some(field) if SCHEDULE_FIELDS.contains(&field))
+            })
+            .collect()
+    }
+}
+
+impl Draft {
+    fn settings(&self) -> DraftSettings {
+        DraftSettings {
+            name: self.name.clone(),
+            start_time: self.start_time,
+            close_time: self.close_time,
+            beverage_count: self.beverage_count,
+            max_slot_quantity: self.max_slot_quantity,
+            max_window_quantity: self.max_window_quantity,
+            quantity_window: self.quantity_window,
+            purchase_cooldown: self.purchase_cooldown,
+            throttle_suspicious_users: self.throttle_suspicious_users,
+            drift_percentage: self.drift_percentage,
+            drift_interval: self.drift_interval,
+            predictions_enabled: self.predictions_enabled,
+            points_budget: self.points_budget,
+            time_zone: self.time_zone.clone(),
+            venue_name: self.venue_name.clone(),
+            venue_address: self.venue_address.clone(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+            currency: self.currency.clone(),
+            price_step: self.price_step,
+        }
+    }
+
+    #[tracing::instrument(name = "Draft::create")]
+    pub async fn create(
+        owner_id: i64,
+        settings: &DraftSettings,
+        db: &Pool<Postgres>,
+    ) -> Result<Draft, sqlx::Error> {
+        sqlx::query_as!(
+            Draft,
+            r#"
+            INSERT INTO game_drafts (owner_id, name, start_time, close_time, beverage_count, max_slot_quantity, max_window_quantity, quantity_window, purchase_cooldown, throttle_suspicious_users, drift_percentage, drift_interval, predictions_enabled, points_budget, time_zone, venue_name, venue_address, latitude, longitude, currency, price_step)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)
+            RETURNING *
+            "#,
+            owner_id,
+            settings.name,
+            settings.start_time,
+            settings.close_time,
+            settings.beverage_count,
+            settings.max_slot_quantity,
+            settings.max_window_quantity,
+            settings.quantity_window,
+            settings.purchase_cooldown,
+            settings.throttle_suspicious_users,
+            settings.drift_percentage,
+            settings.drift_interval,
+            settings.predictions_enabled,
+            settings.points_budget,
+            settings.time_zone,
+            settings.venue_name,
+            settings.venue_address,
+            settings.latitude,
+            settings.longitude,
+            settings.currency,
+            settings.price_step
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// find a draft of the owner, the drafts of other users don't exist for them
+    #[tracing::instrument(name = "Draft::find")]
+    pub async fn find(id: i64, owner_id: i64, db: &Pool<Postgres>) -> Result<Draft, sqlx::Error> {
+        sqlx::query_as!(
+            Draft,
+            "SELECT * FROM game_drafts WHERE id = $1 AND owner_id = $2",
+            id,
+            owner_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "Draft::find_by_owner")]
+    pub async fn find_by_owner(
+        owner_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Draft>, sqlx::Error> {
+        sqlx::query_as!(
+            Draft,
+            "SELECT * FROM game_drafts WHERE owner_id = $1 ORDER BY updated_at DESC",
+            owner_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "Draft::update")]
+    pub async fn update(
+        &self,
+        settings: &DraftSettings,
+        db: &Pool<Postgres>,
+    ) -> Result<Draft, sqlx::Error> {
+        sqlx::query_as!(
+            Draft,
+            r#"
+            UPDATE game_drafts
+            SET name = $1, start_time = $2, close_time = $3, beverage_count = $4, max_slot_quantity = $5, max_window_quantity = $6, quantity_window = $7, purchase_cooldown = $8, throttle_suspicious_users = $9, drift_percentage = $10, drift_interval = $11, predictions_enabled = $12, points_budget = $13, time_zone = $14, venue_name = $15, venue_address = $16, latitude = $17, longitude = $18, currency = $19, price_step = $20
+            WHERE id = $21
+            RETURNING *
+            "#,
+            settings.name,
+            settings.start_time,
+            settings.close_time,
+            settings.beverage_count,
+            settings.max_slot_quantity,
+            settings.max_window_quantity,
+            settings.quantity_window,
+            settings.purchase_cooldown,
+            settings.throttle_suspicious_users,
+            settings.drift_percentage,
+            settings.drift_interval,
+            settings.predictions_enabled,
+            settings.points_budget,
+            settings.time_zone,
+            settings.venue_name,
+            settings.venue_address,
+            settings.latitude,
+            settings.longitude,
+            settings.currency,
+            settings.price_step,
+            self.id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// delete the draft and everything that's staged for it
+    #[tracing::instrument(name = "Draft::delete")]
+    pub async fn delete(&self, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let mut tx = db::begin(Operation::DeleteDraft, db).await?;
+
+        sqlx::query!("DELETE FROM draft_beverages WHERE draft_id = $1", self.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM draft_invitations WHERE draft_id = $1", self.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM game_drafts WHERE id = $1", self.id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    #[tracing::instrument(name = "Draft::beverages")]
+    pub async fn beverages(&self, db: &Pool<Postgres>) -> Result<Vec<DraftBeverage>, sqlx::Error> {
+        sqlx::query_as!(
+            DraftBeverage,
+            r#"
+            SELECT slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price
+            FROM draft_beverages
+            WHERE draft_id = $1
+            ORDER BY slot_no
+            "#,
+            self.id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// create or replace the beverage in a slot
+    #[tracing::instrument(name = "Draft::save_beverage")]
+    pub async fn save_beverage(
+        &self,
+        beverage: &Beverage,
+        db: &Pool<Postgres>,
+    ) -> Result<DraftBeverage, ServiceError> {
+        if beverage.slot_no >= self.beverage_count {
+            bad_request!("a beverage slot exceeds the maximum configured beverage slots");
+        }
+
+        let beverage = sqlx::query_as!(
+            DraftBeverage,
+            r#"
+            INSERT INTO draft_beverages (draft_id, slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT (draft_id, slot_no) DO UPDATE
+            SET name = $3, image_url = $4, min_price = $5, max_price = $6, starting_price = $7, alcoholic = $8, cost_price = $9
+            RETURNING slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price
+            "#,
+            self.id,
+            beverage.slot_no,
+            beverage.name,
+            beverage.image_url,
+            beverage.min_price,
+            beverage.max_price,
+            beverage.starting_price,
+            beverage.alcoholic,
+            beverage.cost_price
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(beverage)
+    }
+
+    /// the users who get invited when the draft is published
+    #[tracing::instrument(name = "Draft::invitations")]
+    pub async fn invitations(&self, db: &Pool<Postgres>) -> Result<Vec<UserResponse>, sqlx::Error> {
+        sqlx::query_as!(
+            UserResponse,
+            r#"
+            SELECT users.id, users.username
+            FROM users
+            INNER JOIN draft_invitations ON draft_invitations.user_id = users.id
+            WHERE draft_invitations.draft_id = $1
+            ORDER BY users.username
+            "#,
+            self.id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "Draft::stage_invitation")]
+    pub async fn stage_invitation(
+        &self,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(), ServiceError> {
+        if user_id == self.owner_id {
+            bad_request!("the owner is always part of the game");
+        }
+
+        sqlx::query!(
+            "INSERT INTO draft_invitations (draft_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            self.id,
+            user_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Draft::unstage_invitation")]
+    pub async fn unstage_invitation(
+        &self,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "DELETE FROM draft_invitations WHERE draft_id = $1 AND user_id = $2",
+            self.id,
+            user_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn with_staging(self, db: &Pool<Postgres>) -> Result<DraftResponse, sqlx::Error> {
+        let beverages = self.beverages(db).await?;
+        let invitations = self.invitations(db).await?;
+
+        Ok(DraftResponse {
+            draft: self,
+            beverages,
+            invitations,
+        })
+    }
+
+    /// Validate the draft like a new game and turn it into one
+    ///
+    /// The game is created with the staged beverages and the staged users are invited,
+    /// the draft is removed afterwards.
+    #[tracing::instrument(name = "Draft::publish")]
+    pub async fn publish(self, db: &Pool<Postgres>) -> Result<Published, ServiceError> {
+        let settings = self.settings();
+        if settings.start_time.is_none() || settings.close_time.is_none() {
+            bad_request!("the game needs a start and close time before it can be published");
+        }
+
+        let new_game = settings.to_game(self.owner_id);
+        new_game.validate()?;
+
+        let staged_beverages = self.beverages(db).await?;
+        let mut beverages = Vec::with_capacity(staged_beverages.len());
+        for staged in staged_beverages {
+            let beverage = staged.into_beverage(self.owner_id);
+            if beverage.slot_no >= new_game.beverage_count {
+                bad_request!("a beverage slot exceeds the maximum configured beverage slots");
+            }
+            beverage.validate()?;
+            beverages.push(beverage);
+        }
+
+        let invitations = self.invitations(db).await?;
+
+        let game = Game::create(new_game, db).await?;
+
+        for mut beverage in beverages {
+            beverage.game_id = game.id;
+            beverage.save(db).await?;
+        }
+
+        let mut invited_users = Vec::with_capacity(invitations.len());
+        for user in invitations {
+            game.invite_user(user.id, db).await?;
+            invited_users.push(user.id);
+        }
+
+        self.delete(db).await?;
+
+        Ok(Published {
+            game,
+            invited_users,
+        })
+    }
+}
+
+impl DraftBeverage {
+    fn into_beverage(self, user_id: i64) -> Beverage {
+        Beverage {
+            game_id: 0,
+            user_id,
+            slot_no: self.slot_no,
+            name: self.name,
+            image_url: self.image_url,
+            min_price: self.min_price,
+            max_price: self.max_price,
+            starting_price: self.starting_price,
+            current_price: self.starting_price,
+            alcoholic: self.alcoholic,
+            cost_price: self.cost_price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> DraftSettings {
+        serde_json::from_value(serde_json::json!({
+            "name": "some game",
+            "beverageCount": 8
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn schedule_is_optional() {
+        assert!(settings().validate().is_ok());
+
+        // a partial schedule is fine as well, it's only checked when it's complete
+        let mut draft = settings();
+        draft.start_time = Some(Utc::now() - Duration::days(1));
+        assert!(draft.validate().is_ok());
+
+        draft.close_time = Some(Utc::now());
+        assert_eq!(draft.violations()[0].field, Some("startTime"));
+    }
+
+    #[test]
+    fn settings_are_validated() {
+        let mut draft = settings();
+        draft.beverage_count = 1;
+
+        let violations = draft.violations();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].field, Some("beverageCount"));
+    }
+}
@@ -0,0 +1,262 @@
+//! Where a game is played
+//!
+//! The venue is optional, games with coordinates can be found with a `near` filter.
+
+use std::str::FromStr;
+
+use crate::errors::ServiceError;
+use crate::games::GameResponse;
+use crate::validator::Violations;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// the radius of a `near` filter without a radius
+pub const DEFAULT_RADIUS_KM: f64 = 10.0;
+const MAX_RADIUS_KM: f64 = 500.0;
+
+const MAX_VENUE_NAME: usize = 100;
+const MAX_VENUE_ADDRESS: usize = 200;
+
+/// The venue of a game, every field is optional
+#[derive(Debug, Clone, Copy)]
+pub struct Venue<'a> {
+    pub name: Option<&'a str>,
+    pub address: Option<&'a str>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+}
+
+impl<'a> Venue<'a> {
+    /// add the violations of the venue
+    pub fn check(&self, violations: &mut Violations) {
+        if let Some(name) = self.name {
+            violations.check(
+                !name.trim().is_empty(),
+                "venueName",
+                "the venue name is too short",
+            );
+            violations.check(
+                name.trim().len() <= MAX_VENUE_NAME,
+                "venueName",
+                "the venue name is too long, maximum 100 characters",
+            );
+        }
+        if let Some(address) = self.address {
+            violations.check(
+                !address.trim().is_empty(),
+                "venueAddress",
+                "the address is too short",
+            );
+            violations.check(
+                address.trim().len() <= MAX_VENUE_ADDRESS,
+                "venueAddress",
+                "the address is too long, maximum 200 characters",
+            );
+        }
+
+        violations.check(
+            self.latitude.is_some() == self.longitude.is_some(),
+            "longitude",
+            "the latitude requires a longitude and vice versa",
+        );
+        if let Some(latitude) = self.latitude {
+            violations.check(
+                latitude.is_finite() && latitude.abs() <= 90.0,
+                "latitude",
+                "the latitude should be between -90 and 90",
+            );
+        }
+        if let Some(longitude) = self.longitude {
+            violations.check(
+                longitude.is_finite() && longitude.abs() <= 180.0,
+                "longitude",
+                "the longitude should be between -180 and 180",
+            );
+        }
+    }
+
+    /// fail with the first violation of the venue
+    pub fn validate(&self) -> Result<(), ServiceError> {
+        let mut violations = Violations::default();
+        self.check(&mut violations);
+
+        crate::validator::first_violation(violations.into_inner())
+    }
+}
+
+/// A coordinate, parsed from `lat,lng`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl FromStr for Coordinate {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ServiceError::BadRequest(String::from("near should look like lat,lng"));
+
+        let mut parts = s.split(',');
+        let latitude: f64 = parts
+            .next()
+            .and_then(|part| part.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        let longitude: f64 = parts
+            .next()
+            .and_then(|part| part.trim().parse().ok())
+            .ok_or_else(invalid)?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+
+        Venue {
+            name: None,
+            address: None,
+            latitude: Some(latitude),
+            longitude: Some(longitude),
+        }
+        .validate()?;
+
+        Ok(Coordinate {
+            latitude,
+            longitude,
+        })
+    }
+}
+
+impl Coordinate {
+    /// the great-circle distance in kilometers
+    pub fn distance_km(&self, other: &Coordinate) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let delta_lat = lat2 - lat1;
+        let delta_lng = (other.longitude - self.longitude).to_radians();
+
+        let a = (delta_lat / 2.0).sin().powi(2)
+            + lat1.cos() * lat2.cos() * (delta_lng / 2.0).sin().powi(2);
+
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Games within a radius of a coordinate
+#[derive(Debug, Clone, Copy)]
+pub struct Near {
+    pub center: Coordinate,
+    pub radius_km: f64,
+}
+
+impl Near {
+    pub fn new(center: &str, radius_km: Option<f64>) -> Result<Near, ServiceError> {
+        let radius_km = radius_km.unwrap_or(DEFAULT_RADIUS_KM);
+        if !(radius_km > 0.0 && radius_km <= MAX_RADIUS_KM) {
+            bad_request!("the radius should be between 0 and 500 kilometers");
+        }
+
+        Ok(Near {
+            center: center.parse()?,
+            radius_km,
+        })
+    }
+
+    /// games without coordinates are never near
+    pub fn contains(&self, game: &GameResponse) -> bool {
+        match (game.latitude, game.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                let location = Coordinate {
+                    latitude,
+                    longitude,
+                };
+                self.center.distance_km(&location) <= self.radius_km
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn violations(venue: Venue<'_>) -> Vec<&'static str> {
+        let mut violations = Violations::default();
+        venue.check(&mut violations);
+
+        violations
+            .into_inner()
+            .into_iter()
+            .filter_map(|violation| violation.field)
+            .collect()
+    }
+
+    #[test]
+    fn invalid_venue() {
+        let venue = Venue {
+            name: Some("De Fuif"),
+            address: Some("Grote Markt 1, Brussel"),
+            latitude: Some(50.8467),
+            longitude: Some(4.3525),
+        };
+        assert!(violations(venue).is_empty());
+
+        assert_eq!(
+            violations(Venue {
+                latitude: None,
+                ..venue
+            }),
+            vec!["longitude"]
+        );
+        assert_eq!(
+            violations(Venue {
+                latitude: Some(91.0),
+                longitude: Some(-181.0),
+                ..venue
+            }),
+            vec!["latitude", "longitude"]
+        );
+        assert_eq!(
+            violations(Venue {
+                name: Some(" "),
+                ..venue
+            }),
+            vec!["venueName"]
+        );
+    }
+
+    #[test]
+    fn parse_coordinate() {
+        assert_eq!(
+            "50.8467, 4.3525".parse::<Coordinate>().unwrap(),
+            Coordinate {
+                latitude: 50.8467,
+                longitude: 4.3525
+            }
+        );
+        assert!("50.8467".parse::<Coordinate>().is_err());
+        assert!("50,4,3".parse::<Coordinate>().is_err());
+        assert!("north,south".parse::<Coordinate>().is_err());
+        assert!("100,4".parse::<Coordinate>().is_err());
+    }
+
+    #[test]
+    fn distance() {
+        let brussels = Coordinate {
+            latitude: 50.8467,
+            longitude: 4.3525,
+        };
+        let ghent = Coordinate {
+            latitude: 51.0543,
+            longitude: 3.7174,
+        };
+
+        let distance = brussels.distance_km(&ghent);
+        assert!((distance - 50.0).abs() < 2.0, "{}", distance);
+        assert_eq!(brussels.distance_km(&brussels), 0.0);
+    }
+
+    #[test]
+    fn invalid_radius() {
+        assert!(Near::new("50.8,4.3", None).is_ok());
+        assert!(Near::new("50.8,4.3", Some(0.0)).is_err());
+        assert!(Near::new("50.8,4.3", Some(1000.0)).is_err());
+    }
+}
@@ -1,3 +1,23 @@
+pub mod active;
+pub mod blackouts;
+pub mod currency;
+pub mod devices;
+pub mod drafts;
+pub mod location;
 mod models;
+mod price_range;
+mod replay;
+pub mod results;
 pub mod routes;
+pub mod rules;
+pub mod series;
+pub mod slot_groups;
+mod suggestions;
+pub mod templates;
+pub mod timezone;
+pub mod update_interval;
+pub mod waitlist;
 pub use models::{Beverage, Game, GameResponse, GameState};
+pub use price_range::PriceRange;
+pub use replay::{Replay, ReplayOptions};
+pub use suggestions::Suggestion;
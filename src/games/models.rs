@@ -1,15 +1,21 @@
 use actix_web::Result;
 use chrono::Duration;
 use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
 use sqlx::{Pool, Postgres};
 use url::Url;
 
+use crate::db::{self, Operation};
 use crate::errors::ServiceError;
 use crate::invitations::{NewInvitation, State};
 use crate::transactions::models::SalesCount;
 use crate::users::{User, UserResponse};
+use crate::validator::{first_violation, Violation, Violations};
+use crate::games::currency::{self, Pricing};
+use crate::games::location::Venue;
+use crate::games::slot_groups::{PriceParameters, SlotGroup};
+use crate::games::timezone::{self, LocalSchedule, LocalizedGame};
 use crate::market::MarketAgent;
-use crate::websocket::server::NotificationServer;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -22,6 +28,40 @@ pub struct Game {
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
     pub beverage_count: i16,
+    /// the maximum amount of a single beverage in one order
+    pub max_slot_quantity: Option<i32>,
+    /// the maximum amount of a single beverage a user can purchase during the `quantity_window`
+    pub max_window_quantity: Option<i32>,
+    /// the window in seconds used by `max_window_quantity`
+    pub quantity_window: Option<i32>,
+    /// the minimum amount of seconds between two orders of the same user
+    pub purchase_cooldown: Option<i32>,
+    /// slow down users that are flagged for suspicious purchases
+    #[serde(default)]
+    pub throttle_suspicious_users: bool,
+    /// the percentage all prices change every `drift_interval`, negative values deflate the prices
+    pub drift_percentage: Option<f64>,
+    /// the interval in seconds between two drift steps
+    pub drift_interval: Option<i32>,
+    /// allow players to predict the price changes of their beverages
+    #[serde(default)]
+    pub predictions_enabled: bool,
+    /// when set, the players spend points instead of real money and start with this budget
+    pub points_budget: Option<i64>,
+    /// the IANA time zone the game is played in, the times are still stored in UTC
+    #[serde(default = "timezone::default_time_zone")]
+    pub time_zone: String,
+    /// where the game is played, the coordinates are used to find games nearby
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// the ISO 4217 code of the currency the prices are in
+    #[serde(default = "currency::default_currency")]
+    pub currency: String,
+    /// the prices are rounded to a multiple of this, in the minor unit of the currency
+    #[serde(default = "currency::default_price_step")]
+    pub price_step: i64,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -33,6 +73,115 @@ pub struct CreateGame {
     pub start_time: DateTime<Utc>,
     pub close_time: DateTime<Utc>,
     pub beverage_count: i16,
+    pub max_slot_quantity: Option<i32>,
+    pub max_window_quantity: Option<i32>,
+    pub quantity_window: Option<i32>,
+    pub purchase_cooldown: Option<i32>,
+    #[serde(default)]
+    pub throttle_suspicious_users: bool,
+    pub drift_percentage: Option<f64>,
+    pub drift_interval: Option<i32>,
+    #[serde(default)]
+    pub predictions_enabled: bool,
+    pub points_budget: Option<i64>,
+    #[serde(default = "timezone::default_time_zone")]
+    pub time_zone: String,
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    /// the ISO 4217 code of the currency the prices are in
+    #[serde(default = "currency::default_currency")]
+    pub currency: String,
+    /// the prices are rounded to a multiple of this, in the minor unit of the currency
+    #[serde(default = "currency::default_price_step")]
+    pub price_step: i64,
+}
+
+/// The settings an owner changes, the fields that are left out keep their stored value
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateGame {
+    pub id: i64,
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_slot_quantity: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub max_window_quantity: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub quantity_window: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub purchase_cooldown: Option<Option<i32>>,
+    pub throttle_suspicious_users: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub drift_percentage: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub drift_interval: Option<Option<i32>>,
+    pub predictions_enabled: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub points_budget: Option<Option<i64>>,
+    pub time_zone: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub venue_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub venue_address: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub latitude: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub longitude: Option<Option<f64>>,
+    pub currency: Option<String>,
+    pub price_step: Option<i64>,
+}
+
+/// a field that's `null` clears the value, unlike a field that's left out
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::Deserialize<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+impl UpdateGame {
+    /// The stored game with the changes applied
+    ///
+    /// The economy of a game is fixed once it has started,
+    /// the players already spent their budget in the old currency and price steps.
+    pub fn apply(self, game: Game) -> Result<Game, ServiceError> {
+        if !game.not_started() && self.changes_economy(&game) {
+            bad_request!("the points budget, currency and price step can't change once the game has started");
+        }
+
+        let game = Game {
+            name: self.name.unwrap_or(game.name),
+            max_slot_quantity: self.max_slot_quantity.unwrap_or(game.max_slot_quantity),
+            max_window_quantity: self.max_window_quantity.unwrap_or(game.max_window_quantity),
+            quantity_window: self.quantity_window.unwrap_or(game.quantity_window),
+            purchase_cooldown: self.purchase_cooldown.unwrap_or(game.purchase_cooldown),
+            throttle_suspicious_users: self.throttle_suspicious_users.unwrap_or(game.throttle_suspicious_users),
+            drift_percentage: self.drift_percentage.unwrap_or(game.drift_percentage),
+            drift_interval: self.drift_interval.unwrap_or(game.drift_interval),
+            predictions_enabled: self.predictions_enabled.unwrap_or(game.predictions_enabled),
+            points_budget: self.points_budget.unwrap_or(game.points_budget),
+            time_zone: self.time_zone.unwrap_or(game.time_zone),
+            venue_name: self.venue_name.unwrap_or(game.venue_name),
+            venue_address: self.venue_address.unwrap_or(game.venue_address),
+            latitude: self.latitude.unwrap_or(game.latitude),
+            longitude: self.longitude.unwrap_or(game.longitude),
+            currency: self.currency.unwrap_or(game.currency),
+            price_step: self.price_step.unwrap_or(game.price_step),
+            ..game
+        };
+        first_violation(game.violations())?;
+
+        Ok(game)
+    }
+
+    fn changes_economy(&self, game: &Game) -> bool {
+        self.points_budget.map_or(false, |budget| budget != game.points_budget)
+            || self.currency.as_ref().map_or(false, |currency| *currency != game.currency)
+            || self.price_step.map_or(false, |price_step| price_step != game.price_step)
+    }
 }
 
 /// GameFilter a struct that the client
@@ -46,6 +195,10 @@ pub struct GameFilter {
     pub completed: Option<bool>,
     /// list games created by a specific user
     pub owner_id: Option<i64>,
+    /// only list games near `lat,lng`
+    pub near: Option<String>,
+    /// the radius in kilometers around `near`
+    pub radius: Option<f64>,
 }
 
 /// A GameUser is a user who is invited for a game
@@ -65,6 +218,13 @@ pub struct GameResponse {
     pub start_time: DateTime<Utc>,
     pub close_time: DateTime<Utc>,
     pub beverage_count: i16,
+    pub time_zone: String,
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub currency: String,
+    pub price_step: i64,
     pub owner: UserResponse,
 }
 
@@ -87,22 +247,38 @@ impl Game {
     ///
     /// When something fails, the transaction rolls-back, returns an error
     /// and nothing will have happened.
-    #[tracing::instrument(name = "game::create", skip(notifier))]
-    pub async fn create(new_game: CreateGame, db: &Pool<Postgres>, notifier: actix::Addr<NotificationServer>) -> Result<Game, ServiceError> {
-        let mut tx = db.begin().await?;
+    #[tracing::instrument(name = "game::create")]
+    pub async fn create(new_game: CreateGame, db: &Pool<Postgres>) -> Result<Game, ServiceError> {
+        let mut tx = db::begin(Operation::CreateGame, db).await?;
 
         let game: Game = sqlx::query_as!(
             Game,
             r#"
-            INSERT INTO games (name, owner_id, start_time, close_time, beverage_count)
-            VALUES ($1, $2, $3, $4, $5)
+            INSERT INTO games (name, owner_id, start_time, close_time, beverage_count, max_slot_quantity, max_window_quantity, quantity_window, purchase_cooldown, throttle_suspicious_users, drift_percentage, drift_interval, predictions_enabled, points_budget, time_zone, venue_name, venue_address, latitude, longitude, currency, price_step)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21)
             RETURNING *;
             "#,
             new_game.name,
             new_game.owner_id,
             new_game.start_time,
             new_game.close_time,
-            new_game.beverage_count
+            new_game.beverage_count,
+            new_game.max_slot_quantity,
+            new_game.max_window_quantity,
+            new_game.quantity_window,
+            new_game.purchase_cooldown,
+            new_game.throttle_suspicious_users,
+            new_game.drift_percentage,
+            new_game.drift_interval,
+            new_game.predictions_enabled,
+            new_game.points_budget,
+            new_game.time_zone,
+            new_game.venue_name,
+            new_game.venue_address,
+            new_game.latitude,
+            new_game.longitude,
+            new_game.currency,
+            new_game.price_step
         )
         .fetch_one(&mut *tx)
         .await?;
@@ -113,10 +289,12 @@ impl Game {
             .await?;
 
         SalesCount::initialize_slots(&game, &mut tx).await?;
+        // the game, the owner's invitation and a sales count per slot
+        tx.record_rows(2 + game.beverage_count as u64);
 
         tx.commit().await?;
 
-        MarketAgent::new(db.clone(), notifier, game.clone()).start();
+        MarketAgent::new(db.clone(), game.clone()).start();
 
         Ok(game)
     }
@@ -168,7 +346,11 @@ impl Game {
         let game = sqlx::query!(r#"
             SELECT games.id
             FROM (games INNER JOIN invitations ON invitations.game_id = games.id) 
-            WHERE games.id = $1 AND invitations.user_id = $2 AND invitations.state = $3 AND games.start_time < NOW() AND games.close_time > NOW()"#,
+            WHERE games.id = $1 AND invitations.user_id = $2 AND invitations.state = $3 AND games.start_time < NOW() AND games.close_time > NOW()
+            AND NOT EXISTS (
+                SELECT 1 FROM purchase_blackouts
+                WHERE purchase_blackouts.game_id = games.id AND purchase_blackouts.start_time <= NOW() AND purchase_blackouts.end_time > NOW()
+            )"#,
             game_id,
             user_id,
             State::Accepted as _,
@@ -224,7 +406,7 @@ impl Game {
         if !filter.completed.unwrap_or(true) {
             return sqlx::query_as!(
                 GameResponse,
-                r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, (users.id, users.username) as "owner!: UserResponse"
+                r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, games.time_zone, games.venue_name, games.venue_address, games.latitude, games.longitude, games.currency, games.price_step, (users.id, users.username) as "owner!: UserResponse"
                 FROM (games INNER JOIN users ON games.owner_id = users.id)
                 WHERE games.close_time > NOW()
                 ORDER BY games.start_time DESC"#
@@ -233,7 +415,7 @@ impl Game {
 
         sqlx::query_as!(
             GameResponse,
-            r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, (users.id, users.username) as "owner!: UserResponse"
+            r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, games.time_zone, games.venue_name, games.venue_address, games.latitude, games.longitude, games.currency, games.price_step, (users.id, users.username) as "owner!: UserResponse"
             FROM (games INNER JOIN users ON games.owner_id = users.id)
             ORDER BY games.start_time DESC"#
         ).fetch_all(db).await
@@ -249,7 +431,7 @@ impl Game {
         if !filter.completed.unwrap_or(true) {
             return sqlx::query_as!(
                 GameResponse,
-                r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, (users.id, users.username) as "owner!: UserResponse"
+                r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, games.time_zone, games.venue_name, games.venue_address, games.latitude, games.longitude, games.currency, games.price_step, (users.id, users.username) as "owner!: UserResponse"
                 FROM (games INNER JOIN users ON games.owner_id = users.id)
                 WHERE games.id IN (
                     SELECT game_id FROM invitations WHERE user_id = $1 AND state = $2
@@ -262,7 +444,7 @@ impl Game {
 
         let games = sqlx::query_as!(
             GameResponse,
-            r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, (users.id, users.username) as "owner!: UserResponse"
+            r#"SELECT games.id, games.name, games.start_time, games.close_time, games.beverage_count, games.time_zone, games.venue_name, games.venue_address, games.latitude, games.longitude, games.currency, games.price_step, (users.id, users.username) as "owner!: UserResponse"
             FROM (games INNER JOIN users ON games.owner_id = users.id)
             WHERE games.id IN (
                 SELECT game_id FROM invitations WHERE user_id = $1 AND state = $2
@@ -293,7 +475,7 @@ impl Game {
     /// Returns a list of users who have not yet been invited for a game
     #[tracing::instrument(name = "Game::find_available_users")]
     pub async fn find_available_users(game_id: i64, db: &Pool<Postgres>) -> Result<Vec<UserResponse>, sqlx::Error> {
-        sqlx::query_as!(UserResponse, "SELECT id, username FROM users WHERE id NOT IN (SELECT user_id FROM invitations WHERE game_id = $1)", game_id).fetch_all(db).await
+        sqlx::query_as!(UserResponse, "SELECT id, username FROM users WHERE id NOT IN (SELECT user_id FROM invitations WHERE game_id = $1) AND id NOT IN (SELECT user_id FROM guests)", game_id).fetch_all(db).await
     }
 
     /// validates if a user is actually partaking in a game (invited and accepted)
@@ -319,6 +501,79 @@ impl Game {
         Ok(row.is_some())
     }
 
+    /// the factor all prices are multiplied with at a given moment
+    ///
+    /// Every `drift_interval` seconds since the start of the game, the prices change by `drift_percentage`
+    pub fn drift_factor(&self, at: DateTime<Utc>) -> f64 {
+        match (self.drift_percentage, self.drift_interval) {
+            (Some(percentage), Some(interval)) => {
+                let at = std::cmp::min(at, self.close_time);
+                let elapsed = at.signed_duration_since(self.start_time).num_seconds().max(0);
+                let steps = elapsed / i64::from(interval);
+
+                (1.0 + percentage / 100.0).powi(steps as i32)
+            }
+            _ => 1.0,
+        }
+    }
+
+    /// the time zone of the game, UTC when it's unknown
+    pub fn tz(&self) -> Tz {
+        timezone::parse(&self.time_zone).unwrap_or(Tz::UTC)
+    }
+
+    /// the game with its schedule in its own time zone
+    pub fn localized(&self) -> LocalizedGame<'_> {
+        LocalizedGame {
+            game: self,
+            local: LocalSchedule::new(self.start_time, self.close_time, self.tz()),
+        }
+    }
+
+    pub fn venue(&self) -> Venue<'_> {
+        Venue {
+            name: self.venue_name.as_deref(),
+            address: self.venue_address.as_deref(),
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+
+    pub fn pricing(&self) -> Pricing<'_> {
+        Pricing {
+            currency: &self.currency,
+            price_step: self.price_step,
+        }
+    }
+
+    fn rules(&self) -> Rules {
+        Rules {
+            max_slot_quantity: self.max_slot_quantity,
+            max_window_quantity: self.max_window_quantity,
+            quantity_window: self.quantity_window,
+            purchase_cooldown: self.purchase_cooldown,
+            drift_percentage: self.drift_percentage,
+            drift_interval: self.drift_interval,
+            points_budget: self.points_budget,
+        }
+    }
+
+    /// the rules the settings an owner can change have to follow, the schedule can't be changed
+    fn violations(&self) -> Vec<Violation> {
+        let mut violations = Violations::default();
+
+        violations.check(timezone::parse(&self.time_zone).is_some(), "timeZone", "unknown time zone, use a name like Europe/Brussels");
+
+        violations.check(!self.name.trim().is_empty(), "name", "name is too short");
+        violations.check(self.name.trim().len() <= 40, "name", "name is too long, maximum 40 characters");
+
+        self.venue().check(&mut violations);
+        self.pricing().check(&mut violations);
+        self.rules().check(&mut violations);
+
+        violations.into_inner()
+    }
+
     /// returns true if a user is an admin or created the game
     pub const fn is_owner(&self, user: &User) -> bool {
         user.is_admin || user.id == self.owner_id
@@ -328,8 +583,24 @@ impl Game {
     pub async fn update(&self, db: &Pool<Postgres>) -> Result<Game, sqlx::Error> {
         let game = sqlx::query_as!(
             Game,
-            "UPDATE games SET name = $1 WHERE id = $2 RETURNING *",
+            "UPDATE games SET name = $1, max_slot_quantity = $2, max_window_quantity = $3, quantity_window = $4, purchase_cooldown = $5, throttle_suspicious_users = $6, drift_percentage = $7, drift_interval = $8, predictions_enabled = $9, points_budget = $10, time_zone = $11, venue_name = $12, venue_address = $13, latitude = $14, longitude = $15, currency = $16, price_step = $17 WHERE id = $18 RETURNING *",
             self.name,
+            self.max_slot_quantity,
+            self.max_window_quantity,
+            self.quantity_window,
+            self.purchase_cooldown,
+            self.throttle_suspicious_users,
+            self.drift_percentage,
+            self.drift_interval,
+            self.predictions_enabled,
+            self.points_budget,
+            self.time_zone,
+            self.venue_name,
+            self.venue_address,
+            self.latitude,
+            self.longitude,
+            self.currency,
+            self.price_step,
             self.id
         )
         .fetch_one(db)
@@ -358,6 +629,8 @@ impl Game {
         let mut beverages = self.get_beverages(&mut *db).await?;
         let sales = SalesCount::find_by_game_for_update(self.id, &mut *db).await?;
         let average_sales = SalesCount::average_sales(&sales);
+        let drift_factor = self.drift_factor(Utc::now());
+        let parameters = SlotGroup::parameters(self.id, &mut *db).await?;
 
         for beverage in beverages.iter_mut() {
             for sale in &sales {
@@ -368,7 +641,12 @@ impl Game {
                 debug!("game({}) - beverage: {}", self.id, beverage.name);
                 assert_eq!(sale.slot_no, beverage.slot_no);
                 let offset = sale.get_offset(average_sales);
-                let price = beverage.calculate_price(offset);
+                let price = beverage.calculate_price(
+                    offset,
+                    drift_factor,
+                    self.price_step,
+                    &parameters.get(&beverage.slot_no).copied().unwrap_or_default(),
+                );
                 debug!("setting price to: {}", price);
                 beverage.set_price(price);
                 beverage.save_price(&mut *db).await?;
@@ -392,38 +670,194 @@ impl Game {
     }
 }
 
+#[cfg(test)]
+impl Game {
+    /// A game without purchase limits that has just started, tests override the fields they need
+    pub(crate) fn fixture() -> Game {
+        let start_time = Utc::now();
+
+        Game {
+            id: 1,
+            name: String::from("some game"),
+            owner_id: 1,
+            start_time,
+            close_time: start_time + Duration::hours(2),
+            created_at: None,
+            updated_at: None,
+            beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
+        }
+    }
+}
+
+#[cfg(test)]
+impl CreateGame {
+    /// A valid game that starts tomorrow, tests override the fields they need
+    pub(crate) fn fixture() -> CreateGame {
+        let start_time = Utc::now() + Duration::days(1);
+
+        CreateGame {
+            name: String::from("some game"),
+            owner_id: 1,
+            start_time,
+            close_time: start_time + Duration::hours(2),
+            beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
+        }
+    }
+}
+
 impl crate::validator::Validate<CreateGame> for CreateGame {
     fn validate(&self) -> Result<(), ServiceError> {
-        if self.start_time <= Utc::now() {
-            bad_request!("the game can't start in the past");
-        }
+        first_violation(self.violations())
+    }
+
+    fn violations(&self) -> Vec<Violation> {
+        let mut violations = Violations::default();
+
+        violations.check(self.start_time > Utc::now(), "startTime", "the game can't start in the past");
+
+        let tz = timezone::parse(&self.time_zone);
+        violations.check(tz.is_some(), "timeZone", "unknown time zone, use a name like Europe/Brussels");
 
         let duration: Duration = self.close_time.signed_duration_since(self.start_time);
-        if duration.num_seconds() < MIN_GAME_SECONDS {
-            bad_request!("this game has not gone on long enough, minimum duration is 30 minutes");
+        violations.check(
+            duration.num_seconds() >= MIN_GAME_SECONDS,
+            "closeTime",
+            "this game has not gone on long enough, minimum duration is 30 minutes",
+        );
+        // the maximum follows the wall clock, so a game can last until the same time the next day when the clocks change
+        let wall_clock = timezone::wall_clock_duration(self.start_time, self.close_time, tz.unwrap_or(Tz::UTC));
+        violations.check(wall_clock.num_seconds() <= MAX_GAME_SECONDS, "closeTime", "the max duration of a game is 24 hours");
+
+        violations.check(!self.name.trim().is_empty(), "name", "name is too short");
+        violations.check(self.name.trim().len() <= 40, "name", "name is too long, maximum 40 characters");
+
+        Venue {
+            name: self.venue_name.as_deref(),
+            address: self.venue_address.as_deref(),
+            latitude: self.latitude,
+            longitude: self.longitude,
         }
+        .check(&mut violations);
 
-        if duration.num_seconds() > MAX_GAME_SECONDS {
-            bad_request!("the max duration of a game is 24 hours");
+        Pricing {
+            currency: &self.currency,
+            price_step: self.price_step,
         }
-
-        if self.name.trim().is_empty() {
-            bad_request!("name is too short");
+        .check(&mut violations);
+
+        violations.check(self.beverage_count >= 2, "beverageCount", "at least 2 beverages should be used");
+        violations.check(self.beverage_count <= 16, "beverageCount", "maximum 16 different beverages allowed");
+
+        Rules {
+            max_slot_quantity: self.max_slot_quantity,
+            max_window_quantity: self.max_window_quantity,
+            quantity_window: self.quantity_window,
+            purchase_cooldown: self.purchase_cooldown,
+            drift_percentage: self.drift_percentage,
+            drift_interval: self.drift_interval,
+            points_budget: self.points_budget,
         }
+        .check(&mut violations);
 
-        if self.name.trim().len() > 40 {
-            bad_request!("name is too long, maximum 40 characters");
-        }
+        violations.into_inner()
+    }
+}
 
-        if self.beverage_count < 2 {
-            bad_request!("at least 2 beverages should be used");
-        }
+/// The purchase limits, the price drift and the points budget of a game
+struct Rules {
+    max_slot_quantity: Option<i32>,
+    max_window_quantity: Option<i32>,
+    quantity_window: Option<i32>,
+    purchase_cooldown: Option<i32>,
+    drift_percentage: Option<f64>,
+    drift_interval: Option<i32>,
+    points_budget: Option<i64>,
+}
 
-        if self.beverage_count > 16 {
-            bad_request!("maximum 16 different beverages allowed");
+impl Rules {
+    fn check(&self, violations: &mut Violations) {
+        violations.check(
+            self.max_slot_quantity.unwrap_or(1) >= 1,
+            "maxSlotQuantity",
+            "the maximum quantity per order should be at least 1",
+        );
+        violations.check(
+            self.max_window_quantity.unwrap_or(1) >= 1,
+            "maxWindowQuantity",
+            "the maximum quantity per window should be at least 1",
+        );
+        violations.check(
+            self.quantity_window.unwrap_or(1) >= 1,
+            "quantityWindow",
+            "the quantity window should be at least 1 second",
+        );
+        violations.check(
+            self.max_window_quantity.is_some() == self.quantity_window.is_some(),
+            "quantityWindow",
+            "the maximum quantity per window requires a quantity window and vice versa",
+        );
+
+        violations.check(
+            self.purchase_cooldown.unwrap_or(1) >= 1,
+            "purchaseCooldown",
+            "the purchase cooldown should be at least 1 second",
+        );
+
+        if let Some(percentage) = self.drift_percentage {
+            violations.check(
+                percentage != 0.0 && percentage.abs() <= 50.0 && percentage.is_finite(),
+                "driftPercentage",
+                "the price drift should be between -50% and 50%",
+            );
         }
-
-        Ok(())
+        violations.check(
+            self.drift_interval.unwrap_or(60) >= 60,
+            "driftInterval",
+            "the drift interval should be at least 1 minute",
+        );
+        violations.check(
+            self.drift_percentage.is_some() == self.drift_interval.is_some(),
+            "driftInterval",
+            "the price drift requires a drift interval and vice versa",
+        );
+
+        violations.check(
+            self.points_budget.unwrap_or(1) >= 1,
+            "pointsBudget",
+            "the points budget should be at least 1 point",
+        );
     }
 }
 
@@ -445,6 +879,19 @@ pub struct Beverage {
 
     #[serde(skip_deserializing)]
     pub current_price: i64,
+
+    /// counts towards the drink limit of the game, see `transactions::drink_limits`
+    #[serde(default = "default_alcoholic")]
+    pub alcoholic: bool,
+
+    /// what the beverage costs the bar, only shown to the user who configured the beverage
+    #[serde(default)]
+    pub cost_price: Option<i64>,
+}
+
+/// beverages are alcoholic unless the owner marks them as soft drinks
+pub(crate) fn default_alcoholic() -> bool {
+    true
 }
 
 impl Beverage {
@@ -456,10 +903,10 @@ impl Beverage {
         }
 
         let beverage = sqlx::query_as!(Beverage, r#"
-            INSERT INTO beverages (game_id, user_id, slot_no, name, image_url, min_price, max_price, starting_price, current_price)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            INSERT INTO beverages (game_id, user_id, slot_no, name, image_url, min_price, max_price, starting_price, current_price, alcoholic, cost_price)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             RETURNING *"#, 
-            self.game_id, self.user_id, self.slot_no, self.name, self.image_url, self.min_price, self.max_price, self.starting_price, self.current_price
+            self.game_id, self.user_id, self.slot_no, self.name, self.image_url, self.min_price, self.max_price, self.starting_price, self.current_price, self.alcoholic, self.cost_price
         ).fetch_one(db).await?;
 
         Ok(beverage)
@@ -499,7 +946,7 @@ impl Beverage {
             Beverage,
             r#"
             UPDATE beverages
-            SET name = $1, image_url = $2, min_price = $3, max_price = $4, starting_price = $5
+            SET name = $1, image_url = $2, min_price = $3, max_price = $4, starting_price = $5, alcoholic = $9, cost_price = $10
             WHERE slot_no = $6 AND game_id = $7 AND user_id = $8
             RETURNING *
             "#,
@@ -510,7 +957,9 @@ impl Beverage {
             self.starting_price,
             self.slot_no,
             self.game_id,
-            self.user_id
+            self.user_id,
+            self.alcoholic,
+            self.cost_price
         )
         .fetch_one(db)
         .await
@@ -526,8 +975,16 @@ impl Beverage {
     }
 
     /// calculate the price of a beverage based on it's offset from the average sales
-    pub const fn calculate_price(&self, offset: i64) -> i64 {
-        let price = self.starting_price + offset * (self.starting_price / 20);
+    /// and the drift factor of the game, rounded to the price step of the game
+    pub fn calculate_price(
+        &self,
+        offset: i64,
+        drift_factor: f64,
+        price_step: i64,
+        parameters: &PriceParameters,
+    ) -> i64 {
+        let base_price = (self.starting_price as f64 * drift_factor).round() as i64;
+        let price = parameters.apply(base_price, offset);
 
         if price > self.max_price {
             return self.max_price;
@@ -535,13 +992,8 @@ impl Beverage {
             return self.min_price;
         }
 
-        // round to 10 cents
-        let mod_ten = price % 10;
-        if mod_ten >= 5 {
-            price + (10 - mod_ten)
-        } else {
-            price - mod_ten
-        }
+        // a coarse price step can round past the limits
+        currency::round(price, price_step).clamp(self.min_price, self.max_price)
     }
 
     /// set the current price
@@ -557,37 +1009,52 @@ impl Beverage {
 
 impl crate::validator::Validate<Beverage> for Beverage {
     fn validate(&self) -> Result<(), ServiceError> {
-        if self.slot_no < 0 {
-            bad_request!("the slot number cannot be negative");
-        }
+        first_violation(self.violations())
+    }
 
-        if self.min_price <= 0 {
-            bad_request!("the minimum price has to be above 0");
-        }
+    fn violations(&self) -> Vec<Violation> {
+        let mut violations = Violations::default();
 
-        if self.starting_price <= self.min_price {
-            bad_request!("the starting price should be bigger than the minimum price");
-        }
+        violations.check(self.slot_no >= 0, "slotNo", "the slot number cannot be negative");
 
-        if self.max_price <= self.starting_price {
-            bad_request!("the the maximum price should be bigger than the starting price");
-        }
+        violations.check(self.min_price > 0, "minPrice", "the minimum price has to be above 0");
+        violations.check(
+            self.starting_price > self.min_price,
+            "startingPrice",
+            "the starting price should be bigger than the minimum price",
+        );
+        violations.check(
+            self.max_price > self.starting_price,
+            "maxPrice",
+            "the the maximum price should be bigger than the starting price",
+        );
 
         if let Some(url) = self.image_url.as_ref() {
-            if Url::parse(url).is_err() {
-                bad_request!("the image url is not a valid url");
-            }
+            violations.check(Url::parse(url).is_ok(), "imageUrl", "the image url is not a valid url");
         }
 
-        if self.name.trim().is_empty() {
-            bad_request!("name is too short");
+        violations.check(!self.name.trim().is_empty(), "name", "name is too short");
+        violations.check(self.name.trim().len() <= 40, "name", "name is too long, maximum 40 characters");
+
+        if let Some(cost_price) = self.cost_price {
+            violations.check(cost_price >= 0, "costPrice", "the cost price cannot be negative");
         }
 
-        if self.name.trim().len() > 40 {
-            bad_request!("name is too long, maximum 40 characters");
+        violations.into_inner()
+    }
+
+    fn warnings(&self) -> Vec<Violation> {
+        let mut warnings = Violations::default();
+
+        if let Some(cost_price) = self.cost_price {
+            warnings.check(
+                cost_price <= self.min_price,
+                "costPrice",
+                "the cost price is above the minimum price, the beverage can be sold at a loss",
+            );
         }
 
-        Ok(())
+        warnings.into_inner()
     }
 }
 
@@ -597,6 +1064,46 @@ mod tests {
     use crate::validator::Validator;
     use std::ops::Add;
 
+    #[test]
+    fn time_zone_aware_duration() {
+        use crate::validator::Validate;
+
+        // 18:00 until 18:00 the next day in Brussels takes 25 hours when the clocks go back
+        let mut game = CreateGame {
+            name: String::from("some_name"),
+            owner_id: 1,
+            start_time: "2026-10-24T16:00:00Z".parse().unwrap(),
+            close_time: "2026-10-25T17:00:00Z".parse().unwrap(),
+            beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: String::from("Europe/Brussels"),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
+        };
+        let violates = |game: &CreateGame, field: &str| game.violations().iter().any(|v| v.field == Some(field));
+
+        assert!(!violates(&game, "closeTime"));
+        assert!(!violates(&game, "timeZone"));
+
+        game.time_zone = timezone::default_time_zone();
+        assert!(violates(&game, "closeTime"));
+
+        game.time_zone = String::from("Europe/Atlantis");
+        assert!(violates(&game, "timeZone"));
+    }
+
     #[test]
     fn invalid_game_duration() {
         let time: DateTime<Utc> = Utc::now().add(Duration::days(1));
@@ -609,6 +1116,22 @@ mod tests {
             start_time: time,
             close_time: time,
             beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
         };
 
         let game_with_smaller_end_time = CreateGame {
@@ -617,6 +1140,22 @@ mod tests {
             start_time: time,
             close_time: smaller_time,
             beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
         };
 
         let game_with_equal_bigger_end_time = CreateGame {
@@ -625,6 +1164,22 @@ mod tests {
             start_time: smaller_time,
             close_time: time,
             beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
         };
 
         assert!(Validator::new(game_with_same_times).validate().is_err());
@@ -648,6 +1203,22 @@ mod tests {
             start_time,
             close_time,
             beverage_count: 8,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
         };
 
         assert!(Validator::new(game.clone()).validate().is_ok());
@@ -674,10 +1245,65 @@ mod tests {
             slot_no: 0,
             user_id: 0,
             current_price: 250,
+            alcoholic: true,
+            cost_price: None,
+        };
+
+        assert!(beverage.calculate_price(500, 1.0, 10, &PriceParameters::default()) <= beverage.max_price);
+        assert!(beverage.calculate_price(-500, 1.0, 10, &PriceParameters::default()) >= beverage.min_price);
+        assert_eq!(beverage.calculate_price(0, 1.0, 10, &PriceParameters::default()), 250);
+        assert_eq!(beverage.calculate_price(0, 1.2, 10, &PriceParameters::default()), 300);
+        assert_eq!(beverage.calculate_price(0, 10.0, 10, &PriceParameters::default()), beverage.max_price);
+        assert_eq!(beverage.calculate_price(0, 1.0, 50, &PriceParameters::default()), 250);
+        // rounds up past the maximum price
+        assert_eq!(beverage.calculate_price(0, 1.95, 300, &PriceParameters::default()), beverage.max_price);
+    }
+
+    #[test]
+    fn all_beverage_violations() {
+        use crate::validator::Validate;
+
+        let beverage = Beverage {
+            game_id: 1,
+            name: String::new(),
+            image_url: None,
+            max_price: 200,
+            min_price: 0,
+            starting_price: 250,
+            slot_no: 0,
+            user_id: 0,
+            current_price: 250,
+            alcoholic: true,
+            cost_price: None,
         };
 
-        assert!(beverage.calculate_price(500) <= beverage.max_price);
-        assert!(beverage.calculate_price(-500) >= beverage.min_price);
+        let fields: Vec<_> = beverage.violations().into_iter().filter_map(|violation| violation.field).collect();
+        assert_eq!(fields, vec!["minPrice", "maxPrice", "name"]);
+        assert!(beverage.validate().is_err());
+    }
+
+    #[test]
+    fn price_drift() {
+        let start_time: DateTime<Utc> = Utc::now();
+        let mut game = Game {
+            start_time,
+            ..Game::fixture()
+        };
+
+        assert_eq!(game.drift_factor(start_time.add(Duration::hours(1))), 1.0);
+
+        game.drift_percentage = Some(10.0);
+        game.drift_interval = Some(30 * 60);
+
+        assert_eq!(game.drift_factor(start_time.add(Duration::minutes(-10))), 1.0);
+        assert_eq!(game.drift_factor(start_time.add(Duration::minutes(29))), 1.0);
+        assert!((game.drift_factor(start_time.add(Duration::minutes(30))) - 1.1).abs() < 1e-9);
+        assert!((game.drift_factor(start_time.add(Duration::minutes(61))) - 1.21).abs() < 1e-9);
+        // the drift stops when the game is finished
+        assert_eq!(
+            game.drift_factor(start_time.add(Duration::hours(10))),
+            game.drift_factor(start_time.add(Duration::hours(2)))
+        );
     }
 
     #[test]
@@ -690,6 +1316,22 @@ mod tests {
             name: String::from("some game"),
             start_time: start_time,
             close_time: close_time,
+            max_slot_quantity: None,
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
         };
 
         assert!(Validator::new(game.clone()).validate().is_err());
@@ -703,4 +1345,74 @@ mod tests {
         game.beverage_count = 2;
         assert!(Validator::new(game.clone()).validate().is_ok());
     }
+
+    #[test]
+    fn valid_purchase_limits() {
+        let start_time: DateTime<Utc> = Utc::now().add(Duration::days(1));
+        let close_time = start_time.add(Duration::hours(1));
+        let mut game = CreateGame {
+            owner_id: 1,
+            beverage_count: 8,
+            name: String::from("some game"),
+            start_time,
+            close_time,
+            max_slot_quantity: Some(0),
+            max_window_quantity: None,
+            quantity_window: None,
+            purchase_cooldown: None,
+            throttle_suspicious_users: false,
+            drift_percentage: None,
+            drift_interval: None,
+            predictions_enabled: false,
+            points_budget: None,
+            time_zone: timezone::default_time_zone(),
+            venue_name: None,
+            venue_address: None,
+            latitude: None,
+            longitude: None,
+            currency: currency::default_currency(),
+            price_step: currency::DEFAULT_PRICE_STEP,
+        };
+
+        assert!(Validator::new(game.clone()).validate().is_err());
+
+        game.max_slot_quantity = Some(10);
+        assert!(Validator::new(game.clone()).validate().is_ok());
+
+        game.max_window_quantity = Some(20);
+        assert!(Validator::new(game.clone()).validate().is_err());
+
+        game.quantity_window = Some(60 * 15);
+        assert!(Validator::new(game.clone()).validate().is_ok());
+    }
+
+    #[test]
+    fn update_game() {
+        let update = |changes: serde_json::Value, game: Game| {
+            serde_json::from_value::<UpdateGame>(changes).unwrap().apply(game)
+        };
+        let game = Game {
+            start_time: Utc::now() + Duration::hours(1),
+            max_slot_quantity: Some(5),
+            ..Game::fixture()
+        };
+
+        // the fields that are left out keep their value, null clears them
+        let updated = update(serde_json::json!({ "id": 1, "name": "renamed" }), game.clone()).unwrap();
+        assert_eq!(updated.name, "renamed");
+        assert_eq!(updated.max_slot_quantity, Some(5));
+        let updated = update(serde_json::json!({ "id": 1, "maxSlotQuantity": null }), game.clone()).unwrap();
+        assert_eq!(updated.max_slot_quantity, None);
+
+        assert!(update(serde_json::json!({ "id": 1, "maxWindowQuantity": 10 }), game.clone()).is_err());
+        assert!(update(serde_json::json!({ "id": 1, "maxWindowQuantity": 10, "quantityWindow": 60 }), game.clone()).is_ok());
+        assert!(update(serde_json::json!({ "id": 1, "driftPercentage": 80.0, "driftInterval": 60 }), game.clone()).is_err());
+
+        assert!(update(serde_json::json!({ "id": 1, "currency": "USD" }), game.clone()).is_ok());
+        let started = Game { start_time: Utc::now(), ..game };
+        assert!(update(serde_json::json!({ "id": 1, "currency": "USD" }), started.clone()).is_err());
+        assert!(update(serde_json::json!({ "id": 1, "pointsBudget": 100 }), started.clone()).is_err());
+        // sending the stored economy back is fine
+        assert!(update(serde_json::json!({ "id": 1, "currency": started.currency, "priceStep": started.price_step }), started).is_ok());
+    }
 }
@@ -0,0 +1,161 @@
+//! Suggest the price range of a beverage
+//!
+//! Every sale above the average raises the price by 5% of the starting price, and the drift of the game
+//! moves the whole range, so a range that's too narrow gets pinned to the ceiling in the first minutes.
+//! The range is based on how far the prices moved in the games that have already finished.
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+/// the relative price change of a single sale above or below the average
+const PRICE_STEP: f64 = 0.05;
+/// the amount of price steps a beverage is expected to move without any history
+const DEFAULT_STEPS: f64 = 10.0;
+/// the minimum amount of historical prices before the history is trusted
+const MIN_SAMPLES: i64 = 100;
+/// extra room on top of the historical volatility
+const HEADROOM: f64 = 1.25;
+const MIN_SPREAD: f64 = 0.2;
+/// the minimum price can't go below 10% of the starting price
+const MAX_SPREAD: f64 = 0.9;
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceRange {
+    pub starting_price: i64,
+    pub min_price: i64,
+    pub max_price: i64,
+    /// how far the prices move from their starting price, relative to the starting price
+    pub volatility: f64,
+    /// the amount of historical prices the volatility is based on,
+    /// zero when there wasn't enough history and the default volatility is used
+    pub samples: i64,
+}
+
+impl PriceRange {
+    /// Suggest a price range for a beverage of a game
+    #[tracing::instrument(name = "PriceRange::suggest", skip(game, db))]
+    pub async fn suggest(
+        game: &Game,
+        starting_price: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<PriceRange, ServiceError> {
+        if starting_price <= 0 {
+            bad_request!("the starting price has to be above 0");
+        }
+
+        let history = sqlx::query!(
+            r#"
+            SELECT
+                percentile_cont(0.9) WITHIN GROUP (
+                    ORDER BY ABS(price_histories.price - beverages.starting_price)::float8 / beverages.starting_price
+                ) as volatility,
+                COUNT(*) as "samples!"
+            FROM price_histories
+            INNER JOIN beverages ON beverages.game_id = price_histories.game_id
+                AND beverages.user_id = price_histories.user_id
+                AND beverages.slot_no = price_histories.slot_no
+            INNER JOIN games ON games.id = price_histories.game_id
+            WHERE games.close_time < NOW() AND beverages.starting_price > 0
+            "#
+        )
+        .fetch_one(db)
+        .await?;
+
+        let volatility = match history.volatility {
+            Some(volatility) if history.samples >= MIN_SAMPLES => Some(volatility),
+            _ => None,
+        };
+
+        Ok(PriceRange::calculate(
+            starting_price,
+            game.drift_factor(game.close_time),
+            volatility,
+            history.samples,
+            game.price_step,
+        ))
+    }
+
+    fn calculate(
+        starting_price: i64,
+        drift_factor: f64,
+        volatility: Option<f64>,
+        samples: i64,
+        price_step: i64,
+    ) -> PriceRange {
+        let (volatility, samples) = match volatility {
+            Some(volatility) => (volatility, samples),
+            None => (PRICE_STEP * DEFAULT_STEPS, 0),
+        };
+        let spread = (volatility * HEADROOM).clamp(MIN_SPREAD, MAX_SPREAD);
+
+        let starting = starting_price as f64;
+        // a rising drift needs room above, a falling drift below
+        let min_price = round(
+            starting * drift_factor.min(1.0) * (1.0 - spread),
+            price_step,
+        );
+        let max_price = round(
+            starting * drift_factor.max(1.0) * (1.0 + spread),
+            price_step,
+        );
+
+        PriceRange {
+            starting_price,
+            min_price: min_price.max(1).min(starting_price - 1),
+            max_price: max_price.max(starting_price + 1),
+            volatility,
+            samples,
+        }
+    }
+}
+
+/// round to the price step of the game, like the beverage prices
+fn round(price: f64, price_step: i64) -> i64 {
+    let price_step = price_step as f64;
+    ((price / price_step).round() * price_step) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_price_range() {
+        let range = PriceRange::calculate(200, 1.0, None, 12, 10);
+        assert_eq!(range.min_price, 80);
+        assert_eq!(range.max_price, 330);
+        assert_eq!(range.samples, 0);
+    }
+
+    #[test]
+    fn historical_price_range() {
+        let range = PriceRange::calculate(200, 1.0, Some(0.1), 500, 10);
+        assert_eq!(range.min_price, 160);
+        assert_eq!(range.max_price, 240);
+
+        let range = PriceRange::calculate(200, 1.0, Some(3.0), 500, 10);
+        assert_eq!(range.min_price, 20);
+        assert_eq!(range.max_price, 380);
+    }
+
+    #[test]
+    fn drifting_price_range() {
+        let range = PriceRange::calculate(200, 1.5, Some(0.2), 500, 10);
+        assert_eq!(range.min_price, 150);
+        assert_eq!(range.max_price, 380);
+
+        let range = PriceRange::calculate(200, 0.5, Some(0.2), 500, 10);
+        assert_eq!(range.min_price, 80);
+        assert_eq!(range.max_price, 250);
+    }
+
+    #[test]
+    fn valid_price_range() {
+        let range = PriceRange::calculate(5, 1.0, None, 0, 10);
+        assert!(range.min_price > 0);
+        assert!(range.min_price < range.starting_price);
+        assert!(range.max_price > range.starting_price);
+    }
+}
@@ -0,0 +1,304 @@
+//! Replays the price evolution and the sales of a finished game
+//!
+//! The events are streamed as server-sent events, with the time between them
+//! scaled down so the whole game fits in the requested replay duration.
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures::future;
+use futures::stream::{self, LocalBoxStream, StreamExt};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+/// Default length of a replay in seconds
+const DEFAULT_REPLAY_DURATION: u64 = 120;
+/// Maximum length of a replay in seconds
+const MAX_REPLAY_DURATION: u64 = 60 * 10;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayOptions {
+    /// the length of the replay in seconds, defaults to 2 minutes
+    pub duration: Option<u64>,
+}
+
+impl ReplayOptions {
+    fn duration(&self) -> Result<Duration, ServiceError> {
+        let duration = self.duration.unwrap_or(DEFAULT_REPLAY_DURATION);
+
+        if duration == 0 || duration > MAX_REPLAY_DURATION {
+            bad_request!(format!(
+                "the replay duration should be between 1 and {} seconds",
+                MAX_REPLAY_DURATION
+            ));
+        }
+
+        Ok(Duration::from_secs(duration))
+    }
+}
+
+/// Something that happened during the game
+#[derive(Debug, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ReplayEvent {
+    /// The price of a beverage changed
+    #[serde(rename_all = "camelCase")]
+    PriceUpdate {
+        slot_no: i16,
+        price: i64,
+        tick: i64,
+        created_at: DateTime<Utc>,
+    },
+    /// Someone purchased a beverage
+    #[serde(rename_all = "camelCase")]
+    Sale {
+        slot_no: i16,
+        amount: i32,
+        price: i64,
+        created_at: DateTime<Utc>,
+    },
+}
+
+impl ReplayEvent {
+    fn created_at(&self) -> DateTime<Utc> {
+        match self {
+            ReplayEvent::PriceUpdate { created_at, .. } => *created_at,
+            ReplayEvent::Sale { created_at, .. } => *created_at,
+        }
+    }
+
+    /// format the event as a server-sent event
+    fn to_sse(&self) -> Result<Bytes, ServiceError> {
+        let data = serde_json::to_string(self).map_err(|e| {
+            error!("unable to serialize replay event: {}", e);
+            ServiceError::InternalServerError
+        })?;
+
+        let name = match self {
+            ReplayEvent::PriceUpdate { .. } => "priceUpdate",
+            ReplayEvent::Sale { .. } => "sale",
+        };
+
+        Ok(Bytes::from(format!("event: {}\ndata: {}\n\n", name, data)))
+    }
+}
+
+/// The events of a replay are loaded in pages this size, so a long game doesn't end up in memory
+///
+/// A replay takes minutes, so it doesn't keep a query open while it waits between the events.
+const PAGE_SIZE: i64 = 500;
+
+#[derive(Debug)]
+pub struct Replay {
+    game: Game,
+    user_id: i64,
+    db: Pool<Postgres>,
+}
+
+/// The events that are loaded but not replayed yet, merged in the order they happened
+#[derive(Debug)]
+struct Pages {
+    game_id: i64,
+    user_id: i64,
+    db: Pool<Postgres>,
+    prices: VecDeque<ReplayEvent>,
+    /// the time and the id of the last loaded price update
+    price_cursor: Option<(DateTime<Utc>, i64)>,
+    prices_done: bool,
+    sales: VecDeque<ReplayEvent>,
+    /// the time of the order and the id of the last loaded sale
+    sale_cursor: Option<(DateTime<Utc>, i64)>,
+    sales_done: bool,
+}
+
+impl Pages {
+    /// the next event, price updates are replayed before sales happening at the same time
+    async fn next(&mut self) -> Result<Option<ReplayEvent>, sqlx::Error> {
+        if self.prices.is_empty() && !self.prices_done {
+            self.load_prices().await?;
+        }
+        if self.sales.is_empty() && !self.sales_done {
+            self.load_sales().await?;
+        }
+
+        let price_first = match (self.prices.front(), self.sales.front()) {
+            (Some(price), Some(sale)) => price.created_at() <= sale.created_at(),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => return Ok(None),
+        };
+
+        if price_first {
+            Ok(self.prices.pop_front())
+        } else {
+            Ok(self.sales.pop_front())
+        }
+    }
+
+    async fn load_prices(&mut self) -> Result<(), sqlx::Error> {
+        let after = self.price_cursor.map(|(at, _)| at);
+        let after_id = self.price_cursor.map(|(_, id)| id);
+        let records = sqlx::query!(
+            r#"
+            SELECT id, slot_no, price, tick, created_at FROM price_histories
+            WHERE game_id = $1 AND user_id = $2
+                AND ($3::timestamptz IS NULL OR (created_at, id) > ($3, $4))
+            ORDER BY created_at, id
+            LIMIT $5
+            "#,
+            self.game_id,
+            self.user_id,
+            after,
+            after_id,
+            PAGE_SIZE
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        self.prices_done = (records.len() as i64) < PAGE_SIZE;
+        if let Some(record) = records.last() {
+            self.price_cursor = Some((record.created_at, record.id));
+        }
+        self.prices
+            .extend(records.into_iter().map(|record| ReplayEvent::PriceUpdate {
+                slot_no: record.slot_no,
+                price: record.price,
+                tick: record.tick,
+                created_at: record.created_at,
+            }));
+
+        Ok(())
+    }
+
+    async fn load_sales(&mut self) -> Result<(), sqlx::Error> {
+        let after = self.sale_cursor.map(|(at, _)| at);
+        let after_id = self.sale_cursor.map(|(_, id)| id);
+        let records = sqlx::query!(
+            r#"
+            SELECT transactions.id, transactions.slot_no, transactions.amount, transactions.price, orders.created_at
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            WHERE orders.game_id = $1
+                AND ($2::timestamptz IS NULL OR (orders.created_at, transactions.id) > ($2, $3))
+            ORDER BY orders.created_at, transactions.id
+            LIMIT $4
+            "#,
+            self.game_id,
+            after,
+            after_id,
+            PAGE_SIZE
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        self.sales_done = (records.len() as i64) < PAGE_SIZE;
+        if let Some(record) = records.last() {
+            self.sale_cursor = Some((record.created_at, record.id));
+        }
+        self.sales
+            .extend(records.into_iter().map(|record| ReplayEvent::Sale {
+                slot_no: record.slot_no,
+                amount: record.amount,
+                price: record.price,
+                created_at: record.created_at,
+            }));
+
+        Ok(())
+    }
+}
+
+impl Replay {
+    /// Replay the price changes of the user's beverages and all the sales of a game
+    pub fn new(game: Game, user_id: i64, db: &Pool<Postgres>) -> Result<Replay, ServiceError> {
+        if !game.is_finished() {
+            bad_request!("only finished games can be replayed");
+        }
+
+        Ok(Replay {
+            game,
+            user_id,
+            db: db.clone(),
+        })
+    }
+
+    /// Stream the events, spread over `duration`
+    pub fn stream(
+        self,
+        options: &ReplayOptions,
+    ) -> Result<LocalBoxStream<'static, Result<Bytes, ServiceError>>, ServiceError> {
+        let scale = Replay::scale(&self.game, options.duration()?);
+
+        let pages = Pages {
+            game_id: self.game.id,
+            user_id: self.user_id,
+            db: self.db,
+            prices: VecDeque::new(),
+            price_cursor: None,
+            prices_done: false,
+            sales: VecDeque::new(),
+            sale_cursor: None,
+            sales_done: false,
+        };
+
+        let events = stream::unfold(
+            Some((pages, self.game.start_time)),
+            move |state| async move {
+                let (mut pages, previous) = state?;
+                let event = match pages.next().await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        error!("unable to load the replay events: {}", e);
+                        return Some((Err(ServiceError::InternalServerError), None));
+                    }
+                };
+
+                let gap = (event.created_at() - previous).to_std().unwrap_or_default();
+                actix_rt::time::delay_for(gap.mul_f64(scale)).await;
+
+                let created_at = event.created_at();
+                Some((event.to_sse(), Some((pages, created_at))))
+            },
+        );
+
+        let end = stream::once(future::ok(Bytes::from_static(b"event: end\ndata: {}\n\n")));
+
+        Ok(events.chain(end).boxed_local())
+    }
+
+    /// the factor used to speed up the game
+    fn scale(game: &Game, duration: Duration) -> f64 {
+        match (game.close_time - game.start_time).to_std() {
+            Ok(game_duration) if game_duration.as_secs_f64() > 0.0 => {
+                duration.as_secs_f64() / game_duration.as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_duration() {
+        let options = ReplayOptions { duration: None };
+        assert_eq!(
+            options.duration().unwrap(),
+            Duration::from_secs(DEFAULT_REPLAY_DURATION)
+        );
+
+        let options = ReplayOptions { duration: Some(0) };
+        assert!(options.duration().is_err());
+
+        let options = ReplayOptions {
+            duration: Some(MAX_REPLAY_DURATION + 1),
+        };
+        assert!(options.duration().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Public results of a finished game
+//!
+//! The owner can share the results without the visitors needing an account.
+//! The usernames of the players are only shown when the owner opted in.
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShareOptions {
+    #[serde(default)]
+    pub show_usernames: bool,
+}
+
+/// The secret that gives read access to the results of a game
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedResults {
+    pub token: String,
+    pub show_usernames: bool,
+    /// the path of the results, relative to the api host
+    pub path: String,
+}
+
+/// The sanitized results of a game
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultsSummary {
+    pub name: String,
+    pub start_time: DateTime<Utc>,
+    pub close_time: DateTime<Utc>,
+    pub time_zone: String,
+    pub participants: i64,
+    pub beverages_sold: i64,
+    pub revenue: i64,
+    pub crash_count: i64,
+    pub beverages: Vec<BeverageResult>,
+    pub players: Vec<PlayerResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BeverageResult {
+    pub slot_no: i16,
+    /// the name the game owner gave the beverage
+    pub name: Option<String>,
+    pub sales: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerResult {
+    pub rank: usize,
+    /// empty unless the owner opted in to show the usernames
+    pub username: Option<String>,
+    pub beverages: i64,
+}
+
+impl SharedResults {
+    fn new(token: String, show_usernames: bool) -> Self {
+        SharedResults {
+            path: format!("/api/results/{}", token),
+            token,
+            show_usernames,
+        }
+    }
+
+    /// create a new token for the results of a finished game, the previous one stops working
+    #[tracing::instrument(name = "SharedResults::share", skip(game, db))]
+    pub async fn share(
+        game: &Game,
+        options: &ShareOptions,
+        db: &Pool<Postgres>,
+    ) -> Result<SharedResults, ServiceError> {
+        if !game.is_finished() {
+            bad_request!("only the results of finished games can be shared");
+        }
+
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        sqlx::query!(
+            r#"
+            INSERT INTO shared_results (game_id, token, show_usernames)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (game_id) DO UPDATE SET token = $2, show_usernames = $3, created_at = NOW()
+            "#,
+            game.id,
+            token,
+            options.show_usernames
+        )
+        .execute(db)
+        .await?;
+
+        Ok(SharedResults::new(token, options.show_usernames))
+    }
+
+    #[tracing::instrument(name = "SharedResults::revoke", skip(db))]
+    pub async fn revoke(game_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM shared_results WHERE game_id = $1", game_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl ResultsSummary {
+    /// the results the token gives access to
+    #[tracing::instrument(name = "ResultsSummary::find", skip(token, db))]
+    pub async fn find(token: &str, db: &Pool<Postgres>) -> Result<ResultsSummary, sqlx::Error> {
+        let game = sqlx::query!(
+            r#"
+            SELECT games.id, games.name, games.start_time, games.close_time, games.time_zone, games.owner_id,
+                shared_results.show_usernames,
+                (SELECT COUNT(*) FROM user_sales WHERE user_sales.game_id = games.id AND user_sales.sales > 0) AS "participants!",
+                (SELECT COALESCE(SUM(user_sales.spent), 0)::BIGINT FROM user_sales WHERE user_sales.game_id = games.id) AS "revenue!",
+                (SELECT COUNT(*) FROM market_crashes WHERE market_crashes.game_id = games.id) AS "crash_count!"
+            FROM shared_results
+            INNER JOIN games ON games.id = shared_results.game_id
+            WHERE shared_results.token = $1
+            "#,
+            token
+        )
+        .fetch_one(db)
+        .await?;
+
+        let beverages: Vec<BeverageResult> = sqlx::query_as!(
+            BeverageResult,
+            r#"
+            SELECT sales_counts.slot_no, beverages.name AS "name?", sales_counts.sales
+            FROM sales_counts
+            LEFT JOIN beverages ON beverages.game_id = sales_counts.game_id
+                AND beverages.slot_no = sales_counts.slot_no AND beverages.user_id = $2
+            WHERE sales_counts.game_id = $1
+            ORDER BY sales_counts.slot_no
+            "#,
+            game.id,
+            game.owner_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let players = sqlx::query!(
+            r#"
+            SELECT users.username, user_sales.sales
+            FROM user_sales
+            INNER JOIN users ON users.id = user_sales.user_id
+            WHERE user_sales.game_id = $1 AND user_sales.sales > 0
+            ORDER BY user_sales.sales DESC, users.id
+            "#,
+            game.id
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .enumerate()
+        .map(|(index, player)| PlayerResult {
+            rank: index + 1,
+            username: Some(player.username).filter(|_| game.show_usernames),
+            beverages: player.sales,
+        })
+        .collect();
+
+        Ok(ResultsSummary {
+            name: game.name,
+            start_time: game.start_time,
+            close_time: game.close_time,
+            time_zone: game.time_zone,
+            participants: game.participants,
+            beverages_sold: beverages.iter().map(|beverage| beverage.sales).sum(),
+            revenue: game.revenue,
+            crash_count: game.crash_count,
+            beverages,
+            players,
+        })
+    }
+}
@@ -1,20 +1,45 @@
-use actix_identity::Identity;
 use actix_web::http::StatusCode;
 use actix_web::web;
 use actix_web::web::{Data, HttpResponse, Json, Path, Query};
-use actix_web::{delete, get, post, put};
+use actix_web::{delete, get, post, put, HttpRequest};
+use chrono::{DateTime, Utc};
 
 use crate::auth;
-use crate::games::models::{Beverage, CreateGame, Game, GameFilter};
-use crate::market::PriceHistory;
+use crate::auth::Identity;
+use crate::events::DomainEvent;
+use crate::games::active::GameParam;
+use crate::games::blackouts::{Blackout, NewBlackout};
+use crate::games::devices::{Device, NewDevice, Viewer};
+use crate::games::drafts::{Draft, DraftSettings};
+use crate::games::location::Near;
+use crate::games::models::{Beverage, Game, GameFilter, UpdateGame};
+use crate::games::results::{ResultsSummary, ShareOptions, SharedResults};
+use crate::games::rules::{HouseRules, NewHouseRules};
+use crate::games::series::{GameSeries, NewGame};
+use crate::games::slot_groups::{NewSlotGroup, SlotGroup};
+use crate::games::templates::{NewTemplate, Template, TemplateParam};
+use crate::games::update_interval::{NewUpdateInterval, UpdateInterval};
+use crate::games::waitlist::{self, Capacity, NewCapacity, WaitlistEntry};
+use crate::games::{PriceRange, Replay, ReplayOptions, Suggestion};
+use crate::guests::Guest;
+use crate::invitations::UserInvite;
+use crate::market::{Market, PriceHistory, PriceHistoryFilter, Resolution};
+use crate::response_cache;
 use crate::server::{self, State};
-use crate::validator::Validator;
+use crate::streaming;
+use crate::validator::{Preview, Validator};
+use crate::websocket::server::GameId;
 
 #[get("/games")]
 async fn find_all(query: Query<GameFilter>, state: Data<State>, id: Identity) -> server::Response {
     let user = auth::get_user(&id)?;
 
-    let games;
+    let near = match query.near.as_deref() {
+        Some(center) => Some(Near::new(center, query.radius)?),
+        None => None,
+    };
+
+    let mut games;
 
     if user.is_admin {
         debug!("user is admin, showing all games");
@@ -23,48 +48,318 @@ async fn find_all(query: Query<GameFilter>, state: Data<State>, id: Identity) ->
         games = Game::find_by_user(user.id, query.into_inner(), &state.db).await?;
     }
 
-    http_ok_json!(games);
+    if let Some(near) = near {
+        games.retain(|game| near.contains(game));
+    }
+
+    Ok(response_cache::cacheable(HttpResponse::Ok().json(games)))
+}
+
+/// List the drafts of the current user
+#[get("/games/drafts")]
+async fn find_drafts(state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let drafts = Draft::find_by_owner(user.id, &state.db).await?;
+
+    http_ok_json!(drafts);
+}
+
+/// Start setting up a game, the schedule can be added later on
+#[post("/games/drafts")]
+async fn create_draft(
+    settings: Json<Validator<DraftSettings>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    Guest::forbid(user.id, &state.db).await?;
+    let settings = settings.into_inner().validate()?;
+
+    let draft = Draft::create(user.id, &settings, &state.db).await?;
+
+    http_created_json!(draft);
+}
+
+#[get("/games/drafts/{id}")]
+async fn find_draft(draft_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let draft = Draft::find(*draft_id, user.id, &state.db)
+        .await?
+        .with_staging(&state.db)
+        .await?;
+
+    http_ok_json!(draft);
+}
+
+#[put("/games/drafts/{id}")]
+async fn update_draft(
+    draft_id: Path<i64>,
+    settings: Json<Validator<DraftSettings>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let settings = settings.into_inner().validate()?;
+
+    let draft = Draft::find(*draft_id, user.id, &state.db).await?;
+    let draft = draft.update(&settings, &state.db).await?;
+
+    http_ok_json!(draft);
+}
+
+#[delete("/games/drafts/{id}")]
+async fn delete_draft(draft_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let draft = Draft::find(*draft_id, user.id, &state.db).await?;
+    draft.delete(&state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::OK))
+}
+
+/// Create or replace the beverage of a slot in a draft
+#[put("/games/drafts/{id}/beverages")]
+async fn save_draft_beverage(
+    draft_id: Path<i64>,
+    beverage: Json<Validator<Beverage>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let beverage = beverage.into_inner().validate()?;
+
+    let draft = Draft::find(*draft_id, user.id, &state.db).await?;
+    let beverage = draft.save_beverage(&beverage, &state.db).await?;
+
+    http_ok_json!(beverage);
+}
+
+/// Stage an invitation, it's sent once the draft is published
+#[post("/games/drafts/{id}/invitations")]
+async fn stage_invitation(
+    draft_id: Path<i64>,
+    invite: Json<UserInvite>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let user_id = match invite.user_id {
+        Some(user_id) => user_id,
+        None => bad_request!("only users with an account can be invited to a draft"),
+    };
+
+    let draft = Draft::find(*draft_id, user.id, &state.db).await?;
+    Guest::forbid(user_id, &state.db).await?;
+    draft.stage_invitation(user_id, &state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::CREATED))
+}
+
+#[delete("/games/drafts/{id}/invitations/{user_id}")]
+async fn unstage_invitation(
+    path: Path<(i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (draft_id, user_id) = path.into_inner();
+
+    let draft = Draft::find(draft_id, user.id, &state.db).await?;
+    draft.unstage_invitation(user_id, &state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::OK))
+}
+
+/// Turn a draft into a game and send the staged invitations
+#[post("/games/drafts/{id}/publish")]
+async fn publish_draft(draft_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let draft = Draft::find(*draft_id, user.id, &state.db).await?;
+    let published = draft.publish(&state.db).await?;
+    let game = published.game;
+
+    state.events.publish(DomainEvent::GameCreated(game.clone()));
+    for user_id in published.invited_users {
+        state.events.publish(DomainEvent::InvitationCreated {
+            game_id: GameId(game.id),
+            user_id,
+        });
+    }
+
+    http_created_json!(game.localized());
 }
 
 #[get("/games/{id}")]
 async fn find(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
     let user = auth::get_user(&id)?;
 
-    if !user.is_admin && !Game::verify_user_participation(*game_id, user.id, &state.db).await? {
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
         forbidden!("user is not in game");
     }
-    let game = Game::find_by_id(*game_id, &state.db).await?;
+    let game = state.games.find_by_id(*game_id).await?;
+
+    http_ok_json!(game.localized());
+}
+
+/// Store the game in the session, so the sales and stats endpoints accept `current` as game id
+#[post("/session/active-game/{id}")]
+async fn set_active_game(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("user is not in game");
+    }
+    let game = state.games.find_by_id(*game_id).await?;
+
+    auth::set_active_game(&id, Some(game.id))?;
+
+    http_ok_json!(game.localized());
+}
+
+#[delete("/session/active-game")]
+async fn clear_active_game(id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+
+    auth::set_active_game(&id, None)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Get the current state of the stock market of a game
+///
+/// Display devices can use this with their device token
+#[get("/games/{id}/market")]
+async fn market(
+    req: HttpRequest,
+    game_id: Path<i64>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let viewer = Viewer::identify(*game_id, &req, &id, &state.db).await?;
+
+    if let Viewer::User(user) = &viewer {
+        if !user.is_admin
+            && !state
+                .games
+                .verify_user_participation(*game_id, user.id)
+                .await?
+        {
+            forbidden!("user is not in game");
+        }
+    }
+    let game = state.games.find_by_id(*game_id).await?;
+
+    let market = Market::find(&game, &state.db).await?;
 
-    http_ok_json!(game);
+    http_ok_json!(market);
 }
 
+/// Create a game, with `?template_id=` the owner starts with the beverages of the template
 #[post("/games")]
 async fn create(
-    game: Json<Validator<CreateGame>>,
+    game: Json<Validator<NewGame>>,
+    template: Query<TemplateParam>,
     state: Data<State>,
     id: Identity,
 ) -> server::Response {
-    let mut game = game.into_inner().validate()?;
+    let NewGame {
+        game: mut new_game,
+        recurrence,
+    } = game.into_inner().validate()?;
 
-    game.owner_id = auth::get_user(&id)?.id;
+    new_game.owner_id = auth::get_user(&id)?.id;
+    Guest::forbid(new_game.owner_id, &state.db).await?;
 
-    let game = Game::create(game, &state.db, state.notifier.clone()).await?;
+    let template = match template.template_id {
+        Some(template_id) => {
+            let template = Template::find(template_id, new_game.owner_id, &state.db).await?;
+            template.check_fits(new_game.beverage_count)?;
+            Some(template)
+        }
+        None => None,
+    };
 
-    http_created_json!(game);
+    let game = Game::create(new_game, &state.db).await?;
+    if let Some(template) = template {
+        template.apply(&game, &state.db).await?;
+    }
+    if let Some(recurrence) = recurrence {
+        GameSeries::create(&game, recurrence, &state.db).await?;
+    }
+
+    state.events.publish(DomainEvent::GameCreated(game.clone()));
+
+    http_created_json!(game.localized());
+}
+
+#[get("/game-templates")]
+async fn find_templates(state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let templates = Template::find_by_owner(user.id, &state.db).await?;
+
+    http_ok_json!(templates);
+}
+
+/// Save the beverages you configured in a game as a template
+#[post("/game-templates")]
+async fn create_template(
+    template: Json<Validator<NewTemplate>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    Guest::forbid(user.id, &state.db).await?;
+    let template = template.into_inner().validate()?;
+
+    let template = template.save(user.id, &state.db).await?;
+
+    http_created_json!(template);
+}
+
+#[delete("/game-templates/{id}")]
+async fn delete_template(
+    template_id: Path<i64>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let template = Template::find(*template_id, user.id, &state.db).await?;
+    template.delete(&state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
 }
 
 #[put("/games")]
-async fn update(game: Json<Game>, state: Data<State>, id: Identity) -> server::Response {
+async fn update(changes: Json<UpdateGame>, state: Data<State>, id: Identity) -> server::Response {
     let user = auth::get_user(&id)?;
 
-    let old_game = Game::find_by_id(game.id, &state.db).await?;
+    let old_game = Game::find_by_id(changes.id, &state.db).await?;
     if old_game.owner_id != user.id && !user.is_admin {
-        forbidden!("Only game owners can delete games");
+        forbidden!("Only game owners can update games");
     }
 
+    let game = changes.into_inner().apply(old_game)?;
     let game = game.update(&state.db).await?;
 
-    http_ok_json!(game);
+    state.events.publish(DomainEvent::GameUpdated(game.clone()));
+
+    http_ok_json!(game.localized());
 }
 
 #[delete("/games/{id}")]
@@ -78,9 +373,468 @@ async fn delete(game_id: Path<i64>, state: Data<State>, id: Identity) -> server:
 
     game.delete(&state.db).await?;
 
+    state
+        .events
+        .publish(DomainEvent::GameDeleted(GameId(game.id)));
+
     Ok(HttpResponse::new(StatusCode::OK))
 }
 
+/// Compare the games of a recurring game with each other
+#[get("/games/{id}/series")]
+async fn series(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let stats = GameSeries::find_by_game(*game_id, &state.db)
+        .await?
+        .stats(&state.db)
+        .await?;
+
+    http_ok_json!(stats);
+}
+
+/// The seconds between the price updates of a game
+#[get("/games/{id}/update-interval")]
+async fn find_update_interval(
+    game_id: Path<i64>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let interval = UpdateInterval::find(*game_id, &state.db).await?;
+
+    http_ok_json!(interval);
+}
+
+/// Change the price update interval, the running game picks it up from its next price update
+#[put("/games/{id}/update-interval")]
+async fn save_update_interval(
+    game_id: Path<i64>,
+    interval: Json<Validator<NewUpdateInterval>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let interval = interval.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can change the update interval");
+    }
+
+    let interval = UpdateInterval::save(game.id, &interval, &state.db).await?;
+
+    http_ok_json!(interval);
+}
+
+/// The maximum amount of players of a game, and how many are playing or waiting
+#[get("/games/{id}/capacity")]
+async fn find_capacity(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let capacity = Capacity::find(*game_id, &state.db).await?;
+
+    http_ok_json!(capacity);
+}
+
+/// Change the maximum amount of players, waitlisted users take the places that open up
+#[put("/games/{id}/capacity")]
+async fn save_capacity(
+    game_id: Path<i64>,
+    capacity: Json<Validator<NewCapacity>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let capacity = capacity.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can change the maximum amount of players");
+    }
+
+    let promoted = Capacity::save(game.id, &capacity, &state.db).await?;
+    waitlist::publish_promotions(game.id, &promoted, &state.events);
+
+    let capacity = Capacity::find(game.id, &state.db).await?;
+
+    http_ok_json!(capacity);
+}
+
+/// The users waiting for a place in a full game, the next one first
+#[get("/games/{id}/waitlist")]
+async fn find_waitlist(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the waitlist");
+    }
+
+    let waitlist = WaitlistEntry::find(game.id, &state.db).await?;
+
+    http_ok_json!(waitlist);
+}
+
+/// Remove a player from a game, the next waitlisted user takes their place
+#[delete("/games/{id}/players/{user_id}")]
+async fn remove_player(
+    info: Path<(i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, user_id) = info.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can remove players");
+    }
+
+    if user_id == game.owner_id {
+        bad_request!("the game owner can't be removed from their game");
+    }
+
+    let departure = waitlist::leave(game.id, user_id, &state.db).await?;
+
+    state.events.publish(DomainEvent::InvitationResponded {
+        game_id: GameId(game.id),
+        user_id,
+        accepted: false,
+    });
+    waitlist::publish_promotions(game.id, &departure.promoted, &state.events);
+
+    http_ok_json!(departure.invitation);
+}
+
+/// The current and upcoming purchase blackouts of a game
+#[get("/games/{id}/blackouts")]
+async fn find_blackouts(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let blackouts = Blackout::find(*game_id, &state.db).await?;
+
+    http_ok_json!(blackouts);
+}
+
+/// Reject purchases for a while, the players are told when the blackout starts and ends
+#[post("/games/{id}/blackouts")]
+async fn create_blackout(
+    game_id: Path<i64>,
+    blackout: Json<Validator<NewBlackout>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let blackout = blackout.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can plan a blackout");
+    }
+
+    let blackout = blackout.save(game.id, &state.db).await?;
+
+    http_created_json!(blackout);
+}
+
+/// Cancel a blackout, the players can purchase again right away
+#[delete("/games/{id}/blackouts/{blackout_id}")]
+async fn delete_blackout(
+    info: Path<(i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, blackout_id) = info.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can cancel a blackout");
+    }
+
+    if let Some(blackout) = Blackout::delete(game.id, blackout_id, &state.db).await? {
+        state.events.publish(DomainEvent::BlackoutEnded(blackout));
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The slot groups of a game, with the price algorithm of every group
+#[get("/games/{id}/slot-groups")]
+async fn find_slot_groups(
+    game_id: Path<i64>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the slot groups");
+    }
+
+    let groups = SlotGroup::find(game.id, &state.db).await?;
+
+    http_ok_json!(groups);
+}
+
+/// Price some slots with a different algorithm, to compare it with the other slots
+#[post("/games/{id}/slot-groups")]
+async fn create_slot_group(
+    game_id: Path<i64>,
+    group: Json<Validator<NewSlotGroup>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let group = group.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can create a slot group");
+    }
+
+    let group = group.save(&game, &state.db).await?;
+
+    http_created_json!(group);
+}
+
+/// The slots of the group go back to the default price algorithm
+#[delete("/games/{id}/slot-groups/{group_id}")]
+async fn delete_slot_group(
+    info: Path<(i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, group_id) = info.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can delete a slot group");
+    }
+
+    SlotGroup::delete(&game, group_id, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The house rules of a game and when the current user acknowledged them
+#[get("/games/{id}/rules")]
+async fn find_rules(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let rules = HouseRules::find_for_player(*game_id, user.id, &state.db).await?;
+
+    http_ok_json!(rules);
+}
+
+#[put("/games/{id}/rules")]
+async fn save_rules(
+    game_id: Path<i64>,
+    rules: Json<Validator<NewHouseRules>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let rules = rules.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can change the house rules");
+    }
+
+    let rules = HouseRules::save(game.id, &rules, &state.db).await?;
+
+    http_ok_json!(rules);
+}
+
+#[delete("/games/{id}/rules")]
+async fn delete_rules(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can remove the house rules");
+    }
+
+    HouseRules::delete(game.id, &state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::OK))
+}
+
+/// Acknowledge the house rules, required before the first purchase
+#[post("/games/{id}/rules/acknowledge")]
+async fn acknowledge_rules(
+    game_id: Path<i64>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !state
+        .games
+        .verify_user_participation(*game_id, user.id)
+        .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    HouseRules::acknowledge(*game_id, user.id, &state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::OK))
+}
+
+/// Share the results of a finished game with a public url, the previous url stops working
+#[post("/games/{id}/share-results")]
+async fn share_results(
+    game_id: Path<i64>,
+    options: Option<Json<ShareOptions>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can share the results");
+    }
+
+    let options = options.map(Json::into_inner).unwrap_or_default();
+    let shared = SharedResults::share(&game, &options, &state.db).await?;
+
+    http_created_json!(shared);
+}
+
+#[delete("/games/{id}/share-results")]
+async fn unshare_results(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can stop sharing the results");
+    }
+
+    SharedResults::revoke(game.id, &state.db).await?;
+
+    Ok(HttpResponse::new(StatusCode::OK))
+}
+
+/// The shared results of a game, no account is needed
+#[get("/results/{token}")]
+async fn shared_results(token: Path<String>, state: Data<State>) -> server::Response {
+    let results = ResultsSummary::find(&token, &state.db).await?;
+
+    http_ok_json!(results);
+}
+
+/// Register a display device, the token is only returned once
+#[post("/games/{id}/devices")]
+async fn register_device(
+    game_id: Path<i64>,
+    device: Json<Validator<NewDevice>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let device = device.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can register devices");
+    }
+
+    let device = Device::register(game.id, &device, &state.db).await?;
+
+    http_created_json!(device);
+}
+
+/// The display devices of a game and when they were last seen
+#[get("/games/{id}/devices")]
+async fn find_devices(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the devices");
+    }
+
+    let devices = Device::find_active(game.id, &state.db).await?;
+
+    http_ok_json!(devices);
+}
+
+/// Revoke a device, its open websocket connections are closed
+#[delete("/games/{id}/devices/{device_id}")]
+async fn revoke_device(
+    path: Path<(i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, device_id) = path.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can revoke devices");
+    }
+
+    let device = Device::revoke(game.id, device_id, &state.db).await?;
+    state.events.publish(DomainEvent::DeviceRevoked {
+        game_id: GameId(game.id),
+        device_id: device.id,
+    });
+
+    http_ok_json!(device);
+}
+
 #[get("/games/{id}/beverages")]
 async fn get_beverages(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
     let user = auth::get_user(&id)?;
@@ -139,25 +893,269 @@ async fn update_beverage_config(
     http_created_json!(config);
 }
 
+#[derive(Deserialize)]
+struct SuggestionQuery {
+    q: String,
+}
+
+/// Suggest beverage names, based on a list of common drinks and the beverages the user configured before
+#[get("/beverages/suggest")]
+async fn suggest_beverages(
+    query: Query<SuggestionQuery>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let suggestions = Suggestion::find(user.id, &query.q, &state.db).await?;
+
+    http_ok_json!(suggestions);
+}
+
+#[derive(Deserialize)]
+struct PriceRangeQuery {
+    starting_price: i64,
+}
+
+/// Suggest a minimum and maximum price around the starting price of a beverage
+#[get("/games/{id}/beverages/suggest-range")]
+async fn suggest_price_range(
+    game_id: Path<i64>,
+    query: Query<PriceRangeQuery>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !state
+        .games
+        .verify_user_participation(*game_id, user.id)
+        .await?
+    {
+        forbidden!("you are not in this game");
+    }
+    let game = state.games.find_by_id(*game_id).await?;
+
+    let range = PriceRange::suggest(&game, query.starting_price, &state.db).await?;
+
+    http_ok_json!(range);
+}
+
+/// Display devices see the price history of the game owner
+///
+/// Clients that missed a price update load the ticks after the last one they received with `sinceTick`.
+/// The history is streamed while the game runs, the history of a finished game is cached instead.
 #[get("/games/{id}/stats/price-history")]
-async fn price_history(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+async fn price_history(
+    req: HttpRequest,
+    game: Path<GameParam>,
+    filter: Query<PriceHistoryFilter>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let game_id = game.resolve(&id)?;
+    let viewer = Viewer::identify(game_id, &req, &id, &state.db).await?;
+    let (user_id, since_tick) = (viewer.user_id(), filter.since_tick);
+
+    if state.games.find_by_id(game_id).await?.is_finished() {
+        let prices = PriceHistory::load(user_id, game_id, since_tick, &state.db).await?;
+
+        return Ok(response_cache::cacheable(HttpResponse::Ok().json(prices)));
+    }
+
+    let db = state.db.clone();
+    let prices = streaming::json_array(move |array| async move {
+        array
+            .send_all(PriceHistory::stream(user_id, game_id, since_tick, &db))
+            .await
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(prices))
+}
+
+/// The price history of every beverage of every player, to chart the prices of the competitors
+///
+/// The prices of a long game can be downsampled with `resolution`, like `60s` or `5m`.
+#[get("/games/{id}/stats/price-history/all")]
+async fn game_price_history(
+    game: Path<GameParam>,
+    filter: Query<Resolution>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
     let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    /// the name a player gave a beverage, without the prices the player configured
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct BeverageName {
+        user_id: i64,
+        slot_no: i16,
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct GamePriceHistory {
+        beverages: Vec<BeverageName>,
+        prices: Vec<PriceHistory>,
+    }
+
+    let resolution = filter.seconds()?;
+    let beverages = Beverage::find_by_game(game_id, &state.db)
+        .await?
+        .into_iter()
+        .map(|beverage| BeverageName {
+            user_id: beverage.user_id,
+            slot_no: beverage.slot_no,
+            name: beverage.name,
+        })
+        .collect();
+    let prices = PriceHistory::load_game(game_id, resolution, &state.db).await?;
+
+    http_ok_json!(GamePriceHistory { beverages, prices });
+}
+
+#[derive(Deserialize)]
+struct PriceMoment {
+    t: DateTime<Utc>,
+}
+
+/// The prices of every beverage at a moment, to settle disputes about what a purchase cost
+#[get("/games/{id}/prices/at")]
+async fn prices_at(
+    req: HttpRequest,
+    game: Path<GameParam>,
+    moment: Query<PriceMoment>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let game_id = game.resolve(&id)?;
+    let viewer = Viewer::identify(game_id, &req, &id, &state.db).await?;
+
+    if moment.t > Utc::now() {
+        bad_request!("the prices of the future aren't known yet");
+    }
 
-    let prices = PriceHistory::load(user.id, *game_id, &state.db).await?;
+    let prices = PriceHistory::effective_at(viewer.user_id(), game_id, moment.t, &state.db).await?;
 
     http_ok_json!(prices);
 }
 
+/// Replay the prices and sales of a finished game as a stream of server-sent events
+#[get("/games/{id}/replay")]
+async fn replay(
+    game: Path<GameParam>,
+    options: Query<ReplayOptions>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(game_id, user.id)
+            .await?
+    {
+        forbidden!("user is not in game");
+    }
+    let game = state.games.find_by_id(game_id).await?;
+
+    let events = Replay::new(game, user.id, &state.db)?.stream(&options)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(events))
+}
+
+/// List every rule a game payload breaks, without creating the game
+#[post("/validate/game")]
+async fn validate_game(game: Json<NewGame>, id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+
+    http_ok_json!(Preview::new(&game.into_inner()));
+}
+
+/// List every rule a beverage payload breaks, without storing the beverage
+#[post("/validate/beverage")]
+async fn validate_beverage(beverage: Json<Beverage>, id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+
+    http_ok_json!(Preview::new(&beverage.into_inner()));
+}
+
 pub fn register(cfg: &mut web::ServiceConfig) {
+    // the draft routes go first, or "drafts" would be matched as a game id
+    cfg.service(set_active_game);
+    cfg.service(clear_active_game);
+    cfg.service(find_drafts);
+    cfg.service(create_draft);
+    cfg.service(find_draft);
+    cfg.service(update_draft);
+    cfg.service(delete_draft);
+    cfg.service(save_draft_beverage);
+    cfg.service(stage_invitation);
+    cfg.service(unstage_invitation);
+    cfg.service(publish_draft);
+
     cfg.service(find_all);
     cfg.service(find);
+    cfg.service(market);
+    cfg.service(series);
+    cfg.service(find_update_interval);
+    cfg.service(save_update_interval);
+    cfg.service(find_blackouts);
+    cfg.service(create_blackout);
+    cfg.service(delete_blackout);
+    cfg.service(find_slot_groups);
+    cfg.service(create_slot_group);
+    cfg.service(delete_slot_group);
+    cfg.service(find_capacity);
+    cfg.service(save_capacity);
+    cfg.service(find_waitlist);
+    cfg.service(remove_player);
+    cfg.service(find_rules);
+    cfg.service(save_rules);
+    cfg.service(delete_rules);
+    cfg.service(acknowledge_rules);
+    cfg.service(share_results);
+    cfg.service(unshare_results);
+    cfg.service(shared_results);
+    cfg.service(register_device);
+    cfg.service(find_devices);
+    cfg.service(revoke_device);
     cfg.service(create);
+    cfg.service(find_templates);
+    cfg.service(create_template);
+    cfg.service(delete_template);
     cfg.service(update);
     cfg.service(delete);
 
     cfg.service(create_beverage);
     cfg.service(get_beverages);
     cfg.service(update_beverage_config);
+    cfg.service(suggest_beverages);
+    cfg.service(suggest_price_range);
 
     cfg.service(price_history);
+    cfg.service(game_price_history);
+    cfg.service(prices_at);
+    cfg.service(replay);
+
+    cfg.service(validate_game);
+    cfg.service(validate_beverage);
 }
@@ -0,0 +1,194 @@
+//! The house rules of a game
+//!
+//! The owner can attach markdown rules to a game, the players have to acknowledge them
+//! before their first purchase.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+
+/// the maximum length of the rules in bytes
+const MAX_RULES_LENGTH: usize = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HouseRules {
+    pub game_id: i64,
+    /// markdown, it's up to the clients to render it
+    pub body: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewHouseRules {
+    pub body: String,
+}
+
+/// The rules of a game for a specific player
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerRules {
+    #[serde(flatten)]
+    pub rules: HouseRules,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+}
+
+impl crate::validator::Validate<NewHouseRules> for NewHouseRules {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.body.trim().is_empty() {
+            bad_request!("the rules are empty, remove them instead");
+        }
+        if self.body.len() > MAX_RULES_LENGTH {
+            bad_request!("the rules are too long, maximum 10000 characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl HouseRules {
+    #[tracing::instrument(name = "HouseRules::find", skip(db))]
+    pub async fn find(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<HouseRules>, sqlx::Error> {
+        sqlx::query_as!(
+            HouseRules,
+            "SELECT game_id, body, updated_at FROM game_rules WHERE game_id = $1",
+            game_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// the rules and when the player acknowledged them
+    #[tracing::instrument(name = "HouseRules::find_for_player", skip(db))]
+    pub async fn find_for_player(
+        game_id: i64,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<PlayerRules, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT game_rules.game_id, game_rules.body, game_rules.updated_at, rules_acknowledgements.acknowledged_at AS "acknowledged_at?"
+            FROM game_rules
+            LEFT JOIN rules_acknowledgements
+                ON rules_acknowledgements.game_id = game_rules.game_id AND rules_acknowledgements.user_id = $2
+            WHERE game_rules.game_id = $1
+            "#,
+            game_id,
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(PlayerRules {
+            rules: HouseRules {
+                game_id: row.game_id,
+                body: row.body,
+                updated_at: row.updated_at,
+            },
+            acknowledged_at: row.acknowledged_at,
+        })
+    }
+
+    #[tracing::instrument(name = "HouseRules::save", skip(rules, db))]
+    pub async fn save(
+        game_id: i64,
+        rules: &NewHouseRules,
+        db: &Pool<Postgres>,
+    ) -> Result<HouseRules, sqlx::Error> {
+        sqlx::query_as!(
+            HouseRules,
+            r#"
+            INSERT INTO game_rules (game_id, body)
+            VALUES ($1, $2)
+            ON CONFLICT (game_id) DO UPDATE SET body = $2, updated_at = NOW()
+            RETURNING game_id, body, updated_at
+            "#,
+            game_id,
+            rules.body
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "HouseRules::delete", skip(db))]
+    pub async fn delete(game_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM game_rules WHERE game_id = $1", game_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "HouseRules::acknowledge", skip(db))]
+    pub async fn acknowledge(
+        game_id: i64,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO rules_acknowledgements (game_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            game_id,
+            user_id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Refuse the first purchase of a player who hasn't acknowledged the rules yet
+    ///
+    /// Players who already purchased something before the rules were added can keep going.
+    pub async fn check_acknowledged(
+        game_id: i64,
+        user_id: i64,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<(), ServiceError> {
+        let row = sqlx::query!(
+            r#"
+            SELECT
+                EXISTS (SELECT 1 FROM game_rules WHERE game_id = $1)
+                AND NOT EXISTS (SELECT 1 FROM rules_acknowledgements WHERE game_id = $1 AND user_id = $2)
+                AND NOT EXISTS (SELECT 1 FROM orders WHERE game_id = $1 AND user_id = $2)
+                AS "blocked!"
+            "#,
+            game_id,
+            user_id
+        )
+        .fetch_one(tx)
+        .await?;
+
+        if row.blocked {
+            forbidden!("acknowledge the house rules of this game before your first purchase");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Validate;
+
+    #[test]
+    fn invalid_rules() {
+        let rules = |body: &str| NewHouseRules {
+            body: body.to_string(),
+        };
+
+        assert!(rules("# House rules\n\n- no shots before 22:00")
+            .validate()
+            .is_ok());
+        assert!(rules(" \n").validate().is_err());
+        assert!(rules(&"a".repeat(MAX_RULES_LENGTH + 1)).validate().is_err());
+    }
+}
@@ -0,0 +1,507 @@
+//! Recurring games, like a weekly bar night
+//!
+//! A series is created together with its first game, the scheduler creates the next occurrence
+//! once the latest one has started. The beverages and the accepted participants are copied over,
+//! so the occurrences can be compared with each other.
+
+use std::time::Duration as StdDuration;
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::cache::CacheHandle;
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+use crate::games::models::{CreateGame, Game};
+use crate::games::timezone;
+use crate::invitations::State;
+use crate::validator::{first_violation, Validate, Violation, Violations};
+
+/// how often the scheduler looks for series that need a new occurrence
+const SCHEDULE_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+const MAX_OCCURRENCES: i32 = 52;
+const MAX_INTERVAL_DAYS: i32 = 365;
+
+/// How a game repeats itself
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recurrence {
+    pub interval_days: i32,
+    /// the total amount of games in the series, including the first one
+    pub occurrences: i32,
+}
+
+/// A new game, which can repeat itself
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewGame {
+    #[serde(flatten)]
+    pub game: CreateGame,
+    pub recurrence: Option<Recurrence>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSeries {
+    pub id: i64,
+    pub owner_id: i64,
+    pub interval_days: i32,
+    pub occurrences: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The statistics of a game in a series
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Occurrence {
+    pub occurrence: i32,
+    pub game_id: i64,
+    pub start_time: DateTime<Utc>,
+    pub close_time: DateTime<Utc>,
+    pub players: i64,
+    pub orders: i64,
+    pub beverages: i64,
+    pub revenue: i64,
+    /// the relative change of the revenue compared to the previous occurrence
+    pub revenue_change: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesStats {
+    #[serde(flatten)]
+    pub series: GameSeries,
+    pub games: Vec<Occurrence>,
+}
+
+impl Validate<NewGame> for NewGame {
+    fn validate(&self) -> Result<(), ServiceError> {
+        first_violation(self.violations())
+    }
+
+    fn violations(&self) -> Vec<Violation> {
+        let mut violations = self.game.violations();
+
+        if let Some(recurrence) = self.recurrence {
+            let mut checks = Violations::default();
+            checks.check(
+                recurrence.interval_days >= 1 && recurrence.interval_days <= MAX_INTERVAL_DAYS,
+                "recurrence.intervalDays",
+                "a game can repeat itself every 1 to 365 days",
+            );
+            checks.check(
+                recurrence.occurrences >= 2 && recurrence.occurrences <= MAX_OCCURRENCES,
+                "recurrence.occurrences",
+                "a series contains 2 to 52 games",
+            );
+            violations.extend(checks.into_inner());
+        }
+
+        violations
+    }
+}
+
+impl GameSeries {
+    /// start a series with the first game
+    #[tracing::instrument(name = "GameSeries::create")]
+    pub async fn create(
+        game: &Game,
+        recurrence: Recurrence,
+        db: &Pool<Postgres>,
+    ) -> Result<GameSeries, sqlx::Error> {
+        let mut tx = db::begin(Operation::CreateSeries, db).await?;
+
+        let series = sqlx::query_as!(
+            GameSeries,
+            r#"
+            INSERT INTO game_series (owner_id, interval_days, occurrences)
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+            game.owner_id,
+            recurrence.interval_days,
+            recurrence.occurrences
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO series_games (series_id, game_id, occurrence) VALUES ($1, $2, 1)",
+            series.id,
+            game.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(series)
+    }
+
+    #[tracing::instrument(name = "GameSeries::find_by_game")]
+    pub async fn find_by_game(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<GameSeries, sqlx::Error> {
+        sqlx::query_as!(
+            GameSeries,
+            r#"
+            SELECT game_series.*
+            FROM game_series
+            INNER JOIN series_games ON series_games.series_id = game_series.id
+            WHERE series_games.game_id = $1
+            "#,
+            game_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// the series of which the latest occurrence has started, and which aren't complete yet
+    #[tracing::instrument(name = "GameSeries::due")]
+    pub async fn due(db: &Pool<Postgres>) -> Result<Vec<GameSeries>, sqlx::Error> {
+        sqlx::query_as!(
+            GameSeries,
+            r#"
+            SELECT game_series.*
+            FROM game_series
+            INNER JOIN series_games ON series_games.series_id = game_series.id
+            INNER JOIN games ON games.id = series_games.game_id
+            WHERE series_games.occurrence = (
+                SELECT MAX(latest.occurrence) FROM series_games latest WHERE latest.series_id = game_series.id
+            )
+            AND series_games.occurrence < game_series.occurrences
+            AND games.start_time <= NOW()
+            "#
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// the game that follows the previous occurrence, at the same wall clock time
+    fn next_game(&self, previous: &Game) -> CreateGame {
+        let interval = Duration::days(self.interval_days as i64);
+        let tz = previous.tz();
+        let next = |time: DateTime<Utc>| {
+            timezone::resolve(time.with_timezone(&tz).naive_local() + interval, tz)
+        };
+
+        CreateGame {
+            name: previous.name.clone(),
+            owner_id: previous.owner_id,
+            start_time: next(previous.start_time),
+            close_time: next(previous.close_time),
+            beverage_count: previous.beverage_count,
+            max_slot_quantity: previous.max_slot_quantity,
+            max_window_quantity: previous.max_window_quantity,
+            quantity_window: previous.quantity_window,
+            purchase_cooldown: previous.purchase_cooldown,
+            throttle_suspicious_users: previous.throttle_suspicious_users,
+            drift_percentage: previous.drift_percentage,
+            drift_interval: previous.drift_interval,
+            predictions_enabled: previous.predictions_enabled,
+            points_budget: previous.points_budget,
+            time_zone: previous.time_zone.clone(),
+            venue_name: previous.venue_name.clone(),
+            venue_address: previous.venue_address.clone(),
+            latitude: previous.latitude,
+            longitude: previous.longitude,
+            currency: previous.currency.clone(),
+            price_step: previous.price_step,
+        }
+    }
+
+    /// Create the next occurrence of the series
+    ///
+    /// Returns nothing when another instance is already creating it, or when it already exists.
+    #[tracing::instrument(name = "GameSeries::materialize")]
+    pub async fn materialize(&self, db: &Pool<Postgres>) -> Result<Option<Game>, ServiceError> {
+        let mut tx = db::begin(Operation::MaterializeSeries, db).await?;
+
+        let locked = sqlx::query!(
+            "SELECT id FROM game_series WHERE id = $1 FOR UPDATE SKIP LOCKED",
+            self.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if locked.is_none() {
+            return Ok(None);
+        }
+
+        let latest = sqlx::query!(
+            r#"
+            SELECT occurrence, game_id
+            FROM series_games
+            WHERE series_id = $1
+            ORDER BY occurrence DESC
+            LIMIT 1
+            "#,
+            self.id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if latest.occurrence >= self.occurrences {
+            return Ok(None);
+        }
+
+        let previous = Game::find_by_id(latest.game_id, &mut *tx).await?;
+        if previous.start_time > Utc::now() {
+            return Ok(None);
+        }
+
+        let game = Game::create(self.next_game(&previous), db).await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO beverages (game_id, user_id, slot_no, name, image_url, min_price, max_price, starting_price, current_price, alcoholic, cost_price)
+            SELECT $2, user_id, slot_no, name, image_url, min_price, max_price, starting_price, starting_price, alcoholic, cost_price
+            FROM beverages
+            WHERE game_id = $1
+            "#,
+            previous.id,
+            game.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        // the owner has already been added when the game was created
+        sqlx::query!(
+            r#"
+            INSERT INTO invitations (game_id, user_id, state)
+            SELECT $2, user_id, $3
+            FROM invitations
+            WHERE game_id = $1 AND state = $3
+            ON CONFLICT DO NOTHING
+            "#,
+            previous.id,
+            game.id,
+            State::Accepted as _
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "INSERT INTO series_games (series_id, game_id, occurrence) VALUES ($1, $2, $3)",
+            self.id,
+            game.id,
+            latest.occurrence + 1
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        info!(
+            "created occurrence {} of series {}: game {}",
+            latest.occurrence + 1,
+            self.id,
+            game.id
+        );
+
+        Ok(Some(game))
+    }
+
+    /// the statistics of every game in the series
+    #[tracing::instrument(name = "GameSeries::stats")]
+    pub async fn stats(self, db: &Pool<Postgres>) -> Result<SeriesStats, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                series_games.occurrence,
+                games.id AS game_id,
+                games.start_time,
+                games.close_time,
+                (SELECT COUNT(DISTINCT orders.user_id) FROM orders WHERE orders.game_id = games.id) AS "players!",
+                (SELECT COUNT(*) FROM orders WHERE orders.game_id = games.id) AS "orders!",
+                (SELECT COALESCE(SUM(transactions.amount), 0) FROM transactions INNER JOIN orders ON orders.id = transactions.order_id WHERE orders.game_id = games.id) AS "beverages!",
+                (SELECT COALESCE(SUM(transactions.amount * transactions.price), 0)::BIGINT FROM transactions INNER JOIN orders ON orders.id = transactions.order_id WHERE orders.game_id = games.id) AS "revenue!"
+            FROM series_games
+            INNER JOIN games ON games.id = series_games.game_id
+            WHERE series_games.series_id = $1
+            ORDER BY series_games.occurrence
+            "#,
+            self.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut previous_revenue = None;
+        let games = rows
+            .into_iter()
+            .map(|row| {
+                let revenue_change = revenue_change(previous_revenue, row.revenue);
+                previous_revenue = Some(row.revenue);
+
+                Occurrence {
+                    occurrence: row.occurrence,
+                    game_id: row.game_id,
+                    start_time: row.start_time,
+                    close_time: row.close_time,
+                    players: row.players,
+                    orders: row.orders,
+                    beverages: row.beverages,
+                    revenue: row.revenue,
+                    revenue_change,
+                }
+            })
+            .collect();
+
+        Ok(SeriesStats {
+            series: self,
+            games,
+        })
+    }
+}
+
+/// the relative change between two occurrences, empty when there's nothing to compare with
+fn revenue_change(previous: Option<i64>, current: i64) -> Option<f64> {
+    match previous {
+        Some(previous) if previous > 0 => Some((current - previous) as f64 / previous as f64),
+        _ => None,
+    }
+}
+
+/// Create the next occurrence of every series of which the latest game has started
+pub fn schedule(db: Pool<Postgres>, events: EventBus, cache: CacheHandle) {
+    actix_rt::spawn(async move {
+        loop {
+            actix_rt::time::delay_for(SCHEDULE_INTERVAL).await;
+
+            // one instance creates the games, so a game isn't created twice
+            match cache.lock("series", SCHEDULE_INTERVAL).await {
+                Some(lock) => {
+                    materialize(&db, &events).await;
+                    lock.release().await;
+                }
+                None => debug!("the series are scheduled by another instance"),
+            }
+        }
+    });
+}
+
+async fn materialize(db: &Pool<Postgres>, events: &EventBus) {
+    let due = match GameSeries::due(db).await {
+        Ok(due) => due,
+        Err(e) => {
+            error!("unable to find the series that need a new game: {}", e);
+            return;
+        }
+    };
+
+    for series in due {
+        match series.materialize(db).await {
+            Ok(Some(game)) => events.publish(DomainEvent::GameCreated(game)),
+            Ok(None) => (),
+            Err(e) => error!(
+                "unable to create the next game of series {}: {}",
+                series.id, e
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_game(recurrence: Option<Recurrence>) -> NewGame {
+        NewGame {
+            game: CreateGame::fixture(),
+            recurrence,
+        }
+    }
+
+    #[test]
+    fn invalid_recurrence() {
+        assert!(new_game(None).validate().is_ok());
+        assert!(new_game(Some(Recurrence {
+            interval_days: 7,
+            occurrences: 10
+        }))
+        .validate()
+        .is_ok());
+
+        let violations = new_game(Some(Recurrence {
+            interval_days: 0,
+            occurrences: 1,
+        }))
+        .violations();
+        assert_eq!(violations.len(), 2);
+        assert_eq!(violations[0].field, Some("recurrence.intervalDays"));
+        assert_eq!(violations[1].field, Some("recurrence.occurrences"));
+    }
+
+    #[test]
+    fn parse_new_game() {
+        let game: NewGame = serde_json::from_value(serde_json::json!({
+            "name": "bar night",
+            "startTime": "2026-10-23T20:00:00Z",
+            "closeTime": "2026-10-24T01:00:00Z",
+            "beverageCount": 8,
+            "recurrence": { "intervalDays": 7, "occurrences": 10 }
+        }))
+        .unwrap();
+
+        assert_eq!(game.game.beverage_count, 8);
+        assert_eq!(game.recurrence.unwrap().occurrences, 10);
+    }
+
+    fn series() -> GameSeries {
+        GameSeries {
+            id: 1,
+            owner_id: 1,
+            interval_days: 7,
+            occurrences: 10,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn game() -> Game {
+        let start_time = Utc::now();
+
+        Game {
+            start_time,
+            close_time: start_time + Duration::hours(5),
+            beverage_count: 4,
+            max_slot_quantity: Some(3),
+            ..Game::fixture()
+        }
+    }
+
+    #[test]
+    fn next_occurrence() {
+        let previous = game();
+
+        let next = series().next_game(&previous);
+        assert_eq!(next.start_time, previous.start_time + Duration::weeks(1));
+        assert_eq!(next.close_time, previous.close_time + Duration::weeks(1));
+        assert_eq!(next.beverage_count, 4);
+        assert_eq!(next.max_slot_quantity, Some(3));
+    }
+
+    #[test]
+    fn next_occurrence_after_clocks_change() {
+        // Friday 20:00 until 01:00 in Brussels, the clocks go back on the 25th
+        let previous = Game {
+            start_time: "2026-10-23T18:00:00Z".parse().unwrap(),
+            close_time: "2026-10-23T23:00:00Z".parse().unwrap(),
+            time_zone: String::from("Europe/Brussels"),
+            ..game()
+        };
+
+        let next = series().next_game(&previous);
+        assert_eq!(next.start_time.to_rfc3339(), "2026-10-30T19:00:00+00:00");
+        assert_eq!(next.close_time.to_rfc3339(), "2026-10-31T00:00:00+00:00");
+        assert_eq!(next.time_zone, "Europe/Brussels");
+    }
+
+    #[test]
+    fn compare_revenue() {
+        assert_eq!(revenue_change(None, 100), None);
+        assert_eq!(revenue_change(Some(0), 100), None);
+        assert_eq!(revenue_change(Some(100), 150), Some(0.5));
+        assert_eq!(revenue_change(Some(200), 100), Some(-0.5));
+    }
+}
@@ -0,0 +1,464 @@
+//! Split tests of the price algorithm within a single game
+//!
+//! The game owner puts slots in groups, and every group is priced with its own algorithm and sensitivity.
+//! The slots without a group keep the default parameters, so they're the control group.
+//! The groups can only be changed before the game starts, so the comparison covers the whole game.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+/// every sale above the average moves the price by 5% by default
+pub const DEFAULT_SENSITIVITY: f64 = 5.0;
+const MAX_SENSITIVITY: f64 = 50.0;
+const MAX_NAME_LENGTH: usize = 50;
+
+/// How the price of a slot reacts to its sales
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "price_algorithm", rename_all = "SCREAMING_SNAKE_CASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum PriceAlgorithm {
+    /// the price moves by the same amount for every sale
+    Linear,
+    /// the price moves by the same percentage for every sale, so it runs away faster
+    Exponential,
+}
+
+impl Default for PriceAlgorithm {
+    fn default() -> Self {
+        PriceAlgorithm::Linear
+    }
+}
+
+/// The parameters the price of a slot is calculated with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceParameters {
+    pub algorithm: PriceAlgorithm,
+    /// the percentage the price moves for every sale above or below the average
+    pub sensitivity: f64,
+}
+
+impl Default for PriceParameters {
+    fn default() -> Self {
+        PriceParameters {
+            algorithm: PriceAlgorithm::Linear,
+            sensitivity: DEFAULT_SENSITIVITY,
+        }
+    }
+}
+
+impl PriceParameters {
+    /// the price before it's rounded and limited to the price range of the beverage
+    pub fn apply(&self, base_price: i64, offset: i64) -> i64 {
+        match self.algorithm {
+            PriceAlgorithm::Linear => {
+                base_price + offset * (base_price as f64 * self.sensitivity / 100.0) as i64
+            }
+            PriceAlgorithm::Exponential => {
+                let factor = (1.0 + self.sensitivity / 100.0).powi(offset as i32);
+                (base_price as f64 * factor).round() as i64
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotGroup {
+    pub id: i64,
+    pub game_id: i64,
+    pub name: String,
+    pub algorithm: PriceAlgorithm,
+    pub sensitivity: f64,
+    pub slots: Vec<i16>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewSlotGroup {
+    pub name: String,
+    pub slots: Vec<i16>,
+    #[serde(default)]
+    pub algorithm: PriceAlgorithm,
+    /// defaults to 5%
+    pub sensitivity: Option<f64>,
+}
+
+/// The sales and the prices of a group, to compare it with the other groups
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupComparison {
+    /// empty for the slots without a group, which use the default parameters
+    pub group_id: Option<i64>,
+    pub name: Option<String>,
+    pub algorithm: PriceAlgorithm,
+    pub sensitivity: f64,
+    pub slots: Vec<i16>,
+    pub sales: i64,
+    pub revenue: i64,
+    /// the groups can differ in size, this is what they're compared on
+    pub revenue_per_slot: Option<f64>,
+    /// how far the prices moved from their starting price on average, relative to the starting price
+    pub volatility: Option<f64>,
+}
+
+impl crate::validator::Validate<NewSlotGroup> for NewSlotGroup {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.name.trim().is_empty() {
+            bad_request!("the group name is too short");
+        }
+        if self.name.trim().chars().count() > MAX_NAME_LENGTH {
+            bad_request!("the group name is too long, maximum 50 characters");
+        }
+        if self.slots.is_empty() {
+            bad_request!("a group needs at least one slot");
+        }
+        let unique: HashSet<i16> = self.slots.iter().copied().collect();
+        if unique.len() != self.slots.len() {
+            bad_request!("a slot can only be added to a group once");
+        }
+        if let Some(sensitivity) = self.sensitivity {
+            if !(sensitivity > 0.0 && sensitivity <= MAX_SENSITIVITY) {
+                bad_request!(format!(
+                    "the sensitivity should be above 0 and at most {}%",
+                    MAX_SENSITIVITY
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// the groups can't change once the game started, the comparison wouldn't be fair anymore
+fn check_not_started(game: &Game) -> Result<(), ServiceError> {
+    if !game.not_started() {
+        bad_request!("the slot groups can't be changed once the game started");
+    }
+
+    Ok(())
+}
+
+impl NewSlotGroup {
+    #[tracing::instrument(name = "NewSlotGroup::save", skip(game, db))]
+    pub async fn save(&self, game: &Game, db: &Pool<Postgres>) -> Result<SlotGroup, ServiceError> {
+        check_not_started(game)?;
+        if let Some(slot_no) = self
+            .slots
+            .iter()
+            .find(|&&slot_no| slot_no < 0 || slot_no >= game.beverage_count)
+        {
+            bad_request!(format!("the game doesn't have beverage slot {}", slot_no));
+        }
+
+        let mut tx = db::begin(Operation::SaveSlotGroup, db).await?;
+
+        let taken = sqlx::query!(
+            "SELECT slot_no FROM slot_group_slots WHERE game_id = $1 AND slot_no = any($2)",
+            game.id,
+            &self.slots
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        if let Some(row) = taken.first() {
+            bad_request!(format!("slot {} is already in a group", row.slot_no));
+        }
+
+        let group = sqlx::query!(
+            r#"
+            INSERT INTO slot_groups (game_id, name, algorithm, sensitivity)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, game_id, name, algorithm AS "algorithm!: PriceAlgorithm", sensitivity, created_at
+            "#,
+            game.id,
+            self.name.trim(),
+            self.algorithm as _,
+            self.sensitivity.unwrap_or(DEFAULT_SENSITIVITY)
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        for slot_no in &self.slots {
+            sqlx::query!(
+                "INSERT INTO slot_group_slots (game_id, slot_no, group_id) VALUES ($1, $2, $3)",
+                game.id,
+                slot_no,
+                group.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        let mut slots = self.slots.clone();
+        slots.sort_unstable();
+
+        Ok(SlotGroup {
+            id: group.id,
+            game_id: group.game_id,
+            name: group.name,
+            algorithm: group.algorithm,
+            sensitivity: group.sensitivity,
+            slots,
+            created_at: group.created_at,
+        })
+    }
+}
+
+impl SlotGroup {
+    #[tracing::instrument(name = "SlotGroup::find", skip(db))]
+    pub async fn find(game_id: i64, db: &Pool<Postgres>) -> Result<Vec<SlotGroup>, sqlx::Error> {
+        sqlx::query_as!(
+            SlotGroup,
+            r#"
+            SELECT slot_groups.id, slot_groups.game_id, slot_groups.name,
+                slot_groups.algorithm AS "algorithm!: PriceAlgorithm", slot_groups.sensitivity,
+                COALESCE(
+                    ARRAY(
+                        SELECT slot_no FROM slot_group_slots
+                        WHERE group_id = slot_groups.id
+                        ORDER BY slot_no
+                    ),
+                    '{}'
+                ) AS "slots!",
+                slot_groups.created_at
+            FROM slot_groups
+            WHERE slot_groups.game_id = $1
+            ORDER BY slot_groups.name
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "SlotGroup::delete", skip(game, db))]
+    pub async fn delete(
+        game: &Game,
+        group_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(), ServiceError> {
+        check_not_started(game)?;
+
+        let mut tx = db::begin(Operation::DeleteSlotGroup, db).await?;
+
+        sqlx::query!(
+            "DELETE FROM slot_group_slots WHERE game_id = $1 AND group_id = $2",
+            game.id,
+            group_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM slot_groups WHERE game_id = $1 AND id = $2 RETURNING id",
+            game.id,
+            group_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// The parameters of the slots that are in a group, the other slots use the default parameters
+    #[tracing::instrument(name = "SlotGroup::parameters", skip(db))]
+    pub async fn parameters(
+        game_id: i64,
+        db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ) -> Result<HashMap<i16, PriceParameters>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT slot_group_slots.slot_no,
+                slot_groups.algorithm AS "algorithm!: PriceAlgorithm", slot_groups.sensitivity
+            FROM slot_group_slots
+            INNER JOIN slot_groups ON slot_groups.id = slot_group_slots.group_id
+            WHERE slot_group_slots.game_id = $1
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.slot_no,
+                    PriceParameters {
+                        algorithm: row.algorithm,
+                        sensitivity: row.sensitivity,
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+impl GroupComparison {
+    /// Compare the groups of a game with each other and with the slots without a group
+    #[tracing::instrument(name = "GroupComparison::load", skip(game, db))]
+    pub async fn load(
+        game: &Game,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<GroupComparison>, sqlx::Error> {
+        let groups = SlotGroup::find(game.id, db).await?;
+
+        let sales = sqlx::query!(
+            r#"
+            SELECT slot_group_slots.group_id AS "group_id?",
+                SUM(transactions.amount)::BIGINT AS "sales!",
+                SUM(transactions.amount * transactions.price)::BIGINT AS "revenue!"
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            LEFT JOIN slot_group_slots ON slot_group_slots.game_id = orders.game_id
+                AND slot_group_slots.slot_no = transactions.slot_no
+            WHERE orders.game_id = $1
+            GROUP BY slot_group_slots.group_id
+            "#,
+            game.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let volatility = sqlx::query!(
+            r#"
+            SELECT slot_group_slots.group_id AS "group_id?",
+                AVG(ABS(price_histories.price - beverages.starting_price)::float8 / beverages.starting_price) AS "volatility!"
+            FROM price_histories
+            INNER JOIN beverages ON beverages.game_id = price_histories.game_id
+                AND beverages.user_id = price_histories.user_id
+                AND beverages.slot_no = price_histories.slot_no
+            LEFT JOIN slot_group_slots ON slot_group_slots.game_id = price_histories.game_id
+                AND slot_group_slots.slot_no = price_histories.slot_no
+            WHERE price_histories.game_id = $1 AND beverages.starting_price > 0
+            GROUP BY slot_group_slots.group_id
+            "#,
+            game.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(compare(
+            groups,
+            game.beverage_count,
+            sales
+                .into_iter()
+                .map(|row| (row.group_id, (row.sales, row.revenue)))
+                .collect(),
+            volatility
+                .into_iter()
+                .map(|row| (row.group_id, row.volatility))
+                .collect(),
+        ))
+    }
+}
+
+/// the slots without a group come first, then the groups
+fn compare(
+    groups: Vec<SlotGroup>,
+    beverage_count: i16,
+    sales: HashMap<Option<i64>, (i64, i64)>,
+    volatility: HashMap<Option<i64>, f64>,
+) -> Vec<GroupComparison> {
+    let grouped: HashSet<i16> = groups
+        .iter()
+        .flat_map(|group| group.slots.iter().copied())
+        .collect();
+    let defaults = PriceParameters::default();
+
+    let control = GroupComparison {
+        group_id: None,
+        name: None,
+        algorithm: defaults.algorithm,
+        sensitivity: defaults.sensitivity,
+        slots: (0..beverage_count)
+            .filter(|slot_no| !grouped.contains(slot_no))
+            .collect(),
+        sales: 0,
+        revenue: 0,
+        revenue_per_slot: None,
+        volatility: volatility.get(&None).copied(),
+    };
+    let groups = groups.into_iter().map(|group| GroupComparison {
+        group_id: Some(group.id),
+        name: Some(group.name),
+        algorithm: group.algorithm,
+        sensitivity: group.sensitivity,
+        slots: group.slots,
+        sales: 0,
+        revenue: 0,
+        revenue_per_slot: None,
+        volatility: volatility.get(&Some(group.id)).copied(),
+    });
+
+    std::iter::once(control)
+        .chain(groups)
+        .filter(|comparison| !comparison.slots.is_empty())
+        .map(|mut comparison| {
+            let (sales, revenue) = sales.get(&comparison.group_id).copied().unwrap_or_default();
+            comparison.sales = sales;
+            comparison.revenue = revenue;
+            comparison.revenue_per_slot = Some(revenue as f64 / comparison.slots.len() as f64);
+            comparison
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_algorithms() {
+        let linear = PriceParameters::default();
+        assert_eq!(linear.apply(250, 0), 250);
+        assert_eq!(linear.apply(250, 2), 274);
+        assert_eq!(linear.apply(250, -2), 226);
+
+        let exponential = PriceParameters {
+            algorithm: PriceAlgorithm::Exponential,
+            sensitivity: 10.0,
+        };
+        assert_eq!(exponential.apply(200, 0), 200);
+        assert_eq!(exponential.apply(200, 2), 242);
+        assert_eq!(exponential.apply(200, -1), 182);
+    }
+
+    #[test]
+    fn comparison() {
+        let group = SlotGroup {
+            id: 7,
+            game_id: 1,
+            name: String::from("steep"),
+            algorithm: PriceAlgorithm::Exponential,
+            sensitivity: 10.0,
+            slots: vec![1, 3],
+            created_at: Utc::now(),
+        };
+        let sales = vec![(None, (10, 2000)), (Some(7), (4, 1000))]
+            .into_iter()
+            .collect();
+        let volatility = vec![(Some(7), 0.3)].into_iter().collect();
+
+        let comparison = compare(vec![group], 4, sales, volatility);
+
+        assert_eq!(comparison.len(), 2);
+        assert_eq!(comparison[0].group_id, None);
+        assert_eq!(comparison[0].slots, vec![0, 2]);
+        assert_eq!(comparison[0].revenue_per_slot, Some(1000.0));
+        assert_eq!(comparison[0].volatility, None);
+        assert_eq!(comparison[1].group_id, Some(7));
+        assert_eq!(comparison[1].sales, 4);
+        assert_eq!(comparison[1].revenue_per_slot, Some(500.0));
+        assert_eq!(comparison[1].volatility, Some(0.3));
+    }
+}
@@ -0,0 +1,139 @@
+use sqlx::{Pool, Postgres};
+
+/// The maximum amount of suggestions returned
+const MAX_SUGGESTIONS: usize = 10;
+
+lazy_static! {
+    /// A list of commonly served drinks
+    static ref DRINKS: Vec<Suggestion> =
+        serde_json::from_str(include_str!("drinks.json")).expect("invalid drinks dataset");
+}
+
+/// A beverage name suggestion, used when configuring the beverages of a game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Suggestion {
+    pub name: String,
+    #[serde(default)]
+    pub category: Option<String>,
+    #[serde(default)]
+    pub image_url: Option<String>,
+}
+
+impl Suggestion {
+    /// Suggest beverages the user configured before, followed by the drinks from the dataset
+    #[tracing::instrument(name = "Suggestion::find", skip(db))]
+    pub async fn find(
+        user_id: i64,
+        query: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Suggestion>, sqlx::Error> {
+        let query = query.trim();
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut suggestions = Suggestion::previously_used(user_id, query, db).await?;
+
+        for drink in Suggestion::search_dataset(query) {
+            if suggestions.len() == MAX_SUGGESTIONS {
+                break;
+            }
+            if !suggestions
+                .iter()
+                .any(|suggestion| suggestion.name.eq_ignore_ascii_case(&drink.name))
+            {
+                suggestions.push(drink);
+            }
+        }
+
+        Ok(suggestions)
+    }
+
+    /// The beverages with a matching name the user configured in earlier games, using the most recent image
+    async fn previously_used(
+        user_id: i64,
+        query: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Suggestion>, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT DISTINCT ON (LOWER(name)) name, image_url
+            FROM beverages
+            WHERE user_id = $1 AND STRPOS(LOWER(name), LOWER($2)) > 0
+            ORDER BY LOWER(name), game_id DESC
+            LIMIT $3
+            "#,
+            user_id,
+            query,
+            MAX_SUGGESTIONS as i64
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut suggestions: Vec<Suggestion> = rows
+            .into_iter()
+            .map(|row| Suggestion {
+                category: DRINKS
+                    .iter()
+                    .find(|drink| drink.name.eq_ignore_ascii_case(&row.name))
+                    .and_then(|drink| drink.category.clone()),
+                name: row.name,
+                image_url: row.image_url,
+            })
+            .collect();
+        sort_by_relevance(&mut suggestions, query);
+
+        Ok(suggestions)
+    }
+
+    /// The drinks from the dataset containing the query, the names starting with the query first
+    fn search_dataset(query: &str) -> Vec<Suggestion> {
+        let needle = query.to_lowercase();
+
+        let mut drinks: Vec<Suggestion> = DRINKS
+            .iter()
+            .filter(|drink| drink.name.to_lowercase().contains(&needle))
+            .cloned()
+            .collect();
+        sort_by_relevance(&mut drinks, query);
+        drinks.truncate(MAX_SUGGESTIONS);
+
+        drinks
+    }
+}
+
+/// Put the names starting with the query first, then sort alphabetically
+fn sort_by_relevance(suggestions: &mut [Suggestion], query: &str) {
+    let needle = query.to_lowercase();
+
+    suggestions.sort_by_cached_key(|suggestion| {
+        let name = suggestion.name.to_lowercase();
+        (!name.starts_with(&needle), name)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_dataset() {
+        assert!(!DRINKS.is_empty());
+
+        let suggestions = Suggestion::search_dataset("WEST");
+        let names: Vec<&str> = suggestions.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["Westmalle Dubbel", "Westmalle Tripel", "Westvleteren 12"]
+        );
+        assert_eq!(suggestions[0].category.as_deref(), Some("beer"));
+
+        // names starting with the query come first
+        let suggestions = Suggestion::search_dataset("tonic");
+        assert_eq!(suggestions[0].name, "Tonic");
+        assert_eq!(suggestions[1].name, "Gin Tonic");
+
+        assert!(Suggestion::search_dataset("a").len() <= MAX_SUGGESTIONS);
+    }
+}
@@ -0,0 +1,254 @@
+//! Beverage lineups that are reused for recurring events
+//!
+//! A template is saved from the beverages a user configured in one of their games.
+//! A game created from a template starts with the same beverages for its owner.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Done, Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+const MAX_NAME_LENGTH: usize = 50;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Template {
+    pub id: i64,
+    pub owner_id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub beverages: Vec<TemplateBeverage>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateBeverage {
+    pub slot_no: i16,
+    pub name: String,
+    pub image_url: Option<String>,
+    pub min_price: i64,
+    pub max_price: i64,
+    pub starting_price: i64,
+    pub alcoholic: bool,
+    pub cost_price: Option<i64>,
+}
+
+/// Save the beverages of a game as a template
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewTemplate {
+    pub name: String,
+    pub game_id: i64,
+}
+
+/// Create a game from a template
+#[derive(Debug, Deserialize)]
+pub struct TemplateParam {
+    pub template_id: Option<i64>,
+}
+
+impl crate::validator::Validate<NewTemplate> for NewTemplate {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.name.trim().is_empty() {
+            bad_request!("the template name is too short");
+        }
+        if self.name.trim().chars().count() > MAX_NAME_LENGTH {
+            bad_request!("the template name is too long, maximum 50 characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl NewTemplate {
+    /// Copy the beverages the user configured in the game into a new template
+    #[tracing::instrument(name = "NewTemplate::save", skip(db))]
+    pub async fn save(&self, owner_id: i64, db: &Pool<Postgres>) -> Result<Template, ServiceError> {
+        let mut tx = db::begin(Operation::SaveTemplate, db).await?;
+
+        let template = sqlx::query!(
+            "INSERT INTO game_templates (owner_id, name) VALUES ($1, $2) RETURNING id",
+            owner_id,
+            self.name.trim()
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let copied = sqlx::query!(
+            r#"
+            INSERT INTO game_template_beverages (template_id, slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price)
+            SELECT $1, slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price
+            FROM beverages
+            WHERE game_id = $2 AND user_id = $3
+            "#,
+            template.id,
+            self.game_id,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        if copied.rows_affected() == 0 {
+            bad_request!("you didn't configure any beverages in this game");
+        }
+
+        tx.commit().await?;
+
+        Ok(Template::find(template.id, owner_id, db).await?)
+    }
+}
+
+impl Template {
+    #[tracing::instrument(name = "Template::find", skip(db))]
+    pub async fn find(
+        id: i64,
+        owner_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Template, sqlx::Error> {
+        let template = sqlx::query!(
+            "SELECT id, owner_id, name, created_at FROM game_templates WHERE id = $1 AND owner_id = $2",
+            id,
+            owner_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(Template {
+            id: template.id,
+            owner_id: template.owner_id,
+            name: template.name,
+            created_at: template.created_at,
+            beverages: Template::beverages(id, db).await?,
+        })
+    }
+
+    #[tracing::instrument(name = "Template::find_by_owner", skip(db))]
+    pub async fn find_by_owner(
+        owner_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Template>, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT id, owner_id, name, created_at FROM game_templates WHERE owner_id = $1 ORDER BY name",
+            owner_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut templates = Vec::with_capacity(rows.len());
+        for row in rows {
+            templates.push(Template {
+                id: row.id,
+                owner_id: row.owner_id,
+                name: row.name,
+                created_at: row.created_at,
+                beverages: Template::beverages(row.id, db).await?,
+            });
+        }
+
+        Ok(templates)
+    }
+
+    async fn beverages(
+        template_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<TemplateBeverage>, sqlx::Error> {
+        sqlx::query_as!(
+            TemplateBeverage,
+            r#"
+            SELECT slot_no, name, image_url, min_price, max_price, starting_price, alcoholic, cost_price
+            FROM game_template_beverages
+            WHERE template_id = $1
+            ORDER BY slot_no
+            "#,
+            template_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "Template::delete", skip(db))]
+    pub async fn delete(&self, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let mut tx = db::begin(Operation::DeleteTemplate, db).await?;
+
+        sqlx::query!(
+            "DELETE FROM game_template_beverages WHERE template_id = $1",
+            self.id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM game_templates WHERE id = $1", self.id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await
+    }
+
+    /// Make sure the beverage slots of the template exist in a game with this many beverages
+    pub fn check_fits(&self, beverage_count: i16) -> Result<(), ServiceError> {
+        if self
+            .beverages
+            .iter()
+            .any(|beverage| beverage.slot_no >= beverage_count)
+        {
+            bad_request!("the template has more beverages than the game");
+        }
+
+        Ok(())
+    }
+
+    /// Give the owner of a new game the beverages of the template
+    #[tracing::instrument(name = "Template::apply", skip(db))]
+    pub async fn apply(&self, game: &Game, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            INSERT INTO beverages (game_id, user_id, slot_no, name, image_url, min_price, max_price, starting_price, current_price, alcoholic, cost_price)
+            SELECT $1, $2, slot_no, name, image_url, min_price, max_price, starting_price, starting_price, alcoholic, cost_price
+            FROM game_template_beverages
+            WHERE template_id = $3
+            "#,
+            game.id,
+            game.owner_id,
+            self.id
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(slots: &[i16]) -> Template {
+        Template {
+            id: 1,
+            owner_id: 1,
+            name: String::from("monthly cantus"),
+            created_at: Utc::now(),
+            beverages: slots
+                .iter()
+                .map(|&slot_no| TemplateBeverage {
+                    slot_no,
+                    name: format!("beverage {}", slot_no),
+                    image_url: None,
+                    min_price: 100,
+                    max_price: 300,
+                    starting_price: 200,
+                    alcoholic: true,
+                    cost_price: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn fits() {
+        assert!(template(&[0, 1, 2]).check_fits(3).is_ok());
+        assert!(template(&[0, 2]).check_fits(4).is_ok());
+        assert!(template(&[0, 1, 2, 3]).check_fits(3).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! The time zone of a game
+//!
+//! The times of a game are stored in UTC, the time zone is used to show them to the players
+//! and to keep recurring games at the same wall clock time when the clocks change.
+
+use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::games::Game;
+
+pub const DEFAULT_TIME_ZONE: &str = "UTC";
+
+/// the longest gap that's skipped when the clocks go forward
+const MAX_GAP_MINUTES: i64 = 24 * 60;
+const GAP_STEP_MINUTES: i64 = 15;
+
+pub fn default_time_zone() -> String {
+    DEFAULT_TIME_ZONE.to_string()
+}
+
+/// parse an IANA time zone name, like `Europe/Brussels`
+pub fn parse(name: &str) -> Option<Tz> {
+    name.parse().ok()
+}
+
+/// The start and close time of a game in its own time zone
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocalSchedule {
+    pub time_zone: String,
+    pub start_time: DateTime<FixedOffset>,
+    pub close_time: DateTime<FixedOffset>,
+}
+
+/// A game with its schedule in its own time zone
+#[derive(Debug, Serialize)]
+pub struct LocalizedGame<'a> {
+    #[serde(flatten)]
+    pub game: &'a Game,
+    pub local: LocalSchedule,
+}
+
+impl LocalSchedule {
+    pub fn new(start_time: DateTime<Utc>, close_time: DateTime<Utc>, tz: Tz) -> Self {
+        LocalSchedule {
+            time_zone: tz.name().to_string(),
+            start_time: localize(start_time, tz),
+            close_time: localize(close_time, tz),
+        }
+    }
+}
+
+/// the same moment, with the offset of the time zone at that moment
+pub fn localize(time: DateTime<Utc>, tz: Tz) -> DateTime<FixedOffset> {
+    let local = time.with_timezone(&tz);
+    local.with_timezone(&local.offset().fix())
+}
+
+/// The duration according to the wall clock
+///
+/// This differs from the elapsed time when the clocks change in between,
+/// a game from 18:00 until 18:00 the next day takes 25 hours when the clocks go back.
+pub fn wall_clock_duration(
+    start_time: DateTime<Utc>,
+    close_time: DateTime<Utc>,
+    tz: Tz,
+) -> Duration {
+    close_time.with_timezone(&tz).naive_local() - start_time.with_timezone(&tz).naive_local()
+}
+
+/// Convert a wall clock time to UTC
+///
+/// A time that's skipped when the clocks go forward is moved to the end of the gap,
+/// a time that occurs twice when the clocks go back uses the first occurrence.
+pub fn resolve(local: NaiveDateTime, tz: Tz) -> DateTime<Utc> {
+    let mut skipped = 0;
+
+    while skipped <= MAX_GAP_MINUTES {
+        match tz.from_local_datetime(&(local + Duration::minutes(skipped))) {
+            LocalResult::Single(time) => return time.with_timezone(&Utc),
+            LocalResult::Ambiguous(earliest, _) => return earliest.with_timezone(&Utc),
+            LocalResult::None => skipped += GAP_STEP_MINUTES,
+        }
+    }
+
+    DateTime::from_utc(local, Utc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn brussels() -> Tz {
+        parse("Europe/Brussels").unwrap()
+    }
+
+    #[test]
+    fn parse_time_zones() {
+        assert_eq!(parse("UTC"), Some(Tz::UTC));
+        assert!(parse("Europe/Brussels").is_some());
+        assert!(parse("Europe/Atlantis").is_none());
+    }
+
+    #[test]
+    fn localize_times() {
+        let summer = Utc.ymd(2026, 7, 1).and_hms(18, 0, 0);
+        let winter = Utc.ymd(2026, 12, 1).and_hms(18, 0, 0);
+
+        assert_eq!(
+            localize(summer, brussels()).to_rfc3339(),
+            "2026-07-01T20:00:00+02:00"
+        );
+        assert_eq!(
+            localize(winter, brussels()).to_rfc3339(),
+            "2026-12-01T19:00:00+01:00"
+        );
+    }
+
+    #[test]
+    fn clocks_going_back() {
+        // 18:00 CEST until 18:00 CET
+        let start_time = Utc.ymd(2026, 10, 24).and_hms(16, 0, 0);
+        let close_time = Utc.ymd(2026, 10, 25).and_hms(17, 0, 0);
+
+        assert_eq!(close_time - start_time, Duration::hours(25));
+        assert_eq!(
+            wall_clock_duration(start_time, close_time, brussels()),
+            Duration::hours(24)
+        );
+
+        // 02:30 happens twice
+        let ambiguous = NaiveDate::from_ymd(2026, 10, 25).and_hms(2, 30, 0);
+        assert_eq!(
+            resolve(ambiguous, brussels()),
+            Utc.ymd(2026, 10, 25).and_hms(0, 30, 0)
+        );
+    }
+
+    #[test]
+    fn clocks_going_forward() {
+        // 02:30 doesn't exist, the clocks jump from 02:00 to 03:00
+        let skipped = NaiveDate::from_ymd(2026, 3, 29).and_hms(2, 30, 0);
+        assert_eq!(
+            resolve(skipped, brussels()),
+            Utc.ymd(2026, 3, 29).and_hms(1, 0, 0)
+        );
+
+        let regular = NaiveDate::from_ymd(2026, 3, 29).and_hms(20, 0, 0);
+        assert_eq!(
+            resolve(regular, brussels()),
+            Utc.ymd(2026, 3, 29).and_hms(18, 0, 0)
+        );
+    }
+}
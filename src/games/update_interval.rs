@@ -0,0 +1,117 @@
+//! The interval between the price updates of a game
+//!
+//! Games use the global interval, unless the owner picks one within the bounds the administrators set.
+//! The market agent reads the interval of its game before every price update,
+//! so a change applies from the next update without restarting anything.
+
+use std::time::Duration;
+
+use sqlx::{Pool, Postgres};
+
+use crate::config::Config;
+use crate::errors::ServiceError;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateInterval {
+    pub game_id: i64,
+    /// the seconds between the price updates of the game
+    pub seconds: u64,
+    /// false when the game uses the global interval
+    pub custom: bool,
+    /// the bounds the game owner can choose from
+    pub minimum: u64,
+    pub maximum: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUpdateInterval {
+    /// `null` goes back to the global interval
+    pub seconds: Option<u64>,
+}
+
+impl crate::validator::Validate<NewUpdateInterval> for NewUpdateInterval {
+    fn validate(&self) -> Result<(), ServiceError> {
+        let (minimum, maximum) = Config::update_interval_bounds();
+
+        if let Some(seconds) = self.seconds {
+            if seconds < minimum || seconds > maximum {
+                bad_request!(format!(
+                    "the update interval should be between {} and {} seconds",
+                    minimum, maximum
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl UpdateInterval {
+    /// the interval of the game, a custom interval is kept within the current bounds
+    fn new(game_id: i64, custom: Option<i32>) -> UpdateInterval {
+        let (minimum, maximum) = Config::update_interval_bounds();
+
+        let seconds = match custom {
+            Some(seconds) => (seconds as u64).max(minimum).min(maximum),
+            None => Config::price_update_interval(),
+        };
+
+        UpdateInterval {
+            game_id,
+            seconds,
+            custom: custom.is_some(),
+            minimum,
+            maximum,
+        }
+    }
+
+    #[tracing::instrument(name = "UpdateInterval::find", skip(db))]
+    pub async fn find(game_id: i64, db: &Pool<Postgres>) -> Result<UpdateInterval, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT seconds FROM game_update_intervals WHERE game_id = $1",
+            game_id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        Ok(UpdateInterval::new(game_id, row.map(|row| row.seconds)))
+    }
+
+    #[tracing::instrument(name = "UpdateInterval::save", skip(db))]
+    pub async fn save(
+        game_id: i64,
+        interval: &NewUpdateInterval,
+        db: &Pool<Postgres>,
+    ) -> Result<UpdateInterval, sqlx::Error> {
+        match interval.seconds {
+            Some(seconds) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO game_update_intervals (game_id, seconds)
+                    VALUES ($1, $2)
+                    ON CONFLICT (game_id) DO UPDATE SET seconds = EXCLUDED.seconds, updated_at = NOW()
+                    "#,
+                    game_id,
+                    seconds as i32
+                )
+                .execute(db)
+                .await?;
+            }
+            None => {
+                sqlx::query!(
+                    "DELETE FROM game_update_intervals WHERE game_id = $1",
+                    game_id
+                )
+                .execute(db)
+                .await?;
+            }
+        }
+
+        UpdateInterval::find(game_id, db).await
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.seconds)
+    }
+}
@@ -0,0 +1,360 @@
+//! The maximum amount of players of a game, and the waitlist of a full game
+//!
+//! A user who accepts the invitation of a full game is put on the waitlist, their invitation stays pending.
+//! When a player declines or is removed by the game owner, or the owner raises the maximum,
+//! the users who waited longest take the open places and are notified.
+//! Every accepted invitation goes through `accept`, including the guests who join through the invite link
+//! and the users who register with an external invitation.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, Transaction};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+use crate::invitations::{Invitation, State};
+use crate::websocket::server::GameId;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capacity {
+    pub game_id: i64,
+    /// empty when the game has no maximum
+    pub max_players: Option<i32>,
+    /// the users who accepted their invitation
+    pub players: i64,
+    pub waitlisted: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewCapacity {
+    /// `null` removes the maximum
+    pub max_players: Option<i32>,
+}
+
+impl crate::validator::Validate<NewCapacity> for NewCapacity {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if let Some(max_players) = self.max_players {
+            if max_players < 1 {
+                bad_request!("a game needs room for at least one player");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A user waiting for a place in a game
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaitlistEntry {
+    pub user_id: i64,
+    pub username: String,
+    /// one for the user who gets the next open place
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The outcome of accepting an invitation
+#[derive(Debug)]
+pub enum Admission {
+    Accepted(Invitation),
+    /// the game is full, the user is on the waitlist at this position
+    Waitlisted(i64),
+}
+
+/// The declined invitation of a player who left, and the waitlisted users who took their place
+#[derive(Debug)]
+pub struct Departure {
+    pub invitation: Invitation,
+    pub promoted: Vec<i64>,
+}
+
+/// Wait for other changes to the players of the game, so two users can't take the last place
+async fn lock_game(game_id: i64, tx: &mut Transaction<'_, Postgres>) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT id FROM games WHERE id = $1 FOR UPDATE", game_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    Ok(())
+}
+
+/// the open places of a game, empty when the game has no maximum
+async fn open_places(
+    game_id: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT game_capacities.max_players,
+            (SELECT COUNT(*) FROM invitations WHERE game_id = $1 AND state = 'ACCEPTED') AS "players!"
+        FROM game_capacities
+        WHERE game_id = $1
+        "#,
+        game_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    Ok(row.map(|row| (i64::from(row.max_players) - row.players).max(0)))
+}
+
+/// Accept the invitations of the users who waited longest, as long as there are open places
+async fn promote(
+    game_id: i64,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Vec<i64>, sqlx::Error> {
+    let places = open_places(game_id, tx).await?;
+    if places == Some(0) {
+        return Ok(Vec::new());
+    }
+
+    let promoted: Vec<i64> = sqlx::query!(
+        r#"
+        SELECT user_id FROM game_waitlist
+        WHERE game_id = $1
+        ORDER BY created_at, user_id
+        LIMIT $2
+        "#,
+        game_id,
+        places
+    )
+    .fetch_all(&mut *tx)
+    .await?
+    .into_iter()
+    .map(|row| row.user_id)
+    .collect();
+
+    if promoted.is_empty() {
+        return Ok(promoted);
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE invitations SET state = 'ACCEPTED'
+        WHERE game_id = $1 AND user_id = ANY($2)
+        "#,
+        game_id,
+        &promoted
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM game_waitlist WHERE game_id = $1 AND user_id = ANY($2)",
+        game_id,
+        &promoted
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(promoted)
+}
+
+impl Capacity {
+    #[tracing::instrument(name = "Capacity::find", skip(db))]
+    pub async fn find(game_id: i64, db: &Pool<Postgres>) -> Result<Capacity, sqlx::Error> {
+        sqlx::query_as!(
+            Capacity,
+            r#"
+            SELECT $1::BIGINT AS "game_id!",
+                (SELECT max_players FROM game_capacities WHERE game_id = $1) AS max_players,
+                (SELECT COUNT(*) FROM invitations WHERE game_id = $1 AND state = 'ACCEPTED') AS "players!",
+                (SELECT COUNT(*) FROM game_waitlist WHERE game_id = $1) AS "waitlisted!"
+            "#,
+            game_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Change the maximum, returns the waitlisted users who got a place
+    ///
+    /// Lowering the maximum below the current players doesn't remove anyone.
+    #[tracing::instrument(name = "Capacity::save", skip(db))]
+    pub async fn save(
+        game_id: i64,
+        capacity: &NewCapacity,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let mut tx = db::begin(Operation::SaveCapacity, db).await?;
+        lock_game(game_id, &mut tx).await?;
+
+        match capacity.max_players {
+            Some(max_players) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO game_capacities (game_id, max_players)
+                    VALUES ($1, $2)
+                    ON CONFLICT (game_id) DO UPDATE SET max_players = EXCLUDED.max_players, updated_at = NOW()
+                    "#,
+                    game_id,
+                    max_players
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+            None => {
+                sqlx::query!("DELETE FROM game_capacities WHERE game_id = $1", game_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+
+        let promoted = promote(game_id, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(promoted)
+    }
+}
+
+impl WaitlistEntry {
+    /// the users waiting for a place in the game, the next one first
+    #[tracing::instrument(name = "WaitlistEntry::find", skip(db))]
+    pub async fn find(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<WaitlistEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            WaitlistEntry,
+            r#"
+            SELECT game_waitlist.user_id, users.username, game_waitlist.created_at,
+                ROW_NUMBER() OVER (ORDER BY game_waitlist.created_at, game_waitlist.user_id) AS "position!"
+            FROM game_waitlist
+            INNER JOIN users ON users.id = game_waitlist.user_id
+            WHERE game_waitlist.game_id = $1
+            ORDER BY game_waitlist.created_at, game_waitlist.user_id
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await
+    }
+}
+
+/// Accept an invitation, the user is put on the waitlist when the game is full
+#[tracing::instrument(name = "waitlist::accept", skip(db))]
+pub async fn accept(
+    invitation: &Invitation,
+    db: &Pool<Postgres>,
+) -> Result<Admission, sqlx::Error> {
+    let mut tx = db::begin(Operation::AcceptInvitation, db).await?;
+    let admission = accept_in(invitation, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(admission)
+}
+
+/// Accept an invitation as part of a transaction, like the one that creates the user of the invitation
+pub async fn accept_in(
+    invitation: &Invitation,
+    tx: &mut Transaction<'_, Postgres>,
+) -> Result<Admission, sqlx::Error> {
+    lock_game(invitation.game_id, tx).await?;
+
+    let full = !matches!(invitation.state, State::Accepted)
+        && open_places(invitation.game_id, tx).await? == Some(0);
+
+    if full {
+        sqlx::query!(
+            r#"
+            INSERT INTO game_waitlist (game_id, user_id)
+            VALUES ($1, $2)
+            ON CONFLICT (game_id, user_id) DO NOTHING
+            "#,
+            invitation.game_id,
+            invitation.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let position = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "position!" FROM game_waitlist
+            WHERE game_id = $1 AND (created_at, user_id) <= (
+                SELECT created_at, user_id FROM game_waitlist WHERE game_id = $1 AND user_id = $2
+            )
+            "#,
+            invitation.game_id,
+            invitation.user_id
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .position;
+
+        return Ok(Admission::Waitlisted(position));
+    }
+
+    let accepted = sqlx::query_as!(
+        Invitation,
+        r#"
+        UPDATE invitations SET state = 'ACCEPTED'
+        WHERE id = $1
+        RETURNING id, game_id, user_id, state as "state!: State", created_at, updated_at
+        "#,
+        invitation.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM game_waitlist WHERE game_id = $1 AND user_id = $2",
+        invitation.game_id,
+        invitation.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(Admission::Accepted(accepted))
+}
+
+/// Decline the invitation of a user, the waitlisted users take the place they leave
+#[tracing::instrument(name = "waitlist::leave", skip(db))]
+pub async fn leave(
+    game_id: i64,
+    user_id: i64,
+    db: &Pool<Postgres>,
+) -> Result<Departure, sqlx::Error> {
+    let mut tx = db::begin(Operation::LeaveGame, db).await?;
+    lock_game(game_id, &mut tx).await?;
+
+    let invitation = sqlx::query_as!(
+        Invitation,
+        r#"
+        UPDATE invitations SET state = 'DECLINED'
+        WHERE game_id = $1 AND user_id = $2
+        RETURNING id, game_id, user_id, state as "state!: State", created_at, updated_at
+        "#,
+        game_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "DELETE FROM game_waitlist WHERE game_id = $1 AND user_id = $2",
+        game_id,
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let promoted = promote(game_id, &mut tx).await?;
+    tx.commit().await?;
+
+    Ok(Departure {
+        invitation,
+        promoted,
+    })
+}
+
+/// Let the waitlisted users know they got a place in the game
+pub fn publish_promotions(game_id: i64, promoted: &[i64], events: &EventBus) {
+    for &user_id in promoted {
+        events.publish(DomainEvent::WaitlistPromoted {
+            game_id: GameId(game_id),
+            user_id,
+        });
+    }
+}
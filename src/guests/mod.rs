@@ -0,0 +1,8 @@
+//! Guests join a game through its invite link, without registering
+//!
+//! The game owner shares the link, or a QR code of it, and whoever opens it picks a username and is logged in.
+//! A guest can only take part in that game, and is anonymized once it has finished, unless they set
+//! a password to keep the account. The sales of an anonymized guest are kept, as they're part of the game.
+mod models;
+pub mod routes;
+pub use models::{schedule, Conversion, Guest, GuestLink, NewGuest};
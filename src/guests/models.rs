@@ -0,0 +1,342 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::cache::CacheHandle;
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::waitlist::{self, Admission};
+use crate::games::Game;
+use crate::invitations::NewInvitation;
+use crate::users::{Credentials, User};
+use crate::validator::Validate;
+
+/// how often the guests of finished games are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The invite link of a game, the clients turn the token into a link or a QR code
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GuestLink {
+    pub game_id: i64,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user who joined a game through its invite link
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Guest {
+    pub user_id: i64,
+    pub game_id: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct NewGuest {
+    pub token: String,
+    pub username: String,
+}
+
+/// A guest who sets a password to keep the account
+#[derive(Deserialize)]
+pub struct Conversion {
+    pub password: String,
+}
+
+impl Validate<Conversion> for Conversion {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.password.len() < 8 {
+            bad_request!("your password should at least be 8 characters long");
+        }
+
+        Ok(())
+    }
+}
+
+impl GuestLink {
+    /// Create the invite link of a game, an existing link stops working
+    #[tracing::instrument(name = "GuestLink::rotate", skip(db))]
+    pub async fn rotate(game_id: i64, db: &Pool<Postgres>) -> Result<GuestLink, sqlx::Error> {
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        sqlx::query_as!(
+            GuestLink,
+            r#"
+            INSERT INTO guest_links (game_id, token)
+            VALUES ($1, $2)
+            ON CONFLICT (game_id) DO UPDATE SET token = EXCLUDED.token, created_at = NOW()
+            RETURNING game_id, token, created_at
+            "#,
+            game_id,
+            token
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "GuestLink::revoke", skip(db))]
+    pub async fn revoke(game_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM guest_links WHERE game_id = $1", game_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl Guest {
+    #[tracing::instrument(name = "Guest::find", skip(db))]
+    pub async fn find(user_id: i64, db: &Pool<Postgres>) -> Result<Option<Guest>, sqlx::Error> {
+        sqlx::query_as!(
+            Guest,
+            "SELECT user_id, game_id, created_at FROM guests WHERE user_id = $1",
+            user_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Guests can only take part in the game they joined
+    pub async fn forbid(user_id: i64, db: &Pool<Postgres>) -> Result<(), ServiceError> {
+        if Guest::find(user_id, db).await?.is_some() {
+            forbidden!("guests can only take part in the game they joined, set a password first");
+        }
+
+        Ok(())
+    }
+
+    /// Create a guest for the game of the invite link, the guest is put on the waitlist when the game is full
+    #[tracing::instrument(name = "Guest::join", skip(guest, db))]
+    pub async fn join(
+        guest: &NewGuest,
+        db: &Pool<Postgres>,
+    ) -> Result<(User, Game, Admission), ServiceError> {
+        let username = guest.username.trim();
+        Credentials {
+            username: username.to_string(),
+            // the guest doesn't have a password, this only checks the username
+            password: "unused-password".to_string(),
+        }
+        .validate()?;
+
+        let mut tx = db::begin(Operation::JoinAsGuest, db).await?;
+
+        let link = sqlx::query!(
+            "SELECT game_id FROM guest_links WHERE token = $1",
+            guest.token
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let game_id = match link {
+            Some(link) => link.game_id,
+            None => return Err(ServiceError::NotFound),
+        };
+
+        let game = Game::find_by_id(game_id, &mut *tx).await?;
+        if game.is_finished() {
+            forbidden!("the game has already finished");
+        }
+
+        let user =
+            User::create_passwordless(username, &mut tx)
+                .await
+                .map_err(|error| match error {
+                    ServiceError::Conflict(_) => {
+                        ServiceError::Conflict("the username is already taken".to_string())
+                    }
+                    _ => error,
+                })?;
+
+        let invitation = NewInvitation::new(game.id, user.id).save(&mut *tx).await?;
+        let admission = waitlist::accept_in(&invitation, &mut tx).await?;
+
+        sqlx::query!(
+            "INSERT INTO guests (user_id, game_id) VALUES ($1, $2)",
+            user.id,
+            game.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((user, game, admission))
+    }
+
+    /// Turn the guest into a full account, the account is no longer purged
+    #[tracing::instrument(name = "Guest::convert", skip(self, conversion, db))]
+    pub async fn convert(
+        &self,
+        conversion: Conversion,
+        db: &Pool<Postgres>,
+    ) -> Result<User, ServiceError> {
+        let mut user = User::find(self.user_id, db).await?;
+        user.update_password(conversion.password, db).await?;
+
+        sqlx::query!("DELETE FROM guests WHERE user_id = $1", self.user_id)
+            .execute(db)
+            .await?;
+
+        Ok(user)
+    }
+
+    /// the guests of the games that have finished
+    #[tracing::instrument(name = "Guest::expired", skip(db))]
+    async fn expired(db: &Pool<Postgres>) -> Result<Vec<Guest>, sqlx::Error> {
+        sqlx::query_as!(
+            Guest,
+            r#"
+            SELECT guests.user_id, guests.game_id, guests.created_at
+            FROM guests
+            INNER JOIN games ON games.id = guests.game_id
+            WHERE games.close_time < NOW()
+            "#
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Anonymize the guest, the sales and everything else that's part of the game are kept
+    ///
+    /// Deleting the sales would rewrite the stats, the prices and the revenue of the other players.
+    /// The guest is renamed to a name that can't be registered, and everything that could
+    /// identify the guest or log in as the guest is deleted.
+    #[tracing::instrument(name = "Guest::purge", skip(self, db), fields(user_id = self.user_id))]
+    async fn purge(&self, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let mut tx = db::begin(Operation::PurgeGuest, db).await?;
+
+        sqlx::query!(
+            "UPDATE users SET username = $2, updated_at = NOW() WHERE id = $1",
+            self.user_id,
+            anonymous_name(self.user_id)
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!("DELETE FROM muted_users WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM draft_invitations WHERE user_id = $1",
+            self.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM passkeys WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM passkey_challenges WHERE user_id = $1",
+            self.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM sessions WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM login_devices WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!(
+            "DELETE FROM login_failures WHERE user_id = $1",
+            self.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM calendar_tokens WHERE user_id = $1",
+            self.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM user_exports WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query!("DELETE FROM game_waitlist WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!("DELETE FROM guests WHERE user_id = $1", self.user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// The name of a purged guest, it doesn't pass the validation of a username so nobody can register it
+fn anonymous_name(user_id: i64) -> String {
+    format!("guest #{}", user_id)
+}
+
+/// Purge the guests of the finished games every hour
+pub fn schedule(db: Pool<Postgres>, cache: CacheHandle) {
+    actix_rt::spawn(async move {
+        loop {
+            actix_rt::time::delay_for(PURGE_INTERVAL).await;
+
+            match cache.lock("guests", PURGE_INTERVAL).await {
+                Some(lock) => {
+                    purge(&db).await;
+                    lock.release().await;
+                }
+                None => debug!("the guests are purged by another instance"),
+            }
+        }
+    });
+}
+
+async fn purge(db: &Pool<Postgres>) {
+    let guests = match Guest::expired(db).await {
+        Ok(guests) => guests,
+        Err(e) => {
+            error!("unable to find the expired guests: {}", e);
+            return;
+        }
+    };
+
+    for guest in guests {
+        match guest.purge(db).await {
+            Ok(()) => info!(
+                "anonymized guest {} of game {}",
+                guest.user_id, guest.game_id
+            ),
+            Err(e) => error!("unable to purge guest {}: {}", guest.user_id, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Validator;
+
+    #[test]
+    fn short_password() {
+        let conversion = Conversion {
+            password: "short".to_string(),
+        };
+        assert!(Validator::new(conversion).validate().is_err());
+
+        let conversion = Conversion {
+            password: "long enough".to_string(),
+        };
+        assert!(Validator::new(conversion).validate().is_ok());
+    }
+
+    #[test]
+    fn anonymous_names_cant_be_registered() {
+        let credentials = Credentials {
+            username: anonymous_name(42),
+            password: "long enough".to_string(),
+        };
+
+        assert_eq!(credentials.username, "guest #42");
+        assert!(Validator::new(credentials).validate().is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::{delete, post, web, HttpRequest, HttpResponse};
+use serde_json::json;
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::events::DomainEvent;
+use crate::games::waitlist::Admission;
+use crate::guests::{Conversion, Guest, GuestLink, NewGuest};
+use crate::server::{self, State};
+use crate::validator::Validator;
+use crate::websocket::server::GameId;
+
+/// Create the invite link of a game, an existing link stops working
+#[post("/games/{id}/guest-link")]
+async fn create_link(game_id: Path<i64>, id: Identity, state: Data<State>) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can share an invite link");
+    }
+    if game.is_finished() {
+        bad_request!("the game has already finished");
+    }
+
+    let link = GuestLink::rotate(game.id, &state.db).await?;
+
+    http_created_json!(link);
+}
+
+#[delete("/games/{id}/guest-link")]
+async fn revoke_link(game_id: Path<i64>, id: Identity, state: Data<State>) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can revoke the invite link");
+    }
+
+    GuestLink::revoke(game.id, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Join a game as a guest, the guest is logged in immediately
+#[post("/guests")]
+async fn join(
+    guest: Json<NewGuest>,
+    req: HttpRequest,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let (user, game, admission) = Guest::join(&guest, &state.db).await?;
+
+    auth::remember(&id, &user, &req, &state.db).await?;
+
+    // a guest of a full game waits for a place like everyone else
+    let waitlist_position = match admission {
+        Admission::Accepted(_) => {
+            state.events.publish(DomainEvent::InvitationResponded {
+                game_id: GameId(game.id),
+                user_id: user.id,
+                accepted: true,
+            });
+            None
+        }
+        Admission::Waitlisted(position) => Some(position),
+    };
+
+    http_created_json!(json!({
+        "user": user,
+        "game": game.localized(),
+        "waitlistPosition": waitlist_position
+    }));
+}
+
+/// Set a password, the guest becomes a regular user and is no longer purged
+#[post("/guests/convert")]
+async fn convert(
+    conversion: Json<Validator<Conversion>>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    if !state.auth.manages_passwords() {
+        forbidden!("the passwords are managed by the identity provider");
+    }
+
+    let guest = match Guest::find(user.id, &state.db).await? {
+        Some(guest) => guest,
+        None => bad_request!("you already have an account"),
+    };
+
+    let user = guest
+        .convert(conversion.into_inner().validate()?, &state.db)
+        .await?;
+
+    http_ok_json!(user);
+}
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(create_link);
+    cfg.service(revoke_link);
+    cfg.service(join);
+    cfg.service(convert);
+}
@@ -0,0 +1,183 @@
+//! Shared client for outbound HTTP requests
+//!
+//! Failed requests are retried a few times with an exponential backoff and some jitter.
+//! Every upstream host has a circuit breaker, after too many consecutive failures the requests
+//! to that host fail immediately for a while, so a slow upstream can't stall the actix workers.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use reqwest::{RequestBuilder, Response};
+
+use crate::errors::ServiceError;
+
+/// How many times a failed request is retried
+const MAX_RETRIES: u32 = 2;
+/// The backoff before the first retry, this doubles for every retry
+const BASE_BACKOFF: Duration = Duration::from_millis(100);
+/// The amount of consecutive failures before the circuit opens
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long the circuit stays open before a request is let through again
+const OPEN_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    reqwest: reqwest::Client,
+    breakers: Arc<Mutex<HashMap<String, CircuitBreaker>>>,
+}
+
+impl HttpClient {
+    pub fn new(connect_timeout: Duration, timeout: Duration) -> Result<Self, reqwest::Error> {
+        let reqwest = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(timeout)
+            .build()?;
+
+        Ok(HttpClient {
+            reqwest,
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        self.reqwest.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder {
+        self.reqwest.post(url)
+    }
+
+    /// Send a request, retrying connection errors, timeouts and server errors
+    #[tracing::instrument(skip(self, request))]
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, ServiceError> {
+        let request = request.build()?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        let mut attempt = 0;
+        loop {
+            if !self.breaker(&host, CircuitBreaker::allow_request) {
+                return Err(ServiceError::BadGateway(format!("{} is unavailable", host)));
+            }
+
+            let retry = request
+                .try_clone()
+                .expect("requests without a streaming body can be cloned");
+
+            match self.reqwest.execute(retry).await {
+                Ok(res) if !res.status().is_server_error() => {
+                    self.breaker(&host, CircuitBreaker::success);
+                    return Ok(res);
+                }
+                res => {
+                    self.breaker(&host, CircuitBreaker::failure);
+
+                    if attempt == MAX_RETRIES {
+                        return Ok(res?);
+                    }
+                    match res {
+                        Ok(res) => warn!("{} responded with {}, retrying", host, res.status()),
+                        Err(e) => warn!("request to {} failed, retrying: {}", host, e),
+                    }
+                }
+            }
+
+            actix_rt::time::delay_for(backoff(attempt)).await;
+            attempt += 1;
+        }
+    }
+
+    /// Run a function on the circuit breaker of a host
+    fn breaker<T>(&self, host: &str, f: impl FnOnce(&mut CircuitBreaker) -> T) -> T {
+        let mut breakers = self
+            .breakers
+            .lock()
+            .expect("the circuit breakers are poisoned");
+        f(breakers.entry(host.to_string()).or_default())
+    }
+}
+
+/// The exponential backoff before retrying an attempt, with up to 50% jitter
+fn backoff(attempt: u32) -> Duration {
+    let backoff = BASE_BACKOFF * 2u32.pow(attempt);
+    let jitter = rand::thread_rng().gen_range(0..=backoff.as_millis() as u64 / 2);
+
+    backoff + Duration::from_millis(jitter)
+}
+
+#[derive(Debug, Default)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    /// returns false while the circuit is open
+    ///
+    /// Once the open duration passed, requests are let through again until one fails
+    fn allow_request(&mut self) -> bool {
+        match self.opened_at {
+            Some(opened_at) => opened_at.elapsed() >= OPEN_DURATION,
+            None => true,
+        }
+    }
+
+    fn success(&mut self) {
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+    }
+
+    fn failure(&mut self) {
+        self.consecutive_failures += 1;
+
+        if self.consecutive_failures >= FAILURE_THRESHOLD {
+            if self.opened_at.is_none() {
+                warn!(
+                    "opening the circuit after {} failures",
+                    self.consecutive_failures
+                );
+            }
+            self.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuit_breaker() {
+        let mut breaker = CircuitBreaker::default();
+
+        for _ in 1..FAILURE_THRESHOLD {
+            breaker.failure();
+            assert!(breaker.allow_request());
+        }
+
+        breaker.failure();
+        assert!(!breaker.allow_request());
+
+        // let the open duration pass
+        breaker.opened_at = Some(Instant::now() - OPEN_DURATION);
+        assert!(breaker.allow_request());
+
+        // the circuit opens again when the request fails
+        breaker.failure();
+        assert!(!breaker.allow_request());
+
+        breaker.success();
+        assert!(breaker.allow_request());
+    }
+
+    #[test]
+    fn backoff_with_jitter() {
+        for attempt in 0..MAX_RETRIES {
+            let minimum = BASE_BACKOFF * 2u32.pow(attempt);
+            let backoff = backoff(attempt);
+
+            assert!(backoff >= minimum);
+            assert!(backoff <= minimum + minimum / 2);
+        }
+    }
+}
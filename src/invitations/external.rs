@@ -0,0 +1,167 @@
+//! Invitations for people who don't have an account yet
+//!
+//! The game owner invites an email address, which creates an external invitation with a secret token.
+//! Registering with the token turns the external invitation into an accepted invitation to the game.
+//! The server doesn't send emails, the clients turn the token into a registration link the owner shares.
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::waitlist::{self, Admission};
+use crate::invitations::NewInvitation;
+
+/// how long the registration link keeps working
+const EXPIRY_DAYS: i64 = 14;
+/// the maximum length of an email address
+const MAX_EMAIL_LENGTH: usize = 254;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExternalInvitation {
+    pub id: i64,
+    pub game_id: i64,
+    pub email: String,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A rough check, the address is only used by the owner to recognise the invitation
+pub fn validate_email(email: &str) -> Result<(), ServiceError> {
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !domain.contains('@')
+        }
+        None => false,
+    };
+
+    if !valid || email.len() > MAX_EMAIL_LENGTH || email.contains(char::is_whitespace) {
+        bad_request!("this is not a valid email address");
+    }
+
+    Ok(())
+}
+
+impl ExternalInvitation {
+    /// Invite an email address to a game, inviting it again replaces the token
+    #[tracing::instrument(name = "ExternalInvitation::create", skip(db))]
+    pub async fn create(
+        game_id: i64,
+        invited_by: i64,
+        email: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<ExternalInvitation, ServiceError> {
+        let email = email.trim().to_lowercase();
+        validate_email(&email)?;
+
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        let expires_at = Utc::now() + Duration::days(EXPIRY_DAYS);
+
+        let invitation = sqlx::query_as!(
+            ExternalInvitation,
+            r#"
+            INSERT INTO external_invitations (game_id, invited_by, email, token, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (game_id, email) DO UPDATE
+            SET token = EXCLUDED.token, invited_by = EXCLUDED.invited_by,
+                created_at = NOW(), expires_at = EXCLUDED.expires_at
+            WHERE external_invitations.converted_at IS NULL
+            RETURNING id, game_id, email, token, created_at, expires_at
+            "#,
+            game_id,
+            invited_by,
+            email,
+            token,
+            expires_at
+        )
+        .fetch_optional(db)
+        .await?;
+
+        match invitation {
+            Some(invitation) => Ok(invitation),
+            None => Err(ServiceError::Conflict(String::from(
+                "this email address already registered with an invitation",
+            ))),
+        }
+    }
+
+    /// Check the token before an account is created with it
+    #[tracing::instrument(name = "ExternalInvitation::verify", skip(token, db))]
+    pub async fn verify(token: &str, db: &Pool<Postgres>) -> Result<(), ServiceError> {
+        let found = sqlx::query!(
+            r#"
+            SELECT id FROM external_invitations
+            WHERE token = $1 AND converted_at IS NULL AND expires_at > NOW()
+            "#,
+            token
+        )
+        .fetch_optional(db)
+        .await?;
+
+        if found.is_none() {
+            bad_request!("the invitation link is invalid or expired");
+        }
+
+        Ok(())
+    }
+
+    /// Turn the invitation into an accepted invitation of the new user, or put the user on the waitlist
+    #[tracing::instrument(name = "ExternalInvitation::convert", skip(token, db))]
+    pub async fn convert(
+        token: &str,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Admission, ServiceError> {
+        let mut tx = db::begin(Operation::ConvertInvitation, db).await?;
+
+        let converted = sqlx::query!(
+            r#"
+            UPDATE external_invitations
+            SET user_id = $2, converted_at = NOW()
+            WHERE token = $1 AND converted_at IS NULL AND expires_at > NOW()
+            RETURNING game_id
+            "#,
+            token,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let game_id = match converted {
+            Some(converted) => converted.game_id,
+            None => bad_request!("the invitation link is invalid or expired"),
+        };
+
+        let invitation = NewInvitation::new(game_id, user_id).save(&mut *tx).await?;
+        let admission = waitlist::accept_in(&invitation, &mut tx).await?;
+
+        tx.commit().await?;
+
+        Ok(admission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn email_addresses() {
+        assert!(validate_email("bart@example.com").is_ok());
+        assert!(validate_email("bart.willems+fuif@mail.example.be").is_ok());
+
+        assert!(validate_email("bart").is_err());
+        assert!(validate_email("@example.com").is_err());
+        assert!(validate_email("bart@localhost").is_err());
+        assert!(validate_email("bart@example.").is_err());
+        assert!(validate_email("bart@@example.com").is_err());
+        assert!(validate_email("bart @example.com").is_err());
+    }
+}
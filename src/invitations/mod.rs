@@ -1,3 +1,4 @@
+pub mod external;
 mod models;
 pub mod routes;
 pub use models::{Invitation, NewInvitation, State, UserInvite};
@@ -84,22 +84,6 @@ pub struct InvitationResponse {
 }
 
 impl Invitation {
-    /// Update the invitation, returns the persisted invitation
-    pub async fn update(&self, db: &Pool<Postgres>) -> Result<Invitation, sqlx::Error> {
-        sqlx::query_as!(
-            Invitation,
-            r#"
-            UPDATE invitations 
-            SET state = $1 
-            WHERE id = $2 
-            RETURNING id, game_id, user_id, state as "state!: State", created_at, updated_at;"#,
-            self.state as _,
-            self.id
-        )
-        .fetch_one(db)
-        .await
-    }
-
     pub async fn find_by_id(id: i64, db: &Pool<Postgres>) -> Result<Invitation, sqlx::Error> {
         sqlx::query_as!(
             Invitation,
@@ -121,7 +105,7 @@ impl Invitation {
     ) -> Result<Vec<InvitationResponse>, sqlx::Error> {
         let rows = sqlx::query!(
             r#"
-            SELECT invitations.id, invitations.state as "state!: State", games.id AS "game_id", games.name, games.start_time, games.close_time, games.beverage_count, users.id AS "user_id", users.username
+            SELECT invitations.id, invitations.state as "state!: State", games.id AS "game_id", games.name, games.start_time, games.close_time, games.beverage_count, games.time_zone, games.venue_name, games.venue_address, games.latitude, games.longitude, games.currency, games.price_step, users.id AS "user_id", users.username
             FROM invitations
             INNER JOIN games ON invitations.game_id = games.id
             INNER JOIN users ON games.owner_id = users.id
@@ -145,6 +129,13 @@ impl Invitation {
                     name: record.name,
                     start_time: record.start_time,
                     close_time: record.close_time,
+                    time_zone: record.time_zone,
+                    venue_name: record.venue_name,
+                    venue_address: record.venue_address,
+                    latitude: record.latitude,
+                    longitude: record.longitude,
+                    currency: record.currency,
+                    price_step: record.price_step,
                     owner: UserResponse {
                         id: record.user_id,
                         username: record.username,
@@ -155,24 +146,13 @@ impl Invitation {
 
         Ok(invitations)
     }
-
-    /// mark a game as accepted, this does not automatically persist
-    pub fn accept(&mut self) -> &mut Self {
-        self.state = State::Accepted;
-        self
-    }
-
-    /// mark a game as accepted, this does not automatically persist
-    pub fn decline(&mut self) -> &mut Self {
-        self.state = State::Declined;
-        self
-    }
 }
 
 /// InviteMessage is what the client sends us to invite an
-/// existing user to an existing game
+/// existing user, or someone without an account by email, to an existing game
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UserInvite {
-    pub user_id: i64,
+    pub user_id: Option<i64>,
+    pub email: Option<String>,
 }
@@ -1,12 +1,18 @@
-use actix_identity::Identity;
 use actix_web::http::StatusCode;
 use actix_web::web::{Data, HttpResponse, Json, Path};
 use actix_web::{get, post, web};
 
 use crate::auth;
+use crate::auth::Identity;
+use crate::events::DomainEvent;
+use crate::games::rules::HouseRules;
+use crate::games::waitlist::{self, Admission};
 use crate::games::Game;
+use crate::guests::Guest;
+use crate::invitations::external::ExternalInvitation;
 use crate::invitations::{Invitation, State, UserInvite};
 use crate::server;
+use crate::websocket::server::GameId;
 
 #[get("/invitations")]
 async fn my_invitations(id: Identity, state: Data<server::State>) -> server::Response {
@@ -44,7 +50,7 @@ async fn find_available_users(
     http_ok_json!(users);
 }
 
-/// Invite a user to a game
+/// Invite a user to a game, or an email address which gets a registration link
 #[post("/games/{id}/invitations")]
 async fn invite_user(
     game_id: Path<i64>,
@@ -60,11 +66,45 @@ async fn invite_user(
     if !game.is_owner(&user) {
         forbidden!("Only the game owner can invite users");
     }
-    game.invite_user(invite.user_id, &state.db).await?;
+
+    let user_id = match (invite.user_id, invite.email) {
+        (Some(user_id), None) => user_id,
+        (None, Some(email)) => {
+            let invitation =
+                ExternalInvitation::create(game.id, user.id, &email, &state.db).await?;
+            http_created_json!(invitation);
+        }
+        _ => bad_request!("invite either a user or an email address"),
+    };
+
+    Guest::forbid(user_id, &state.db).await?;
+    game.invite_user(user_id, &state.db).await?;
+
+    state.events.publish(DomainEvent::InvitationCreated {
+        game_id: GameId(game.id),
+        user_id,
+    });
 
     Ok(HttpResponse::new(StatusCode::CREATED))
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InvitationAnswer {
+    #[serde(flatten)]
+    invitation: Invitation,
+    house_rules: Option<HouseRules>,
+}
+
+/// The game is full, the invitation stays pending until a place opens up
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Waitlisted {
+    #[serde(flatten)]
+    invitation: Invitation,
+    position: i64,
+}
+
 #[post("/invitations/{id}/{response}")]
 async fn respond(
     info: Path<(i64, State)>,
@@ -76,21 +116,49 @@ async fn respond(
     let info = info.into_inner();
     let response = &info.1;
 
-    let mut invite = Invitation::find_by_id(info.0, &state.db).await?;
+    let invite = Invitation::find_by_id(info.0, &state.db).await?;
 
     if user.id != invite.user_id && !user.is_admin {
         forbidden!("this is not the invite you're looking for");
     }
 
-    match response {
-        State::Accepted => invite.accept(),
-        State::Declined => invite.decline(),
+    let invite = match response {
+        State::Accepted => match waitlist::accept(&invite, &state.db).await? {
+            Admission::Accepted(invite) => invite,
+            Admission::Waitlisted(position) => {
+                return Ok(HttpResponse::Accepted().json(Waitlisted {
+                    invitation: invite,
+                    position,
+                }));
+            }
+        },
+        State::Declined => {
+            let departure = waitlist::leave(invite.game_id, invite.user_id, &state.db).await?;
+            waitlist::publish_promotions(invite.game_id, &departure.promoted, &state.events);
+
+            departure.invitation
+        }
         _ => bad_request!("you can only accept or decline an invite"),
     };
 
-    let invite = invite.update(&state.db).await?;
+    let accepted = matches!(invite.state, State::Accepted);
+    state.events.publish(DomainEvent::InvitationResponded {
+        game_id: GameId(invite.game_id),
+        user_id: invite.user_id,
+        accepted,
+    });
+
+    // the players read the house rules when they join
+    let house_rules = if accepted {
+        HouseRules::find(invite.game_id, &state.db).await?
+    } else {
+        None
+    };
 
-    http_ok_json!(invite);
+    http_ok_json!(InvitationAnswer {
+        invitation: invite,
+        house_rules,
+    });
 }
 
 pub fn register(cfg: &mut web::ServiceConfig) {
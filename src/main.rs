@@ -19,20 +19,39 @@ use dotenv::dotenv;
 #[macro_use]
 mod macros;
 
+mod access_log;
 mod admin;
 mod auth;
+mod blocking;
 mod cache;
 mod config;
+mod db;
+mod deadline;
 mod ddg;
 mod errors;
+mod events;
 mod games;
+mod guests;
+mod http;
 mod invitations;
 mod market;
+mod mqtt;
+mod pool;
+mod predictions;
+mod quota;
+mod rate_limit;
+mod repositories;
+mod response_cache;
+mod retention;
 mod server;
+mod shutdown;
 mod stats;
+mod streaming;
+mod teams;
 mod transactions;
 mod users;
 mod validator;
+mod webhooks;
 mod websocket;
 
 #[actix_web::main]
@@ -54,14 +73,30 @@ async fn init() -> anyhow::Result<(), Error> {
     // Create a tracing layer with the configured tracer
     let opentelemetry = tracing_opentelemetry::layer().with_tracer(tracer);
 
+    let (text, json) = match config::Config::log_format() {
+        access_log::LogFormat::Text => (
+            Some(tracing_subscriber::fmt::layer().with_writer(std::io::stdout)),
+            None,
+        ),
+        access_log::LogFormat::Json => (
+            None,
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(std::io::stdout),
+            ),
+        ),
+    };
+
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stdout))
+        .with(text)
+        .with(json)
         .with(tracing_subscriber::EnvFilter::from_default_env())
         .with(opentelemetry)
         .try_init()
         .expect("unable to initialize the tokio tracer");
 
-    cache::Cache::init();
+    mqtt::init();
 
     debug!("launching the actix webserver");
     server::launch().await?;
@@ -1,20 +1,117 @@
 use std::{
+    collections::{HashMap, HashSet},
     fmt,
+    sync::Mutex as SyncMutex,
     time::{Duration, Instant},
 };
 
-use actix::Addr;
 use chrono::{DateTime, Utc};
-use sqlx::{Pool, Postgres};
+use futures::stream::BoxStream;
+use opentelemetry::metrics::UpDownCounter;
+use rand::Rng;
+use sqlx::postgres::{PgConnection, PgListener};
+use sqlx::{Connection, Pool, Postgres};
+use tokio::sync::Mutex;
 
+use crate::db::{self, Operation};
 use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+use crate::games::blackouts::Blackout;
 use crate::games::Game;
-use crate::websocket::server::{GameId, NotificationServer, PriceUpdate};
-use crate::websocket::Notification;
+use crate::games::update_interval::UpdateInterval;
+use crate::mqtt;
+use crate::transactions::crashes;
+use crate::websocket::server::{GameId, PriceUpdate};
 use crate::{config::Config, games::Beverage};
 
+/// Postgres channel used to share the price updates between all running instances
+const PRICE_UPDATE_CHANNEL: &str = "price_updates";
+
+/// Send all prices every this many price updates, so clients can resync
+const FULL_SNAPSHOT_INTERVAL: u64 = 10;
+
+/// The share of the interval a price update can be early or late
+const TICK_JITTER: f64 = 0.1;
+
+/// How often every instance looks for the games it has no market agent for
+const AGENT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    /// The price update transactions that are running right now, exported as a Prometheus gauge
+    static ref PRICE_UPDATES_IN_FLIGHT: UpDownCounter<i64> =
+        opentelemetry::global::meter("rustfuif_api")
+            .i64_up_down_counter("price_update_transactions")
+            .with_description("The amount of price update transactions that are running")
+            .init();
+
+    /// The games this instance runs a market agent for
+    static ref RUNNING_AGENTS: SyncMutex<HashSet<i64>> = SyncMutex::new(HashSet::new());
+}
+
+/// Counts a price update transaction as running until it's dropped
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        PRICE_UPDATES_IN_FLIGHT.add(1, &[]);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        PRICE_UPDATES_IN_FLIGHT.add(-1, &[]);
+    }
+}
+
+/// Marks the market agent of a game as running on this instance until it's dropped
+struct Running {
+    game_id: i64,
+}
+
+impl Running {
+    /// Returns `None` when this instance already runs an agent for the game
+    fn register(game_id: i64) -> Option<Self> {
+        let mut running = RUNNING_AGENTS.lock().expect("running agents lock poisoned");
+        if running.insert(game_id) {
+            Some(Running { game_id })
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        if let Ok(mut running) = RUNNING_AGENTS.lock() {
+            running.remove(&self.game_id);
+        }
+    }
+}
+
+/// The delay until the next price update of an agent
+///
+/// The first update lands at a random moment within the interval, so the games that run at the same
+/// time don't all update their prices at the same moment. Every next update is a bit early or late,
+/// so the agents don't drift back into sync.
+fn next_delay(interval: Duration, first: bool) -> Duration {
+    if !Config::use_jitter() {
+        return interval;
+    }
+
+    let mut rng = rand::thread_rng();
+    let factor = if first {
+        rng.gen_range(0.0..1.0)
+    } else {
+        rng.gen_range(1.0 - TICK_JITTER..1.0 + TICK_JITTER)
+    };
+
+    interval.mul_f64(factor)
+}
+
 #[must_use = "this `MarketStatus` may be a `Crash` variant, which should be handled"]
-#[derive(Serialize, Debug, Copy, Clone)]
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Copy, Clone)]
+#[sqlx(rename = "market_status", rename_all = "UPPERCASE")]
 pub enum MarketStatus {
     Regular,
     Crash,
@@ -26,24 +123,44 @@ pub enum MarketStatus {
 /// set to their lowest price
 #[derive(Debug)]
 pub(crate) struct StockMarket {
-    last_crash: Instant,
+    last_crash: DateTime<Utc>,
     status: MarketStatus,
 }
 
 impl StockMarket {
-    pub(crate) fn new() -> Self {
+    pub(crate) fn new(last_crash: DateTime<Utc>) -> Self {
         StockMarket {
-            // Make sure the market doesn't instantly crash
-            last_crash: Instant::now(),
+            last_crash,
             status: MarketStatus::Regular,
         }
     }
 
+    /// The market of a game as it was left by the last leader,
+    /// a market that never crashed counts from the start of the game so it doesn't instantly crash
+    async fn load(game: &Game, db: &Pool<Postgres>) -> Result<Self, sqlx::Error> {
+        let last_crash_at = sqlx::query!(
+            "SELECT last_crash_at FROM markets WHERE game_id = $1",
+            game.id
+        )
+        .fetch_optional(db)
+        .await?
+        .and_then(|row| row.last_crash_at);
+
+        Ok(StockMarket::new(last_crash_at.unwrap_or(game.start_time)))
+    }
+
+    /// The seconds since the last crash, negative before the game started
+    fn seconds_since_crash(&self) -> i64 {
+        Utc::now()
+            .signed_duration_since(self.last_crash)
+            .num_seconds()
+    }
+
     /// instantly crash the stockmarket
     /// this should only be used by administrators
     fn crash(&mut self) {
         // let mut inner = self.inner.write().await;
-        self.last_crash = Instant::now();
+        self.last_crash = Utc::now();
         self.status = MarketStatus::Crash;
     }
 
@@ -58,11 +175,9 @@ impl StockMarket {
     /// 20 minutes ago
     pub(crate) fn can_crash(&self) -> bool {
         // let inner = self.inner.read().await;
-        debug!(
-            "Last crash: {} seconds ago",
-            self.last_crash.elapsed().as_secs()
-        );
-        self.last_crash.elapsed().as_secs() > Config::market_crash_interval()
+        let seconds = self.seconds_since_crash();
+        debug!("Last crash: {} seconds ago", seconds);
+        seconds > Config::market_crash_interval() as i64
     }
 
     /// crash the stock market if it has been a while since the last crash
@@ -83,10 +198,136 @@ impl StockMarket {
     }
 }
 
+/// The connection that holds the market locks of this instance, shared by all its agents
+#[derive(Debug, Default)]
+struct LockConnection {
+    connection: Option<PgConnection>,
+    /// increased on every reconnect, the locks of an older connection were released with it
+    generation: u64,
+}
+
+lazy_static! {
+    static ref LOCK_CONNECTION: Mutex<LockConnection> = Mutex::new(LockConnection::default());
+}
+
+impl LockConnection {
+    /// The generation and the connection, it reconnects when the connection was lost
+    async fn connection(&mut self) -> Result<(u64, &mut PgConnection), sqlx::Error> {
+        if let Some(mut connection) = self.connection.take() {
+            if connection.ping().await.is_ok() {
+                return Ok((self.generation, self.connection.insert(connection)));
+            }
+            warn!("lost the connection holding the market locks");
+        }
+
+        let connection = PgConnection::connect(Config::database_url()).await?;
+        self.generation += 1;
+
+        Ok((self.generation, self.connection.insert(connection)))
+    }
+}
+
+/// Makes sure only one instance updates the prices of a game.
+///
+/// The leader holds a session level advisory lock on the lock connection of its instance.
+/// Every instance keeps a single lock connection for all its games, so the instances that aren't
+/// the leader don't reconnect on every tick. When the leader dies, its connection gets closed
+/// and the lock is released, so another instance can take over on its next tick.
+#[derive(Debug)]
+struct MarketLock {
+    game_id: i64,
+    /// the generation of the lock connection that holds the lock
+    held_by: Option<u64>,
+}
+
+impl MarketLock {
+    fn new(game_id: i64) -> Self {
+        MarketLock {
+            game_id,
+            held_by: None,
+        }
+    }
+
+    /// Returns true if this instance is the leader for the game,
+    /// tries to become the leader if there currently is none
+    #[tracing::instrument(name = "MarketLock::acquire")]
+    async fn acquire(&mut self) -> bool {
+        let mut lock_connection = LOCK_CONNECTION.lock().await;
+
+        match self.try_lock(&mut lock_connection).await {
+            Ok(locked) => locked,
+            Err(e) => {
+                error!("unable to acquire the market lock: {}", e);
+                self.held_by = None;
+                false
+            }
+        }
+    }
+
+    /// Returns false if another instance holds the lock
+    async fn try_lock(
+        &mut self,
+        lock_connection: &mut LockConnection,
+    ) -> Result<bool, sqlx::Error> {
+        let (generation, connection) = lock_connection.connection().await?;
+
+        match self.held_by {
+            Some(held_by) if held_by == generation => return Ok(true),
+            Some(_) => warn!("lost the market lock for Game({})", self.game_id),
+            None => (),
+        }
+
+        let lock = sqlx::query!(
+            r#"SELECT pg_try_advisory_lock($1) as "locked!""#,
+            self.game_id
+        )
+        .fetch_one(&mut *connection)
+        .await?;
+
+        if !lock.locked {
+            self.held_by = None;
+            return Ok(false);
+        }
+
+        info!("acquired the market lock for Game({})", self.game_id);
+        self.held_by = Some(generation);
+        Ok(true)
+    }
+
+    /// Release the lock so another instance could take over
+    async fn release(&mut self) {
+        let held_by = match self.held_by.take() {
+            Some(held_by) => held_by,
+            None => return,
+        };
+
+        let mut lock_connection = LOCK_CONNECTION.lock().await;
+        // the lock was released with the connection that held it
+        if lock_connection.generation != held_by {
+            return;
+        }
+        let connection = match lock_connection.connection.as_mut() {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        if let Err(e) = sqlx::query!(
+            r#"SELECT pg_advisory_unlock($1) as "unlocked!""#,
+            self.game_id
+        )
+        .fetch_one(connection)
+        .await
+        {
+            error!("unable to release the market lock: {}", e);
+        }
+    }
+}
+
 pub struct MarketAgent {
     db: Pool<Postgres>,
-    notifier: Addr<NotificationServer>,
-    market: StockMarket,
+    /// loaded when this instance becomes the leader, the previous leader may have crashed the market
+    market: Option<StockMarket>,
+    lock: MarketLock,
     game: Game,
 }
 
@@ -99,36 +340,76 @@ impl fmt::Debug for MarketAgent {
 }
 
 impl MarketAgent {
-    pub fn new(db: Pool<Postgres>, notifier: Addr<NotificationServer>, game: Game) -> Self {
+    pub fn new(db: Pool<Postgres>, game: Game) -> Self {
         Self {
             db,
-            notifier,
-            market: StockMarket::new(),
+            market: None,
+            lock: MarketLock::new(game.id),
             game,
         }
     }
 
-    /// Start a periodic price updater
+    /// Start a periodic price updater, unless this instance already runs one for the game
+    ///
+    /// Every instance starts an agent for each game, but only the instance
+    /// holding the market lock for a game updates its prices.
     pub(crate) fn start(mut self) {
+        let running = match Running::register(self.game.id) {
+            Some(running) => running,
+            None => return,
+        };
+
         tokio::spawn(async move {
+            let _running = running;
             debug!("Starting market agent for Game({})", self.game.id);
             if self.game.not_started() {
                 actix_rt::time::delay_for(self.game.duration_until_start()).await;
             }
 
+            let mut first = true;
             while self.game.in_progress() {
                 if self.game.is_finished() {
                     debug!("Game({}) is finished", self.game.id);
                     break;
                 }
-                actix_rt::time::delay_for(MarketAgent::interval()).await;
+                let delay = next_delay(self.game_interval().await, first);
+                first = false;
+                actix_rt::time::delay_for(delay).await;
+
+                if !self.lock.acquire().await {
+                    debug!("Game({}) is updated by another instance", self.game.id);
+                    self.market = None;
+                    continue;
+                }
 
                 self.update().await;
             }
+
+            self.lock.release().await;
+        });
+    }
+
+    /// Periodically start an agent for every unfinished game this instance has no agent for
+    ///
+    /// The games created on other instances only get an agent here,
+    /// so this instance can take over their prices when the leader dies.
+    pub(crate) fn supervise(db: Pool<Postgres>) {
+        tokio::spawn(async move {
+            loop {
+                match Game::unfinished(&db).await {
+                    Ok(games) => {
+                        for game in games {
+                            MarketAgent::new(db.clone(), game).start();
+                        }
+                    }
+                    Err(e) => error!("unable to load the unfinished games: {}", e),
+                }
+                actix_rt::time::delay_for(AGENT_POLL_INTERVAL).await;
+            }
         });
     }
 
-    /// Update the prices and notify the users
+    /// Update the prices, the users get notified through the price update listener
     #[tracing::instrument(name = "StockMarket::update")]
     pub(crate) async fn update(&mut self) {
         match self.update_prices().await {
@@ -137,19 +418,9 @@ impl MarketAgent {
             }
             Ok(MarketStatus::Regular) => {
                 debug!("succesfully updated the prices");
-                self.notifier
-                    .do_send(Notification::PriceUpdate(PriceUpdate {
-                        market_status: MarketStatus::Regular,
-                        game_id: GameId(self.game.id),
-                    }));
             }
             Ok(MarketStatus::Crash) => {
                 info!("succesfully updated the prices, with stock market crash");
-                self.notifier
-                    .do_send(Notification::PriceUpdate(PriceUpdate {
-                        market_status: MarketStatus::Crash,
-                        game_id: GameId(self.game.id),
-                    }));
             }
         };
     }
@@ -158,10 +429,18 @@ impl MarketAgent {
     async fn update_prices(&mut self) -> Result<MarketStatus, ServiceError> {
         let start = Instant::now();
 
-        let market_status = self.market.update();
+        let market = match self.market.as_mut() {
+            Some(market) => market,
+            None => {
+                let market = StockMarket::load(&self.game, &self.db).await?;
+                self.market.insert(market)
+            }
+        };
+        let market_status = market.update();
         info!("Stock Market Status: {:?}", market_status);
 
-        let mut tx = self.db.begin().await?;
+        let _in_flight = InFlight::start();
+        let mut tx = db::begin(Operation::PriceUpdate, &self.db).await?;
 
         let beverages = match market_status {
             MarketStatus::Crash => self.game.crash_prices(&mut tx).await?,
@@ -170,12 +449,35 @@ impl MarketAgent {
 
         let changes: Vec<PriceChange> = beverages.iter().map(|beverage| beverage.into()).collect();
 
-        PriceHistory::save(&changes, &mut tx).await?;
+        let tick = Market::save(self.game.id, market_status, &mut tx).await?;
+        if let MarketStatus::Crash = market_status {
+            crashes::record(self.game.id, &mut tx).await?;
+        }
+
+        PriceHistory::save(&changes, tick, &mut tx).await?;
+        tx.record_rows(beverages.len() as u64 + changes.len() as u64);
+
+        // the notification is only sent when the transaction commits
+        MarketAgent::publish(
+            PriceUpdate {
+                market_status,
+                game_id: GameId(self.game.id),
+                tick,
+                // notification payloads are limited to 8000 bytes,
+                // so every listener loads the new prices itself
+                prices: Vec::new(),
+                snapshot: false,
+            },
+            &mut tx,
+        )
+        .await?;
 
         tx.commit().await?;
         info!("updated game({}) in {:?}", self.game.id, start.elapsed());
 
-        if self.market.has_crashed().await {
+        mqtt::publish_prices(self.game.id, tick, market_status, &beverages);
+
+        if market.has_crashed().await {
             return Ok(MarketStatus::Crash);
         }
 
@@ -186,17 +488,316 @@ impl MarketAgent {
     pub(crate) fn interval() -> Duration {
         Duration::from_secs(Config::price_update_interval())
     }
+
+    /// the price update interval of the game, the owner can change it while the game is running
+    async fn game_interval(&self) -> Duration {
+        match UpdateInterval::find(self.game.id, &self.db).await {
+            Ok(interval) => interval.duration(),
+            Err(e) => {
+                error!("unable to load the update interval of {:?}: {}", self, e);
+                MarketAgent::interval()
+            }
+        }
+    }
+
+    /// Share a price update with every running instance
+    async fn publish(
+        update: PriceUpdate,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<(), ServiceError> {
+        let payload = serde_json::to_string(&update).map_err(|e| {
+            error!("unable to serialize the price update: {}", e);
+            ServiceError::InternalServerError
+        })?;
+
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(PRICE_UPDATE_CHANNEL)
+            .bind(payload)
+            .execute(&mut *db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Publish the price updates of all instances on the local event bus
+    pub(crate) fn listen(db: Pool<Postgres>, events: EventBus) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = MarketAgent::forward_price_updates(&db, &events).await {
+                    error!("price update listener failed: {}", e);
+                }
+                actix_rt::time::delay_for(Duration::from_secs(5)).await;
+            }
+        });
+    }
+
+    async fn forward_price_updates(
+        db: &Pool<Postgres>,
+        events: &EventBus,
+    ) -> Result<(), sqlx::Error> {
+        let mut listener = PgListener::connect(Config::database_url()).await?;
+        listener.listen(PRICE_UPDATE_CHANNEL).await?;
+
+        let mut tracker = PriceTracker::default();
+
+        loop {
+            let notification = listener.recv().await?;
+
+            match serde_json::from_str::<PriceUpdate>(notification.payload()) {
+                Ok(mut update) => match BeveragePrice::find_by_game(update.game_id.0, db).await {
+                    Ok(prices) => {
+                        tracker.track(&mut update, prices);
+                        events.publish(DomainEvent::PricesUpdated(update));
+                    }
+                    Err(e) => error!(
+                        "unable to load the new prices of game {}: {}",
+                        update.game_id.0, e
+                    ),
+                },
+                Err(e) => error!("received an invalid price update: {}", e),
+            }
+        }
+    }
+}
+
+/// The current price of a beverage
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct BeveragePrice {
+    pub user_id: i64,
+    pub slot_no: i16,
+    pub price: i64,
+    /// the price before this update, used to show the price trend
+    pub previous_price: Option<i64>,
+}
+
+impl BeveragePrice {
+    /// returns true when the price differs from the previously published price
+    pub fn changed(&self) -> bool {
+        self.previous_price != Some(self.price)
+    }
+
+    /// Return the current price of every beverage in a game
+    #[tracing::instrument(name = "BeveragePrice::find_by_game", skip(db))]
+    pub(crate) async fn find_by_game(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<BeveragePrice>, sqlx::Error> {
+        let records = sqlx::query!(
+            "SELECT user_id, slot_no, current_price FROM beverages WHERE game_id = $1",
+            game_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| BeveragePrice {
+                user_id: record.user_id,
+                slot_no: record.slot_no,
+                price: record.current_price,
+                previous_price: None,
+            })
+            .collect())
+    }
+}
+
+/// Remembers the last published prices, so clients can receive only the changed beverages
+#[derive(Debug, Default)]
+struct PriceTracker {
+    games: HashMap<i64, TrackedPrices>,
+}
+
+#[derive(Debug, Default)]
+struct TrackedPrices {
+    /// the price of each (user_id, slot_no)
+    prices: HashMap<(i64, i16), i64>,
+    updates_since_snapshot: u64,
+}
+
+impl PriceTracker {
+    /// Set the new prices on the update, together with the previously published prices
+    fn track(&mut self, update: &mut PriceUpdate, mut prices: Vec<BeveragePrice>) {
+        let game = self.games.entry(update.game_id.0).or_default();
+
+        update.snapshot = game.updates_since_snapshot == 0;
+        game.updates_since_snapshot = (game.updates_since_snapshot + 1) % FULL_SNAPSHOT_INTERVAL;
+
+        for price in prices.iter_mut() {
+            price.previous_price = game
+                .prices
+                .insert((price.user_id, price.slot_no), price.price);
+        }
+
+        update.prices = prices;
+    }
 }
 
+/// The state of a game's market, shared by all instances
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
-pub struct PriceHistory {
-    id: i64,
+pub struct Market {
     game_id: i64,
-    user_id: i64,
-    slot_no: i16,
-    price: i64,
-    created_at: DateTime<Utc>,
+    status: MarketStatus,
+    last_crash_at: Option<DateTime<Utc>>,
+    last_update_at: Option<DateTime<Utc>>,
+    /// empty when the game is finished
+    next_update_at: Option<DateTime<Utc>>,
+    /// the interval in seconds between price updates
+    update_interval: u64,
+    /// the factor the prices are currently multiplied with because of the game's price drift
+    drift_factor: f64,
+    /// the tick of the last price update, 0 before the first update
+    tick: i64,
+    /// purchases are rejected until this blackout ends
+    blackout: Option<Blackout>,
+}
+
+impl Market {
+    #[tracing::instrument(name = "Market::find")]
+    pub async fn find(game: &Game, db: &Pool<Postgres>) -> Result<Market, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"SELECT status as "status: MarketStatus", last_crash_at, last_update_at, tick FROM markets WHERE game_id = $1"#,
+            game.id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        let (status, last_crash_at, last_update_at, tick) = match row {
+            Some(row) => (row.status, row.last_crash_at, row.last_update_at, row.tick),
+            // the prices have not been updated yet
+            None => (MarketStatus::Regular, None, None, 0),
+        };
+
+        let update_interval = UpdateInterval::find(game.id, db).await?.seconds;
+
+        let next_update_at = if game.is_finished() {
+            None
+        } else {
+            let last_update_at = last_update_at.unwrap_or(game.start_time);
+            Some(last_update_at + chrono::Duration::seconds(update_interval as i64))
+        };
+
+        Ok(Market {
+            game_id: game.id,
+            status,
+            last_crash_at,
+            last_update_at,
+            next_update_at,
+            update_interval,
+            drift_factor: game.drift_factor(Utc::now()),
+            tick,
+            blackout: Blackout::active(game.id, db).await?,
+        })
+    }
+
+    /// Store the state of the market after a price update, returns the tick of the update
+    #[tracing::instrument(name = "Market::save", skip(db))]
+    async fn save(
+        game_id: i64,
+        status: MarketStatus,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO markets (game_id, status, last_crash_at, last_update_at, tick)
+            VALUES ($1, $2, CASE WHEN $2 = 'CRASH'::market_status THEN NOW() END, NOW(), 1)
+            ON CONFLICT (game_id) DO UPDATE
+            SET status = EXCLUDED.status,
+                last_update_at = EXCLUDED.last_update_at,
+                last_crash_at = COALESCE(EXCLUDED.last_crash_at, markets.last_crash_at),
+                tick = markets.tick + 1
+            RETURNING tick
+            "#,
+            game_id,
+            status as _
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(row.tick)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceHistory {
+    pub id: i64,
+    pub game_id: i64,
+    pub user_id: i64,
+    pub slot_no: i16,
+    pub price: i64,
+    pub created_at: DateTime<Utc>,
+    /// the price update this price belongs to, see `Market::save`
+    pub tick: i64,
+}
+
+/// The price a beverage slot had at a moment
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EffectivePrice {
+    pub slot_no: i16,
+    pub price: i64,
+    /// the price change that set the price, empty when it was still the starting price
+    pub changed_at: Option<DateTime<Utc>>,
+    pub tick: Option<i64>,
+}
+
+/// Only load the price changes after a tick
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PriceHistoryFilter {
+    pub since_tick: Option<i64>,
+}
+
+/// the longest interval the price history can be downsampled to, in seconds
+const MAX_RESOLUTION: i64 = 24 * 60 * 60;
+
+/// Downsample the price history of a whole game, which keeps the last price of every interval
+#[derive(Debug, Deserialize)]
+pub struct Resolution {
+    /// the length of an interval, like `60s`, `5m` or `1h`
+    pub resolution: Option<String>,
+}
+
+impl Resolution {
+    /// the resolution in seconds, empty when the prices aren't downsampled
+    pub fn seconds(&self) -> Result<Option<i64>, ServiceError> {
+        match self.resolution.as_deref() {
+            Some(resolution) => parse_resolution(resolution).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// parse an interval like `90`, `60s`, `5m` or `1h` into seconds
+fn parse_resolution(resolution: &str) -> Result<i64, ServiceError> {
+    let resolution = resolution.trim();
+    let (amount, unit) = match resolution.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => resolution.split_at(index),
+        None => (resolution, "s"),
+    };
+
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => {
+            bad_request!("use a resolution like 60s, 5m or 1h");
+        }
+    };
+    let seconds = match amount.parse::<i64>() {
+        Ok(amount) => amount.saturating_mul(multiplier),
+        Err(_) => {
+            bad_request!("use a resolution like 60s, 5m or 1h");
+        }
+    };
+
+    if !(1..=MAX_RESOLUTION).contains(&seconds) {
+        bad_request!("the resolution should be between 1 second and 24 hours");
+    }
+
+    Ok(seconds)
 }
 
 #[derive(Debug)]
@@ -209,18 +810,176 @@ pub(crate) struct PriceChange {
 }
 
 impl PriceHistory {
-    /// Return all price changes for a single beverage
+    /// Return the price changes of the beverages of a user, in the order of their ticks
+    ///
+    /// Clients that missed price updates only load the ticks after the last one they received.
     #[tracing::instrument(name = "PriceHistory::load")]
     pub async fn load(
         user_id: i64,
         game_id: i64,
+        since_tick: Option<i64>,
         db: &Pool<Postgres>,
     ) -> Result<Vec<PriceHistory>, sqlx::Error> {
         sqlx::query_as!(
             PriceHistory,
-            "SELECT * FROM price_histories WHERE user_id = $1 AND game_id = $2",
+            r#"
+            SELECT * FROM price_histories
+            WHERE user_id = $1 AND game_id = $2 AND tick > $3
+            ORDER BY tick, slot_no
+            "#,
             user_id,
-            game_id
+            game_id,
+            since_tick.unwrap_or(0)
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// The price changes of `load` as a stream, for the clients that load a whole history
+    pub fn stream(
+        user_id: i64,
+        game_id: i64,
+        since_tick: Option<i64>,
+        db: &Pool<Postgres>,
+    ) -> BoxStream<'_, Result<PriceHistory, sqlx::Error>> {
+        sqlx::query_as!(
+            PriceHistory,
+            r#"
+            SELECT * FROM price_histories
+            WHERE user_id = $1 AND game_id = $2 AND tick > $3
+            ORDER BY tick, slot_no
+            "#,
+            user_id,
+            game_id,
+            since_tick.unwrap_or(0)
+        )
+        .fetch(db)
+    }
+
+    /// Return the price changes of every beverage of every player in a game, by player and slot
+    ///
+    /// With a `resolution` in seconds, only the last price change of every interval is returned.
+    #[tracing::instrument(name = "PriceHistory::load_game", skip(db))]
+    pub async fn load_game(
+        game_id: i64,
+        resolution: Option<i64>,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<PriceHistory>, sqlx::Error> {
+        match resolution {
+            Some(resolution) => {
+                sqlx::query_as!(
+                    PriceHistory,
+                    r#"
+                    SELECT DISTINCT ON (user_id, slot_no, FLOOR(EXTRACT(EPOCH FROM created_at) / $2)) *
+                    FROM price_histories
+                    WHERE game_id = $1
+                    ORDER BY user_id, slot_no, FLOOR(EXTRACT(EPOCH FROM created_at) / $2),
+                        created_at DESC, id DESC
+                    "#,
+                    game_id,
+                    resolution as f64
+                )
+                .fetch_all(db)
+                .await
+            }
+            None => {
+                sqlx::query_as!(
+                    PriceHistory,
+                    r#"
+                    SELECT * FROM price_histories
+                    WHERE game_id = $1
+                    ORDER BY user_id, slot_no, created_at, id
+                    "#,
+                    game_id
+                )
+                .fetch_all(db)
+                .await
+            }
+        }
+    }
+
+    /// Return the most recent price change of the given beverage slots
+    #[tracing::instrument(name = "PriceHistory::latest", skip(db))]
+    pub(crate) async fn latest(
+        user_id: i64,
+        game_id: i64,
+        slots: &[i16],
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<Vec<PriceHistory>, sqlx::Error> {
+        sqlx::query_as!(
+            PriceHistory,
+            r#"
+            SELECT DISTINCT ON (slot_no) *
+            FROM price_histories
+            WHERE user_id = $1 AND game_id = $2 AND slot_no = any($3)
+            ORDER BY slot_no, created_at DESC, id DESC
+            "#,
+            user_id,
+            game_id,
+            slots
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Return the price changes that were in effect at a moment, the last one before it of every slot
+    ///
+    /// A slot without a price change before the moment still had its starting price.
+    #[tracing::instrument(name = "PriceHistory::at", skip(db))]
+    pub(crate) async fn at(
+        user_id: i64,
+        game_id: i64,
+        slots: &[i16],
+        at: DateTime<Utc>,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<Vec<PriceHistory>, sqlx::Error> {
+        sqlx::query_as!(
+            PriceHistory,
+            r#"
+            SELECT DISTINCT ON (slot_no) *
+            FROM price_histories
+            WHERE user_id = $1 AND game_id = $2 AND slot_no = any($3) AND created_at <= $4
+            ORDER BY slot_no, created_at DESC, id DESC
+            "#,
+            user_id,
+            game_id,
+            slots,
+            at
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Reconstruct the prices of all beverages of a user at a moment
+    #[tracing::instrument(name = "PriceHistory::effective_at", skip(db))]
+    pub async fn effective_at(
+        user_id: i64,
+        game_id: i64,
+        at: DateTime<Utc>,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<EffectivePrice>, sqlx::Error> {
+        sqlx::query_as!(
+            EffectivePrice,
+            r#"
+            SELECT beverages.slot_no, COALESCE(changes.price, beverages.starting_price) AS "price!",
+                changes.created_at AS "changed_at?", changes.tick AS "tick?"
+            FROM beverages
+            LEFT JOIN LATERAL (
+                SELECT price, created_at, tick
+                FROM price_histories
+                WHERE price_histories.user_id = beverages.user_id
+                    AND price_histories.game_id = beverages.game_id
+                    AND price_histories.slot_no = beverages.slot_no
+                    AND price_histories.created_at <= $3
+                ORDER BY created_at DESC, id DESC
+                LIMIT 1
+            ) changes ON true
+            WHERE beverages.user_id = $1 AND beverages.game_id = $2
+            ORDER BY beverages.slot_no
+            "#,
+            user_id,
+            game_id,
+            at
         )
         .fetch_all(db)
         .await
@@ -229,12 +988,13 @@ impl PriceHistory {
     #[tracing::instrument(name = "PriceHistory::save", skip(db))]
     async fn save(
         changes: &[PriceChange],
+        tick: i64,
         db: &mut sqlx::Transaction<'_, Postgres>,
     ) -> Result<(), sqlx::Error> {
         for change in changes {
             sqlx::query!(
-                "INSERT INTO price_histories (game_id, user_id, slot_no, price, created_at) VALUES ($1, $2, $3, $4, $5)", 
-                change.game_id, change.user_id, change.slot_no, change.price, change.created_at
+                "INSERT INTO price_histories (game_id, user_id, slot_no, price, created_at, tick) VALUES ($1, $2, $3, $4, $5, $6)", 
+                change.game_id, change.user_id, change.slot_no, change.price, change.created_at, tick
             ).execute(&mut *db).await?;
         }
         Ok(())
@@ -252,3 +1012,80 @@ impl From<&Beverage> for PriceChange {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(slot_no: i16, price: i64) -> BeveragePrice {
+        BeveragePrice {
+            user_id: 1,
+            slot_no,
+            price,
+            previous_price: None,
+        }
+    }
+
+    fn update() -> PriceUpdate {
+        PriceUpdate {
+            market_status: MarketStatus::Regular,
+            game_id: GameId(1),
+            tick: 1,
+            prices: Vec::new(),
+            snapshot: false,
+        }
+    }
+
+    #[test]
+    fn track_price_changes() {
+        let mut tracker = PriceTracker::default();
+
+        // the first update always contains every price
+        let mut first = update();
+        tracker.track(&mut first, vec![price(0, 100), price(1, 200)]);
+        assert!(first.snapshot);
+        assert_eq!(
+            first.prices.iter().filter(|price| price.changed()).count(),
+            2
+        );
+
+        let mut second = update();
+        tracker.track(&mut second, vec![price(0, 100), price(1, 180)]);
+        assert!(!second.snapshot);
+        let changed: Vec<&BeveragePrice> = second
+            .prices
+            .iter()
+            .filter(|price| price.changed())
+            .collect();
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].slot_no, 1);
+        assert_eq!(changed[0].previous_price, Some(200));
+
+        for _ in 2..FULL_SNAPSHOT_INTERVAL {
+            let mut calm = update();
+            tracker.track(&mut calm, vec![price(0, 100), price(1, 180)]);
+            assert!(!calm.snapshot);
+            assert!(!calm.prices.iter().any(|price| price.changed()));
+        }
+
+        let mut resync = update();
+        tracker.track(&mut resync, vec![price(0, 100), price(1, 180)]);
+        assert!(resync.snapshot);
+    }
+
+    #[test]
+    fn resolutions() {
+        assert_eq!(parse_resolution("60s").unwrap(), 60);
+        assert_eq!(parse_resolution("90").unwrap(), 90);
+        assert_eq!(parse_resolution("5m").unwrap(), 300);
+        assert_eq!(parse_resolution("1h").unwrap(), 3600);
+        assert_eq!(parse_resolution("24h").unwrap(), MAX_RESOLUTION);
+
+        assert!(parse_resolution("0s").is_err());
+        assert!(parse_resolution("25h").is_err());
+        assert!(parse_resolution("1d").is_err());
+        assert!(parse_resolution("m").is_err());
+        assert!(parse_resolution("-5m").is_err());
+        assert!(parse_resolution("9223372036854775807h").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Connection pool saturation detection and backpressure
+//!
+//! When every connection is in use, new requests queue up inside the pool until they time out.
+//! Requests that would wait behind too many others are rejected right away with a 503,
+//! and a saturation that doesn't go away is reported.
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::web::Data;
+use actix_web::Error;
+use futures::future::{ok, Ready};
+use futures::Future;
+use sqlx::pool::PoolOptions;
+use sqlx::{Pool, Postgres};
+
+use crate::config::Config;
+use crate::errors::ServiceError;
+use crate::server::State;
+
+/// The amount of seconds clients should wait before retrying a rejected request
+pub const RETRY_AFTER: u64 = 2;
+/// How often the pool is checked for saturation
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the pool can stay saturated before it's reported
+const SATURATION_ALERT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref STATS: PoolStats = PoolStats::default();
+}
+
+#[derive(Default)]
+struct PoolStats {
+    /// the amount of requests currently being handled
+    in_flight: AtomicUsize,
+    /// the amount of requests that timed out waiting for a connection
+    timeouts: AtomicUsize,
+    /// the amount of requests rejected because too many requests were waiting
+    rejected: AtomicUsize,
+    saturated: AtomicBool,
+}
+
+/// The state of the connection pool, shown to the administrators
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStatus {
+    pub max_connections: u32,
+    pub active_connections: u32,
+    pub idle_connections: usize,
+    /// the amount of requests waiting for a connection
+    pub waiters: usize,
+    pub saturated: bool,
+    pub timeouts: usize,
+    pub rejected: usize,
+}
+
+impl PoolStatus {
+    pub fn load(db: &Pool<Postgres>) -> PoolStatus {
+        PoolStatus {
+            max_connections: Config::database_max_connections(),
+            active_connections: db.size(),
+            idle_connections: db.num_idle(),
+            waiters: waiters(db),
+            saturated: STATS.saturated.load(Ordering::Relaxed),
+            timeouts: STATS.timeouts.load(Ordering::Relaxed),
+            rejected: STATS.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Connect to the database, waiting at most the acquire timeout for a connection
+pub async fn connect(database_url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
+    PoolOptions::<Postgres>::new()
+        .max_connections(Config::database_max_connections())
+        .connect_timeout(Config::database_acquire_timeout())
+        .connect(database_url)
+        .await
+}
+
+/// count a request that timed out waiting for a connection
+pub(crate) fn add_timeout() {
+    STATS.timeouts.fetch_add(1, Ordering::Relaxed);
+}
+
+fn is_saturated(db: &Pool<Postgres>) -> bool {
+    db.size() >= Config::database_max_connections() && db.num_idle() == 0
+}
+
+/// sqlx doesn't expose the pool's queue, so the requests that don't have a connection
+/// while the pool is saturated are considered waiting
+fn waiters(db: &Pool<Postgres>) -> usize {
+    if !is_saturated(db) {
+        return 0;
+    }
+
+    count_waiters(STATS.in_flight.load(Ordering::Relaxed), db.size())
+}
+
+fn count_waiters(in_flight: usize, connections: u32) -> usize {
+    in_flight.saturating_sub(connections as usize)
+}
+
+/// Report a pool that stays saturated, and report when it recovers
+pub fn monitor(db: Pool<Postgres>) {
+    actix_rt::spawn(async move {
+        let mut saturated_since: Option<Instant> = None;
+
+        loop {
+            actix_rt::time::delay_for(MONITOR_INTERVAL).await;
+
+            if !is_saturated(&db) {
+                if STATS.saturated.swap(false, Ordering::Relaxed) {
+                    info!("the database connection pool recovered from saturation");
+                }
+                saturated_since = None;
+                continue;
+            }
+
+            let since = *saturated_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= SATURATION_ALERT && !STATS.saturated.swap(true, Ordering::Relaxed)
+            {
+                let message = format!(
+                    "the database connection pool is saturated for {} seconds, {} requests are waiting",
+                    since.elapsed().as_secs(),
+                    waiters(&db)
+                );
+                warn!("{}", message);
+                sentry::capture_message(&message, sentry::Level::Warning);
+            }
+        }
+    });
+}
+
+/// Reject requests when too many requests are waiting for a database connection
+pub struct Middleware;
+
+impl Middleware {
+    pub fn default() -> Middleware {
+        Middleware
+    }
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BackpressureMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BackpressureMiddleware { service })
+    }
+}
+
+pub struct BackpressureMiddleware<S> {
+    service: S,
+}
+
+/// Decrements the in-flight requests when the request is handled or cancelled
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        STATS.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        STATS.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<S, B> Service for BackpressureMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let overloaded = request
+            .app_data::<Data<State>>()
+            .map(|state| waiters(&state.db) >= Config::database_max_waiters())
+            .unwrap_or(false);
+
+        if overloaded {
+            STATS.rejected.fetch_add(1, Ordering::Relaxed);
+            return Box::pin(ok(
+                request.error_response(ServiceError::ServiceUnavailable(RETRY_AFTER))
+            ));
+        }
+
+        let in_flight = InFlight::start();
+        let fut = self.service.call(request);
+
+        Box::pin(async move {
+            let res = fut.await;
+            drop(in_flight);
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waiting_requests() {
+        assert_eq!(count_waiters(25, 10), 15);
+        assert_eq!(count_waiters(4, 10), 0);
+    }
+
+    #[test]
+    fn in_flight_requests() {
+        let before = STATS.in_flight.load(Ordering::Relaxed);
+        let request = InFlight::start();
+        assert_eq!(STATS.in_flight.load(Ordering::Relaxed), before + 1);
+        drop(request);
+        assert_eq!(STATS.in_flight.load(Ordering::Relaxed), before);
+    }
+}
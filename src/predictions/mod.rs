@@ -0,0 +1,45 @@
+//! Price prediction mini-game
+//!
+//! Players stake points on the price of one of their beverages rising or falling.
+//! The open predictions are resolved when the prices of the game are updated,
+//! the points are separate from the real purchases and have their own leaderboard.
+use tokio::sync::broadcast::RecvError;
+
+use sqlx::{Pool, Postgres};
+
+use crate::events::{DomainEvent, EventBus};
+
+mod models;
+pub mod routes;
+
+pub use models::{NewPrediction, Prediction};
+
+/// Resolve the open predictions of a game every time its prices are updated
+pub fn subscribe(db: Pool<Postgres>, events: &EventBus) {
+    let mut receiver = events.subscribe();
+    let events = events.clone();
+
+    actix_rt::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(DomainEvent::PricesUpdated(update)) => {
+                    match Prediction::resolve(update.game_id.0, &update.prices, &db).await {
+                        Ok(resolved) if !resolved.is_empty() => {
+                            events.publish(DomainEvent::PredictionsResolved(update.game_id))
+                        }
+                        Ok(_) => (),
+                        Err(e) => error!(
+                            "unable to resolve the predictions of game {}: {}",
+                            update.game_id.0, e
+                        ),
+                    }
+                }
+                Ok(_) => (),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("the prediction resolver missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
@@ -0,0 +1,325 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::Game;
+use crate::market::BeveragePrice;
+
+/// The amount of points every player starts with
+pub const STARTING_POINTS: i64 = 1000;
+
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "prediction_direction", rename_all = "UPPERCASE")]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Rise,
+    Fall,
+}
+
+impl Direction {
+    /// the points returned for a stake, double when the prediction is right
+    /// and the stake itself when the price didn't change
+    pub fn payout(self, stake: i64, price: i64, resolved_price: i64) -> i64 {
+        let rose = resolved_price > price;
+
+        if resolved_price == price {
+            stake
+        } else if rose == (self == Direction::Rise) {
+            stake * 2
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Prediction {
+    pub id: i64,
+    pub game_id: i64,
+    pub user_id: i64,
+    pub slot_no: i16,
+    pub direction: Direction,
+    pub stake: i64,
+    pub price: i64,
+    pub resolved_price: Option<i64>,
+    pub payout: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewPrediction {
+    pub slot_no: i16,
+    pub direction: Direction,
+    pub stake: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub user_id: i64,
+    pub username: String,
+    pub points: i64,
+    pub correct_predictions: i64,
+    pub predictions: i64,
+}
+
+impl NewPrediction {
+    /// Stake points on the current price of a beverage
+    #[tracing::instrument(name = "NewPrediction::save", skip(db))]
+    pub async fn save(
+        &self,
+        game: &Game,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Prediction, ServiceError> {
+        if !game.predictions_enabled {
+            bad_request!("predictions are not enabled for this game");
+        }
+
+        if !game.in_progress() {
+            bad_request!("predictions can only be made while the game is in progress");
+        }
+
+        if self.stake <= 0 {
+            bad_request!("the stake should be at least 1 point");
+        }
+
+        let mut tx = db::begin(Operation::SavePrediction, db).await?;
+
+        // lock the user's predictions, so the points can't be staked twice
+        let points = Prediction::points(game.id, user_id, &mut tx).await?;
+        if self.stake > points {
+            bad_request!(format!("you only have {} points left", points));
+        }
+
+        let prediction = sqlx::query_as!(
+            Prediction,
+            r#"
+            INSERT INTO predictions (game_id, user_id, slot_no, direction, stake, price)
+            SELECT game_id, user_id, slot_no, $4, $5, current_price
+            FROM beverages
+            WHERE game_id = $1 AND user_id = $2 AND slot_no = $3
+            RETURNING id, game_id, user_id, slot_no, direction as "direction: Direction", stake, price,
+                resolved_price, payout, created_at, resolved_at
+            "#,
+            game.id,
+            user_id,
+            self.slot_no,
+            self.direction as _,
+            self.stake
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(ref error) if error.code().as_deref() == Some("23505") => {
+                ServiceError::Conflict(String::from(
+                    "there already is an open prediction for this beverage",
+                ))
+            }
+            e => e.into(),
+        })?;
+
+        let prediction = match prediction {
+            Some(prediction) => prediction,
+            None => bad_request!("this beverage is not configured"),
+        };
+
+        tx.commit().await?;
+
+        Ok(prediction)
+    }
+}
+
+impl Prediction {
+    /// returns the points a user can still stake
+    async fn points(
+        game_id: i64,
+        user_id: i64,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<i64, sqlx::Error> {
+        let rows = sqlx::query!(
+            "SELECT stake, payout FROM predictions WHERE game_id = $1 AND user_id = $2 FOR UPDATE",
+            game_id,
+            user_id
+        )
+        .fetch_all(tx)
+        .await?;
+
+        Ok(rows.iter().fold(STARTING_POINTS, |points, row| {
+            points - row.stake + row.payout.unwrap_or(0)
+        }))
+    }
+
+    /// returns the predictions of a user, most recent first
+    #[tracing::instrument(name = "Prediction::find_by_user", skip(db))]
+    pub async fn find_by_user(
+        game_id: i64,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Prediction>, sqlx::Error> {
+        sqlx::query_as!(
+            Prediction,
+            r#"
+            SELECT id, game_id, user_id, slot_no, direction as "direction: Direction", stake, price,
+                resolved_price, payout, created_at, resolved_at
+            FROM predictions
+            WHERE game_id = $1 AND user_id = $2
+            ORDER BY created_at DESC
+            "#,
+            game_id,
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Resolve the open predictions of a game with the new prices
+    ///
+    /// Every instance receives the price updates, the locked rows are skipped
+    /// so each prediction is only resolved once.
+    #[tracing::instrument(name = "Prediction::resolve", skip(prices, db))]
+    pub async fn resolve(
+        game_id: i64,
+        prices: &[BeveragePrice],
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Prediction>, sqlx::Error> {
+        let mut tx = db::begin(Operation::ResolvePredictions, db).await?;
+
+        let predictions = sqlx::query_as!(
+            Prediction,
+            r#"
+            SELECT id, game_id, user_id, slot_no, direction as "direction: Direction", stake, price,
+                resolved_price, payout, created_at, resolved_at
+            FROM predictions
+            WHERE game_id = $1 AND resolved_at IS NULL
+            FOR UPDATE SKIP LOCKED
+            "#,
+            game_id
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let predictions: Vec<Prediction> = predictions
+            .into_iter()
+            .filter_map(|mut prediction| {
+                if prediction.resolve_with(prices) {
+                    Some(prediction)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for prediction in &predictions {
+            sqlx::query!(
+                "UPDATE predictions SET resolved_price = $1, payout = $2, resolved_at = $3 WHERE id = $4",
+                prediction.resolved_price,
+                prediction.payout,
+                prediction.resolved_at,
+                prediction.id
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(predictions)
+    }
+
+    /// set the outcome of the prediction, returns false when the prices don't contain the beverage
+    fn resolve_with(&mut self, prices: &[BeveragePrice]) -> bool {
+        let price = prices
+            .iter()
+            .find(|price| price.user_id == self.user_id && price.slot_no == self.slot_no);
+
+        match price {
+            Some(price) => {
+                self.resolved_price = Some(price.price);
+                self.payout = Some(self.direction.payout(self.stake, self.price, price.price));
+                self.resolved_at = Some(Utc::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// returns the players of a game ordered by their points
+    #[tracing::instrument(name = "Prediction::leaderboard", skip(db))]
+    pub async fn leaderboard(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<LeaderboardEntry>, sqlx::Error> {
+        sqlx::query_as!(
+            LeaderboardEntry,
+            r#"
+            SELECT
+                users.id as user_id,
+                users.username,
+                $2 + COALESCE(SUM(COALESCE(predictions.payout, 0) - predictions.stake), 0)::BIGINT as "points!",
+                COUNT(predictions.id) FILTER (WHERE predictions.payout > predictions.stake) as "correct_predictions!",
+                COUNT(predictions.id) as "predictions!"
+            FROM invitations
+            INNER JOIN users ON users.id = invitations.user_id
+            LEFT JOIN predictions ON predictions.game_id = invitations.game_id AND predictions.user_id = users.id
+            WHERE invitations.game_id = $1 AND invitations.state = 'ACCEPTED'
+            GROUP BY users.id, users.username
+            ORDER BY 3 DESC, users.username
+            "#,
+            game_id,
+            STARTING_POINTS
+        )
+        .fetch_all(db)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prediction_payout() {
+        assert_eq!(Direction::Rise.payout(100, 200, 250), 200);
+        assert_eq!(Direction::Rise.payout(100, 200, 150), 0);
+        assert_eq!(Direction::Fall.payout(100, 200, 150), 200);
+        assert_eq!(Direction::Fall.payout(100, 200, 250), 0);
+        assert_eq!(Direction::Fall.payout(100, 200, 200), 100);
+    }
+
+    #[test]
+    fn resolve_prediction() {
+        let mut prediction = Prediction {
+            id: 1,
+            game_id: 1,
+            user_id: 2,
+            slot_no: 3,
+            direction: Direction::Rise,
+            stake: 50,
+            price: 200,
+            resolved_price: None,
+            payout: None,
+            created_at: Utc::now(),
+            resolved_at: None,
+        };
+
+        let price = |user_id, slot_no, price| BeveragePrice {
+            user_id,
+            slot_no,
+            price,
+            previous_price: None,
+        };
+
+        assert!(!prediction.resolve_with(&[price(1, 3, 300), price(2, 2, 300)]));
+        assert!(prediction.resolved_at.is_none());
+
+        assert!(prediction.resolve_with(&[price(2, 3, 220)]));
+        assert_eq!(prediction.resolved_price, Some(220));
+        assert_eq!(prediction.payout, Some(100));
+        assert!(prediction.resolved_at.is_some());
+    }
+}
@@ -0,0 +1,64 @@
+use actix_web::web::{Data, Json, Path};
+use actix_web::{get, post, web};
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::predictions::{NewPrediction, Prediction};
+use crate::server::{self, State};
+
+/// stake points on the price of one of your beverages rising or falling at the next price update
+#[post("/games/{id}/predictions")]
+async fn create(
+    game_id: Path<i64>,
+    prediction: Json<NewPrediction>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !state
+        .games
+        .verify_user_participation(*game_id, user.id)
+        .await?
+    {
+        forbidden!("you are not in this game");
+    }
+    let game = state.games.find_by_id(*game_id).await?;
+
+    let prediction = prediction.save(&game, user.id, &state.db).await?;
+
+    http_created_json!(prediction);
+}
+
+#[get("/games/{id}/predictions")]
+async fn find_by_user(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let predictions = Prediction::find_by_user(*game_id, user.id, &state.db).await?;
+
+    http_ok_json!(predictions);
+}
+
+#[get("/games/{id}/predictions/leaderboard")]
+async fn leaderboard(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let leaderboard = Prediction::leaderboard(*game_id, &state.db).await?;
+
+    http_ok_json!(leaderboard);
+}
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(create);
+    cfg.service(find_by_user);
+    cfg.service(leaderboard);
+}
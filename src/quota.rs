@@ -0,0 +1,228 @@
+//! Daily request quotas per user
+//!
+//! Every authenticated request is counted in a window of a day in Redis, the windows start at midnight UTC.
+//! When `DAILY_REQUEST_QUOTA` is set, the requests above the quota are rejected until the next day.
+//! Administrators don't have a quota, and without Redis the requests are neither counted nor limited.
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::web::Data;
+use actix_web::{Error, HttpMessage};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use crate::cache::CacheHandle;
+use crate::config::Config;
+use crate::errors::ServiceError;
+use crate::server::State;
+use crate::users::User;
+
+const DAY: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// The requests of a user today
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// empty when the user doesn't have a quota
+    pub limit: Option<u64>,
+    pub used: u64,
+    pub remaining: Option<u64>,
+    /// false when the requests can't be counted right now
+    pub tracked: bool,
+    pub resets_at: DateTime<Utc>,
+}
+
+impl Usage {
+    fn new(limit: Option<u64>, used: Option<u64>, now: DateTime<Utc>) -> Usage {
+        let tracked = used.is_some();
+        let used = used.unwrap_or_default();
+
+        Usage {
+            limit,
+            used,
+            remaining: limit.map(|limit| limit.saturating_sub(used)),
+            tracked,
+            resets_at: next_reset(now),
+        }
+    }
+
+    /// the usage of today, without counting this request
+    pub async fn load(user: &User, cache: &CacheHandle) -> Usage {
+        let now = Utc::now();
+        let used = cache.window(&key(user.id), DAY).await;
+
+        Usage::new(limit(user), used.map(|window| window.current), now)
+    }
+
+    /// count a request of the user
+    async fn track(user: &User, cache: &CacheHandle) -> Usage {
+        let now = Utc::now();
+        let used = cache.incr_window(&key(user.id), DAY).await;
+
+        Usage::new(limit(user), used.map(|window| window.current), now)
+    }
+
+    pub fn exceeded(&self) -> bool {
+        matches!(self.limit, Some(limit) if self.used > limit)
+    }
+
+    /// the amount of seconds until the quota resets
+    pub fn retry_after(&self) -> i64 {
+        (self.resets_at - Utc::now()).num_seconds().max(0)
+    }
+
+    /// Tell the client about its quota, nothing is added when the user doesn't have one
+    pub fn set_headers(&self, headers: &mut HeaderMap) {
+        let (limit, remaining) = match (self.limit, self.remaining) {
+            (Some(limit), Some(remaining)) => (limit, remaining),
+            _ => return,
+        };
+
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-limit"),
+            HeaderValue::from(limit),
+        );
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-remaining"),
+            HeaderValue::from(remaining),
+        );
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-reset"),
+            HeaderValue::from(self.resets_at.timestamp()),
+        );
+    }
+}
+
+/// the daily quota of a user, administrators don't have one
+fn limit(user: &User) -> Option<u64> {
+    Config::daily_request_quota().filter(|_| !user.is_admin)
+}
+
+fn key(user_id: i64) -> String {
+    format!("quota.{}", user_id)
+}
+
+/// the start of the next day
+fn next_reset(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date() + Duration::days(1)).and_hms(0, 0, 0)
+}
+
+/// the user of the session, the session middleware runs before this one
+fn identified_user(request: &ServiceRequest) -> Option<User> {
+    request.extensions().get::<User>().cloned()
+}
+
+/// Count the requests of every user, and reject them when the quota is exceeded
+pub struct Middleware;
+
+impl Middleware {
+    pub fn default() -> Middleware {
+        Middleware
+    }
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = QuotaMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(QuotaMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct QuotaMiddleware<S> {
+    // the quota is checked before the request is passed on, so the service is shared with that future
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for QuotaMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let cache = match request.app_data::<Data<State>>() {
+            Some(state) => state.cache.clone(),
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+        let user = match identified_user(&request) {
+            Some(user) => user,
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let usage = Usage::track(&user, &cache).await;
+            if usage.exceeded() {
+                return Ok(request.error_response(ServiceError::QuotaExceeded(usage)));
+            }
+
+            let response = service.borrow_mut().call(request);
+            let mut response = response.await?;
+            usage.set_headers(response.headers_mut());
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn quota_reset() {
+        let now = Utc.ymd(2026, 10, 16).and_hms(23, 59, 30);
+
+        assert_eq!(next_reset(now), Utc.ymd(2026, 10, 17).and_hms(0, 0, 0));
+        assert_eq!(key(4), "quota.4");
+    }
+
+    #[test]
+    fn exceeded_quota() {
+        let usage = Usage::new(Some(100), Some(100), Utc::now());
+        assert_eq!(usage.remaining, Some(0));
+        assert!(!usage.exceeded());
+
+        let usage = Usage::new(Some(100), Some(101), Utc::now());
+        assert_eq!(usage.remaining, Some(0));
+        assert!(usage.exceeded());
+
+        let usage = Usage::new(None, Some(5000), Utc::now());
+        assert!(!usage.exceeded());
+
+        let usage = Usage::new(Some(100), None, Utc::now());
+        assert!(!usage.tracked);
+        assert!(!usage.exceeded());
+    }
+}
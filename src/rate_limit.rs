@@ -0,0 +1,312 @@
+//! Rate limits for the endpoints that are worth hammering
+//!
+//! Every client has a token bucket per IP address, and the purchases also have one per user.
+//! The IP address is the one of the connection, unless it comes from one of the `TRUSTED_PROXIES`.
+//! The bucket of a user is taken by `purchase` itself, so the orders over the websocket count as well.
+//! A bucket holds a minute of requests and refills steadily, so short bursts are fine.
+//! The buckets live in Redis so every instance shares them, without Redis every instance keeps its own in memory.
+//! A client with an empty bucket gets a 429 with the seconds until it can retry.
+use std::cell::RefCell;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::HeaderMap;
+use actix_web::web::Data;
+use actix_web::{Error, HttpRequest};
+use futures::future::{ok, Ready};
+use futures::Future;
+
+use crate::cache::{Admission, CacheHandle, TokenBucket};
+use crate::errors::ServiceError;
+use crate::server::State;
+use crate::users::User;
+
+lazy_static! {
+    /// the buckets of this instance, when the cache of the server state can't be reached
+    static ref FALLBACK: CacheHandle = CacheHandle::builder().memory().build();
+}
+
+/// The requests per minute of the rate limited endpoints, the limits that are empty are disabled
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    pub login_per_ip: Option<u32>,
+    pub purchase_per_user: Option<u32>,
+    pub purchase_per_ip: Option<u32>,
+}
+
+/// The reverse proxies in front of the server, only their forwarded headers are trusted
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<Vec<IpAddr>>);
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<IpAddr>) -> Self {
+        TrustedProxies(Arc::new(proxies))
+    }
+
+    /// The address of the client that sent a request
+    ///
+    /// A proxy appends the address it got the request from to `X-Forwarded-For`, the addresses
+    /// before that are set by the client. The client is the last address that isn't a trusted proxy.
+    pub fn client_address(&self, peer: Option<SocketAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?.ip();
+        if !self.0.contains(&peer) {
+            return Some(peer);
+        }
+
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .filter_map(|header| header.to_str().ok())
+            .flat_map(|header| header.split(','))
+            .filter_map(|address| address.trim().parse().ok())
+            .collect();
+
+        let client = forwarded
+            .into_iter()
+            .rev()
+            .find(|address| !self.0.contains(address));
+
+        Some(client.unwrap_or(peer))
+    }
+}
+
+/// The address of the client that sent a request, the forwarded headers are only used behind a trusted proxy
+pub fn client_address(req: &HttpRequest) -> Option<IpAddr> {
+    let proxies = req
+        .app_data::<Data<State>>()
+        .map(|state| state.trusted_proxies.clone())
+        .unwrap_or_default();
+
+    proxies.client_address(req.peer_addr(), req.headers())
+}
+
+/// Limit the login attempts per IP address, to slow down password guessing
+pub const LOGIN: Middleware = Middleware {
+    limit: Limit::Login,
+};
+
+/// Limit the purchases per IP address, against scripted sales that crash the prices
+pub const PURCHASE: Middleware = Middleware {
+    limit: Limit::Purchase,
+};
+
+#[derive(Debug, Clone, Copy)]
+enum Limit {
+    Login,
+    Purchase,
+}
+
+impl Limit {
+    fn name(self) -> &'static str {
+        match self {
+            Limit::Login => "login",
+            Limit::Purchase => "purchase",
+        }
+    }
+
+    /// the requests per minute from one IP address
+    fn per_ip(self, limits: &RateLimits) -> Option<u32> {
+        match self {
+            Limit::Login => limits.login_per_ip,
+            Limit::Purchase => limits.purchase_per_ip,
+        }
+    }
+
+    fn key(self, kind: &str, id: &str) -> String {
+        format!("ratelimit.{}.{}.{}", self.name(), kind, id)
+    }
+}
+
+/// a bucket that holds a minute of requests
+fn bucket(per_minute: u32) -> TokenBucket {
+    TokenBucket {
+        capacity: per_minute,
+        per_second: f64::from(per_minute) / 60.0,
+    }
+}
+
+/// Take a request from a bucket, returns the seconds until the client can retry when the bucket is empty
+async fn take(cache: &CacheHandle, key: &str, per_minute: u32) -> Option<u64> {
+    let bucket = bucket(per_minute);
+
+    let admission = match cache.take_tokens(key, &bucket, 1).await {
+        Some(admission) => admission,
+        None => FALLBACK.take_tokens(key, &bucket, 1).await?,
+    };
+
+    retry_after(&admission)
+}
+
+/// the whole seconds until the client can retry, nothing when the request was allowed
+fn retry_after(admission: &Admission) -> Option<u64> {
+    if admission.allowed {
+        return None;
+    }
+
+    // round up, a client that retries a bit too early is rejected again
+    Some((admission.retry_after.as_secs_f64().ceil() as u64).max(1))
+}
+
+/// Take a purchase from the bucket of the user, whether the order came in over HTTP or the websocket
+pub async fn purchase(user: &User, state: &State) -> Result<(), ServiceError> {
+    let per_minute = match state.rate_limits.purchase_per_user {
+        Some(per_minute) => per_minute,
+        None => return Ok(()),
+    };
+
+    let key = Limit::Purchase.key("user", &user.id.to_string());
+    match take(&state.cache, &key, per_minute).await {
+        Some(retry_after) => {
+            debug!("{} is rate limited for {} seconds", key, retry_after);
+            Err(ServiceError::RateLimited(retry_after))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Reject the requests of clients that exceed a rate limit
+#[derive(Debug, Clone, Copy)]
+pub struct Middleware {
+    limit: Limit,
+}
+
+impl<S, B> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            limit: self.limit,
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    // the buckets are checked before the request is passed on, so the service is shared with that future
+    service: Rc<RefCell<S>>,
+    limit: Limit,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let state = match request.app_data::<Data<State>>() {
+            Some(state) => state,
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+        let (limits, cache) = (state.rate_limits, state.cache.clone());
+        let address = state
+            .trusted_proxies
+            .client_address(request.peer_addr(), request.headers());
+
+        let limit = self.limit;
+        let bucket = limit
+            .per_ip(&limits)
+            .zip(address)
+            .map(|(per_minute, address)| (limit.key("ip", &address.to_string()), per_minute));
+
+        let (key, per_minute) = match bucket {
+            Some(bucket) => bucket,
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if let Some(retry_after) = take(&cache, &key, per_minute).await {
+                debug!("{} is rate limited for {} seconds", key, retry_after);
+                return Ok(request.error_response(ServiceError::RateLimited(retry_after)));
+            }
+
+            let response = service.borrow_mut().call(request);
+            response.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn buckets() {
+        let bucket = bucket(30);
+        assert_eq!(bucket.capacity, 30);
+        assert!((bucket.per_second - 0.5).abs() < f64::EPSILON);
+
+        assert_eq!(
+            Limit::Login.key("ip", "127.0.0.1"),
+            "ratelimit.login.ip.127.0.0.1"
+        );
+        assert_eq!(
+            Limit::Purchase.key("user", "4"),
+            "ratelimit.purchase.user.4"
+        );
+    }
+
+    #[test]
+    fn forwarded_addresses() {
+        let client = |proxies: &TrustedProxies, peer: &str| {
+            let request = TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .header("X-Forwarded-For", "198.51.100.1, 203.0.113.7")
+                .to_http_request();
+            proxies
+                .client_address(request.peer_addr(), request.headers())
+                .unwrap()
+                .to_string()
+        };
+
+        // anyone can set the header
+        let proxies = TrustedProxies::default();
+        assert_eq!(client(&proxies, "10.0.0.2:4000"), "10.0.0.2");
+
+        let proxies = TrustedProxies::new(vec!["10.0.0.2".parse().unwrap()]);
+        assert_eq!(client(&proxies, "10.0.0.2:4000"), "203.0.113.7");
+        assert_eq!(client(&proxies, "192.0.2.9:4000"), "192.0.2.9");
+    }
+
+    #[actix_rt::test]
+    async fn empty_buckets() {
+        let key = Limit::Purchase.key("user", "empty_buckets");
+        let bucket = bucket(2);
+
+        for _ in 0..2 {
+            let admission = FALLBACK.take_tokens(&key, &bucket, 1).await.unwrap();
+            assert_eq!(retry_after(&admission), None);
+        }
+
+        // the bucket refills a token every 30 seconds
+        let admission = FALLBACK.take_tokens(&key, &bucket, 1).await.unwrap();
+        assert!((1..=30).contains(&retry_after(&admission).unwrap()));
+    }
+}
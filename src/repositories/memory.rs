@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::errors::ServiceError;
+use crate::games::{Beverage, Game};
+use crate::repositories::{GameRepo, SaleRepo};
+use crate::transactions::models::NewSale;
+use crate::transactions::Transaction;
+
+/// In-memory storage, used to test the route handlers without a database
+#[derive(Debug, Default)]
+pub struct MemoryRepo {
+    games: Mutex<HashMap<i64, Game>>,
+    /// (game_id, user_id) of users who accepted their invitation
+    participants: Mutex<HashSet<(i64, i64)>>,
+    beverages: Mutex<Vec<Beverage>>,
+    transactions: Mutex<Vec<Transaction>>,
+}
+
+impl MemoryRepo {
+    pub fn add_game(&self, game: Game) {
+        self.participants
+            .lock()
+            .unwrap()
+            .insert((game.id, game.owner_id));
+        self.games.lock().unwrap().insert(game.id, game);
+    }
+
+    pub fn add_beverage(&self, beverage: Beverage) {
+        self.beverages.lock().unwrap().push(beverage);
+    }
+
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.transactions.lock().unwrap().clone()
+    }
+}
+
+#[async_trait(?Send)]
+impl GameRepo for MemoryRepo {
+    async fn find_by_id(&self, id: i64) -> Result<Game, ServiceError> {
+        self.games
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or(ServiceError::NotFound)
+    }
+
+    async fn verify_user_participation(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError> {
+        Ok(self
+            .participants
+            .lock()
+            .unwrap()
+            .contains(&(game_id, user_id)))
+    }
+
+    async fn available_for_purchases(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError> {
+        let game = match self.find_by_id(game_id).await {
+            Ok(game) => game,
+            Err(ServiceError::NotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        Ok(game.in_progress() && self.verify_user_participation(game_id, user_id).await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl SaleRepo for MemoryRepo {
+    /// purchase times aren't tracked, so the quantity windows and cooldowns aren't enforced
+    async fn save(&self, sale: &NewSale) -> Result<Vec<Transaction>, ServiceError> {
+        let game = self.find_by_id(sale.game_id).await?;
+
+        sale.validate(&game, &HashMap::new())?;
+
+        let beverages = self.beverages.lock().unwrap();
+        let mut transactions = self.transactions.lock().unwrap();
+        let order_id = transactions
+            .iter()
+            .map(|item| item.order_id)
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        let mut order = Vec::new();
+        for (slot_no, amount) in &sale.slots {
+            let beverage = match beverages.iter().find(|beverage| {
+                beverage.game_id == sale.game_id
+                    && beverage.user_id == sale.user_id
+                    && beverage.slot_no == *slot_no
+            }) {
+                Some(beverage) => beverage,
+                None => bad_request!("unable to create purchase for beverage without a config"),
+            };
+
+            order.push(Transaction {
+                id: (transactions.len() + order.len()) as i64 + 1,
+                slot_no: *slot_no,
+                order_id,
+                amount: *amount,
+                price: beverage.price(),
+                price_history_id: None,
+                ordered_at: Utc::now(),
+                priced_at: game.start_time,
+            });
+        }
+
+        transactions.extend(order.clone());
+
+        Ok(order)
+    }
+}
@@ -0,0 +1,39 @@
+//! Storage abstractions used by the route handlers
+//!
+//! The postgres implementations delegate to the sqlx models, the in-memory
+//! implementations allow testing the business rules without a database.
+use async_trait::async_trait;
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+use crate::transactions::models::NewSale;
+use crate::transactions::Transaction;
+
+#[cfg(test)]
+pub mod memory;
+mod postgres;
+
+#[async_trait(?Send)]
+pub trait GameRepo {
+    async fn find_by_id(&self, id: i64) -> Result<Game, ServiceError>;
+
+    /// returns true when the user accepted the invitation for the game
+    async fn verify_user_participation(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError>;
+
+    /// returns true when the game is in progress and the user is participating
+    async fn available_for_purchases(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError>;
+}
+
+#[async_trait(?Send)]
+pub trait SaleRepo {
+    /// validate and store a new order, returning the purchased items
+    async fn save(&self, sale: &NewSale) -> Result<Vec<Transaction>, ServiceError>;
+}
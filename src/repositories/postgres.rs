@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+use crate::repositories::{GameRepo, SaleRepo};
+use crate::transactions::models::NewSale;
+use crate::transactions::Transaction;
+
+#[async_trait(?Send)]
+impl GameRepo for Pool<Postgres> {
+    async fn find_by_id(&self, id: i64) -> Result<Game, ServiceError> {
+        Ok(Game::find_by_id(id, self).await?)
+    }
+
+    async fn verify_user_participation(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError> {
+        Game::verify_user_participation(game_id, user_id, self).await
+    }
+
+    async fn available_for_purchases(
+        &self,
+        game_id: i64,
+        user_id: i64,
+    ) -> Result<bool, ServiceError> {
+        Game::available_for_purchases(game_id, user_id, self).await
+    }
+}
+
+#[async_trait(?Send)]
+impl SaleRepo for Pool<Postgres> {
+    async fn save(&self, sale: &NewSale) -> Result<Vec<Transaction>, ServiceError> {
+        sale.save(self).await
+    }
+}
@@ -0,0 +1,301 @@
+//! Cached JSON responses of busy GET routes
+//!
+//! During a party every client polls the game list and the price histories, while they rarely change.
+//! The middleware looks up the responses of the routes below in Redis, keyed by the path, the query and the user.
+//! Administrators share their responses, players only see their own games and beverages.
+//! The route handlers decide which responses can be stored by marking them with `cacheable`,
+//! so the price history is only stored once the game has finished.
+//!
+//! A change to the games invalidates the cached game lists of every instance,
+//! by bumping the generation of the route which is part of the key.
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Body, ResponseBody, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use actix_web::web::Data;
+use actix_web::{Error, HttpMessage, HttpResponse};
+use futures::future::{ok, Ready};
+use futures::Future;
+use tokio::sync::broadcast::RecvError;
+
+use crate::cache::{CacheHandle, CacheIdentifier};
+use crate::events::{DomainEvent, EventBus};
+use crate::server::State;
+use crate::users::User;
+
+const CACHE_HEADER: &str = "x-cache";
+
+/// the generations are kept longer than any cached response
+const GENERATION_TTL: u64 = 24 * 60 * 60;
+
+/// A GET route of which the responses are cached
+#[derive(Debug)]
+struct CachedRoute {
+    name: &'static str,
+    /// the path, `{name}` matches any segment
+    pattern: &'static str,
+    /// the seconds a response is cached
+    ttl: u64,
+}
+
+const GAMES: &str = "games";
+
+const ROUTES: [CachedRoute; 2] = [
+    CachedRoute {
+        name: GAMES,
+        pattern: "/api/games",
+        ttl: 60,
+    },
+    // the price history of a finished game doesn't change anymore
+    CachedRoute {
+        name: "price-history",
+        pattern: "/api/games/{id}/stats/price-history",
+        ttl: 60 * 60,
+    },
+];
+
+/// Marks a response that the middleware can store
+#[derive(Debug, Clone, Copy)]
+struct Cacheable;
+
+/// Allow the middleware to store the response, when its route is cached
+pub fn cacheable(mut response: HttpResponse) -> HttpResponse {
+    response.extensions_mut().insert(Cacheable);
+    response
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedResponse {
+    body: String,
+}
+
+impl CacheIdentifier for CachedResponse {}
+
+/// Returns true when every segment of the path matches the pattern
+fn matches(pattern: &str, path: &str) -> bool {
+    let mut patterns = pattern.split('/');
+    let mut segments = path.split('/');
+
+    loop {
+        match (patterns.next(), segments.next()) {
+            (None, None) => return true,
+            (Some(pattern), Some(segment)) => {
+                let wildcard = pattern.starts_with('{') && pattern.ends_with('}');
+                if !wildcard && pattern != segment {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+fn route(request: &ServiceRequest) -> Option<&'static CachedRoute> {
+    if request.method() != Method::GET {
+        return None;
+    }
+
+    ROUTES
+        .iter()
+        .find(|route| matches(route.pattern, request.path()))
+}
+
+fn generation_key(route: &str) -> String {
+    format!("response_cache.generation.{}", route)
+}
+
+/// who the response belongs to
+fn audience(user: &User) -> String {
+    if user.is_admin {
+        String::from("admin")
+    } else {
+        format!("user.{}", user.id)
+    }
+}
+
+/// the key of a response, without a generation the response isn't cached
+async fn key(route: &CachedRoute, request: &ServiceRequest, cache: &CacheHandle) -> Option<String> {
+    let user = request.extensions().get::<User>()?.clone();
+    let generation = cache.counter(&generation_key(route.name)).await?;
+
+    Some(format!(
+        "{}.{}.{}.{}?{}",
+        route.name,
+        generation,
+        audience(&user),
+        request.path(),
+        request.query_string()
+    ))
+}
+
+/// Drop the cached responses of a route on every instance
+pub async fn invalidate(route: &str, cache: &CacheHandle) {
+    cache
+        .increment(&generation_key(route), Duration::from_secs(GENERATION_TTL))
+        .await;
+}
+
+/// Invalidate the cached game lists when a game or an invitation changes
+pub fn subscribe(events: &EventBus, cache: CacheHandle) {
+    let mut receiver = events.subscribe();
+
+    actix_rt::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(DomainEvent::GameCreated(_))
+                | Ok(DomainEvent::GameUpdated(_))
+                | Ok(DomainEvent::GameDeleted(_))
+                | Ok(DomainEvent::InvitationCreated { .. })
+                | Ok(DomainEvent::InvitationResponded { .. })
+                | Ok(DomainEvent::WaitlistPromoted { .. }) => invalidate(GAMES, &cache).await,
+                Ok(_) => (),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("the response cache missed {} events", skipped);
+                    invalidate(GAMES, &cache).await;
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+fn set_cache_header(response: &mut ServiceResponse, value: &'static str) {
+    response.headers_mut().insert(
+        HeaderName::from_static(CACHE_HEADER),
+        HeaderValue::from_static(value),
+    );
+}
+
+/// Serve the cached responses of the cached routes
+pub struct Middleware;
+
+impl Middleware {
+    pub fn default() -> Middleware {
+        Middleware
+    }
+}
+
+impl<S> Transform<S> for Middleware
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ResponseCacheMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ResponseCacheMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct ResponseCacheMiddleware<S> {
+    // the cache is checked before the request is passed on, so the service is shared with that future
+    service: Rc<RefCell<S>>,
+}
+
+impl<S> Service for ResponseCacheMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = Error;
+    #[allow(clippy::type_complexity)]
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, request: ServiceRequest) -> Self::Future {
+        let route = match route(&request) {
+            Some(route) => route,
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+        let cache = match request.app_data::<Data<State>>() {
+            Some(state) => state.cache.clone(),
+            None => return Box::pin(self.service.borrow_mut().call(request)),
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let key = match key(route, &request, &cache).await {
+                Some(key) => key,
+                None => {
+                    let response = service.borrow_mut().call(request);
+                    return response.await;
+                }
+            };
+
+            if let Some(cached) = cache.get::<CachedResponse, _>(&key).await {
+                let response = HttpResponse::Ok()
+                    .content_type("application/json")
+                    .header(CACHE_HEADER, "HIT")
+                    .body(cached.body);
+
+                return Ok(request.into_response(response));
+            }
+
+            let response = service.borrow_mut().call(request);
+            let mut response = response.await?;
+
+            let json = HeaderValue::from_static("application/json");
+            let cacheable = response.status() == StatusCode::OK
+                && response
+                    .response()
+                    .extensions()
+                    .get::<Cacheable>()
+                    .is_some()
+                && response.headers().get(header::CONTENT_TYPE) == Some(&json);
+            if !cacheable {
+                return Ok(response);
+            }
+
+            if let ResponseBody::Body(Body::Bytes(bytes)) = response.response().body() {
+                if let Ok(body) = std::str::from_utf8(bytes) {
+                    let cached = CachedResponse {
+                        body: body.to_string(),
+                    };
+                    cache
+                        .set_expiring(&cached, &key, Duration::from_secs(route.ttl))
+                        .await;
+                }
+            }
+            set_cache_header(&mut response, "MISS");
+
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_routes() {
+        assert!(matches("/api/games", "/api/games"));
+        assert!(!matches("/api/games", "/api/games/1"));
+        assert!(matches(
+            "/api/games/{id}/stats/price-history",
+            "/api/games/12/stats/price-history"
+        ));
+        assert!(!matches(
+            "/api/games/{id}/stats/price-history",
+            "/api/games/12/stats/sales"
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+//! Data retention of finished games
+//!
+//! When `RETENTION_MONTHS` is configured, the transactions and price histories of games that closed
+//! before the retention period are purged. The games, orders, sales counts and user sales are kept,
+//! so the results and the analytics of old games remain available.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::cache::CacheHandle;
+use crate::config::Config;
+use crate::db::{self, Operation};
+
+/// how often the expired games are purged
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+
+/// the maximum amount of games that are purged in a single run
+const MAX_GAMES: i64 = 100;
+
+/// A game of which the details have expired
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpiredGame {
+    pub game_id: i64,
+    pub name: String,
+    pub close_time: DateTime<Utc>,
+    pub transactions: i64,
+    pub price_histories: i64,
+}
+
+/// What the next run of the retention job deletes
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionReport {
+    /// empty when the retention is disabled
+    pub retention_months: Option<i32>,
+    /// the games that closed before this time are purged
+    pub cutoff: Option<DateTime<Utc>>,
+    pub games: Vec<ExpiredGame>,
+    pub transactions: i64,
+    pub price_histories: i64,
+}
+
+impl RetentionReport {
+    /// a dry run of the retention job, nothing gets deleted
+    #[tracing::instrument(name = "RetentionReport::load", skip(db))]
+    pub async fn load(db: &Pool<Postgres>) -> Result<RetentionReport, sqlx::Error> {
+        let months = match Config::retention_months() {
+            Some(months) => months,
+            None => {
+                return Ok(RetentionReport {
+                    retention_months: None,
+                    cutoff: None,
+                    games: Vec::new(),
+                    transactions: 0,
+                    price_histories: 0,
+                })
+            }
+        };
+
+        let cutoff = cutoff(months, db).await?;
+        let games = ExpiredGame::find(cutoff, db).await?;
+
+        Ok(RetentionReport {
+            retention_months: Some(months),
+            cutoff: Some(cutoff),
+            transactions: games.iter().map(|game| game.transactions).sum(),
+            price_histories: games.iter().map(|game| game.price_histories).sum(),
+            games,
+        })
+    }
+}
+
+impl ExpiredGame {
+    /// the games that closed before the cutoff and still have details
+    #[tracing::instrument(name = "ExpiredGame::find", skip(db))]
+    async fn find(
+        cutoff: DateTime<Utc>,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<ExpiredGame>, sqlx::Error> {
+        sqlx::query_as!(
+            ExpiredGame,
+            r#"
+            WITH expired AS (
+                SELECT games.id, games.name, games.close_time,
+                    (SELECT COUNT(*) FROM transactions INNER JOIN orders ON orders.id = transactions.order_id WHERE orders.game_id = games.id) AS transactions,
+                    (SELECT COUNT(*) FROM price_histories WHERE price_histories.game_id = games.id) AS price_histories
+                FROM games
+                WHERE games.close_time < $1
+            )
+            SELECT id AS game_id, name, close_time,
+                transactions AS "transactions!", price_histories AS "price_histories!"
+            FROM expired
+            WHERE transactions > 0 OR price_histories > 0
+            ORDER BY close_time, id
+            LIMIT $2
+            "#,
+            cutoff,
+            MAX_GAMES
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Delete the transactions and the price histories of the game
+    ///
+    /// The user sales trigger is skipped, so the aggregates of the game stay intact.
+    #[tracing::instrument(name = "ExpiredGame::purge", skip(self, db), fields(game_id = self.game_id))]
+    async fn purge(&self, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let mut tx = db::begin(Operation::PurgeGame, db).await?;
+
+        sqlx::query!("SELECT set_config('rustfuif.purging', 'on', true)")
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+            r#"
+            DELETE FROM transactions
+            USING orders
+            WHERE orders.id = transactions.order_id AND orders.game_id = $1
+            "#,
+            self.game_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM price_histories WHERE game_id = $1",
+            self.game_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// the games that closed before this time are expired
+async fn cutoff(months: i32, db: &Pool<Postgres>) -> Result<DateTime<Utc>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT NOW() - make_interval(months => $1) AS "cutoff!""#,
+        months
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(row.cutoff)
+}
+
+/// Purge the expired games once a day, when a retention period is configured
+pub fn schedule(db: Pool<Postgres>, cache: CacheHandle) {
+    let months = match Config::retention_months() {
+        Some(months) => months,
+        None => return,
+    };
+
+    actix_rt::spawn(async move {
+        loop {
+            actix_rt::time::delay_for(PURGE_INTERVAL).await;
+
+            // one instance purges the games, the others skip this round
+            match cache.lock("retention", PURGE_INTERVAL).await {
+                Some(lock) => {
+                    purge(months, &db).await;
+                    lock.release().await;
+                }
+                None => debug!("the expired games are purged by another instance"),
+            }
+        }
+    });
+}
+
+async fn purge(months: i32, db: &Pool<Postgres>) {
+    let games = match cutoff(months, db).await {
+        Ok(cutoff) => ExpiredGame::find(cutoff, db).await,
+        Err(e) => Err(e),
+    };
+    let games = match games {
+        Ok(games) => games,
+        Err(e) => {
+            error!("unable to find the expired games: {}", e);
+            return;
+        }
+    };
+
+    for game in games {
+        match game.purge(db).await {
+            Ok(()) => info!(
+                "purged {} transactions and {} price histories of game {}",
+                game.transactions, game.price_histories, game.game_id
+            ),
+            Err(e) => error!("unable to purge game {}: {}", game.game_id, e),
+        }
+    }
+}
@@ -1,35 +1,85 @@
+use std::sync::Arc;
+
 use actix::prelude::*;
 use actix_cors::Cors;
 use actix_identity::{CookieIdentityPolicy, IdentityService};
+use actix_service::ServiceFactory;
 use actix_web::cookie::SameSite;
+use actix_web::dev::{Body, ResponseBody, Service, ServiceRequest, ServiceResponse};
 use actix_web::error::JsonPayloadError;
 use actix_web::middleware::normalize::TrailingSlash;
 use actix_web::{get, middleware, web, App, HttpRequest, HttpResponse, HttpServer};
-use actix_web_opentelemetry::RequestTracing;
+use actix_web_opentelemetry::{RequestMetrics, RequestTracing};
+use futures::TryFutureExt;
 use sqlx::{Pool, Postgres};
 use time::Duration;
 
+use crate::access_log;
 use crate::admin;
-use crate::auth;
+use crate::auth::{self, backends::AuthBackend};
+use crate::cache::{self, CacheHandle};
 use crate::config::Config;
 use crate::ddg;
+use crate::deadline;
 use crate::errors::ServiceError;
+use crate::events::{self, EventBus};
 use crate::games;
+use crate::guests;
+use crate::http::HttpClient;
 use crate::invitations;
 use crate::market::MarketAgent;
+use crate::pool;
+use crate::predictions;
+use crate::quota;
+use crate::rate_limit::{RateLimits, TrustedProxies};
+use crate::repositories::{GameRepo, SaleRepo};
+use crate::response_cache;
+use crate::retention;
+use crate::shutdown;
 use crate::stats;
+use crate::teams;
 use crate::transactions;
 use crate::users;
+use crate::webhooks;
 use crate::websocket;
+use crate::websocket::queries::{Health, NotifierHealth};
 use crate::websocket::server::NotificationServer;
 
 pub type Response = Result<HttpResponse, ServiceError>;
 
+/// How long the readiness probe waits for the notification server
+const PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
 #[get("/health")]
 async fn health() -> &'static str {
     "ok"
 }
 
+/// Ready when the database and the notification server respond, and the server isn't shutting down
+#[get("/health/ready")]
+async fn ready(state: web::Data<State>) -> HttpResponse {
+    #[derive(Serialize)]
+    struct Readiness {
+        database: bool,
+        notifier: Option<NotifierHealth>,
+    }
+
+    let database = sqlx::query("SELECT 1").execute(&state.db).await.is_ok();
+    let notifier = state
+        .notifier
+        .send(Health)
+        .timeout(PROBE_TIMEOUT)
+        .await
+        .ok();
+
+    let mut res = if database && notifier.is_some() && !shutdown::is_shutting_down() {
+        HttpResponse::Ok()
+    } else {
+        HttpResponse::ServiceUnavailable()
+    };
+    res.json(Readiness { database, notifier })
+}
+
 fn json_error_handler(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Error {
     match error {
         JsonPayloadError::Overflow => ServiceError::PayloadTooLarge.into(),
@@ -37,9 +87,163 @@ fn json_error_handler(error: JsonPayloadError, _: &HttpRequest) -> actix_web::Er
     }
 }
 
+/// Shared dependencies of the route handlers
+#[derive(Clone)]
 pub struct State {
     pub db: Pool<Postgres>,
     pub notifier: Addr<NotificationServer>,
+    pub events: EventBus,
+    pub games: Arc<dyn GameRepo + Send + Sync>,
+    pub sales: Arc<dyn SaleRepo + Send + Sync>,
+    /// shared client for outbound HTTP requests
+    pub http: HttpClient,
+    /// where the users log in
+    pub auth: Arc<dyn AuthBackend + Send + Sync>,
+    /// the cache of the route handlers and background jobs
+    pub cache: CacheHandle,
+    pub rate_limits: RateLimits,
+    pub trusted_proxies: TrustedProxies,
+}
+
+impl State {
+    pub fn new(
+        db: Pool<Postgres>,
+        notifier: Addr<NotificationServer>,
+        events: EventBus,
+        http: HttpClient,
+        cache: CacheHandle,
+    ) -> Self {
+        State {
+            games: Arc::new(db.clone()),
+            sales: Arc::new(db.clone()),
+            db,
+            notifier,
+            events,
+            auth: auth::backends::from_config(http.clone()),
+            http,
+            cache,
+            rate_limits: Config::rate_limits(),
+            trusted_proxies: Config::trusted_proxies(),
+        }
+    }
+
+    /// Connect to the database and start the notification server
+    pub async fn build(database_url: &str) -> anyhow::Result<Self> {
+        let db = pool::connect(database_url).await?;
+        let notifier = {
+            let db = db.clone();
+            Supervisor::start(move |_| NotificationServer::new(db))
+        };
+
+        let events = EventBus::new();
+        NotificationServer::subscribe(notifier.clone(), &events);
+        predictions::subscribe(db.clone(), &events);
+        teams::subscribe(db.clone(), &events);
+        let cache = cache::build();
+        response_cache::subscribe(&events, cache.clone());
+
+        let http = HttpClient::new(Config::http_connect_timeout(), Config::http_timeout())?;
+
+        Ok(State::new(db, notifier, events, http, cache))
+    }
+
+    /// Start updating the prices of all the games that haven't finished yet,
+    /// including the games that get created on other instances later on
+    pub async fn start_market(&self) -> anyhow::Result<()> {
+        MarketAgent::listen(self.db.clone(), self.events.clone());
+        MarketAgent::supervise(self.db.clone());
+
+        Ok(())
+    }
+}
+
+/// The prometheus middleware, which also serves the metrics
+pub type Metrics = RequestMetrics<fn(&ServiceRequest) -> bool>;
+
+/// Create the prometheus middleware, this should only be called once
+pub fn metrics() -> Metrics {
+    let exporter = opentelemetry_prometheus::exporter().init();
+
+    RequestMetrics::new(
+        opentelemetry::global::meter("rustfuif_api"),
+        Some(is_metrics_route as fn(&ServiceRequest) -> bool),
+        Some(exporter),
+    )
+}
+
+fn is_metrics_route(req: &ServiceRequest) -> bool {
+    req.path() == "/metrics" && req.method() == actix_web::http::Method::GET
+}
+
+/// Build the application with all its middleware and routes
+pub fn app(
+    state: State,
+    metrics: Metrics,
+) -> App<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<Body>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+    Body,
+> {
+    App::new()
+        .data(state)
+        .wrap(sentry_actix::Sentry::new())
+        .wrap(middleware::DefaultHeaders::new().header("X-Version", env!("CARGO_PKG_VERSION")))
+        .wrap(middleware::Compress::default())
+        .wrap(deadline::Middleware::new(Config::request_timeout()))
+        .wrap(access_log::Middleware::default())
+        .wrap(middleware::NormalizePath::new(TrailingSlash::Trim))
+        .wrap(pool::Middleware::default())
+        .wrap(quota::Middleware::default())
+        .wrap(stats::Middleware::default().error_budget(Config::error_budget()))
+        .wrap(metrics)
+        .wrap(RequestTracing::new())
+        // TODO: set this to something more restrictive
+        .wrap(Cors::permissive().supports_credentials())
+        .wrap(auth::sessions::Middleware::default())
+        .wrap(IdentityService::new(
+            CookieIdentityPolicy::new(Config::session_private_key().as_bytes())
+                .name("auth-cookie")
+                .same_site(SameSite::Strict)
+                .visit_deadline(Duration::weeks(2))
+                .max_age_time(Duration::weeks(2))
+                .secure(true),
+        ))
+        // box the response body, the middleware body types can't be named in the return type
+        .wrap_fn(|req, srv| {
+            srv.call(req)
+                .map_ok(|res| res.map_body(|_, body| ResponseBody::Other(Body::from_message(body))))
+        })
+        .data(
+            web::JsonConfig::default()
+                .error_handler(json_error_handler)
+                .limit(262_144),
+        )
+        .data(web::PayloadConfig::default().limit(262_144))
+        .service(stats::route)
+        .service(web::resource("/ws/admin").to(websocket::routes::admin_route))
+        .service(web::resource("/ws/game/{game_id}").to(websocket::routes::game_route))
+        .service(
+            web::scope("/api")
+                .wrap(response_cache::Middleware::default())
+                .configure(games::routes::register)
+                .configure(invitations::routes::register)
+                .configure(guests::routes::register)
+                .configure(auth::routes::register)
+                .configure(transactions::routes::register)
+                .configure(users::routes::register)
+                .configure(ddg::routes::register)
+                .configure(admin::routes::register)
+                .configure(events::routes::register)
+                .configure(predictions::routes::register)
+                .configure(teams::routes::register)
+                .service(health)
+                .service(ready),
+        )
 }
 
 pub async fn launch() -> anyhow::Result<()> {
@@ -51,78 +255,40 @@ pub async fn launch() -> anyhow::Result<()> {
         }
     };
 
-    let exporter = opentelemetry_prometheus::exporter().init();
+    let metrics = metrics();
 
-    let prometheus_metrics = actix_web_opentelemetry::RequestMetrics::new(
-        opentelemetry::global::meter("rustfuif_api"),
-        Some(|req: &actix_web::dev::ServiceRequest| {
-            req.path() == "/metrics" && req.method() == actix_web::http::Method::GET
-        }),
-        Some(exporter),
-    );
+    let state = State::build(Config::database_url()).await?;
+    state.start_market().await?;
+    pool::monitor(state.db.clone());
+    games::series::schedule(state.db.clone(), state.events.clone(), state.cache.clone());
+    games::blackouts::schedule(state.db.clone(), state.events.clone(), state.cache.clone());
+    retention::schedule(state.db.clone(), state.cache.clone());
+    guests::schedule(state.db.clone(), state.cache.clone());
+    webhooks::start(state.http.clone(), state.cache.clone());
+    users::export::start(state.db.clone(), state.cache.clone());
 
-    let db = Pool::<Postgres>::connect(Config::database_url()).await?;
-    let notifier = NotificationServer::new().start();
+    let db = state.db.clone();
+    let notifier = state.notifier.clone();
 
-    let games = games::Game::unfinished(&db).await?;
-    for game in games {
-        MarketAgent::new(db.clone(), notifier.clone(), game).start();
+    let mut server = HttpServer::new(move || app(state.clone(), metrics.clone()))
+        .disable_signals()
+        .shutdown_timeout(Config::server_shutdown_timeout().as_secs())
+        .keep_alive(Config::server_keep_alive())
+        .client_timeout(Config::server_client_timeout().as_millis() as u64)
+        .max_connections(Config::server_max_connections());
+    if let Some(workers) = Config::server_workers() {
+        server = server.workers(workers);
     }
 
-    HttpServer::new(move || {
-        let state = State {
-            db: db.clone(),
-            notifier: notifier.clone(),
-        };
+    let server = server
+        .bind(format!("{}:{}", Config::api_host(), Config::api_port()))?
+        .run();
+
+    shutdown::listen(server.clone(), notifier);
+    server.await?;
 
-        App::new()
-            .data(state)
-            .wrap(sentry_actix::Sentry::new())
-            .wrap(middleware::DefaultHeaders::new().header("X-Version", env!("CARGO_PKG_VERSION")))
-            .wrap(middleware::Compress::default())
-            .wrap(
-                middleware::Logger::default()
-                    .exclude_regex("^/api/health")
-                    .exclude_regex("^/stats"),
-            )
-            .wrap(middleware::NormalizePath::new(TrailingSlash::Trim))
-            .wrap(stats::Middleware::default())
-            .wrap(prometheus_metrics.clone())
-            .wrap(RequestTracing::new())
-            // TODO: set this to something more restrictive
-            .wrap(Cors::permissive().supports_credentials())
-            .wrap(IdentityService::new(
-                CookieIdentityPolicy::new(Config::session_private_key().as_bytes())
-                    .name("auth-cookie")
-                    .same_site(SameSite::Strict)
-                    .visit_deadline(Duration::weeks(2))
-                    .max_age_time(Duration::weeks(2))
-                    .secure(true),
-            ))
-            .data(
-                web::JsonConfig::default()
-                    .error_handler(json_error_handler)
-                    .limit(262_144),
-            )
-            .data(web::PayloadConfig::default().limit(262_144))
-            .service(stats::route)
-            .service(web::resource("/ws/admin").to(websocket::routes::admin_route))
-            .service(web::resource("/ws/game/{game_id}").to(websocket::routes::game_route))
-            .service(
-                web::scope("/api")
-                    .configure(games::routes::register)
-                    .configure(invitations::routes::register)
-                    .configure(auth::routes::register)
-                    .configure(transactions::routes::register)
-                    .configure(users::routes::register)
-                    .configure(ddg::routes::register)
-                    .configure(admin::routes::register)
-                    .service(health),
-            )
-    })
-    .bind(format!("{}:{}", Config::api_host(), Config::api_port()))?
-    .run()
-    .await?;
+    shutdown::drain(db).await;
+    info!("the server has shut down");
 
     Ok(())
 }
@@ -0,0 +1,87 @@
+//! Graceful shutdown of the server
+//!
+//! The server is restarted for every deploy, which can happen in the middle of a party.
+//! On SIGTERM or ctrl-c the websocket clients are told when to reconnect, new sales are refused
+//! and the requests in flight get the shutdown timeout to finish.
+//! The database pool is closed last, which waits for the transactions that are still running.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix::prelude::*;
+use actix_rt::signal::{self, unix};
+use actix_web::dev::Server;
+use sqlx::{Pool, Postgres};
+
+use crate::config::Config;
+use crate::errors::ServiceError;
+use crate::websocket::server::{Notification, NotificationServer};
+
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+
+/// true once the server received a shutdown signal
+pub fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Refuse new sales while the server shuts down, the client can retry them on the new server
+pub fn accept_sales() -> Result<(), ServiceError> {
+    if is_shutting_down() {
+        return Err(ServiceError::ServiceUnavailable(
+            Config::server_reconnect_after().as_secs(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// resolves on SIGTERM or ctrl-c
+async fn signal() -> std::io::Result<()> {
+    let mut terminate = unix::signal(unix::SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = terminate.recv() => info!("received SIGTERM"),
+        interrupt = signal::ctrl_c() => {
+            interrupt?;
+            info!("received SIGINT");
+        }
+    }
+
+    Ok(())
+}
+
+/// Stop the server gracefully once a shutdown signal arrives
+pub fn listen(server: Server, notifier: Addr<NotificationServer>) {
+    actix_rt::spawn(async move {
+        if let Err(e) = signal().await {
+            error!("unable to listen for shutdown signals: {}", e);
+            return;
+        }
+
+        SHUTTING_DOWN.store(true, Ordering::Relaxed);
+
+        let reconnect_after = Config::server_reconnect_after().as_secs();
+        if let Err(e) = notifier
+            .send(Notification::ServerShutdown { reconnect_after })
+            .await
+        {
+            error!(
+                "unable to notify the websocket clients about the shutdown: {}",
+                e
+            );
+        }
+
+        server.stop(true).await;
+    });
+}
+
+/// Wait for the database transactions that are still running, as long as the shutdown timeout allows
+pub async fn drain(db: Pool<Postgres>) {
+    let timeout = Config::server_shutdown_timeout();
+
+    if actix_rt::time::timeout(timeout, db.close()).await.is_err() {
+        warn!(
+            "the database connections didn't close within {} seconds",
+            timeout.as_secs()
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use std::pin::Pin;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::task::{Context, Poll};
+use std::time::Instant;
 
 use actix_service::{Service, Transform};
 use actix_web::dev::{ServiceRequest, ServiceResponse};
@@ -11,11 +13,17 @@ use futures::future::{ok, Ready};
 use futures::Future;
 use futures::{future::TryFutureExt, try_join};
 
-use crate::cache;
+use crate::deadline;
 use crate::errors::ServiceError;
 use crate::games::Game;
 use crate::server::{Response, State};
+use crate::webhooks;
+use crate::websocket::outbox::Outbox;
 use crate::websocket::queries::ActiveSessionCount;
+use crate::websocket::server::NotificationServer;
+
+/// The amount of seconds the rolling error rate is calculated over
+const ERROR_WINDOW: usize = 60;
 
 lazy_static! {
     static ref STATS: Stats = Stats::new();
@@ -24,6 +32,13 @@ lazy_static! {
 pub struct Stats {
     requests: AtomicUsize,
     errors: AtomicUsize,
+    /// logins with a wrong password or an unknown username
+    login_failures: AtomicUsize,
+    /// password logins rejected because of too many failures
+    throttled_logins: AtomicUsize,
+    error_rate: ErrorRate,
+    /// wether the error budget is exceeded, so the alert isn't fired for every error
+    alerting: AtomicBool,
 }
 
 impl Stats {
@@ -31,6 +46,10 @@ impl Stats {
         Stats {
             requests: AtomicUsize::new(0),
             errors: AtomicUsize::new(0),
+            login_failures: AtomicUsize::new(0),
+            throttled_logins: AtomicUsize::new(0),
+            error_rate: ErrorRate::new(),
+            alerting: AtomicBool::new(false),
         }
     }
 
@@ -38,8 +57,18 @@ impl Stats {
         STATS.requests.fetch_add(1, Ordering::Relaxed);
     }
 
-    fn add_error() {
+    /// returns the amount of server errors in the last minute
+    fn add_error() -> usize {
         STATS.errors.fetch_add(1, Ordering::Relaxed);
+        STATS.error_rate.record()
+    }
+
+    pub fn add_login_failure() {
+        STATS.login_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_throttled_login() {
+        STATS.throttled_logins.fetch_add(1, Ordering::Relaxed);
     }
 
     pub fn load_requests() -> usize {
@@ -49,6 +78,128 @@ impl Stats {
     pub fn load_errors() -> usize {
         STATS.errors.load(Ordering::Relaxed)
     }
+
+    pub fn load_login_failures() -> usize {
+        STATS.login_failures.load(Ordering::Relaxed)
+    }
+
+    pub fn load_throttled_logins() -> usize {
+        STATS.throttled_logins.load(Ordering::Relaxed)
+    }
+
+    /// the amount of server errors in the last minute
+    pub fn load_error_rate() -> usize {
+        STATS.error_rate.rate()
+    }
+
+    pub fn load_alerting() -> bool {
+        STATS.alerting.load(Ordering::Relaxed)
+    }
+}
+
+/// Rolling count of the server errors, with a bucket per second
+struct ErrorRate {
+    started: Instant,
+    /// the second each bucket belongs to and the amount of errors in that second
+    buckets: Mutex<[(u64, usize); ERROR_WINDOW]>,
+}
+
+impl ErrorRate {
+    fn new() -> Self {
+        ErrorRate {
+            started: Instant::now(),
+            buckets: Mutex::new([(0, 0); ERROR_WINDOW]),
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    fn record(&self) -> usize {
+        self.record_at(self.now())
+    }
+
+    fn rate(&self) -> usize {
+        self.rate_at(self.now())
+    }
+
+    /// add an error and return the amount of errors in the window
+    fn record_at(&self, second: u64) -> usize {
+        let mut buckets = self.buckets.lock().expect("error rate lock poisoned");
+
+        let bucket = &mut buckets[second as usize % ERROR_WINDOW];
+        if bucket.0 != second {
+            *bucket = (second, 0);
+        }
+        bucket.1 += 1;
+
+        ErrorRate::sum(&buckets, second)
+    }
+
+    fn rate_at(&self, second: u64) -> usize {
+        ErrorRate::sum(
+            &self.buckets.lock().expect("error rate lock poisoned"),
+            second,
+        )
+    }
+
+    fn sum(buckets: &[(u64, usize); ERROR_WINDOW], second: u64) -> usize {
+        buckets
+            .iter()
+            .filter(|(bucket, _)| bucket + (ERROR_WINDOW as u64) > second)
+            .map(|(_, errors)| errors)
+            .sum()
+    }
+}
+
+/// Alert when the server returns too many errors
+#[derive(Debug, Clone)]
+pub struct ErrorBudget {
+    /// the maximum amount of server errors per minute
+    pub threshold: usize,
+    /// this URL receives a POST request when the threshold is exceeded
+    pub webhook: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBudgetAlert {
+    errors_per_minute: usize,
+    threshold: usize,
+}
+
+impl ErrorBudget {
+    /// fire the alert once when the error rate exceeds the threshold,
+    /// it's fired again after the error rate dropped below the threshold
+    fn check(&self, error_rate: usize, state: Option<Data<State>>) {
+        if error_rate <= self.threshold {
+            STATS.alerting.store(false, Ordering::Relaxed);
+            return;
+        }
+
+        if STATS.alerting.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let message = format!(
+            "the error budget is exceeded, {} server errors in the last minute",
+            error_rate
+        );
+        warn!("{}", message);
+        sentry::capture_message(&message, sentry::Level::Warning);
+
+        if let (Some(url), Some(state)) = (self.webhook.clone(), state) {
+            let alert = ErrorBudgetAlert {
+                errors_per_minute: error_rate,
+                threshold: self.threshold,
+            };
+
+            actix_rt::spawn(async move {
+                webhooks::dispatch(url, &alert, &state.http, &state.cache).await
+            });
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -56,6 +207,12 @@ pub struct StatsResponse {
     requests: usize,
     errors: usize,
     active_ws_sessions: usize,
+    swept_ws_sessions: usize,
+    /// the notifications that were dropped for slow websocket clients
+    dropped_ws_notifications: usize,
+    slow_ws_disconnects: usize,
+    /// the requests that were cancelled after their deadline
+    deadline_exceeded: usize,
     active_games: i64,
     active_db_connections: usize,
     idle_db_connections: usize,
@@ -79,19 +236,31 @@ pub async fn route(state: Data<State>) -> Response {
         requests: STATS.requests.load(Ordering::Relaxed),
         errors: STATS.errors.load(Ordering::Relaxed),
         active_ws_sessions,
+        swept_ws_sessions: NotificationServer::swept_sessions(),
+        dropped_ws_notifications: Outbox::dropped_notifications(),
+        slow_ws_disconnects: Outbox::slow_disconnects(),
+        deadline_exceeded: deadline::exceeded(),
         active_games,
         active_db_connections: db.size() as usize,
         idle_db_connections: db.num_idle(),
-        cache_hits: cache::Stats::load_hits(),
-        cache_misses: cache::Stats::load_misses(),
+        cache_hits: state.cache.hits(),
+        cache_misses: state.cache.misses(),
     });
 }
 
-pub struct Middleware;
+pub struct Middleware {
+    error_budget: Option<ErrorBudget>,
+}
 
 impl Middleware {
     pub fn default() -> Middleware {
-        Middleware
+        Middleware { error_budget: None }
+    }
+
+    /// alert when the error rate exceeds the error budget
+    pub fn error_budget(mut self, error_budget: Option<ErrorBudget>) -> Middleware {
+        self.error_budget = error_budget;
+        self
     }
 }
 
@@ -108,12 +277,16 @@ where
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        ok(RequestCountMiddleware { service })
+        ok(RequestCountMiddleware {
+            service,
+            error_budget: self.error_budget.clone(),
+        })
     }
 }
 
 pub struct RequestCountMiddleware<S> {
     service: S,
+    error_budget: Option<ErrorBudget>,
 }
 
 impl<S, B> Service for RequestCountMiddleware<S>
@@ -134,13 +307,19 @@ where
     fn call(&mut self, request: ServiceRequest) -> Self::Future {
         Stats::add_request();
 
+        let error_budget = self.error_budget.clone();
+        let state = request.app_data::<Data<State>>().cloned();
+
         let fut = self.service.call(request);
 
         Box::pin(async move {
             let res = fut.await?;
 
             if res.response().status().is_server_error() {
-                Stats::add_error();
+                let error_rate = Stats::add_error();
+                if let Some(error_budget) = error_budget {
+                    error_budget.check(error_rate, state);
+                }
             }
 
             Ok(res)
@@ -173,4 +352,19 @@ mod tests {
         assert_eq!(STATS.requests.load(Ordering::Relaxed), 2);
         assert_eq!(STATS.errors.load(Ordering::Relaxed), 1);
     }
+
+    #[test]
+    fn rolling_error_rate() {
+        let rate = ErrorRate::new();
+
+        assert_eq!(rate.record_at(10), 1);
+        assert_eq!(rate.record_at(10), 2);
+        assert_eq!(rate.record_at(50), 3);
+        assert_eq!(rate.rate_at(69), 3);
+        // the errors of second 10 are out of the window
+        assert_eq!(rate.rate_at(70), 1);
+        // second 130 reuses the bucket of second 10
+        assert_eq!(rate.record_at(130), 1);
+        assert_eq!(rate.rate_at(200), 0);
+    }
 }
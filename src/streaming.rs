@@ -0,0 +1,164 @@
+//! Streamed JSON arrays for the endpoints that return a lot of rows
+//!
+//! The rows are serialized one by one while they're read from the database, instead of collecting
+//! them in a `Vec` first. The chunks go through a bounded channel, so a slow client holds up the query
+//! instead of the rows piling up in memory, at the cost of a database connection for the whole download.
+//! The status and the headers are sent before the first row is read, so a failing query aborts
+//! the response and the client is left with an incomplete array.
+
+use actix_web::web::Bytes;
+use futures::channel::mpsc;
+use futures::{Future, SinkExt, Stream, StreamExt};
+use serde::Serialize;
+
+use crate::errors::ServiceError;
+
+/// the rows are sent once a chunk grows beyond this many bytes
+const CHUNK_SIZE: usize = 32 * 1024;
+
+/// the amount of chunks that can be serialized ahead of the client
+const BUFFERED_CHUNKS: usize = 4;
+
+/// The body of a streamed response
+pub type Body = mpsc::Receiver<Result<Bytes, ServiceError>>;
+
+/// A JSON array that's sent in chunks while its rows are written
+pub struct JsonArray {
+    sender: mpsc::Sender<Result<Bytes, ServiceError>>,
+    chunk: Vec<u8>,
+    empty: bool,
+}
+
+/// Start a streamed JSON array, `write` adds the rows on a task of its own
+pub fn json_array<F, Fut>(write: F) -> Body
+where
+    F: FnOnce(JsonArray) -> Fut,
+    Fut: Future<Output = ()> + 'static,
+{
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+
+    actix_rt::spawn(write(JsonArray {
+        sender,
+        chunk: Vec::with_capacity(CHUNK_SIZE),
+        empty: true,
+    }));
+
+    receiver
+}
+
+/// Append a row to an array that's being written, the array is opened before the first row
+pub fn append<T: Serialize>(buffer: &mut Vec<u8>, row: &T, first: bool) -> serde_json::Result<()> {
+    buffer.push(if first { b'[' } else { b',' });
+    serde_json::to_writer(buffer, row)
+}
+
+impl JsonArray {
+    /// Add a row, returns false when the response was aborted or the client went away
+    pub async fn push<T: Serialize>(&mut self, row: &T) -> bool {
+        if let Err(e) = append(&mut self.chunk, row, self.empty) {
+            error!("unable to serialize a streamed row: {}", e);
+            self.fail().await;
+            return false;
+        }
+        self.empty = false;
+
+        if self.chunk.len() < CHUNK_SIZE {
+            return true;
+        }
+
+        self.flush().await
+    }
+
+    /// Add every row of a query and close the array, the response is aborted when the query fails
+    pub async fn send_all<T, S>(mut self, rows: S)
+    where
+        T: Serialize,
+        S: Stream<Item = Result<T, sqlx::Error>>,
+    {
+        futures::pin_mut!(rows);
+
+        while let Some(row) = rows.next().await {
+            let row = match row {
+                Ok(row) => row,
+                Err(e) => {
+                    error!("unable to stream the rows: {}", e);
+                    self.fail().await;
+                    return;
+                }
+            };
+
+            if !self.push(&row).await {
+                return;
+            }
+        }
+
+        self.finish().await;
+    }
+
+    /// Close the array and send the remaining rows
+    pub async fn finish(mut self) {
+        let end: &[u8] = if self.empty { b"[]" } else { b"]" };
+        self.chunk.extend_from_slice(end);
+
+        self.flush().await;
+    }
+
+    async fn flush(&mut self) -> bool {
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(CHUNK_SIZE));
+
+        self.sender.send(Ok(Bytes::from(chunk))).await.is_ok()
+    }
+
+    async fn fail(&mut self) {
+        let _ = self
+            .sender
+            .send(Err(ServiceError::InternalServerError))
+            .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    async fn collect(body: Body) -> (String, bool) {
+        let chunks: Vec<Result<Bytes, ServiceError>> = body.collect().await;
+        let failed = chunks.iter().any(|chunk| chunk.is_err());
+        let json = chunks
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+            .collect();
+
+        (json, failed)
+    }
+
+    #[actix_rt::test]
+    async fn streamed_arrays() {
+        let body = json_array(|array| array.send_all(stream::iter(vec![Ok(1), Ok(2), Ok(3)])));
+        assert_eq!(collect(body).await, (String::from("[1,2,3]"), false));
+
+        let body = json_array(|array| array.send_all(stream::iter(Vec::<Result<i64, _>>::new())));
+        assert_eq!(collect(body).await, (String::from("[]"), false));
+
+        let body = json_array(|array| {
+            array.send_all(stream::iter(vec![Ok(1), Err(sqlx::Error::RowNotFound)]))
+        });
+        let (_, failed) = collect(body).await;
+        assert!(failed);
+    }
+
+    #[actix_rt::test]
+    async fn large_arrays() {
+        let rows: Vec<Result<String, sqlx::Error>> =
+            (0..10_000).map(|row| Ok(format!("row {}", row))).collect();
+
+        let (json, failed) = collect(json_array(|array| array.send_all(stream::iter(rows)))).await;
+        let rows: Vec<String> = serde_json::from_str(&json).unwrap();
+
+        assert!(!failed);
+        assert_eq!(rows.len(), 10_000);
+        assert_eq!(rows[9_999], "row 9999");
+    }
+}
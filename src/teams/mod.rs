@@ -0,0 +1,54 @@
+//! Teams of players within a game, like "Bar Left" against "Bar Right"
+//!
+//! The game owner creates the teams and puts the invited players in them,
+//! the team of a player is stored on their invitation.
+//! The players still buy their own beverages, the sales of a team add up on a shared scoreboard
+//! which is pushed to the game after every sale.
+use tokio::sync::broadcast::RecvError;
+
+use sqlx::{Pool, Postgres};
+
+use crate::events::{DomainEvent, EventBus};
+use crate::transactions::Transaction;
+use crate::websocket::server::GameId;
+
+mod models;
+pub mod routes;
+
+pub use models::{NewTeam, Team, TeamLeaderboard};
+
+/// Push the scoreboard of the teams after every sale or voided order in a game with teams
+pub fn subscribe(db: Pool<Postgres>, events: &EventBus) {
+    let mut receiver = events.subscribe();
+    let events = events.clone();
+
+    actix_rt::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(DomainEvent::SaleCreated(sale)) | Ok(DomainEvent::SaleVoided(sale)) => {
+                    publish_leaderboard(sale.game_id, &db, &events).await
+                }
+                Ok(_) => (),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("the team scoreboards missed {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+async fn publish_leaderboard(game_id: GameId, db: &Pool<Postgres>, events: &EventBus) {
+    match Transaction::get_sales_per_team(game_id.0, db).await {
+        // the game doesn't have any teams
+        Ok(teams) if teams.is_empty() => (),
+        Ok(teams) => events.publish(DomainEvent::TeamScoresUpdated(TeamLeaderboard {
+            game_id,
+            teams,
+        })),
+        Err(e) => error!(
+            "unable to load the team scoreboard of game {}: {}",
+            game_id.0, e
+        ),
+    }
+}
@@ -0,0 +1,214 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::transactions::models::TeamSales;
+use crate::websocket::server::GameId;
+
+const MAX_NAME_LENGTH: usize = 50;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Team {
+    pub id: i64,
+    pub game_id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamMember {
+    pub user_id: i64,
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewTeam {
+    pub name: String,
+}
+
+/// The sales of every team of a game, the team that sold most first
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamLeaderboard {
+    pub game_id: GameId,
+    pub teams: Vec<TeamSales>,
+}
+
+impl crate::validator::Validate<NewTeam> for NewTeam {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.name.trim().is_empty() {
+            bad_request!("the team name is too short");
+        }
+        if self.name.trim().chars().count() > MAX_NAME_LENGTH {
+            bad_request!("the team name is too long, maximum 50 characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl NewTeam {
+    #[tracing::instrument(name = "NewTeam::save", skip(db))]
+    pub async fn save(&self, game_id: i64, db: &Pool<Postgres>) -> Result<Team, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            INSERT INTO teams (game_id, name)
+            VALUES ($1, $2)
+            RETURNING id, game_id, name, created_at
+            "#,
+            game_id,
+            self.name.trim()
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(Team {
+            id: row.id,
+            game_id: row.game_id,
+            name: row.name,
+            created_at: row.created_at,
+            members: Vec::new(),
+        })
+    }
+}
+
+impl Team {
+    /// the teams of a game with their players
+    #[tracing::instrument(name = "Team::find_by_game", skip(db))]
+    pub async fn find_by_game(game_id: i64, db: &Pool<Postgres>) -> Result<Vec<Team>, sqlx::Error> {
+        let mut teams: Vec<Team> = sqlx::query!(
+            "SELECT id, game_id, name, created_at FROM teams WHERE game_id = $1 ORDER BY name",
+            game_id
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| Team {
+            id: row.id,
+            game_id: row.game_id,
+            name: row.name,
+            created_at: row.created_at,
+            members: Vec::new(),
+        })
+        .collect();
+
+        let members = sqlx::query!(
+            r#"
+            SELECT invitations.team_id AS "team_id!", users.id AS user_id, users.username
+            FROM invitations
+            INNER JOIN users ON users.id = invitations.user_id
+            WHERE invitations.game_id = $1 AND invitations.team_id IS NOT NULL
+            ORDER BY users.username
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        for member in members {
+            if let Some(team) = teams.iter_mut().find(|team| team.id == member.team_id) {
+                team.members.push(TeamMember {
+                    user_id: member.user_id,
+                    username: member.username,
+                });
+            }
+        }
+
+        Ok(teams)
+    }
+
+    #[tracing::instrument(name = "Team::rename", skip(db))]
+    pub async fn rename(
+        game_id: i64,
+        team_id: i64,
+        team: &NewTeam,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "UPDATE teams SET name = $3 WHERE id = $1 AND game_id = $2 RETURNING id",
+            team_id,
+            game_id,
+            team.name.trim()
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete a team, its players play on their own again
+    #[tracing::instrument(name = "Team::delete", skip(db))]
+    pub async fn delete(
+        game_id: i64,
+        team_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = db::begin(Operation::DeleteTeam, db).await?;
+
+        sqlx::query!(
+            "UPDATE invitations SET team_id = NULL WHERE game_id = $1 AND team_id = $2",
+            game_id,
+            team_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "DELETE FROM teams WHERE id = $1 AND game_id = $2 RETURNING id",
+            team_id,
+            game_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Put an invited player in a team of the game, or take them out of their team
+    #[tracing::instrument(name = "Team::assign", skip(db))]
+    pub async fn assign(
+        game_id: i64,
+        user_id: i64,
+        team_id: Option<i64>,
+        db: &Pool<Postgres>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE invitations SET team_id = $3
+            WHERE game_id = $1 AND user_id = $2
+                AND ($3::BIGINT IS NULL OR EXISTS (SELECT 1 FROM teams WHERE id = $3 AND game_id = $1))
+            RETURNING id
+            "#,
+            game_id,
+            user_id,
+            team_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::validator::Validate;
+
+    #[test]
+    fn team_name() {
+        let team = |name: &str| NewTeam {
+            name: name.to_string(),
+        };
+
+        assert!(team("Bar Left").validate().is_ok());
+        assert!(team("   ").validate().is_err());
+        assert!(team(&"a".repeat(MAX_NAME_LENGTH + 1)).validate().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use actix_web::web::{Data, HttpResponse, Json, Path};
+use actix_web::{delete, get, post, put, web};
+
+use crate::auth;
+use crate::auth::Identity;
+use crate::server::{self, State};
+use crate::teams::{NewTeam, Team};
+use crate::validator::Validator;
+
+/// The teams of a game with their players
+#[get("/games/{id}/teams")]
+async fn find_all(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(*game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let teams = Team::find_by_game(*game_id, &state.db).await?;
+
+    http_ok_json!(teams);
+}
+
+#[post("/games/{id}/teams")]
+async fn create(
+    game_id: Path<i64>,
+    team: Json<Validator<NewTeam>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let team = team.into_inner().validate()?;
+
+    let game = state.games.find_by_id(*game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can create teams");
+    }
+
+    let team = team.save(game.id, &state.db).await?;
+
+    http_created_json!(team);
+}
+
+#[put("/games/{id}/teams/{team_id}")]
+async fn rename(
+    path: Path<(i64, i64)>,
+    team: Json<Validator<NewTeam>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, team_id) = path.into_inner();
+    let team = team.into_inner().validate()?;
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can rename teams");
+    }
+
+    Team::rename(game.id, team_id, &team, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Delete a team, its players play on their own again
+#[delete("/games/{id}/teams/{team_id}")]
+async fn delete(path: Path<(i64, i64)>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, team_id) = path.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can delete teams");
+    }
+
+    Team::delete(game.id, team_id, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Put an invited player in a team, this moves them out of their previous team
+#[put("/games/{id}/teams/{team_id}/members/{user_id}")]
+async fn add_member(
+    path: Path<(i64, i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, team_id, user_id) = path.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can put players in a team");
+    }
+
+    Team::assign(game.id, user_id, Some(team_id), &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/games/{id}/teams/{team_id}/members/{user_id}")]
+async fn remove_member(
+    path: Path<(i64, i64, i64)>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game_id, _, user_id) = path.into_inner();
+
+    let game = state.games.find_by_id(game_id).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can take players out of a team");
+    }
+
+    Team::assign(game.id, user_id, None, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn register(cfg: &mut web::ServiceConfig) {
+    cfg.service(find_all);
+    cfg.service(create);
+    cfg.service(rename);
+    cfg.service(delete);
+    cfg.service(add_member);
+    cfg.service(remove_member);
+}
@@ -0,0 +1,224 @@
+//! Do stock market crashes drive purchases?
+//!
+//! The beverages purchased in the minutes after a crash are compared with the same amount of minutes before it.
+//! The window before a crash can overlap with the window after the previous crash.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+
+const DEFAULT_WINDOW_MINUTES: i32 = 10;
+const MAX_WINDOW_MINUTES: i32 = 60;
+/// the amount of games in the admin analytics
+const MAX_GAMES: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct CrashFilter {
+    /// the length of the windows before and after a crash, defaults to 10 minutes
+    pub minutes: Option<i32>,
+}
+
+impl CrashFilter {
+    fn window(&self) -> Result<i32, ServiceError> {
+        let minutes = self.minutes.unwrap_or(DEFAULT_WINDOW_MINUTES);
+        if !(1..=MAX_WINDOW_MINUTES).contains(&minutes) {
+            bad_request!(format!(
+                "the window should be between 1 and {} minutes",
+                MAX_WINDOW_MINUTES
+            ));
+        }
+
+        Ok(minutes)
+    }
+}
+
+/// The purchases around a single crash
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashEffect {
+    pub crashed_at: DateTime<Utc>,
+    /// the beverages purchased in the window before the crash
+    pub baseline_sales: i64,
+    /// the beverages purchased in the window after the crash
+    pub crash_sales: i64,
+    /// `crash_sales / baseline_sales`, empty without a baseline
+    pub lift: Option<f64>,
+}
+
+/// The effect of every crash of a game
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CrashReport {
+    pub game_id: i64,
+    pub window_minutes: i32,
+    pub crashes: Vec<CrashEffect>,
+    /// the lift of all crashes together
+    pub lift: Option<f64>,
+}
+
+/// The effect of the crashes of a game, for the administrators
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameCrashes {
+    pub game_id: i64,
+    pub name: String,
+    pub crash_count: i64,
+    pub baseline_sales: i64,
+    pub crash_sales: i64,
+    pub lift: Option<f64>,
+}
+
+fn lift(baseline_sales: i64, crash_sales: i64) -> Option<f64> {
+    if baseline_sales <= 0 {
+        return None;
+    }
+
+    Some(crash_sales as f64 / baseline_sales as f64)
+}
+
+/// Remember a crash of the stock market of a game
+#[tracing::instrument(name = "crashes::record", skip(db))]
+pub(crate) async fn record(
+    game_id: i64,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("INSERT INTO market_crashes (game_id) VALUES ($1)", game_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+impl CrashReport {
+    #[tracing::instrument(name = "CrashReport::load", skip(db))]
+    pub async fn load(
+        game_id: i64,
+        filter: &CrashFilter,
+        db: &Pool<Postgres>,
+    ) -> Result<CrashReport, ServiceError> {
+        let window = filter.window()?;
+
+        let crashes: Vec<CrashEffect> = sqlx::query!(
+            r#"
+            SELECT market_crashes.crashed_at,
+                COALESCE((
+                    SELECT SUM(transactions.amount) FROM transactions
+                    INNER JOIN orders ON orders.id = transactions.order_id
+                    WHERE orders.game_id = $1
+                    AND orders.created_at >= market_crashes.crashed_at - make_interval(mins => $2)
+                    AND orders.created_at < market_crashes.crashed_at
+                ), 0)::BIGINT AS "baseline_sales!",
+                COALESCE((
+                    SELECT SUM(transactions.amount) FROM transactions
+                    INNER JOIN orders ON orders.id = transactions.order_id
+                    WHERE orders.game_id = $1
+                    AND orders.created_at >= market_crashes.crashed_at
+                    AND orders.created_at < market_crashes.crashed_at + make_interval(mins => $2)
+                ), 0)::BIGINT AS "crash_sales!"
+            FROM market_crashes
+            WHERE market_crashes.game_id = $1
+            ORDER BY market_crashes.crashed_at
+            "#,
+            game_id,
+            window
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|record| CrashEffect {
+            crashed_at: record.crashed_at,
+            baseline_sales: record.baseline_sales,
+            crash_sales: record.crash_sales,
+            lift: lift(record.baseline_sales, record.crash_sales),
+        })
+        .collect();
+
+        let baseline_sales = crashes.iter().map(|crash| crash.baseline_sales).sum();
+        let crash_sales = crashes.iter().map(|crash| crash.crash_sales).sum();
+
+        Ok(CrashReport {
+            game_id,
+            window_minutes: window,
+            crashes,
+            lift: lift(baseline_sales, crash_sales),
+        })
+    }
+}
+
+impl GameCrashes {
+    /// the games with the most recent crashes
+    #[tracing::instrument(name = "GameCrashes::recent", skip(db))]
+    pub async fn recent(
+        filter: &CrashFilter,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<GameCrashes>, ServiceError> {
+        let window = filter.window()?;
+
+        let games = sqlx::query!(
+            r#"
+            WITH effects AS (
+                SELECT market_crashes.game_id, market_crashes.crashed_at,
+                    COALESCE((
+                        SELECT SUM(transactions.amount) FROM transactions
+                        INNER JOIN orders ON orders.id = transactions.order_id
+                        WHERE orders.game_id = market_crashes.game_id
+                        AND orders.created_at >= market_crashes.crashed_at - make_interval(mins => $1)
+                        AND orders.created_at < market_crashes.crashed_at
+                    ), 0) AS baseline_sales,
+                    COALESCE((
+                        SELECT SUM(transactions.amount) FROM transactions
+                        INNER JOIN orders ON orders.id = transactions.order_id
+                        WHERE orders.game_id = market_crashes.game_id
+                        AND orders.created_at >= market_crashes.crashed_at
+                        AND orders.created_at < market_crashes.crashed_at + make_interval(mins => $1)
+                    ), 0) AS crash_sales
+                FROM market_crashes
+            )
+            SELECT games.id AS game_id, games.name, COUNT(*) AS "crash_count!",
+                SUM(effects.baseline_sales)::BIGINT AS "baseline_sales!",
+                SUM(effects.crash_sales)::BIGINT AS "crash_sales!"
+            FROM effects
+            INNER JOIN games ON games.id = effects.game_id
+            GROUP BY games.id
+            ORDER BY MAX(effects.crashed_at) DESC
+            LIMIT $2
+            "#,
+            window,
+            MAX_GAMES
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|record| GameCrashes {
+            game_id: record.game_id,
+            name: record.name,
+            crash_count: record.crash_count,
+            baseline_sales: record.baseline_sales,
+            crash_sales: record.crash_sales,
+            lift: lift(record.baseline_sales, record.crash_sales),
+        })
+        .collect();
+
+        Ok(games)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_lift() {
+        assert_eq!(lift(10, 25), Some(2.5));
+        assert_eq!(lift(10, 5), Some(0.5));
+        assert_eq!(lift(0, 5), None);
+    }
+
+    #[test]
+    fn crash_window() {
+        assert_eq!(CrashFilter { minutes: None }.window().unwrap(), 10);
+        assert!(CrashFilter { minutes: Some(0) }.window().is_err());
+        assert!(CrashFilter { minutes: Some(61) }.window().is_err());
+    }
+}
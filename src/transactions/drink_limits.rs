@@ -0,0 +1,388 @@
+//! Drink responsibly: a cap on the alcoholic drinks a player buys per hour
+//!
+//! The game owner sets how many alcoholic drinks a player can buy in an hour.
+//! A player who goes over it gets a warning, and so does the owner when they asked for it.
+//! With a cooldown, the player can't buy alcoholic drinks for a while after the warning,
+//! soft drinks stay available.
+//! The sales a kiosk queued while it was offline are checked at the moment they happened,
+//! together with the earlier sales of the queue.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::events::{EventType, GameEvent};
+use crate::games::Game;
+use crate::transactions::models::NewSale;
+use crate::users::User;
+use crate::websocket::server::GameId;
+
+/// the window in seconds the alcoholic drinks are counted in
+const HOUR: i32 = 60 * 60;
+const MAX_COOLDOWN_MINUTES: i32 = 24 * 60;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DrinkLimit {
+    pub game_id: i64,
+    pub max_per_hour: i32,
+    /// the minutes a player can't buy alcoholic drinks after exceeding the limit
+    pub cooldown: Option<i32>,
+    /// send the warnings to the game owner as well
+    pub notify_owner: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewDrinkLimit {
+    /// `null` removes the limit
+    pub max_per_hour: Option<i32>,
+    /// `null` only warns the player
+    pub cooldown: Option<i32>,
+    #[serde(default)]
+    pub notify_owner: bool,
+}
+
+/// A player bought more alcoholic drinks than the limit of the game
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct DrinkWarning {
+    pub game_id: GameId,
+    pub user_id: i64,
+    pub username: String,
+    /// the alcoholic drinks the player bought in the last hour
+    pub drinks: i64,
+    pub max_per_hour: i32,
+    /// the player can't buy alcoholic drinks until then
+    pub blocked_until: Option<DateTime<Utc>>,
+    /// the game owner who gets a copy of the warning
+    #[serde(skip)]
+    pub owner_id: Option<i64>,
+}
+
+impl crate::validator::Validate<NewDrinkLimit> for NewDrinkLimit {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if let Some(max_per_hour) = self.max_per_hour {
+            if max_per_hour <= 0 {
+                bad_request!("the drink limit should allow at least one drink per hour");
+            }
+        }
+
+        if let Some(cooldown) = self.cooldown {
+            if cooldown <= 0 || cooldown > MAX_COOLDOWN_MINUTES {
+                bad_request!(format!(
+                    "the cooldown should be between 1 and {} minutes",
+                    MAX_COOLDOWN_MINUTES
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl DrinkLimit {
+    #[tracing::instrument(name = "DrinkLimit::find", skip(db))]
+    pub async fn find(
+        game_id: i64,
+        db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+    ) -> Result<Option<DrinkLimit>, sqlx::Error> {
+        sqlx::query_as!(
+            DrinkLimit,
+            r#"
+            SELECT game_id, max_per_hour, cooldown, notify_owner
+            FROM game_drink_limits
+            WHERE game_id = $1
+            "#,
+            game_id
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "DrinkLimit::save", skip(db))]
+    pub async fn save(
+        game_id: i64,
+        limit: &NewDrinkLimit,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<DrinkLimit>, sqlx::Error> {
+        let max_per_hour = match limit.max_per_hour {
+            Some(max_per_hour) => max_per_hour,
+            None => {
+                sqlx::query!("DELETE FROM game_drink_limits WHERE game_id = $1", game_id)
+                    .execute(db)
+                    .await?;
+
+                return Ok(None);
+            }
+        };
+
+        sqlx::query_as!(
+            DrinkLimit,
+            r#"
+            INSERT INTO game_drink_limits (game_id, max_per_hour, cooldown, notify_owner)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (game_id) DO UPDATE
+            SET max_per_hour = EXCLUDED.max_per_hour, cooldown = EXCLUDED.cooldown,
+                notify_owner = EXCLUDED.notify_owner, updated_at = NOW()
+            RETURNING game_id, max_per_hour, cooldown, notify_owner
+            "#,
+            game_id,
+            max_per_hour,
+            limit.cooldown,
+            limit.notify_owner
+        )
+        .fetch_one(db)
+        .await
+        .map(Some)
+    }
+}
+
+/// The sales a kiosk queued while it was offline, in the order they happened
+///
+/// A queued sale that takes the player over the limit starts the cooldown for the sales after it,
+/// like the warning would have online.
+#[derive(Debug, Default)]
+pub(crate) struct Batch {
+    /// the last time the earlier sales of the batch went over the limit
+    flagged_at: Option<DateTime<Utc>>,
+}
+
+impl Batch {
+    /// Reject a queued sale with alcoholic drinks that happened during a cooldown
+    pub(crate) async fn check(
+        &self,
+        sale: &NewSale,
+        sold_at: DateTime<Utc>,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<(), ServiceError> {
+        check_at(sale, sold_at, self.flagged_at, db).await
+    }
+
+    /// Count the drinks after a queued sale was inserted, the sale is flagged when it went over the limit
+    pub(crate) async fn count(
+        &mut self,
+        sale: &NewSale,
+        sold_at: DateTime<Utc>,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<(), ServiceError> {
+        let limit = match DrinkLimit::find(sale.game_id, &mut *db).await? {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let drinks = drinks(sale.game_id, sale.user_id, sold_at, &mut *db).await?;
+        if drinks <= i64::from(limit.max_per_hour) {
+            return Ok(());
+        }
+
+        // a player is warned once per hour or once per cooldown
+        let window = Duration::seconds(i64::from(
+            limit.cooldown.map(|minutes| minutes * 60).unwrap_or(HOUR),
+        ));
+        let flagged_at = flagged_at(sale, sold_at, db).await?.max(self.flagged_at);
+        if !matches!(flagged_at, Some(flagged_at) if flagged_at > sold_at - window) {
+            self.flagged_at = Some(sold_at);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reject an order with alcoholic drinks while the player sits out the cooldown of the limit
+#[tracing::instrument(name = "drink_limits::check", skip(db))]
+pub(crate) async fn check(
+    sale: &NewSale,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), ServiceError> {
+    check_at(sale, Utc::now(), None, db).await
+}
+
+/// Check an order at the moment it was placed
+///
+/// `flagged_in_batch` is the last time the earlier orders of a batch went over the limit.
+async fn check_at(
+    sale: &NewSale,
+    at: DateTime<Utc>,
+    flagged_in_batch: Option<DateTime<Utc>>,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), ServiceError> {
+    let limit = match DrinkLimit::find(sale.game_id, &mut *db).await? {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    let flagged_at = flagged_at(sale, at, db).await?.max(flagged_in_batch);
+    let blocked_until = match blocked_until(flagged_at, limit.cooldown, at) {
+        Some(blocked_until) => blocked_until,
+        None => return Ok(()),
+    };
+
+    let slots: Vec<i16> = sale.slots.keys().copied().collect();
+    let order = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM beverages
+            WHERE game_id = $1 AND user_id = $2 AND slot_no = any($3) AND alcoholic
+        ) AS "alcoholic!"
+        "#,
+        sale.game_id,
+        sale.user_id,
+        &slots
+    )
+    .fetch_one(&mut *db)
+    .await?;
+
+    // the alcoholic drinks are available again after the cooldown
+    if order.alcoholic {
+        let retry_after = (blocked_until - at).num_seconds().max(1);
+        return Err(ServiceError::RateLimited(retry_after as u64));
+    }
+
+    Ok(())
+}
+
+/// the last time the player went over the limit, before the moment of an order
+async fn flagged_at(
+    sale: &NewSale,
+    at: DateTime<Utc>,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT MAX(created_at) AS "flagged_at?"
+        FROM game_events
+        WHERE game_id = $1 AND user_id = $2 AND event_type = $3 AND created_at <= $4
+        "#,
+        sale.game_id,
+        sale.user_id,
+        EventType::DrinkLimitExceeded as _,
+        at
+    )
+    .fetch_one(&mut *db)
+    .await?;
+
+    Ok(record.flagged_at)
+}
+
+/// the alcoholic drinks a player bought in the hour before a moment
+async fn drinks(
+    game_id: i64,
+    user_id: i64,
+    at: DateTime<Utc>,
+    db: impl sqlx::Executor<'_, Database = sqlx::Postgres>,
+) -> Result<i64, sqlx::Error> {
+    let record = sqlx::query!(
+        r#"
+        SELECT COALESCE(SUM(transactions.amount), 0)::BIGINT AS "drinks!"
+        FROM transactions
+        INNER JOIN orders ON orders.id = transactions.order_id
+        INNER JOIN beverages ON beverages.game_id = orders.game_id
+            AND beverages.user_id = orders.user_id
+            AND beverages.slot_no = transactions.slot_no
+        WHERE orders.game_id = $1 AND orders.user_id = $2 AND beverages.alcoholic
+            AND orders.created_at > $3::timestamptz - make_interval(secs => $4::int)
+            AND orders.created_at <= $3
+        "#,
+        game_id,
+        user_id,
+        at,
+        HOUR
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(record.drinks)
+}
+
+/// Count the alcoholic drinks of a player after an order
+///
+/// Returns the warning when the player went over the limit, a player is warned once per hour
+/// or once per cooldown.
+#[tracing::instrument(name = "drink_limits::inspect", skip(db))]
+pub async fn inspect(
+    game_id: i64,
+    user_id: i64,
+    db: &Pool<Postgres>,
+) -> Result<Option<DrinkWarning>, ServiceError> {
+    let limit = match DrinkLimit::find(game_id, db).await? {
+        Some(limit) => limit,
+        None => return Ok(None),
+    };
+
+    let drinks = drinks(game_id, user_id, Utc::now(), db).await?;
+    if drinks <= i64::from(limit.max_per_hour) {
+        return Ok(None);
+    }
+
+    let window = limit.cooldown.map(|minutes| minutes * 60).unwrap_or(HOUR);
+    if GameEvent::recently_flagged(game_id, user_id, EventType::DrinkLimitExceeded, window, db)
+        .await?
+    {
+        return Ok(None);
+    }
+
+    let user = User::find(user_id, db).await?;
+    let game = Game::find_by_id(game_id, db).await?;
+
+    let event = GameEvent::create(
+        game_id,
+        Some(user_id),
+        EventType::DrinkLimitExceeded,
+        format!(
+            "{} bought {} alcoholic drinks in the last hour, the limit is {}",
+            user, drinks, limit.max_per_hour
+        ),
+        db,
+    )
+    .await?;
+
+    Ok(Some(DrinkWarning {
+        game_id: GameId(game_id),
+        user_id,
+        username: user.username,
+        drinks,
+        max_per_hour: limit.max_per_hour,
+        blocked_until: blocked_until(Some(event.created_at), limit.cooldown, event.created_at),
+        owner_id: if limit.notify_owner {
+            Some(game.owner_id)
+        } else {
+            None
+        },
+    }))
+}
+
+/// the end of the cooldown after the last time the player exceeded the limit, if it didn't end yet
+fn blocked_until(
+    flagged_at: Option<DateTime<Utc>>,
+    cooldown: Option<i32>,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let blocked_until = flagged_at? + Duration::minutes(i64::from(cooldown?));
+
+    if blocked_until > now {
+        Some(blocked_until)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cooldown() {
+        let now = Utc::now();
+        let flagged_at = now - Duration::minutes(10);
+
+        assert_eq!(
+            blocked_until(Some(flagged_at), Some(30), now),
+            Some(flagged_at + Duration::minutes(30))
+        );
+        assert_eq!(blocked_until(Some(flagged_at), Some(5), now), None);
+        // the limit only warns
+        assert_eq!(blocked_until(Some(flagged_at), None, now), None);
+        assert_eq!(blocked_until(None, Some(30), now), None);
+    }
+}
@@ -0,0 +1,326 @@
+//! Protects the market against users trying to manipulate the prices
+//!
+//! Game owners can configure a cooldown between orders, and every purchase is compared
+//! against the purchase rate of the other players. Users buying a lot more than everyone
+//! else get flagged in the event log of the game, and the game owner gets notified.
+//!
+//! Identical orders placed within a few seconds are rejected as accidental double submissions.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::events::{EventType, GameEvent};
+use crate::games::Game;
+use crate::invitations::State;
+use crate::transactions::models::{NewSale, Transaction};
+use crate::users::User;
+use crate::websocket::server::{GameId, SuspiciousPurchases};
+
+/// the window in seconds used to compare the purchase rate of the players
+const ANOMALY_WINDOW: i32 = 10 * 60;
+/// a purchase rate is suspicious when it's this many standard deviations above the average
+const ANOMALY_THRESHOLD: f64 = 2.0;
+/// don't flag users for just a handful of orders
+const ANOMALY_MIN_ORDERS: i64 = 5;
+/// how long a flagged user stays throttled, in seconds
+const THROTTLE_PERIOD: i32 = 15 * 60;
+/// the minimum amount of seconds between two orders of a throttled user
+const THROTTLE_COOLDOWN: i32 = 60;
+/// identical orders placed within this many seconds are considered a double submission
+const DUPLICATE_WINDOW: i32 = 5;
+
+/// Make sure the user waited long enough since their previous order
+#[tracing::instrument(name = "guard::check_cooldown", skip(db))]
+pub(crate) async fn check_cooldown(
+    game: &Game,
+    user_id: i64,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), ServiceError> {
+    let mut cooldown = game.purchase_cooldown.unwrap_or(0);
+
+    if game.throttle_suspicious_users
+        && GameEvent::recently_flagged(
+            game.id,
+            user_id,
+            EventType::SuspiciousPurchases,
+            THROTTLE_PERIOD,
+            &mut *db,
+        )
+        .await?
+    {
+        cooldown = cooldown.max(THROTTLE_COOLDOWN);
+    }
+
+    if cooldown == 0 {
+        return Ok(());
+    }
+
+    let record = sqlx::query!(
+        r#"SELECT MAX(created_at) as "last_order?" FROM orders WHERE user_id = $1 AND game_id = $2"#,
+        user_id,
+        game.id
+    )
+    .fetch_one(&mut *db)
+    .await?;
+
+    if let Some(remaining) = remaining_cooldown(record.last_order, cooldown, Utc::now()) {
+        return Err(ServiceError::RateLimited(remaining as u64));
+    }
+
+    Ok(())
+}
+
+/// Reject an order that's identical to the previous order of the user, if that one was placed moments ago
+///
+/// The summary row of the user is locked first, so two simultaneous submissions can't both pass
+#[tracing::instrument(name = "guard::check_duplicate", skip(db))]
+pub(crate) async fn check_duplicate(
+    sale: &NewSale,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), ServiceError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sales (game_id, user_id) VALUES ($1, $2)
+        ON CONFLICT (game_id, user_id) DO UPDATE SET sales = user_sales.sales
+        "#,
+        sale.game_id,
+        sale.user_id
+    )
+    .execute(&mut *db)
+    .await?;
+
+    let previous = sqlx::query!(
+        r#"
+        SELECT id FROM orders
+        WHERE user_id = $1
+        AND game_id = $2
+        AND created_at > NOW() - make_interval(secs => $3::int)
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        sale.user_id,
+        sale.game_id,
+        DUPLICATE_WINDOW
+    )
+    .fetch_optional(&mut *db)
+    .await?;
+
+    let order_id = match previous {
+        Some(order) => order.id,
+        None => return Ok(()),
+    };
+
+    let transactions = sqlx::query_as!(
+        Transaction,
+        r#"
+        SELECT transactions.id, transactions.slot_no, transactions.order_id, transactions.amount, transactions.price,
+            transactions.price_history_id, orders.created_at AS ordered_at,
+            COALESCE(transactions.priced_at, games.start_time) AS "priced_at!"
+        FROM transactions
+        INNER JOIN orders ON orders.id = transactions.order_id
+        INNER JOIN games ON games.id = orders.game_id
+        WHERE transactions.order_id = $1
+        ORDER BY transactions.slot_no
+        "#,
+        order_id
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    if is_duplicate(&sale.slots, &transactions) {
+        return Err(ServiceError::DuplicateOrder(transactions));
+    }
+
+    Ok(())
+}
+
+/// an order is a duplicate when it purchases the same amounts of the same beverages
+fn is_duplicate(slots: &HashMap<i16, i32>, previous: &[Transaction]) -> bool {
+    !previous.is_empty()
+        && slots.len() == previous.len()
+        && previous
+            .iter()
+            .all(|transaction| slots.get(&transaction.slot_no) == Some(&transaction.amount))
+}
+
+/// Compare the purchase rate of a user with the other players in the game
+///
+/// Returns the notification for the game owner when the user got flagged
+#[tracing::instrument(name = "guard::inspect", skip(db))]
+pub async fn inspect(
+    game_id: i64,
+    user_id: i64,
+    db: &Pool<Postgres>,
+) -> Result<Option<SuspiciousPurchases>, ServiceError> {
+    let rates = sqlx::query!(
+        r#"
+        SELECT invitations.user_id, COUNT(orders.id) as "orders!"
+        FROM invitations
+        LEFT JOIN orders ON orders.user_id = invitations.user_id
+            AND orders.game_id = invitations.game_id
+            AND orders.created_at > NOW() - make_interval(secs => $2::int)
+        WHERE invitations.game_id = $1 AND invitations.state = $3
+        GROUP BY invitations.user_id
+        "#,
+        game_id,
+        ANOMALY_WINDOW,
+        State::Accepted as _,
+    )
+    .fetch_all(db)
+    .await?;
+
+    let orders = match rates.iter().find(|rate| rate.user_id == user_id) {
+        Some(rate) if rate.orders >= ANOMALY_MIN_ORDERS => rate.orders,
+        _ => return Ok(None),
+    };
+
+    let all_orders: Vec<i64> = rates.iter().map(|rate| rate.orders).collect();
+    let z_score = match z_score(orders, &all_orders) {
+        Some(z_score) if z_score >= ANOMALY_THRESHOLD => z_score,
+        _ => return Ok(None),
+    };
+
+    // the user has already been flagged for this purchasing spree
+    if GameEvent::recently_flagged(
+        game_id,
+        user_id,
+        EventType::SuspiciousPurchases,
+        ANOMALY_WINDOW,
+        db,
+    )
+    .await?
+    {
+        return Ok(None);
+    }
+
+    let user = User::find(user_id, db).await?;
+    let game = Game::find_by_id(game_id, db).await?;
+
+    warn!(
+        "{} is flagged for suspicious purchases in game {}",
+        user, game_id
+    );
+
+    GameEvent::create(
+        game_id,
+        Some(user_id),
+        EventType::SuspiciousPurchases,
+        format!(
+            "{} placed {} orders in the last {} minutes, {:.1} standard deviations above the average",
+            user,
+            orders,
+            ANOMALY_WINDOW / 60,
+            z_score
+        ),
+        db,
+    )
+    .await?;
+
+    Ok(Some(SuspiciousPurchases {
+        game_id: GameId(game_id),
+        owner_id: game.owner_id,
+        user_id,
+        username: user.username,
+        z_score,
+        throttled: game.throttle_suspicious_users,
+    }))
+}
+
+/// returns the remaining seconds of the cooldown, if any
+fn remaining_cooldown(
+    last_order: Option<DateTime<Utc>>,
+    cooldown: i32,
+    now: DateTime<Utc>,
+) -> Option<i64> {
+    let elapsed = (now - last_order?).num_seconds();
+    let remaining = cooldown as i64 - elapsed;
+
+    if remaining > 0 {
+        Some(remaining)
+    } else {
+        None
+    }
+}
+
+/// the amount of standard deviations `value` is above the average of `values`
+fn z_score(value: i64, values: &[i64]) -> Option<f64> {
+    if values.len() < 2 {
+        return None;
+    }
+
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<i64>() as f64 / count;
+    let variance = values
+        .iter()
+        .map(|value| (*value as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    let deviation = variance.sqrt();
+
+    if deviation == 0.0 {
+        return None;
+    }
+
+    Some((value as f64 - mean) / deviation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn z_score_of_purchase_rates() {
+        assert_eq!(z_score(1, &[1]), None);
+        assert_eq!(z_score(3, &[3, 3, 3]), None);
+
+        let rates = [20, 2, 3, 2, 1, 2, 3];
+        assert!(z_score(20, &rates).unwrap() > ANOMALY_THRESHOLD);
+        assert!(z_score(3, &rates).unwrap() < ANOMALY_THRESHOLD);
+    }
+
+    #[test]
+    fn cooldown() {
+        let now = Utc::now();
+
+        assert_eq!(remaining_cooldown(None, 30, now), None);
+        assert_eq!(
+            remaining_cooldown(Some(now - Duration::seconds(10)), 30, now),
+            Some(20)
+        );
+        assert_eq!(
+            remaining_cooldown(Some(now - Duration::seconds(30)), 30, now),
+            None
+        );
+    }
+
+    #[test]
+    fn duplicate_orders() {
+        let transaction = |slot_no: i16, amount: i32| Transaction {
+            id: 1,
+            slot_no,
+            order_id: 1,
+            amount,
+            price: 200,
+            price_history_id: None,
+            ordered_at: Utc::now(),
+            priced_at: Utc::now(),
+        };
+        let previous = vec![transaction(0, 2), transaction(1, 1)];
+
+        let mut slots = HashMap::new();
+        slots.insert(0, 2);
+        slots.insert(1, 1);
+        assert!(is_duplicate(&slots, &previous));
+        assert!(!is_duplicate(&slots, &[]));
+
+        slots.insert(1, 2);
+        assert!(!is_duplicate(&slots, &previous));
+
+        slots.insert(1, 1);
+        slots.insert(2, 1);
+        assert!(!is_duplicate(&slots, &previous));
+    }
+}
@@ -0,0 +1,114 @@
+//! When each beverage was popular
+//!
+//! The purchased amounts are counted per beverage slot and per 10-minute bucket since the start of the game.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::games::Game;
+
+/// the length of a bucket in minutes
+const BUCKET_MINUTES: i64 = 10;
+
+/// A matrix of purchased amounts, `slots[slot].sales[bucket]`
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Heatmap {
+    pub game_id: i64,
+    pub bucket_minutes: i64,
+    /// the start time of every bucket
+    pub buckets: Vec<DateTime<Utc>>,
+    pub slots: Vec<SlotHeat>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotHeat {
+    pub slot_no: i16,
+    /// the purchased amount in every bucket
+    pub sales: Vec<i64>,
+}
+
+impl Heatmap {
+    #[tracing::instrument(name = "Heatmap::load", skip(game, db))]
+    pub async fn load(game: &Game, db: &Pool<Postgres>) -> Result<Heatmap, sqlx::Error> {
+        let bucket_count = bucket_count(game.start_time, game.close_time.min(Utc::now()));
+
+        let records = sqlx::query!(
+            r#"
+            WITH slots AS (
+                SELECT generate_series(0, $2::smallint - 1)::smallint AS slot_no
+            ), buckets AS (
+                SELECT generate_series(0, $4::bigint - 1) AS bucket
+            ), sales AS (
+                SELECT transactions.slot_no,
+                    FLOOR(EXTRACT(EPOCH FROM orders.created_at - $3) / ($5::bigint * 60))::bigint AS bucket,
+                    SUM(transactions.amount)::bigint AS amount
+                FROM transactions
+                INNER JOIN orders ON orders.id = transactions.order_id
+                WHERE orders.game_id = $1 AND orders.created_at >= $3
+                GROUP BY 1, 2
+            )
+            SELECT slots.slot_no AS "slot_no!", COALESCE(sales.amount, 0) AS "amount!"
+            FROM slots
+            CROSS JOIN buckets
+            LEFT JOIN sales ON sales.slot_no = slots.slot_no AND sales.bucket = buckets.bucket
+            ORDER BY slots.slot_no, buckets.bucket
+            "#,
+            game.id,
+            game.beverage_count,
+            game.start_time,
+            bucket_count,
+            BUCKET_MINUTES
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut slots: Vec<SlotHeat> = Vec::with_capacity(game.beverage_count.max(0) as usize);
+        for record in records {
+            match slots.last_mut() {
+                Some(slot) if slot.slot_no == record.slot_no => slot.sales.push(record.amount),
+                _ => slots.push(SlotHeat {
+                    slot_no: record.slot_no,
+                    sales: vec![record.amount],
+                }),
+            }
+        }
+
+        Ok(Heatmap {
+            game_id: game.id,
+            bucket_minutes: BUCKET_MINUTES,
+            buckets: (0..bucket_count)
+                .map(|bucket| game.start_time + Duration::minutes(bucket * BUCKET_MINUTES))
+                .collect(),
+            slots,
+        })
+    }
+}
+
+/// the amount of buckets between the start and the end, the last one can be incomplete
+fn bucket_count(start: DateTime<Utc>, end: DateTime<Utc>) -> i64 {
+    let minutes = (end - start).num_seconds() as f64 / 60.0;
+    if minutes <= 0.0 {
+        return 0;
+    }
+
+    (minutes / BUCKET_MINUTES as f64).ceil() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn count_buckets() {
+        let start = Utc.ymd(2026, 10, 16).and_hms(20, 0, 0);
+
+        assert_eq!(bucket_count(start, start), 0);
+        assert_eq!(bucket_count(start, start - Duration::hours(1)), 0);
+        assert_eq!(bucket_count(start, start + Duration::minutes(10)), 1);
+        assert_eq!(bucket_count(start, start + Duration::minutes(11)), 2);
+        assert_eq!(bucket_count(start, start + Duration::hours(4)), 24);
+    }
+}
@@ -0,0 +1,151 @@
+//! Sales rung up on the till of the bar
+//!
+//! Bars that use a real till import its sales, so they still drive the market.
+//! A batch is a CSV file with `slot_no,amount` lines, or a JSON array of `{ "slotNo", "amount" }` objects.
+//! The whole batch becomes a single order of the game owner, marked as imported, and it's priced
+//! at the current prices of the owner's beverages, like an order placed in the app.
+
+use std::collections::HashMap;
+
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::Game;
+use crate::transactions::models::{NewSale, OrderSource};
+use crate::transactions::Transaction;
+
+const MAX_LINES: usize = 1000;
+const MAX_AMOUNT: i32 = 1000;
+
+/// A line of the till's export
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedSale {
+    pub slot_no: i16,
+    pub amount: i32,
+}
+
+/// Parse a CSV batch, the first line can be a header
+pub fn parse_csv(body: &str) -> Result<Vec<ImportedSale>, ServiceError> {
+    let mut sales = Vec::new();
+
+    for (index, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut columns = line.split(',').map(str::trim);
+        let (slot_no, amount) = match (columns.next(), columns.next(), columns.next()) {
+            (Some(slot_no), Some(amount), None) => (slot_no, amount),
+            _ => bad_request!(format!(
+                "line {}: expected the columns slot_no,amount",
+                index + 1
+            )),
+        };
+
+        match (slot_no.parse(), amount.parse()) {
+            (Ok(slot_no), Ok(amount)) => sales.push(ImportedSale { slot_no, amount }),
+            _ if index == 0 => continue,
+            _ => bad_request!(format!(
+                "line {}: the slot and the amount should be numbers",
+                index + 1
+            )),
+        }
+    }
+
+    Ok(sales)
+}
+
+pub fn parse_json(body: &str) -> Result<Vec<ImportedSale>, ServiceError> {
+    serde_json::from_str(body)
+        .map_err(|e| ServiceError::BadRequest(format!("invalid sales batch: {}", e)))
+}
+
+/// Combine the lines of a batch into a single order of the game owner
+pub fn to_sale(game: &Game, lines: &[ImportedSale]) -> Result<NewSale, ServiceError> {
+    if lines.is_empty() {
+        bad_request!("the batch doesn't contain any sales");
+    }
+    if lines.len() > MAX_LINES {
+        bad_request!(format!("a batch can contain at most {} lines", MAX_LINES));
+    }
+
+    let mut slots: HashMap<i16, i32> = HashMap::new();
+    for (index, line) in lines.iter().enumerate() {
+        if line.slot_no < 0 || line.slot_no >= game.beverage_count {
+            bad_request!(format!(
+                "sale {}: the game doesn't have beverage slot {}",
+                index + 1,
+                line.slot_no
+            ));
+        }
+        if line.amount <= 0 || line.amount > MAX_AMOUNT {
+            bad_request!(format!(
+                "sale {}: the amount should be between 1 and {}",
+                index + 1,
+                MAX_AMOUNT
+            ));
+        }
+
+        *slots.entry(line.slot_no).or_default() += line.amount;
+    }
+
+    Ok(NewSale {
+        user_id: game.owner_id,
+        game_id: game.id,
+        slots,
+        tip: 0,
+    })
+}
+
+/// Insert the batch in a single database transaction, none of the sales are imported when one fails
+///
+/// The purchase limits and cooldowns are meant for the players, so they don't apply to the till.
+#[tracing::instrument(name = "import::save", skip(db))]
+pub async fn save(sale: &NewSale, db: &Pool<Postgres>) -> Result<Vec<Transaction>, ServiceError> {
+    let mut tx = db::begin(Operation::ImportSales, db).await?;
+
+    let game = Game::find_by_id(sale.game_id, &mut *tx).await?;
+    if !game.in_progress() {
+        forbidden!("sales can only be imported while the game is in progress");
+    }
+    if game.points_budget.is_some() {
+        bad_request!("sales can't be imported in a points game");
+    }
+
+    let transactions = sale
+        .insert(&game, OrderSource::Import, None, &mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv_batch() {
+        let sales = parse_csv("slot_no,amount\n0,3\n\n2, 1\n").unwrap();
+        assert_eq!(
+            sales,
+            vec![
+                ImportedSale {
+                    slot_no: 0,
+                    amount: 3
+                },
+                ImportedSale {
+                    slot_no: 2,
+                    amount: 1
+                },
+            ]
+        );
+
+        assert!(parse_csv("0,3\nbeer,1").is_err());
+        assert!(parse_csv("0,3,1").is_err());
+    }
+}
@@ -0,0 +1,148 @@
+//! The gross margin of a game, for the end-of-game report of its owner
+//!
+//! Every player configures their own beverages, so a sale is costed at the cost price
+//! the buyer configured for that slot. Sales of a beverage without a cost price
+//! count towards the revenue, but not towards the margin.
+
+use sqlx::{Pool, Postgres};
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginReport {
+    pub game_id: i64,
+    pub slots: Vec<SlotMargin>,
+    pub total: Margin,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlotMargin {
+    pub slot_no: i16,
+    /// the name the game owner gave the beverage
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub margin: Margin,
+    /// a cost price of the slot is above its minimum price, so it can be sold at a loss
+    pub below_cost: bool,
+}
+
+/// in cents, or points in a points game
+#[derive(Debug, Serialize, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Margin {
+    pub sales: i64,
+    pub revenue: i64,
+    pub cost: i64,
+    pub margin: i64,
+    /// the beverages sold without a cost price
+    pub uncosted_sales: i64,
+}
+
+impl Margin {
+    fn add(mut self, other: &Margin) -> Margin {
+        self.sales += other.sales;
+        self.revenue += other.revenue;
+        self.cost += other.cost;
+        self.margin += other.margin;
+        self.uncosted_sales += other.uncosted_sales;
+        self
+    }
+}
+
+impl MarginReport {
+    #[tracing::instrument(name = "MarginReport::load", skip(db))]
+    pub async fn load(
+        game_id: i64,
+        owner_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<MarginReport, sqlx::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT transactions.slot_no,
+                (
+                    SELECT name FROM beverages
+                    WHERE game_id = $1 AND user_id = $2 AND slot_no = transactions.slot_no
+                ) AS name,
+                SUM(transactions.amount)::BIGINT AS "sales!",
+                SUM(transactions.amount * transactions.price)::BIGINT AS "revenue!",
+                COALESCE(SUM(transactions.amount * beverages.cost_price), 0)::BIGINT AS "cost!",
+                COALESCE(SUM(transactions.amount * (transactions.price - beverages.cost_price)), 0)::BIGINT AS "margin!",
+                COALESCE(SUM(transactions.amount) FILTER (WHERE beverages.cost_price IS NULL), 0)::BIGINT AS "uncosted_sales!",
+                COALESCE(BOOL_OR(beverages.cost_price > beverages.min_price), false) AS "below_cost!"
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            LEFT JOIN beverages ON beverages.game_id = orders.game_id
+                AND beverages.user_id = orders.user_id
+                AND beverages.slot_no = transactions.slot_no
+            WHERE orders.game_id = $1
+            GROUP BY transactions.slot_no
+            ORDER BY transactions.slot_no
+            "#,
+            game_id,
+            owner_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        let slots: Vec<SlotMargin> = rows
+            .into_iter()
+            .map(|row| SlotMargin {
+                slot_no: row.slot_no,
+                name: row.name,
+                margin: Margin {
+                    sales: row.sales,
+                    revenue: row.revenue,
+                    cost: row.cost,
+                    margin: row.margin,
+                    uncosted_sales: row.uncosted_sales,
+                },
+                below_cost: row.below_cost,
+            })
+            .collect();
+
+        Ok(MarginReport {
+            game_id,
+            total: total(&slots),
+            slots,
+        })
+    }
+}
+
+fn total(slots: &[SlotMargin]) -> Margin {
+    slots
+        .iter()
+        .fold(Margin::default(), |total, slot| total.add(&slot.margin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals() {
+        let slot = |slot_no, revenue, cost, margin, uncosted_sales| SlotMargin {
+            slot_no,
+            name: None,
+            margin: Margin {
+                sales: 10,
+                revenue,
+                cost,
+                margin,
+                uncosted_sales,
+            },
+            below_cost: false,
+        };
+
+        assert_eq!(total(&[]), Margin::default());
+        assert_eq!(
+            total(&[slot(0, 2500, 1000, 1500, 0), slot(1, 1500, 0, 0, 10)]),
+            Margin {
+                sales: 20,
+                revenue: 4000,
+                cost: 1000,
+                margin: 1500,
+                uncosted_sales: 10,
+            }
+        );
+    }
+}
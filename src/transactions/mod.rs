@@ -1,4 +1,17 @@
+pub mod crashes;
+pub mod drink_limits;
+pub mod guard;
+pub mod heatmap;
+pub mod import;
+pub mod margins;
 pub mod models;
+pub mod offline;
+pub mod points;
 pub mod routes;
+pub mod search;
+pub mod splits;
+pub mod tickets;
+pub mod tips;
+pub mod voids;
 
 pub use models::Transaction;
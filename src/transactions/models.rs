@@ -3,10 +3,17 @@ use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use sqlx::{Pool, Postgres};
 
+use crate::db::{self, Operation};
 use crate::errors::ServiceError;
+use crate::games::rules::HouseRules;
 use crate::games::{Beverage, Game};
+use crate::invitations::State;
+use crate::market::PriceHistory;
+use crate::transactions::{drink_limits, guard, points, tips};
 
-// TODO: Next migration: remove game_id,created_at & user_id columns from transactions
+/// A line item of an order
+///
+/// Transactions don't have a timestamp of their own, the times are derived from the order and the price history.
 #[derive(Debug, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Transaction {
@@ -15,6 +22,13 @@ pub struct Transaction {
     pub order_id: i64,
     pub amount: i32,
     pub price: i64,
+    /// the price tick this beverage was purchased at,
+    /// empty when the price hasn't changed since the start of the game
+    pub price_history_id: Option<i64>,
+    /// the time at which the order was placed
+    pub ordered_at: DateTime<Utc>,
+    /// the time at which the price was set, the start of the game when the price hasn't changed since
+    pub priced_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Deserialize, Clone, Copy)]
@@ -25,6 +39,30 @@ pub struct Sale {
     pub slot_no: i16,
     pub amount: i32,
     pub price: i64,
+    pub price_history_id: Option<i64>,
+    pub priced_at: Option<DateTime<Utc>>,
+}
+
+/// Where an order was placed
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderSource {
+    /// purchased by a player in the app
+    App,
+    /// rung up on the till of the bar and imported afterwards
+    Import,
+    /// compensates an order that was voided, with the negated amounts
+    Void,
+}
+
+impl OrderSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderSource::App => "app",
+            OrderSource::Import => "import",
+            OrderSource::Void => "void",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -33,6 +71,9 @@ pub struct NewSale {
     pub user_id: i64,
     pub game_id: i64,
     pub slots: HashMap<i16, i32>,
+    /// on top of the beverages, in cents
+    #[serde(default)]
+    pub tip: i64,
 }
 
 /// contains how many sales have been made for a given slot
@@ -47,6 +88,19 @@ pub struct SlotSale {
 pub struct UserSales {
     pub username: String,
     pub sales: i64,
+    /// the total price of the purchased beverages, or points in a points game
+    pub spent: i64,
+}
+
+/// The sales of the players of a team added up
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamSales {
+    pub team_id: i64,
+    pub name: String,
+    pub players: i64,
+    pub sales: i64,
+    pub spent: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -67,38 +121,66 @@ impl NewSale {
         // 3. Calculate the prices for each beverage in the new sale
         // 4. update sales_counts
         // 5. insert in transactions with the current count
-        let mut tx = db.begin().await?;
+        let mut tx = db::begin(Operation::Purchase, db).await?;
 
-        let game = Game::find_by_id(self.game_id, &mut tx).await?;
+        let game = Game::find_by_id(self.game_id, &mut *tx).await?;
 
-        for slot_no in self.slots.keys() {
-            if slot_no > &game.beverage_count || slot_no < &0 {
-                bad_request!("a beverage slot exceeds the maximum configured beverage slots");
-            }
-        }
+        guard::check_duplicate(self, &mut tx).await?;
+        guard::check_cooldown(&game, self.user_id, &mut tx).await?;
+        drink_limits::check(self, &mut tx).await?;
+        HouseRules::check_acknowledged(self.game_id, self.user_id, &mut tx).await?;
+
+        let recent_purchases = match game.quantity_window {
+            Some(window) => self.recent_purchases(window, &mut tx).await?,
+            None => HashMap::new(),
+        };
+        self.validate(&game, &recent_purchases)?;
+
+        let transactions = self.insert(&game, OrderSource::App, None, &mut tx).await?;
+
+        tx.commit().await?;
 
+        Ok(transactions)
+    }
+
+    /// Price the beverages of the order, update the sales counts and insert the order with its transactions
+    ///
+    /// An order that was placed earlier, like an offline sale, is priced at the prices of that moment.
+    pub(crate) async fn insert(
+        &self,
+        game: &Game,
+        source: OrderSource,
+        ordered_at: Option<DateTime<Utc>>,
+        tx: &mut db::Transaction,
+    ) -> Result<Vec<Transaction>, ServiceError> {
         let mut sales: HashMap<i16, Sale> = self.unroll();
         let keys: Vec<i16> = sales.keys().copied().collect();
 
         // Create the order
-        let order_id: i64 = sqlx::query!(
-                "INSERT INTO orders (user_id, game_id) VALUES ($1, $2) RETURNING id",
-                self.user_id, self.game_id
+        let order = sqlx::query!(
+                "INSERT INTO orders (user_id, game_id, source, created_at, tip) VALUES ($1, $2, $3, COALESCE($4, NOW()), $5) RETURNING id, created_at",
+                self.user_id, self.game_id, source.as_str(), ordered_at, self.tip
             )
-            .fetch_one(&mut tx)
-            .await?
-            .id;
+            .fetch_one(&mut **tx)
+            .await?;
 
         // 1
         let beverages = sqlx::query_as!(
             Beverage, 
             "SELECT * FROM beverages WHERE user_id = $1 AND game_id = $2 and slot_no = any($3) FOR UPDATE", 
             self.user_id, self.game_id, &keys)
-            .fetch_all(&mut tx)
+            .fetch_all(&mut **tx)
             .await?;
+        tx.record_rows(beverages.len() as u64);
+
+        let price_ticks = match ordered_at {
+            Some(at) => PriceHistory::at(self.user_id, self.game_id, &keys, at, tx).await?,
+            None => PriceHistory::latest(self.user_id, self.game_id, &keys, tx).await?,
+        };
 
         // 2
-        let mut sales_counts = SalesCount::find_by_game_for_update(self.game_id, &mut tx).await?;
+        let mut sales_counts = SalesCount::find_by_game_for_update(self.game_id, tx).await?;
+        tx.record_rows(sales_counts.len() as u64);
 
         // 3
         for (_, sale) in sales.iter_mut() {
@@ -114,17 +196,31 @@ impl NewSale {
                     error!("a sale was attempted without a pre-existing beverage config");
                     bad_request!("unable to create purchase for beverage without a config");
                 }
+                Some(beverage) if ordered_at.is_some() => {
+                    // the price before the first price change
+                    sale.price = beverage.starting_price;
+                }
                 Some(beverage) => {
                     sale.set_price(beverage);
                 }
             }
+            if let Some(tick) = price_ticks.iter().find(|tick| tick.slot_no == sale.slot_no) {
+                sale.set_price_source(tick);
+                if ordered_at.is_some() {
+                    sale.price = tick.price;
+                }
+            }
         }
 
+        let order_total = sales.values().map(|sale| sale.price * sale.amount as i64).sum();
+        tips::check(self.tip, order_total, game)?;
+        points::check_balance(game, self.user_id, order_total, tx).await?;
+
         // 4
         for sale_count in sales_counts.iter_mut() {
             if let Some(sale) = sales.get(&sale_count.slot_no) {
                 sale_count.sales += sale.amount as i64;
-                sale_count.update(&mut tx).await?;
+                sale_count.update(tx).await?;
             }
         }
 
@@ -132,19 +228,85 @@ impl NewSale {
         let mut transactions: Vec<Transaction> = Vec::new();
 
         for sale in sales.values() {
-            let transaction = sqlx::query_as!(
-                Transaction,
-                "INSERT INTO transactions (slot_no, amount, price, order_id) VALUES ($1, $2, $3, $4) RETURNING *",
-                sale.slot_no, sale.amount, sale.price, order_id
-            ).fetch_one(&mut tx).await?;
-            transactions.push(transaction);
+            let id = sqlx::query!(
+                "INSERT INTO transactions (slot_no, amount, price, order_id, price_history_id, priced_at) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+                sale.slot_no, sale.amount, sale.price, order.id, sale.price_history_id, sale.priced_at
+            ).fetch_one(&mut **tx).await?.id;
+
+            transactions.push(Transaction {
+                id,
+                slot_no: sale.slot_no,
+                order_id: order.id,
+                amount: sale.amount,
+                price: sale.price,
+                price_history_id: sale.price_history_id,
+                ordered_at: order.created_at,
+                priced_at: sale.priced_at.unwrap_or(game.start_time),
+            });
         }
-
-        tx.commit().await?;
+        tx.record_rows(transactions.len() as u64 + 1);
 
         Ok(transactions)
     }
 
+    /// Get the amount of each beverage the user has purchased in the last `window` seconds
+    #[tracing::instrument(name = "NewSale::recent_purchases")]
+    async fn recent_purchases(
+        &self,
+        window: i32,
+        db: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<HashMap<i16, i64>, sqlx::Error> {
+        let records = sqlx::query!(
+            r#"
+            SELECT transactions.slot_no, SUM(transactions.amount) as "amount!"
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            WHERE orders.user_id = $1
+            AND orders.game_id = $2
+            AND orders.created_at > NOW() - make_interval(secs => $3::int)
+            GROUP BY transactions.slot_no
+            "#,
+            self.user_id,
+            self.game_id,
+            window
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(records.into_iter().map(|record| (record.slot_no, record.amount)).collect())
+    }
+
+    /// Make sure the order respects the beverage slots and purchase quantity limits of the game
+    pub(crate) fn validate(&self, game: &Game, recent_purchases: &HashMap<i16, i64>) -> Result<(), ServiceError> {
+        for (slot_no, amount) in &self.slots {
+            if slot_no >= &game.beverage_count || slot_no < &0 {
+                bad_request!("a beverage slot exceeds the maximum configured beverage slots");
+            }
+
+            if *amount < 0 {
+                bad_request!("the amount of a beverage can not be negative");
+            }
+
+            if let Some(max) = game.max_slot_quantity {
+                if *amount > max {
+                    bad_request!(format!("you can order at most {} of the same beverage at once", max));
+                }
+            }
+
+            if let (Some(max), Some(window)) = (game.max_window_quantity, game.quantity_window) {
+                let purchased = recent_purchases.get(slot_no).copied().unwrap_or(0);
+                if purchased + *amount as i64 > max as i64 {
+                    bad_request!(format!(
+                        "you can order at most {} of the same beverage every {} seconds",
+                        max, window
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// turn the map of slots to a map of sales with their slot no as key
     fn unroll(&self) -> HashMap<i16, Sale> {
         let mut sales: HashMap<i16, Sale> = HashMap::new();
@@ -156,6 +318,8 @@ impl NewSale {
                 slot_no: *slot_no,
                 amount: *amount,
                 price: 0,
+                price_history_id: None,
+                priced_at: None,
             };
 
             sales.insert(*slot_no, sale);
@@ -169,6 +333,12 @@ impl Sale {
     fn set_price(&mut self, beverage: &Beverage) {
         self.price = beverage.price();
     }
+
+    /// remember which price tick the price came from
+    fn set_price_source(&mut self, tick: &PriceHistory) {
+        self.price_history_id = Some(tick.id);
+        self.priced_at = Some(tick.created_at);
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -237,10 +407,27 @@ impl Transaction {
         order: &Order,
         db: &Pool<Postgres>,
     ) -> Result<Vec<Transaction>, sqlx::Error> {
-        sqlx::query_as!(Transaction, "SELECT * FROM transactions WHERE order_id = $1 ORDER BY id DESC", order.id).fetch_all(db).await
+        sqlx::query_as!(
+            Transaction,
+            r#"
+            SELECT transactions.id, transactions.slot_no, transactions.order_id, transactions.amount, transactions.price,
+                transactions.price_history_id, orders.created_at AS ordered_at,
+                COALESCE(transactions.priced_at, games.start_time) AS "priced_at!"
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            INNER JOIN games ON games.id = orders.game_id
+            WHERE transactions.order_id = $1
+            ORDER BY transactions.id DESC
+            "#,
+            order.id
+        )
+        .fetch_all(db)
+        .await
     }
 
     /// Get the amount of sales each user has made in a game
+    ///
+    /// The totals are maintained in the `user_sales` table by a trigger on the transactions table
     #[tracing::instrument]
     pub async fn get_sales_per_user(
         game_id: i64,
@@ -249,18 +436,46 @@ impl Transaction {
         sqlx::query_as!(
             UserSales,
             r#"
-            SELECT users.username, SUM(transactions.amount) as "sales!"
-            FROM transactions
-            INNER JOIN orders ON orders.id = transactions.order_id
-            INNER JOIN users ON users.id = orders.user_id
-            WHERE orders.game_id = $1
-            GROUP BY users.username
+            SELECT users.username, user_sales.sales, user_sales.spent
+            FROM user_sales
+            INNER JOIN users ON users.id = user_sales.user_id
+            WHERE user_sales.game_id = $1
             "#,
             game_id
         )
         .fetch_all(db)
         .await
     }
+
+    /// Get the sales of every team in a game, the team that sold most first
+    ///
+    /// Players who haven't bought anything yet still count as players of their team
+    #[tracing::instrument]
+    pub async fn get_sales_per_team(
+        game_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<TeamSales>, sqlx::Error> {
+        sqlx::query_as!(
+            TeamSales,
+            r#"
+            SELECT teams.id AS team_id, teams.name,
+                COUNT(invitations.user_id) AS "players!",
+                COALESCE(SUM(user_sales.sales), 0)::BIGINT AS "sales!",
+                COALESCE(SUM(user_sales.spent), 0)::BIGINT AS "spent!"
+            FROM teams
+            LEFT JOIN invitations ON invitations.team_id = teams.id AND invitations.state = $2
+            LEFT JOIN user_sales ON user_sales.game_id = teams.game_id
+                AND user_sales.user_id = invitations.user_id
+            WHERE teams.game_id = $1
+            GROUP BY teams.id
+            ORDER BY 4 DESC, teams.name
+            "#,
+            game_id,
+            State::Accepted as _
+        )
+        .fetch_all(db)
+        .await
+    }
 }
 
 impl SalesCount {
@@ -314,7 +529,7 @@ impl SalesCount {
     }
 
     #[tracing::instrument(name = "SalesCount::update")]
-    async fn update(&self, db: &mut sqlx::Transaction<'_, Postgres>) -> Result<SalesCount, sqlx::Error> {
+    pub(crate) async fn update(&self, db: &mut sqlx::Transaction<'_, Postgres>) -> Result<SalesCount, sqlx::Error> {
         sqlx::query_as!(
             SalesCount,
             "UPDATE sales_counts SET sales = $1 WHERE game_id = $2 AND slot_no = $3 RETURNING *",
@@ -357,9 +572,42 @@ mod tests {
             user_id: 1,
             game_id: 1,
             slots,
+            tip: 0,
         };
 
         let res = sale.unroll();
         assert_eq!(res.len(), 3);
     }
+
+    #[test]
+    fn purchase_quantity_limits() {
+        let mut slots = HashMap::new();
+        slots.insert(0, 5);
+        slots.insert(1, 1);
+        let sale = NewSale {
+            user_id: 1,
+            game_id: 1,
+            slots,
+            tip: 0,
+        };
+
+        let mut game = Game::fixture();
+
+        let mut recent_purchases = HashMap::new();
+        assert!(sale.validate(&game, &recent_purchases).is_ok());
+
+        game.max_slot_quantity = Some(4);
+        assert!(sale.validate(&game, &recent_purchases).is_err());
+
+        game.max_slot_quantity = Some(5);
+        assert!(sale.validate(&game, &recent_purchases).is_ok());
+
+        game.max_window_quantity = Some(10);
+        game.quantity_window = Some(60);
+        recent_purchases.insert(0, 5);
+        assert!(sale.validate(&game, &recent_purchases).is_ok());
+
+        recent_purchases.insert(0, 6);
+        assert!(sale.validate(&game, &recent_purchases).is_err());
+    }
 }
@@ -0,0 +1,254 @@
+//! Sales a kiosk queued while the venue's WiFi was down
+//!
+//! The kiosk gives every sale an id when it's rung up, and sends the queue once it's back online.
+//! The sales are applied in the order they happened, and priced at the prices of that moment.
+//! A sale that was synced before is skipped, so a kiosk can safely send its queue again
+//! when it didn't receive the answer.
+//! Like the purchases online, the player should be in the game and a sale can't happen during a blackout.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::blackouts::Blackout;
+use crate::games::rules::HouseRules;
+use crate::games::Game;
+use crate::invitations::State;
+use crate::transactions::drink_limits;
+use crate::transactions::models::{NewSale, OrderSource};
+use crate::transactions::Transaction;
+
+const MAX_BATCH: usize = 100;
+/// how far the clock of a kiosk can run ahead
+const MAX_CLOCK_SKEW_SECONDS: i64 = 60;
+
+/// A sale of the kiosk's queue
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueuedSale {
+    /// the UUID the kiosk generated for the sale
+    pub id: String,
+    pub sold_at: DateTime<Utc>,
+    pub slots: HashMap<i16, i32>,
+}
+
+/// The order of a queued sale
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncedSale {
+    pub id: String,
+    pub order_id: i64,
+    /// true when the sale was synced before, its transactions aren't repeated
+    pub duplicate: bool,
+    pub transactions: Vec<Transaction>,
+}
+
+/// a UUID in its usual text form, like `123e4567-e89b-12d3-a456-426614174000`
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip(&[8, 4, 4, 4, 12])
+            .all(|(group, &length)| {
+                group.len() == length && group.chars().all(|c| c.is_ascii_hexdigit())
+            })
+}
+
+/// Check the whole queue before anything is applied
+pub fn validate(game: &Game, sales: &[QueuedSale], now: DateTime<Utc>) -> Result<(), ServiceError> {
+    if sales.is_empty() {
+        bad_request!("the batch doesn't contain any sales");
+    }
+    if sales.len() > MAX_BATCH {
+        bad_request!(format!("a batch can contain at most {} sales", MAX_BATCH));
+    }
+
+    let mut ids = HashSet::new();
+    for (index, sale) in sales.iter().enumerate() {
+        if !is_uuid(&sale.id) {
+            bad_request!(format!("sale {}: the id should be a UUID", index + 1));
+        }
+        if !ids.insert(sale.id.to_lowercase()) {
+            bad_request!(format!("sale {}: the id is used twice", index + 1));
+        }
+        if sale.sold_at < game.start_time || sale.sold_at >= game.close_time {
+            bad_request!(format!(
+                "sale {}: the sale didn't happen while the game was in progress",
+                index + 1
+            ));
+        }
+        if sale.sold_at > now + Duration::seconds(MAX_CLOCK_SKEW_SECONDS) {
+            bad_request!(format!(
+                "sale {}: the sale happened in the future",
+                index + 1
+            ));
+        }
+        if sale.slots.is_empty() {
+            bad_request!(format!(
+                "sale {}: the sale doesn't contain any beverages",
+                index + 1
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the queue in a single database transaction, none of the sales are synced when one fails
+///
+/// The quantity limits apply to every sale on its own, the cooldown doesn't apply
+/// because the sales happened a while ago. The drink limit counts the earlier sales of the queue as well.
+#[tracing::instrument(name = "offline::save", skip(sales, db))]
+pub async fn save(
+    game: &Game,
+    user_id: i64,
+    sales: &mut [QueuedSale],
+    db: &Pool<Postgres>,
+) -> Result<Vec<SyncedSale>, ServiceError> {
+    // the prices and the sales counts are rebuilt in the order the sales happened
+    sales.sort_by_key(|sale| sale.sold_at);
+
+    let mut tx = db::begin(Operation::SyncSales, db).await?;
+
+    // the player can't leave the game while the sales are applied
+    let invitation = sqlx::query!(
+        "SELECT user_id FROM invitations WHERE game_id = $1 AND user_id = $2 AND state = $3 FOR SHARE",
+        game.id,
+        user_id,
+        State::Accepted as _
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if invitation.is_none() {
+        forbidden!("you are not in this game");
+    }
+
+    HouseRules::check_acknowledged(game.id, user_id, &mut tx).await?;
+
+    let mut drinks = drink_limits::Batch::default();
+    let mut synced = Vec::with_capacity(sales.len());
+    for sale in sales.iter() {
+        let id = sale.id.to_lowercase();
+
+        let previous = sqlx::query!(
+            "SELECT order_id FROM offline_sales WHERE user_id = $1 AND client_id = $2",
+            user_id,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(previous) = previous {
+            synced.push(SyncedSale {
+                id,
+                order_id: previous.order_id,
+                duplicate: true,
+                transactions: Vec::new(),
+            });
+            continue;
+        }
+
+        if Blackout::at(game.id, sale.sold_at, &mut *tx)
+            .await?
+            .is_some()
+        {
+            forbidden!(format!(
+                "the game didn't accept purchases at {}, it was in a blackout",
+                sale.sold_at
+            ));
+        }
+
+        let new_sale = NewSale {
+            user_id,
+            game_id: game.id,
+            slots: sale.slots.clone(),
+            tip: 0,
+        };
+        new_sale.validate(game, &HashMap::new())?;
+        drinks.check(&new_sale, sale.sold_at, &mut tx).await?;
+
+        let transactions = new_sale
+            .insert(game, OrderSource::App, Some(sale.sold_at), &mut tx)
+            .await?;
+        drinks.count(&new_sale, sale.sold_at, &mut tx).await?;
+        let order_id = match transactions.first() {
+            Some(transaction) => transaction.order_id,
+            None => bad_request!("the sale doesn't contain any beverages"),
+        };
+
+        sqlx::query!(
+            "INSERT INTO offline_sales (user_id, client_id, order_id) VALUES ($1, $2, $3)",
+            user_id,
+            id,
+            order_id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        synced.push(SyncedSale {
+            id,
+            order_id,
+            duplicate: false,
+            transactions,
+        });
+    }
+
+    tx.commit().await?;
+
+    Ok(synced)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game() -> Game {
+        Game {
+            start_time: Utc::now() - Duration::hours(2),
+            close_time: Utc::now() + Duration::hours(1),
+            beverage_count: 4,
+            ..Game::fixture()
+        }
+    }
+
+    fn sale(id: &str, minutes_ago: i64) -> QueuedSale {
+        QueuedSale {
+            id: id.to_string(),
+            sold_at: Utc::now() - Duration::minutes(minutes_ago),
+            slots: vec![(0, 1)].into_iter().collect(),
+        }
+    }
+
+    #[test]
+    fn uuids() {
+        assert!(is_uuid("123e4567-e89b-12d3-a456-426614174000"));
+        assert!(is_uuid("123E4567-E89B-12D3-A456-426614174000"));
+
+        assert!(!is_uuid("123e4567e89b12d3a456426614174000"));
+        assert!(!is_uuid("123e4567-e89b-12d3-a456-42661417400g"));
+        assert!(!is_uuid("123e4567-e89b-12d3-a456"));
+    }
+
+    #[test]
+    fn queue_validation() {
+        let game = game();
+        let now = Utc::now();
+        let first = "123e4567-e89b-12d3-a456-426614174000";
+        let second = "123e4567-e89b-12d3-a456-426614174001";
+
+        assert!(validate(&game, &[sale(first, 30), sale(second, 10)], now).is_ok());
+
+        assert!(validate(&game, &[], now).is_err());
+        assert!(validate(&game, &[sale("1", 30)], now).is_err());
+        assert!(validate(&game, &[sale(first, 30), sale(first, 10)], now).is_err());
+        // before the game started
+        assert!(validate(&game, &[sale(first, 180)], now).is_err());
+        // the clock of the kiosk runs way ahead
+        assert!(validate(&game, &[sale(first, -10)], now).is_err());
+    }
+}
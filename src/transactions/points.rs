@@ -0,0 +1,156 @@
+//! Games played with points instead of real money
+//!
+//! Every player starts with the points budget of the game and can't spend more than their balance.
+//! The amount each player spent is maintained in the `user_sales` table by a trigger on the transactions.
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+/// The points of a player
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Balance {
+    pub user_id: i64,
+    pub username: String,
+    pub budget: i64,
+    pub spent: i64,
+    pub remaining: i64,
+}
+
+impl Balance {
+    /// returns the balance of a player in a points game
+    #[tracing::instrument(name = "Balance::find", skip(db))]
+    pub async fn find(
+        game: &Game,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Balance, ServiceError> {
+        let budget = Balance::budget(game)?;
+
+        let record = sqlx::query!(
+            r#"
+            SELECT users.id, users.username, COALESCE(user_sales.spent, 0) as "spent!"
+            FROM users
+            LEFT JOIN user_sales ON user_sales.user_id = users.id AND user_sales.game_id = $1
+            WHERE users.id = $2
+            "#,
+            game.id,
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(Balance::new(
+            record.id,
+            record.username,
+            budget,
+            record.spent,
+        ))
+    }
+
+    /// returns the "richest survivors", the players of a points game ordered by their remaining points
+    #[tracing::instrument(name = "Balance::leaderboard", skip(db))]
+    pub async fn leaderboard(
+        game: &Game,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<Balance>, ServiceError> {
+        let budget = Balance::budget(game)?;
+
+        let records = sqlx::query!(
+            r#"
+            SELECT users.id, users.username, COALESCE(user_sales.spent, 0) as "spent!"
+            FROM invitations
+            INNER JOIN users ON users.id = invitations.user_id
+            LEFT JOIN user_sales ON user_sales.user_id = users.id AND user_sales.game_id = invitations.game_id
+            WHERE invitations.game_id = $1 AND invitations.state = 'ACCEPTED'
+            ORDER BY 3, users.username
+            "#,
+            game.id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| Balance::new(record.id, record.username, budget, record.spent))
+            .collect())
+    }
+
+    fn new(user_id: i64, username: String, budget: i64, spent: i64) -> Self {
+        Balance {
+            user_id,
+            username,
+            budget,
+            spent,
+            remaining: budget - spent,
+        }
+    }
+
+    fn budget(game: &Game) -> Result<i64, ServiceError> {
+        match game.points_budget {
+            Some(budget) => Ok(budget),
+            None => bad_request!("this game is not played with points"),
+        }
+    }
+}
+
+/// Make sure the player has enough points left for an order
+///
+/// The summary row of the player is locked until the order is stored, so concurrent orders can't overspend
+#[tracing::instrument(name = "points::check_balance", skip(db))]
+pub(crate) async fn check_balance(
+    game: &Game,
+    user_id: i64,
+    order_total: i64,
+    db: &mut sqlx::Transaction<'_, Postgres>,
+) -> Result<(), ServiceError> {
+    let budget = match game.points_budget {
+        Some(budget) => budget,
+        None => return Ok(()),
+    };
+
+    let spent = sqlx::query!(
+        r#"
+        INSERT INTO user_sales (game_id, user_id) VALUES ($1, $2)
+        ON CONFLICT (game_id, user_id) DO UPDATE SET sales = user_sales.sales
+        RETURNING spent
+        "#,
+        game.id,
+        user_id
+    )
+    .fetch_one(&mut *db)
+    .await?
+    .spent;
+
+    verify_balance(budget, spent, order_total)
+}
+
+fn verify_balance(budget: i64, spent: i64, order_total: i64) -> Result<(), ServiceError> {
+    let remaining = budget - spent;
+
+    if order_total > remaining {
+        bad_request!(format!(
+            "this order costs {} points, you only have {} points left",
+            order_total, remaining
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enforce_balance() {
+        assert!(verify_balance(1000, 0, 1000).is_ok());
+        assert!(verify_balance(1000, 600, 400).is_ok());
+        assert!(verify_balance(1000, 600, 401).is_err());
+        assert!(verify_balance(1000, 1000, 0).is_ok());
+
+        let balance = Balance::new(1, String::from("bart"), 1000, 250);
+        assert_eq!(balance.remaining, 750);
+    }
+}
@@ -1,22 +1,44 @@
 use std::collections::HashMap;
 
-use actix_identity::Identity;
 use actix_web::web;
-use actix_web::web::{Data, Json, Path};
-use actix_web::{get, post};
+use actix_web::web::{Data, HttpResponse, Json, Path, Query};
+use actix_web::{delete, get, post, put, HttpMessage, HttpRequest};
+use chrono::Utc;
 
 use crate::auth;
-use crate::games::Game;
+use crate::auth::Identity;
+use crate::errors::ServiceError;
+use crate::events::DomainEvent;
+use crate::games::active::GameParam;
+use crate::games::devices::Viewer;
+use crate::games::slot_groups::GroupComparison;
+use crate::rate_limit;
 use crate::server;
 use crate::server::State;
+use crate::shutdown;
+use crate::transactions::crashes::{CrashFilter, CrashReport};
+use crate::transactions::drink_limits::{self, DrinkLimit, NewDrinkLimit};
+use crate::transactions::guard;
+use crate::transactions::heatmap::Heatmap;
+use crate::transactions::import;
+use crate::transactions::margins::MarginReport;
 use crate::transactions::models::{NewSale, SalesCount, Transaction};
-use crate::websocket::{server::GameId, Notification, Sale};
+use crate::transactions::offline::{self, QueuedSale};
+use crate::transactions::points::Balance;
+use crate::transactions::search::{OrderFilter, OrderPage};
+use crate::transactions::splits::{self, OrderSplit, SplitResponse};
+use crate::transactions::tickets::Ticket;
+use crate::transactions::tips::TipSummary;
+use crate::transactions::voids;
+use crate::users::User;
+use crate::validator::Validator;
+use crate::websocket::{server::GameId, Sale};
 
 /// Get the total amount of sold beverages
 #[get("/games/{id}/sales/beverages")]
-async fn get_sales(game_id: Path<i64>, id: Identity, state: Data<State>) -> server::Response {
+async fn get_sales(game: Path<GameParam>, id: Identity, state: Data<State>) -> server::Response {
     let user = auth::get_user(&id)?;
-    let game_id = game_id.into_inner();
+    let game_id = game.resolve(&id)?;
 
     let sales = Transaction::orders(user.id, game_id, &state.db).await?;
 
@@ -27,78 +49,713 @@ async fn get_sales(game_id: Path<i64>, id: Identity, state: Data<State>) -> serv
 /// 1 order can contain multiple beverages
 #[get("/games/{id}/sales/orders")]
 async fn get_order_beverages(
-    path: Path<i64>,
+    path: Path<GameParam>,
     id: Identity,
     state: Data<State>,
 ) -> server::Response {
     let user = auth::get_user(&id)?;
-    let game_id = path.into_inner();
+    let game_id = path.resolve(&id)?;
     let sales = Transaction::orders(user.id, game_id, &state.db).await?;
     http_ok_json!(sales);
 }
 
-#[post("/games/{id}/sales")]
+#[derive(Deserialize)]
+struct SaleOptions {
+    /// on top of the beverages, in cents
+    #[serde(default)]
+    tip: i64,
+}
+
+/// Purchase beverages, `?tip=` adds a tip to the order
+#[post("/games/{id}/sales", wrap = "crate::rate_limit::PURCHASE")]
 async fn create_sale(
-    game_id: Path<i64>,
+    game: Path<GameParam>,
     slots: Json<HashMap<i16, i32>>,
+    options: Query<SaleOptions>,
     id: Identity,
     state: Data<State>,
 ) -> server::Response {
     let user = auth::get_user(&id)?;
-    let game_id = game_id.into_inner();
 
-    let user_id = user.id;
+    let sale = NewSale {
+        user_id: user.id,
+        game_id: game.resolve(&id)?,
+        slots: slots.into_inner(),
+        tip: options.tip,
+    };
 
-    if !Game::available_for_purchases(game_id, user_id, &state.db).await? {
-        forbidden!("game is not available for purchases");
+    let transactions = purchase(&sale, &user, &state).await?;
+
+    http_created_json!(transactions);
+}
+
+/// Sync the sales a kiosk queued while it was offline, the sales that were synced before are skipped
+#[post("/games/{id}/sales/batch")]
+async fn sync_sales(
+    game: Path<GameParam>,
+    sales: Json<Vec<QueuedSale>>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+    shutdown::accept_sales()?;
+
+    let game = state.games.find_by_id(game_id).await?;
+
+    let mut sales = sales.into_inner();
+    offline::validate(&game, &sales, Utc::now())?;
+
+    let synced = offline::save(&game, user.id, &mut sales, &state.db).await?;
+
+    for sale in synced.iter().filter(|sale| !sale.duplicate) {
+        state.events.publish(DomainEvent::SaleCreated(Sale {
+            game_id: GameId(game.id),
+            transactions: sale.transactions.clone(),
+        }));
+    }
+
+    http_ok_json!(synced);
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SplitSale {
+    slots: HashMap<i16, i32>,
+    co_payers: Vec<i64>,
+}
+
+#[derive(Serialize)]
+struct SplitOrder {
+    transactions: Vec<Transaction>,
+    splits: Vec<OrderSplit>,
+}
+
+/// Purchase beverages and ask the co-payers to each pay an equal share of the order
+#[post("/games/{id}/sales/split", wrap = "crate::rate_limit::PURCHASE")]
+async fn create_split_sale(
+    game: Path<GameParam>,
+    body: Json<SplitSale>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+    let body = body.into_inner();
+
+    splits::validate_co_payers(user.id, &body.co_payers)?;
+    for co_payer in &body.co_payers {
+        if !state
+            .games
+            .verify_user_participation(game_id, *co_payer)
+            .await?
+        {
+            bad_request!("every co-payer should be participating in the game");
+        }
     }
 
     let sale = NewSale {
         user_id: user.id,
         game_id,
-        slots: slots.into_inner(),
+        slots: body.slots,
+        tip: 0,
     };
 
-    let transactions = sale.save(&state.db).await?;
+    let transactions = purchase(&sale, &user, &state).await?;
+
+    let splits =
+        OrderSplit::create(user.id, game_id, &transactions, &body.co_payers, &state.db).await?;
+    for split in &splits {
+        state
+            .events
+            .publish(DomainEvent::SplitRequested(split.clone()));
+    }
+    splits::schedule_expiration(splits.clone(), state.db.clone(), state.events.clone());
+
+    http_created_json!(SplitOrder {
+        transactions,
+        splits
+    });
+}
+
+/// Get the order splits the user is asked to pay
+#[get("/games/{id}/splits")]
+async fn get_splits(game: Path<GameParam>, id: Identity, state: Data<State>) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    let splits = OrderSplit::find_by_user(game_id, user.id, &state.db).await?;
+
+    http_ok_json!(splits);
+}
+
+/// Accept or decline a share of an order
+#[put("/games/{id}/splits/{split_id}")]
+async fn respond_split(
+    path: Path<(GameParam, i64)>,
+    response: Json<SplitResponse>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game, split_id) = path.into_inner();
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+
+    let split = OrderSplit::respond(split_id, &game, user.id, response.accepted, &state.db).await?;
+
+    state
+        .events
+        .publish(DomainEvent::SplitResolved(split.clone()));
+
+    http_ok_json!(split);
+}
+
+/// The maximum amount of alcoholic drinks a player buys per hour, `null` when the game has no limit
+#[get("/games/{id}/drink-limit")]
+async fn find_drink_limit(
+    game: Path<GameParam>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    if !user.is_admin
+        && !state
+            .games
+            .verify_user_participation(game_id, user.id)
+            .await?
+    {
+        forbidden!("you are not in this game");
+    }
+
+    let limit = DrinkLimit::find(game_id, &state.db).await?;
+
+    http_ok_json!(limit);
+}
+
+#[put("/games/{id}/drink-limit")]
+async fn save_drink_limit(
+    game: Path<GameParam>,
+    limit: Json<Validator<NewDrinkLimit>>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let limit = limit.into_inner().validate()?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can change the drink limit");
+    }
+
+    let limit = DrinkLimit::save(game.id, &limit, &state.db).await?;
 
-    if let Err(e) = state
-        .notifier
-        .send(Notification::NewSale(Sale {
-            game_id: GameId(game_id),
-            transactions: transactions.clone(),
-        }))
-        .await
+    http_ok_json!(limit);
+}
+
+/// store the sale and notify the other players
+pub(crate) async fn purchase(
+    sale: &NewSale,
+    user: &User,
+    state: &State,
+) -> Result<Vec<Transaction>, ServiceError> {
+    shutdown::accept_sales()?;
+    rate_limit::purchase(user, state).await?;
+
+    if !state
+        .games
+        .available_for_purchases(sale.game_id, user.id)
+        .await?
     {
-        error!("unable to notify users about transaction: {}", e);
+        forbidden!("game is not available for purchases");
+    }
+
+    let transactions = state.sales.save(sale).await?;
+
+    state.events.publish(DomainEvent::SaleCreated(Sale {
+        game_id: GameId(sale.game_id),
+        transactions: transactions.clone(),
+    }));
+    if let Some(transaction) = transactions.first() {
+        state.events.publish(DomainEvent::OrderPlaced {
+            game_id: GameId(sale.game_id),
+            order_id: transaction.order_id,
+        });
+    }
+
+    match guard::inspect(sale.game_id, user.id, &state.db).await {
+        Ok(Some(suspicion)) => state
+            .events
+            .publish(DomainEvent::SuspiciousPurchases(suspicion)),
+        Ok(None) => (),
+        Err(e) => error!("unable to inspect the purchases of {}: {}", user, e),
+    }
+
+    match drink_limits::inspect(sale.game_id, user.id, &state.db).await {
+        Ok(Some(warning)) => state
+            .events
+            .publish(DomainEvent::DrinkLimitExceeded(warning)),
+        Ok(None) => (),
+        Err(e) => error!("unable to count the drinks of {}: {}", user, e),
+    }
+
+    Ok(transactions)
+}
+
+/// Void an order that was placed by mistake, the order is compensated by a void order with the negated amounts
+#[delete("/games/{id}/sales/orders/{order_id}")]
+async fn void_order(
+    path: Path<(GameParam, i64)>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let (game, order_id) = path.into_inner();
+    shutdown::accept_sales()?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.in_progress() {
+        forbidden!("orders can only be voided while the game is in progress");
+    }
+
+    let transactions = voids::void(order_id, &game, &user, &state.db).await?;
+
+    state.events.publish(DomainEvent::SaleVoided(Sale {
+        game_id: GameId(game.id),
+        transactions: transactions.clone(),
+    }));
+
+    http_ok_json!(transactions);
+}
+
+/// Import the sales rung up on the till of the bar, as CSV or as JSON
+#[post("/games/{id}/sales/import")]
+async fn import_sales(
+    game: Path<GameParam>,
+    req: HttpRequest,
+    body: String,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can import sales");
     }
+    shutdown::accept_sales()?;
+
+    let lines = match req.content_type() {
+        "text/csv" => import::parse_csv(&body)?,
+        _ => import::parse_json(&body)?,
+    };
+    let sale = import::to_sale(&game, &lines)?;
+    let transactions = import::save(&sale, &state.db).await?;
+
+    state.events.publish(DomainEvent::SaleCreated(Sale {
+        game_id: GameId(game.id),
+        transactions: transactions.clone(),
+    }));
 
     http_created_json!(transactions);
 }
 
+/// Search the orders of a game, only available for the game owner and administrators
+#[get("/games/{id}/orders")]
+async fn search_orders(
+    game: Path<GameParam>,
+    filter: Query<OrderFilter>,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if game.owner_id != user.id && !user.is_admin {
+        forbidden!("only the game owner can search the orders");
+    }
+
+    let orders = OrderPage::search(game.id, &filter, &state.db).await?;
+
+    http_ok_json!(orders);
+}
+
+/// The ESC/POS ticket of an order, for the printer bridge of the game
+///
+/// The player who ordered and the game owner can print it as well.
+#[get("/orders/{id}/ticket")]
+async fn ticket(
+    order_id: Path<i64>,
+    req: HttpRequest,
+    id: Identity,
+    state: Data<State>,
+) -> server::Response {
+    let owner = Ticket::owner(*order_id, &state.db).await?;
+
+    match Viewer::identify(owner.game_id, &req, &id, &state.db).await? {
+        Viewer::Device(device) if !device.is_printer() => {
+            forbidden!("only printers can print the tickets")
+        }
+        Viewer::Device(_) => (),
+        Viewer::User(user) => {
+            let game = state.games.find_by_id(owner.game_id).await?;
+            if user.id != owner.user_id && !game.is_owner(&user) {
+                forbidden!("this is not your order");
+            }
+        }
+    }
+
+    let ticket = Ticket::load(*order_id, &state.db).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .header(
+            "Content-Disposition",
+            format!("inline; filename=\"order-{}.bin\"", ticket.order_id),
+        )
+        .body(ticket.render()))
+}
+
+/// Get the remaining points of the user in a points game
+#[get("/games/{id}/sales/balance")]
+async fn balance(game: Path<GameParam>, id: Identity, state: Data<State>) -> server::Response {
+    let user = auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    if !state
+        .games
+        .verify_user_participation(game_id, user.id)
+        .await?
+    {
+        forbidden!("you are not in this game");
+    }
+    let game = state.games.find_by_id(game_id).await?;
+
+    let balance = Balance::find(&game, user.id, &state.db).await?;
+
+    http_ok_json!(balance);
+}
+
+/// The players of a points game ordered by their remaining points
+#[get("/games/{id}/stats/balances")]
+async fn balances(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+
+    let balances = Balance::leaderboard(&game, &state.db).await?;
+
+    http_ok_json!(balances);
+}
+
 #[get("/games/{id}/stats/sales")]
-async fn beverage_sales(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+async fn beverage_sales(
+    game: Path<GameParam>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
     auth::get_user(&id)?;
-    let game_id = game_id.into_inner();
+    let game_id = game.resolve(&id)?;
 
     let sales = SalesCount::find_by_game(game_id, &state.db).await?;
 
     http_ok_json!(sales);
 }
 
+/// The purchased amount of every beverage per 10 minutes
+#[get("/games/{id}/stats/heatmap")]
+async fn heatmap(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+
+    let heatmap = Heatmap::load(&game, &state.db).await?;
+
+    http_ok_json!(heatmap);
+}
+
+/// The purchases right after every stock market crash compared to right before it
+#[get("/games/{id}/stats/crashes")]
+async fn crash_effects(
+    game: Path<GameParam>,
+    filter: Query<CrashFilter>,
+    state: Data<State>,
+    id: Identity,
+) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the effect of the crashes");
+    }
+
+    let report = CrashReport::load(game.id, &filter, &state.db).await?;
+
+    http_ok_json!(report);
+}
+
 #[get("/games/{id}/stats/users")]
-async fn user_sales(game_id: Path<i64>, state: Data<State>, id: Identity) -> server::Response {
+async fn user_sales(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
     auth::get_user(&id)?;
-    let game_id = game_id.into_inner();
+    let game_id = game.resolve(&id)?;
 
     let sales = Transaction::get_sales_per_user(game_id, &state.db).await?;
 
     http_ok_json!(sales);
 }
 
+/// The tips of a game, per player
+#[get("/games/{id}/stats/tips")]
+async fn tips(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the tips");
+    }
+
+    let tips = TipSummary::load(game.id, &state.db).await?;
+
+    http_ok_json!(tips);
+}
+
+/// The gross margin of a game, per beverage slot
+#[get("/games/{id}/stats/margins")]
+async fn margins(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can see the margins");
+    }
+
+    let report = MarginReport::load(game.id, game.owner_id, &state.db).await?;
+
+    http_ok_json!(report);
+}
+
+/// The revenue and the volatility of every slot group, compared with the slots without a group
+#[get("/games/{id}/stats/slot-groups")]
+async fn slot_groups(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    let user = auth::get_user(&id)?;
+
+    let game = state.games.find_by_id(game.resolve(&id)?).await?;
+    if !game.is_owner(&user) {
+        forbidden!("only the game owner can compare the slot groups");
+    }
+
+    let comparison = GroupComparison::load(&game, &state.db).await?;
+
+    http_ok_json!(comparison);
+}
+
+/// The scoreboard of the teams of a game
+#[get("/games/{id}/stats/teams")]
+async fn team_sales(game: Path<GameParam>, state: Data<State>, id: Identity) -> server::Response {
+    auth::get_user(&id)?;
+    let game_id = game.resolve(&id)?;
+
+    let sales = Transaction::get_sales_per_team(game_id, &state.db).await?;
+
+    http_ok_json!(sales);
+}
+
 pub fn register(cfg: &mut web::ServiceConfig) {
     cfg.service(get_sales);
     cfg.service(get_order_beverages);
     cfg.service(create_sale);
+    cfg.service(create_split_sale);
+    cfg.service(sync_sales);
+    cfg.service(import_sales);
+    cfg.service(void_order);
+    cfg.service(get_splits);
+    cfg.service(respond_split);
+    cfg.service(search_orders);
+    cfg.service(ticket);
     cfg.service(beverage_sales);
     cfg.service(user_sales);
+    cfg.service(team_sales);
+    cfg.service(tips);
+    cfg.service(margins);
+    cfg.service(slot_groups);
+    cfg.service(find_drink_limit);
+    cfg.service(save_drink_limit);
+    cfg.service(heatmap);
+    cfg.service(crash_effects);
+    cfg.service(balance);
+    cfg.service(balances);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use actix::Actor;
+    use actix_identity::{CookieIdentityPolicy, IdentityService, RequestIdentity};
+    use actix_service::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{App, HttpMessage, HttpResponse};
+    use chrono::{Duration, Utc};
+    use sqlx::pool::PoolOptions;
+    use sqlx::Postgres;
+
+    use crate::auth::backends::PasswordBackend;
+    use crate::cache::CacheHandle;
+    use crate::events::EventBus;
+    use crate::games::{Beverage, Game};
+    use crate::http::HttpClient;
+    use crate::rate_limit::{RateLimits, TrustedProxies};
+    use crate::repositories::memory::MemoryRepo;
+    use crate::websocket::server::NotificationServer;
+
+    const COOKIE_KEY_MASTER: [u8; 32] = [0; 32];
+
+    fn state(repo: Arc<MemoryRepo>) -> State {
+        // nothing listens on this port, the purchase guard will log an error instead
+        let db = PoolOptions::<Postgres>::new()
+            .connect_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/rustfuif")
+            .unwrap();
+
+        State {
+            notifier: NotificationServer::new(db.clone()).start(),
+            db,
+            events: EventBus::new(),
+            games: repo.clone(),
+            sales: repo,
+            http: HttpClient::new(
+                std::time::Duration::from_secs(1),
+                std::time::Duration::from_secs(1),
+            )
+            .unwrap(),
+            auth: Arc::new(PasswordBackend),
+            cache: CacheHandle::builder().memory().build(),
+            rate_limits: RateLimits::default(),
+            trusted_proxies: TrustedProxies::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn purchase_limits() {
+        let repo = Arc::new(MemoryRepo::default());
+        repo.add_game(Game {
+            start_time: Utc::now() - Duration::hours(1),
+            close_time: Utc::now() + Duration::hours(1),
+            beverage_count: 2,
+            max_slot_quantity: Some(2),
+            ..Game::fixture()
+        });
+        repo.add_beverage(Beverage {
+            game_id: 1,
+            user_id: 1,
+            slot_no: 0,
+            name: String::from("beer"),
+            image_url: None,
+            min_price: 100,
+            max_price: 300,
+            starting_price: 200,
+            current_price: 200,
+            alcoholic: true,
+            cost_price: None,
+        });
+
+        let mut srv = test::init_service(
+            App::new()
+                .data(state(repo.clone()))
+                // instead of the session middleware, the cookie holds the id of the user
+                .wrap_fn(|req, srv| {
+                    if let Some(user_id) = req.get_identity().and_then(|id| id.parse().ok()) {
+                        req.extensions_mut().insert(User {
+                            id: user_id,
+                            is_admin: false,
+                            username: "user".to_string(),
+                            password: "user".to_string(),
+                            created_at: None,
+                            updated_at: None,
+                        });
+                    }
+                    srv.call(req)
+                })
+                .wrap(IdentityService::new(
+                    CookieIdentityPolicy::new(&COOKIE_KEY_MASTER).secure(false),
+                ))
+                .service(create_sale)
+                .service(
+                    web::resource("/login/{id}").to(|id: Identity, user_id: Path<i64>| {
+                        id.remember(user_id.to_string());
+                        HttpResponse::Ok()
+                    }),
+                ),
+        )
+        .await;
+
+        let resp =
+            test::call_service(&mut srv, TestRequest::with_uri("/login/1").to_request()).await;
+        let cookie = resp.response().cookies().next().unwrap().to_owned();
+
+        let purchase = |amount: i32| {
+            let mut slots = HashMap::new();
+            slots.insert(0, amount);
+            TestRequest::post()
+                .uri("/games/1/sales")
+                .cookie(cookie.clone())
+                .set_json(&slots)
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut srv, purchase(3)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(repo.transactions().is_empty());
+
+        let resp = test::call_service(&mut srv, purchase(2)).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(repo.transactions().len(), 1);
+
+        // users who aren't participating can't purchase anything
+        let resp =
+            test::call_service(&mut srv, TestRequest::with_uri("/login/2").to_request()).await;
+        let cookie = resp.response().cookies().next().unwrap().to_owned();
+
+        let mut slots = HashMap::new();
+        slots.insert(0, 1);
+        let resp = test::call_service(
+            &mut srv,
+            TestRequest::post()
+                .uri("/games/1/sales")
+                .cookie(cookie)
+                .set_json(&slots)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // the orders over the websocket skip the middleware, `purchase` takes from the bucket of the user
+        let mut limited = state(repo.clone());
+        limited.rate_limits.purchase_per_user = Some(1);
+        let user = User {
+            id: 1,
+            is_admin: false,
+            username: "user".to_string(),
+            password: "user".to_string(),
+            created_at: None,
+            updated_at: None,
+        };
+        let sale = NewSale {
+            user_id: 1,
+            game_id: 1,
+            slots,
+            tip: 0,
+        };
+
+        assert!(super::purchase(&sale, &user, &limited).await.is_ok());
+        assert!(matches!(
+            super::purchase(&sale, &user, &limited).await,
+            Err(ServiceError::RateLimited(_))
+        ));
+        assert_eq!(repo.transactions().len(), 2);
+    }
 }
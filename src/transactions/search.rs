@@ -0,0 +1,321 @@
+//! Order search for the bar-side tablet of the game owner
+//!
+//! The status of an order is derived from its splits,
+//! an order is awaiting co-payers as long as one of its splits is pending.
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::transactions::Transaction;
+
+/// The maximum amount of orders returned at once
+const MAX_LIMIT: i64 = 100;
+const DEFAULT_LIMIT: i64 = 25;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum OrderStatus {
+    /// the order is fully paid
+    Settled,
+    /// some co-payers haven't accepted their share of the order yet
+    AwaitingCoPayers,
+}
+
+impl OrderStatus {
+    fn from_pending_splits(awaiting_co_payers: bool) -> Self {
+        if awaiting_co_payers {
+            OrderStatus::AwaitingCoPayers
+        } else {
+            OrderStatus::Settled
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderFilter {
+    pub user_id: Option<i64>,
+    pub status: Option<OrderStatus>,
+    /// only return orders containing this beverage slot
+    pub slot_no: Option<i16>,
+    /// only return orders placed at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// only return orders placed before this time
+    pub until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub offset: i64,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    DEFAULT_LIMIT
+}
+
+impl OrderFilter {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.limit < 1 || self.limit > MAX_LIMIT {
+            bad_request!(format!("the limit should be between 1 and {}", MAX_LIMIT));
+        }
+
+        if self.offset < 0 {
+            bad_request!("the offset can't be negative");
+        }
+
+        if let (Some(from), Some(until)) = (self.from, self.until) {
+            if from >= until {
+                bad_request!("the start of the time range should be before the end");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An order as shown to the bartenders
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderSummary {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    /// `app` or `import`, imported orders were rung up on the till of the bar
+    pub source: String,
+    pub status: OrderStatus,
+    /// the price of the beverages, the tip isn't included
+    pub total_price: i64,
+    pub tip: i64,
+    pub items: Vec<Transaction>,
+}
+
+/// The amount of orders matching the filters, per status
+///
+/// The status filter is ignored for these counts
+#[derive(Debug, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusCounts {
+    pub settled: i64,
+    pub awaiting_co_payers: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub offset: i64,
+    pub limit: i64,
+    /// the amount of orders matching all filters
+    pub total: i64,
+    pub status_counts: StatusCounts,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderPage {
+    pub orders: Vec<OrderSummary>,
+    pub meta: Meta,
+}
+
+impl OrderPage {
+    /// search the orders of a game, most recent first
+    #[tracing::instrument(name = "OrderPage::search", skip(db))]
+    pub async fn search(
+        game_id: i64,
+        filter: &OrderFilter,
+        db: &Pool<Postgres>,
+    ) -> Result<OrderPage, ServiceError> {
+        filter.validate()?;
+
+        let awaiting_co_payers = filter
+            .status
+            .map(|status| status == OrderStatus::AwaitingCoPayers);
+
+        let counts = sqlx::query!(
+            r#"
+            WITH filtered AS (
+                SELECT EXISTS(
+                    SELECT 1 FROM order_splits
+                    WHERE order_splits.order_id = orders.id AND order_splits.state = 'PENDING'
+                ) as awaiting_co_payers
+                FROM orders
+                WHERE orders.game_id = $1
+                AND ($2::bigint IS NULL OR orders.user_id = $2)
+                AND ($3::smallint IS NULL OR EXISTS(
+                    SELECT 1 FROM transactions
+                    WHERE transactions.order_id = orders.id AND transactions.slot_no = $3
+                ))
+                AND ($4::timestamptz IS NULL OR orders.created_at >= $4)
+                AND ($5::timestamptz IS NULL OR orders.created_at < $5)
+            )
+            SELECT
+                COUNT(*) FILTER (WHERE NOT awaiting_co_payers) as "settled!",
+                COUNT(*) FILTER (WHERE awaiting_co_payers) as "awaiting_co_payers!"
+            FROM filtered
+            "#,
+            game_id,
+            filter.user_id,
+            filter.slot_no,
+            filter.from,
+            filter.until
+        )
+        .fetch_one(db)
+        .await?;
+
+        let status_counts = StatusCounts {
+            settled: counts.settled,
+            awaiting_co_payers: counts.awaiting_co_payers,
+        };
+
+        let records = sqlx::query!(
+            r#"
+            WITH filtered AS (
+                SELECT orders.id, orders.user_id, orders.created_at, orders.source, orders.tip, EXISTS(
+                    SELECT 1 FROM order_splits
+                    WHERE order_splits.order_id = orders.id AND order_splits.state = 'PENDING'
+                ) as awaiting_co_payers
+                FROM orders
+                WHERE orders.game_id = $1
+                AND ($2::bigint IS NULL OR orders.user_id = $2)
+                AND ($3::smallint IS NULL OR EXISTS(
+                    SELECT 1 FROM transactions
+                    WHERE transactions.order_id = orders.id AND transactions.slot_no = $3
+                ))
+                AND ($4::timestamptz IS NULL OR orders.created_at >= $4)
+                AND ($5::timestamptz IS NULL OR orders.created_at < $5)
+            )
+            SELECT filtered.id as "id!", filtered.user_id as "user_id!", users.username,
+                filtered.created_at as "created_at!", filtered.source as "source!", filtered.tip as "tip!",
+                filtered.awaiting_co_payers as "awaiting_co_payers!"
+            FROM filtered
+            INNER JOIN users ON users.id = filtered.user_id
+            WHERE ($6::bool IS NULL OR filtered.awaiting_co_payers = $6)
+            ORDER BY filtered.created_at DESC, filtered.id DESC
+            LIMIT $7 OFFSET $8
+            "#,
+            game_id,
+            filter.user_id,
+            filter.slot_no,
+            filter.from,
+            filter.until,
+            awaiting_co_payers,
+            filter.limit,
+            filter.offset
+        )
+        .fetch_all(db)
+        .await?;
+
+        let order_ids: Vec<i64> = records.iter().map(|record| record.id).collect();
+        let mut items: HashMap<i64, Vec<Transaction>> = HashMap::new();
+        for transaction in sqlx::query_as!(
+            Transaction,
+            r#"
+            SELECT transactions.id, transactions.slot_no, transactions.order_id, transactions.amount, transactions.price,
+                transactions.price_history_id, orders.created_at AS ordered_at,
+                COALESCE(transactions.priced_at, games.start_time) AS "priced_at!"
+            FROM transactions
+            INNER JOIN orders ON orders.id = transactions.order_id
+            INNER JOIN games ON games.id = orders.game_id
+            WHERE transactions.order_id = ANY($1)
+            ORDER BY transactions.id DESC
+            "#,
+            &order_ids
+        )
+        .fetch_all(db)
+        .await?
+        {
+            items
+                .entry(transaction.order_id)
+                .or_default()
+                .push(transaction);
+        }
+
+        let orders = records
+            .into_iter()
+            .map(|record| {
+                let items = items.remove(&record.id).unwrap_or_default();
+                OrderSummary {
+                    id: record.id,
+                    user_id: record.user_id,
+                    username: record.username,
+                    created_at: record.created_at,
+                    source: record.source,
+                    status: OrderStatus::from_pending_splits(record.awaiting_co_payers),
+                    total_price: items
+                        .iter()
+                        .map(|item| item.price * item.amount as i64)
+                        .sum(),
+                    tip: record.tip,
+                    items,
+                }
+            })
+            .collect();
+
+        Ok(OrderPage {
+            orders,
+            meta: Meta {
+                offset: filter.offset,
+                limit: filter.limit,
+                total: status_counts.total(filter.status),
+                status_counts,
+            },
+        })
+    }
+}
+
+impl StatusCounts {
+    fn total(&self, status: Option<OrderStatus>) -> i64 {
+        match status {
+            Some(OrderStatus::Settled) => self.settled,
+            Some(OrderStatus::AwaitingCoPayers) => self.awaiting_co_payers,
+            None => self.settled + self.awaiting_co_payers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> OrderFilter {
+        OrderFilter {
+            user_id: None,
+            status: None,
+            slot_no: None,
+            from: None,
+            until: None,
+            offset: 0,
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    #[test]
+    fn validate_filter() {
+        assert!(filter().validate().is_ok());
+
+        let mut invalid = filter();
+        invalid.limit = MAX_LIMIT + 1;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = filter();
+        invalid.offset = -1;
+        assert!(invalid.validate().is_err());
+
+        let mut invalid = filter();
+        invalid.from = Some(Utc::now());
+        invalid.until = Some(Utc::now() - chrono::Duration::hours(1));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn count_orders_per_status() {
+        let counts = StatusCounts {
+            settled: 5,
+            awaiting_co_payers: 2,
+        };
+
+        assert_eq!(counts.total(None), 7);
+        assert_eq!(counts.total(Some(OrderStatus::Settled)), 5);
+        assert_eq!(counts.total(Some(OrderStatus::AwaitingCoPayers)), 2);
+    }
+}
@@ -0,0 +1,365 @@
+//! Split the payment of an order across multiple players
+//!
+//! The purchaser selects the co-payers, who each get asked over the websocket to accept an equal share.
+//! Shares that are declined, or not accepted within the timeout, are paid by the purchaser.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+use crate::games::Game;
+use crate::transactions::points;
+use crate::transactions::Transaction;
+
+/// How long a co-payer has to accept their share
+pub const SPLIT_TIMEOUT: Duration = Duration::from_secs(120);
+/// The maximum amount of co-payers of one order
+const MAX_CO_PAYERS: usize = 8;
+
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "split_state", rename_all = "UPPERCASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum SplitState {
+    Pending,
+    Accepted,
+    Declined,
+    Expired,
+}
+
+/// The share of an order a co-payer is asked to pay
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderSplit {
+    pub id: i64,
+    pub order_id: i64,
+    pub game_id: i64,
+    pub purchaser_id: i64,
+    pub user_id: i64,
+    pub amount: i64,
+    pub state: SplitState,
+    pub created_at: DateTime<Utc>,
+    pub responded_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SplitResponse {
+    pub accepted: bool,
+}
+
+/// returns the share of every co-payer, the purchaser pays the remainder
+fn shares(total: i64, co_payers: usize) -> i64 {
+    total / (co_payers as i64 + 1)
+}
+
+impl OrderSplit {
+    /// Ask the co-payers to pay an equal share of the order
+    #[tracing::instrument(name = "OrderSplit::create", skip(db))]
+    pub async fn create(
+        purchaser_id: i64,
+        game_id: i64,
+        transactions: &[Transaction],
+        co_payers: &[i64],
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<OrderSplit>, ServiceError> {
+        let order_id = match transactions.first() {
+            Some(transaction) => transaction.order_id,
+            None => return Ok(Vec::new()),
+        };
+
+        let total: i64 = transactions
+            .iter()
+            .map(|transaction| transaction.price * transaction.amount as i64)
+            .sum();
+        let amount = shares(total, co_payers.len());
+
+        let mut tx = db::begin(Operation::SplitOrder, db).await?;
+        let mut splits = Vec::new();
+
+        for user_id in co_payers {
+            let split = sqlx::query!(
+                r#"
+                INSERT INTO order_splits (order_id, user_id, amount)
+                VALUES ($1, $2, $3)
+                RETURNING id, state as "state: SplitState", created_at
+                "#,
+                order_id,
+                user_id,
+                amount
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+
+            splits.push(OrderSplit {
+                id: split.id,
+                order_id,
+                game_id,
+                purchaser_id,
+                user_id: *user_id,
+                amount,
+                state: split.state,
+                created_at: split.created_at,
+                responded_at: None,
+            });
+        }
+
+        tx.commit().await?;
+
+        Ok(splits)
+    }
+
+    /// returns the splits of a game the user is asked to pay, most recent first
+    #[tracing::instrument(name = "OrderSplit::find_by_user", skip(db))]
+    pub async fn find_by_user(
+        game_id: i64,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<OrderSplit>, sqlx::Error> {
+        sqlx::query_as!(
+            OrderSplit,
+            r#"
+            SELECT order_splits.id, order_id, orders.game_id, orders.user_id as purchaser_id,
+                order_splits.user_id, amount, state as "state: SplitState",
+                order_splits.created_at, responded_at
+            FROM order_splits
+            INNER JOIN orders ON orders.id = order_splits.order_id
+            WHERE orders.game_id = $1 AND order_splits.user_id = $2
+            ORDER BY order_splits.created_at DESC
+            "#,
+            game_id,
+            user_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// returns the splits of an order
+    #[tracing::instrument(name = "OrderSplit::find_by_order", skip(db))]
+    pub async fn find_by_order(
+        order_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<OrderSplit>, sqlx::Error> {
+        sqlx::query_as!(
+            OrderSplit,
+            r#"
+            SELECT order_splits.id, order_id, orders.game_id, orders.user_id as purchaser_id,
+                order_splits.user_id, amount, state as "state: SplitState",
+                order_splits.created_at, responded_at
+            FROM order_splits
+            INNER JOIN orders ON orders.id = order_splits.order_id
+            WHERE order_id = $1
+            ORDER BY order_splits.id
+            "#,
+            order_id
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Accept or decline a share, an accepted share moves from the purchaser's tab to the co-payer's tab
+    #[tracing::instrument(name = "OrderSplit::respond", skip(db))]
+    pub async fn respond(
+        split_id: i64,
+        game: &Game,
+        user_id: i64,
+        accepted: bool,
+        db: &Pool<Postgres>,
+    ) -> Result<OrderSplit, ServiceError> {
+        let mut tx = db::begin(Operation::RespondSplit, db).await?;
+
+        let mut split = sqlx::query_as!(
+            OrderSplit,
+            r#"
+            SELECT order_splits.id, order_id, orders.game_id, orders.user_id as purchaser_id,
+                order_splits.user_id, amount, state as "state: SplitState",
+                order_splits.created_at, responded_at
+            FROM order_splits
+            INNER JOIN orders ON orders.id = order_splits.order_id
+            WHERE order_splits.id = $1 AND order_splits.user_id = $2 AND orders.game_id = $3
+            FOR UPDATE OF order_splits
+            "#,
+            split_id,
+            user_id,
+            game.id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(ServiceError::NotFound)?;
+
+        if split.state != SplitState::Pending {
+            return Err(ServiceError::Conflict(String::from(
+                "this split has already been resolved",
+            )));
+        }
+
+        if split.is_expired(Utc::now()) {
+            return Err(ServiceError::Conflict(String::from(
+                "this split has expired, the purchaser pays this share",
+            )));
+        }
+
+        if accepted {
+            points::check_balance(game, user_id, split.amount, &mut tx).await?;
+
+            for (user_id, amount) in &[
+                (split.user_id, split.amount),
+                (split.purchaser_id, -split.amount),
+            ] {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO user_sales (game_id, user_id, spent) VALUES ($1, $2, $3)
+                    ON CONFLICT (game_id, user_id) DO UPDATE SET spent = user_sales.spent + EXCLUDED.spent
+                    "#,
+                    game.id,
+                    user_id,
+                    amount
+                )
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        split.state = if accepted {
+            SplitState::Accepted
+        } else {
+            SplitState::Declined
+        };
+        split.responded_at = Some(Utc::now());
+
+        sqlx::query!(
+            "UPDATE order_splits SET state = $1, responded_at = $2 WHERE id = $3",
+            split.state as _,
+            split.responded_at,
+            split.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(split)
+    }
+
+    /// Expire the splits that are still pending, the purchaser keeps paying their shares
+    ///
+    /// returns the splits that expired
+    #[tracing::instrument(name = "OrderSplit::expire", skip(splits, db))]
+    pub async fn expire(
+        splits: Vec<OrderSplit>,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<OrderSplit>, sqlx::Error> {
+        let ids: Vec<i64> = splits.iter().map(|split| split.id).collect();
+
+        let expired = sqlx::query!(
+            r#"
+            UPDATE order_splits SET state = 'EXPIRED', responded_at = NOW()
+            WHERE id = ANY($1) AND state = 'PENDING'
+            RETURNING id, responded_at
+            "#,
+            &ids
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(splits
+            .into_iter()
+            .filter_map(|mut split| {
+                let row = expired.iter().find(|row| row.id == split.id)?;
+                split.state = SplitState::Expired;
+                split.responded_at = row.responded_at;
+                Some(split)
+            })
+            .collect())
+    }
+
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        let timeout = chrono::Duration::from_std(SPLIT_TIMEOUT).expect("invalid split timeout");
+        self.state == SplitState::Pending && self.created_at + timeout < now
+    }
+}
+
+/// Expire the splits nobody responded to after the timeout and notify the purchaser
+pub(crate) fn schedule_expiration(splits: Vec<OrderSplit>, db: Pool<Postgres>, events: EventBus) {
+    actix_rt::spawn(async move {
+        actix_rt::time::delay_for(SPLIT_TIMEOUT).await;
+
+        match OrderSplit::expire(splits, &db).await {
+            Ok(expired) => {
+                for split in expired {
+                    events.publish(DomainEvent::SplitResolved(split));
+                }
+            }
+            Err(e) => error!("unable to expire the order splits: {}", e),
+        }
+    });
+}
+
+/// Make sure the co-payers are valid before the order is placed
+pub(crate) fn validate_co_payers(purchaser_id: i64, co_payers: &[i64]) -> Result<(), ServiceError> {
+    if co_payers.is_empty() {
+        bad_request!("select at least one co-payer");
+    }
+
+    if co_payers.len() > MAX_CO_PAYERS {
+        bad_request!(format!(
+            "an order can be split with at most {} co-payers",
+            MAX_CO_PAYERS
+        ));
+    }
+
+    if co_payers.contains(&purchaser_id) {
+        bad_request!("you can't split an order with yourself");
+    }
+
+    let mut unique = co_payers.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    if unique.len() != co_payers.len() {
+        bad_request!("every co-payer can only be selected once");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_shares() {
+        assert_eq!(shares(900, 2), 300);
+        // the purchaser pays the remainder
+        assert_eq!(shares(1000, 2), 333);
+        assert_eq!(shares(0, 3), 0);
+    }
+
+    #[test]
+    fn valid_co_payers() {
+        assert!(validate_co_payers(1, &[2, 3]).is_ok());
+        assert!(validate_co_payers(1, &[]).is_err());
+        assert!(validate_co_payers(1, &[1, 2]).is_err());
+        assert!(validate_co_payers(1, &[2, 2]).is_err());
+        assert!(validate_co_payers(1, &[2, 3, 4, 5, 6, 7, 8, 9, 10]).is_err());
+    }
+
+    #[test]
+    fn split_expiration() {
+        let split = OrderSplit {
+            id: 1,
+            order_id: 1,
+            game_id: 1,
+            purchaser_id: 1,
+            user_id: 2,
+            amount: 100,
+            state: SplitState::Pending,
+            created_at: Utc::now(),
+            responded_at: None,
+        };
+
+        assert!(!split.is_expired(Utc::now()));
+        assert!(split.is_expired(Utc::now() + chrono::Duration::minutes(3)));
+    }
+}
@@ -0,0 +1,257 @@
+//! Paper tickets for the bar
+//!
+//! An order is rendered as ESC/POS commands, which receipt printers understand.
+//! The printer bridge of a game gets the id of every new order over its websocket
+//! and downloads the ticket with its device token.
+
+use chrono::{DateTime, FixedOffset};
+use sqlx::{Pool, Postgres};
+
+use crate::games::timezone;
+
+/// the characters on a line of a 58mm receipt, wider printers leave a margin
+const WIDTH: usize = 32;
+
+const INITIALIZE: &[u8] = b"\x1b@";
+const ALIGN_LEFT: &[u8] = b"\x1ba\x00";
+const ALIGN_CENTER: &[u8] = b"\x1ba\x01";
+const DOUBLE_SIZE: &[u8] = b"\x1d!\x11";
+const NORMAL_SIZE: &[u8] = b"\x1d!\x00";
+/// feed the paper past the cutter and cut it, leaving a small hinge
+const CUT: &[u8] = b"\x1dVB\x00";
+
+#[derive(Debug)]
+pub struct Ticket {
+    pub order_id: i64,
+    pub game_name: String,
+    /// the player who ordered
+    pub username: String,
+    /// in the time zone of the game
+    pub ordered_at: DateTime<FixedOffset>,
+    pub items: Vec<TicketItem>,
+    /// in cents
+    pub tip: i64,
+}
+
+/// Who may print a ticket is decided by the game and the player of its order
+#[derive(Debug)]
+pub struct TicketOwner {
+    pub game_id: i64,
+    pub user_id: i64,
+}
+
+#[derive(Debug)]
+pub struct TicketItem {
+    pub name: String,
+    pub amount: i32,
+    /// the price of one beverage, in cents
+    pub price: i64,
+}
+
+impl Ticket {
+    #[tracing::instrument(name = "Ticket::owner", skip(db))]
+    pub async fn owner(order_id: i64, db: &Pool<Postgres>) -> Result<TicketOwner, sqlx::Error> {
+        sqlx::query_as!(
+            TicketOwner,
+            "SELECT game_id, user_id FROM orders WHERE id = $1",
+            order_id
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    #[tracing::instrument(name = "Ticket::load", skip(db))]
+    pub async fn load(order_id: i64, db: &Pool<Postgres>) -> Result<Ticket, sqlx::Error> {
+        let order = sqlx::query!(
+            r#"
+            SELECT orders.id, orders.game_id, orders.user_id, orders.created_at, orders.tip,
+                games.name AS game_name, games.time_zone, users.username
+            FROM orders
+            INNER JOIN games ON games.id = orders.game_id
+            INNER JOIN users ON users.id = orders.user_id
+            WHERE orders.id = $1
+            "#,
+            order_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        let items = sqlx::query!(
+            r#"
+            SELECT transactions.slot_no, beverages.name AS "name?", transactions.amount, transactions.price
+            FROM transactions
+            LEFT JOIN beverages ON beverages.game_id = $2 AND beverages.user_id = $3
+                AND beverages.slot_no = transactions.slot_no
+            WHERE transactions.order_id = $1
+            ORDER BY transactions.slot_no
+            "#,
+            order_id,
+            order.game_id,
+            order.user_id
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|item| {
+            let slot_no = item.slot_no;
+            TicketItem {
+                name: item
+                    .name
+                    .unwrap_or_else(|| format!("beverage {}", slot_no + 1)),
+                amount: item.amount,
+                price: item.price,
+            }
+        })
+        .collect();
+
+        let tz = timezone::parse(&order.time_zone).unwrap_or(chrono_tz::Tz::UTC);
+
+        Ok(Ticket {
+            order_id: order.id,
+            game_name: order.game_name,
+            username: order.username,
+            ordered_at: timezone::localize(order.created_at, tz),
+            items,
+            tip: order.tip,
+        })
+    }
+
+    /// The ESC/POS commands that print the ticket and cut the paper
+    pub fn render(&self) -> Vec<u8> {
+        let mut ticket = Vec::new();
+
+        ticket.extend_from_slice(INITIALIZE);
+        ticket.extend_from_slice(ALIGN_CENTER);
+        ticket.extend_from_slice(DOUBLE_SIZE);
+        // the double size halves the characters on a line
+        line(&mut ticket, &truncate(&self.game_name, WIDTH / 2));
+        ticket.extend_from_slice(NORMAL_SIZE);
+        line(&mut ticket, &format!("order #{}", self.order_id));
+        line(
+            &mut ticket,
+            &self.ordered_at.format("%d/%m %H:%M").to_string(),
+        );
+
+        ticket.extend_from_slice(ALIGN_LEFT);
+        line(&mut ticket, &truncate(&self.username, WIDTH));
+        line(&mut ticket, &"-".repeat(WIDTH));
+
+        for item in &self.items {
+            let total = price(item.price * i64::from(item.amount));
+            let description = format!("{}x {}", item.amount, item.name);
+            line(&mut ticket, &columns(&description, &total));
+        }
+
+        if self.tip > 0 {
+            line(&mut ticket, &columns("tip", &price(self.tip)));
+        }
+
+        line(&mut ticket, &"-".repeat(WIDTH));
+        line(&mut ticket, &columns("TOTAL", &price(self.total())));
+
+        ticket.extend_from_slice(b"\n\n\n");
+        ticket.extend_from_slice(CUT);
+
+        ticket
+    }
+
+    /// the price of the whole order with the tip, in cents
+    pub fn total(&self) -> i64 {
+        self.items
+            .iter()
+            .map(|item| item.price * i64::from(item.amount))
+            .sum::<i64>()
+            + self.tip
+    }
+}
+
+/// Printers use their own code page, so anything outside of ASCII is replaced
+fn line(ticket: &mut Vec<u8>, text: &str) {
+    ticket.extend(text.chars().map(|c| {
+        if c.is_ascii() && !c.is_ascii_control() {
+            c as u8
+        } else {
+            b'?'
+        }
+    }));
+    ticket.push(b'\n');
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// the text on the left and the amount on the right of a line
+fn columns(left: &str, right: &str) -> String {
+    let left = truncate(left, WIDTH - right.len() - 1);
+    let padding = WIDTH - left.chars().count() - right.len();
+
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+fn price(cents: i64) -> String {
+    format!("{}.{:02}", cents / 100, cents % 100)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn ticket() -> Ticket {
+        Ticket {
+            order_id: 42,
+            game_name: String::from("Café Fuif"),
+            username: String::from("bart"),
+            ordered_at: FixedOffset::east(2 * 3600)
+                .from_utc_datetime(&Utc.ymd(2026, 10, 16).and_hms(20, 30, 0).naive_utc()),
+            items: vec![
+                TicketItem {
+                    name: String::from("Duvel"),
+                    amount: 2,
+                    price: 350,
+                },
+                TicketItem {
+                    name: String::from("a beverage with a name that doesn't fit on a ticket"),
+                    amount: 1,
+                    price: 1205,
+                },
+            ],
+            tip: 0,
+        }
+    }
+
+    #[test]
+    fn render() {
+        let ticket = ticket();
+        let rendered = ticket.render();
+
+        assert!(rendered.starts_with(INITIALIZE));
+        assert!(rendered.ends_with(CUT));
+
+        let text = String::from_utf8_lossy(&rendered);
+        assert!(text.contains("Caf? Fuif\n"));
+        assert!(text.contains("order #42\n"));
+        assert!(text.contains("16/10 22:30\n"));
+        assert!(text.contains("2x Duvel                    7.00\n"));
+        assert!(text.contains("TOTAL                      19.05\n"));
+
+        for line in text.lines().filter(|line| line.starts_with("1x ")) {
+            assert_eq!(line.len(), WIDTH);
+            assert!(line.ends_with(" 12.05"));
+        }
+        assert_eq!(ticket.total(), 1905);
+        assert!(!text.contains("tip"));
+    }
+
+    #[test]
+    fn render_tip() {
+        let mut ticket = ticket();
+        ticket.tip = 95;
+
+        let rendered = ticket.render();
+        let text = String::from_utf8_lossy(&rendered);
+        assert!(text.contains("tip                         0.95\n"));
+        assert!(text.contains("TOTAL                      20.00\n"));
+    }
+}
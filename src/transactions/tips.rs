@@ -0,0 +1,104 @@
+//! Tips on top of an order, like rounding up to the next euro
+//!
+//! The tip is stored on the order apart from its beverages,
+//! so it isn't demand for the market and it isn't part of what a player spent on beverages.
+
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::games::Game;
+
+/// the highest tip on a single order, in cents
+const MAX_TIP: i64 = 5000;
+
+/// The tips of a game, for its owner
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TipSummary {
+    pub game_id: i64,
+    /// in cents
+    pub total: i64,
+    /// the amount of orders with a tip
+    pub orders: i64,
+    /// the players who tipped, the most generous first
+    pub players: Vec<PlayerTips>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerTips {
+    pub user_id: i64,
+    pub username: String,
+    pub tips: i64,
+    pub orders: i64,
+}
+
+/// Make sure the tip of an order is within the limits
+pub(crate) fn check(tip: i64, order_total: i64, game: &Game) -> Result<(), ServiceError> {
+    if tip == 0 {
+        return Ok(());
+    }
+    if tip < 0 {
+        bad_request!("a tip can't be negative");
+    }
+    if game.points_budget.is_some() {
+        bad_request!("tips can't be given in a points game");
+    }
+    if tip > MAX_TIP {
+        bad_request!(format!("a tip can be at most {} cents", MAX_TIP));
+    }
+    if tip > order_total {
+        bad_request!("a tip can't be more than the beverages of the order");
+    }
+
+    Ok(())
+}
+
+impl TipSummary {
+    #[tracing::instrument(name = "TipSummary::load", skip(db))]
+    pub async fn load(game_id: i64, db: &Pool<Postgres>) -> Result<TipSummary, sqlx::Error> {
+        let players = sqlx::query_as!(
+            PlayerTips,
+            r#"
+            SELECT users.id AS user_id, users.username,
+                SUM(orders.tip)::BIGINT AS "tips!", COUNT(*) AS "orders!"
+            FROM orders
+            INNER JOIN users ON users.id = orders.user_id
+            WHERE orders.game_id = $1 AND orders.tip > 0
+            GROUP BY users.id
+            ORDER BY 3 DESC, users.username
+            "#,
+            game_id
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(TipSummary {
+            game_id,
+            total: players.iter().map(|player| player.tips).sum(),
+            orders: players.iter().map(|player| player.orders).sum(),
+            players,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tip_limits() {
+        let mut game = Game::fixture();
+
+        assert!(check(0, 350, &game).is_ok());
+        // rounding up 3.50 to 4.00
+        assert!(check(50, 350, &game).is_ok());
+
+        assert!(check(-50, 350, &game).is_err());
+        assert!(check(400, 350, &game).is_err());
+        assert!(check(MAX_TIP + 1, 100_000, &game).is_err());
+
+        game.points_budget = Some(1000);
+        assert!(check(50, 350, &game).is_err());
+    }
+}
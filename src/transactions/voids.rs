@@ -0,0 +1,119 @@
+//! Void an order that was placed by mistake
+//!
+//! The order isn't deleted, a void order with the negated amounts compensates it.
+//! The sales counts are decreased so the prices move as if the beverages were never sold,
+//! and the trigger on the transactions corrects the totals of the player.
+use sqlx::{Pool, Postgres};
+
+use crate::db::{self, Operation};
+use crate::errors::ServiceError;
+use crate::games::Game;
+use crate::transactions::models::{OrderSource, SalesCount};
+use crate::transactions::Transaction;
+use crate::users::User;
+
+/// Void an order of the user, or any order of a game the user owns, returns the compensating transactions
+#[tracing::instrument(name = "voids::void", skip(game, db))]
+pub async fn void(
+    order_id: i64,
+    game: &Game,
+    user: &User,
+    db: &Pool<Postgres>,
+) -> Result<Vec<Transaction>, ServiceError> {
+    let mut tx = db::begin(Operation::VoidOrder, db).await?;
+
+    let order = sqlx::query!(
+        r#"
+        SELECT id, user_id, source, tip,
+            EXISTS (SELECT 1 FROM orders voids WHERE voids.voids_order_id = orders.id) AS "voided!",
+            EXISTS (SELECT 1 FROM order_splits WHERE order_splits.order_id = orders.id) AS "split!"
+        FROM orders
+        WHERE id = $1 AND game_id = $2
+        FOR UPDATE
+        "#,
+        order_id,
+        game.id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ServiceError::NotFound)?;
+
+    if order.user_id != user.id && !game.is_owner(user) {
+        forbidden!("only the purchaser and the game owner can void an order");
+    }
+    if order.source == OrderSource::Void.as_str() {
+        bad_request!("a void order can't be voided");
+    }
+    if order.voided {
+        return Err(ServiceError::Conflict(String::from(
+            "this order has already been voided",
+        )));
+    }
+    if order.split {
+        return Err(ServiceError::Conflict(String::from(
+            "an order that's split across players can't be voided",
+        )));
+    }
+
+    let void = sqlx::query!(
+        r#"
+        INSERT INTO orders (user_id, game_id, source, tip, voids_order_id)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, created_at
+        "#,
+        order.user_id,
+        game.id,
+        OrderSource::Void.as_str(),
+        -order.tip,
+        order.id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let records = sqlx::query!(
+        r#"
+        INSERT INTO transactions (slot_no, amount, price, order_id, price_history_id, priced_at)
+        SELECT slot_no, -amount, price, $1, price_history_id, priced_at
+        FROM transactions
+        WHERE order_id = $2
+        ORDER BY id
+        RETURNING id, slot_no, amount, price, price_history_id, priced_at
+        "#,
+        void.id,
+        order.id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    tx.record_rows(records.len() as u64 + 1);
+
+    let mut sales_counts = SalesCount::find_by_game_for_update(game.id, &mut tx).await?;
+    for sales_count in sales_counts.iter_mut() {
+        let voided: i64 = records
+            .iter()
+            .filter(|record| record.slot_no == sales_count.slot_no)
+            .map(|record| record.amount as i64)
+            .sum();
+        if voided == 0 {
+            continue;
+        }
+
+        sales_count.sales += voided;
+        sales_count.update(&mut tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(records
+        .into_iter()
+        .map(|record| Transaction {
+            id: record.id,
+            slot_no: record.slot_no,
+            order_id: void.id,
+            amount: record.amount,
+            price: record.price,
+            price_history_id: record.price_history_id,
+            ordered_at: void.created_at,
+            priced_at: record.priced_at.unwrap_or(game.start_time),
+        })
+        .collect())
+}
@@ -0,0 +1,252 @@
+//! Compare the finished games of an owner
+//!
+//! Every game is compared with the game the owner organised before it, so recurring organizers see
+//! whether their games are getting better. Finished games don't change anymore, so the pages are cached
+//! until the owner finishes another game.
+
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+use crate::cache::{CacheHandle, CacheIdentifier};
+use crate::errors::ServiceError;
+
+const MAX_LIMIT: i64 = 50;
+const DEFAULT_LIMIT: i64 = 10;
+
+#[derive(Debug, Deserialize)]
+pub struct AnalyticsFilter {
+    #[serde(default)]
+    pub offset: i64,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    DEFAULT_LIMIT
+}
+
+impl AnalyticsFilter {
+    fn validate(&self) -> Result<(), ServiceError> {
+        if self.limit < 1 || self.limit > MAX_LIMIT {
+            bad_request!(format!("the limit should be between 1 and {}", MAX_LIMIT));
+        }
+
+        if self.offset < 0 {
+            bad_request!("the offset can't be negative");
+        }
+
+        Ok(())
+    }
+}
+
+/// The results of a finished game
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameAnalytics {
+    pub game_id: i64,
+    pub name: String,
+    pub start_time: DateTime<Utc>,
+    pub close_time: DateTime<Utc>,
+    pub revenue: i64,
+    /// the players who purchased something
+    pub participants: i64,
+    pub beverages: i64,
+    pub drinks_per_head: Option<f64>,
+    pub crash_count: i64,
+    /// the relative changes compared to the previous game, empty for the first game
+    pub trend: Trend,
+}
+
+/// The relative change of a metric compared to the previous game
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trend {
+    pub revenue: Option<f64>,
+    pub participants: Option<f64>,
+    pub drinks_per_head: Option<f64>,
+    pub crash_count: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub offset: i64,
+    pub limit: i64,
+    /// the amount of finished games of the owner
+    pub total: i64,
+}
+
+/// A page of finished games, most recent first
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OwnerAnalytics {
+    pub games: Vec<GameAnalytics>,
+    pub meta: Meta,
+}
+
+impl CacheIdentifier for OwnerAnalytics {}
+
+impl OwnerAnalytics {
+    #[tracing::instrument(name = "OwnerAnalytics::load", skip(db, cache))]
+    pub async fn load(
+        owner_id: i64,
+        filter: &AnalyticsFilter,
+        db: &Pool<Postgres>,
+        cache: &CacheHandle,
+    ) -> Result<OwnerAnalytics, ServiceError> {
+        filter.validate()?;
+
+        let finished = sqlx::query!(
+            r#"
+            SELECT COUNT(*) AS "total!", MAX(close_time) AS last_close_time
+            FROM games
+            WHERE owner_id = $1 AND close_time < NOW()
+            "#,
+            owner_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        // a new page is cached every time a game finishes
+        let cache_key = format!(
+            "{}.{}.{}.{}",
+            owner_id,
+            finished
+                .last_close_time
+                .map(|time| time.timestamp())
+                .unwrap_or_default(),
+            filter.offset,
+            filter.limit
+        );
+        if let Some(analytics) = cache.get(&cache_key).await {
+            return Ok(analytics);
+        }
+
+        // one extra game, to compare the oldest game of the page with
+        let records = sqlx::query!(
+            r#"
+            SELECT games.id, games.name, games.start_time, games.close_time,
+                (SELECT COALESCE(SUM(user_sales.spent), 0)::BIGINT FROM user_sales WHERE user_sales.game_id = games.id) AS "revenue!",
+                (SELECT COUNT(DISTINCT orders.user_id) FROM orders WHERE orders.game_id = games.id) AS "participants!",
+                (SELECT COALESCE(SUM(user_sales.sales), 0)::BIGINT FROM user_sales WHERE user_sales.game_id = games.id) AS "beverages!",
+                (SELECT COUNT(*) FROM market_crashes WHERE market_crashes.game_id = games.id) AS "crash_count!"
+            FROM games
+            WHERE games.owner_id = $1 AND games.close_time < NOW()
+            ORDER BY games.close_time DESC, games.id DESC
+            LIMIT $2 OFFSET $3
+            "#,
+            owner_id,
+            filter.limit + 1,
+            filter.offset
+        )
+        .fetch_all(db)
+        .await?;
+
+        let mut games: Vec<GameAnalytics> = records
+            .into_iter()
+            .map(|record| GameAnalytics {
+                game_id: record.id,
+                name: record.name,
+                start_time: record.start_time,
+                close_time: record.close_time,
+                revenue: record.revenue,
+                participants: record.participants,
+                beverages: record.beverages,
+                drinks_per_head: drinks_per_head(record.beverages, record.participants),
+                crash_count: record.crash_count,
+                trend: Trend::default(),
+            })
+            .collect();
+
+        compare(&mut games);
+        games.truncate(filter.limit as usize);
+
+        let analytics = OwnerAnalytics {
+            games,
+            meta: Meta {
+                offset: filter.offset,
+                limit: filter.limit,
+                total: finished.total,
+            },
+        };
+        cache.set(&analytics, &cache_key).await;
+
+        Ok(analytics)
+    }
+}
+
+fn drinks_per_head(beverages: i64, participants: i64) -> Option<f64> {
+    if participants <= 0 {
+        return None;
+    }
+
+    Some(beverages as f64 / participants as f64)
+}
+
+/// the relative change, empty when there's nothing to compare with
+fn change(previous: Option<f64>, current: Option<f64>) -> Option<f64> {
+    match (previous, current) {
+        (Some(previous), Some(current)) if previous > 0.0 => Some((current - previous) / previous),
+        _ => None,
+    }
+}
+
+/// Fill in the trends, the games are ordered from the most recent to the oldest
+fn compare(games: &mut [GameAnalytics]) {
+    for index in 0..games.len().saturating_sub(1) {
+        let (current, previous) = (&games[index], &games[index + 1]);
+
+        let trend = Trend {
+            revenue: change(Some(previous.revenue as f64), Some(current.revenue as f64)),
+            participants: change(
+                Some(previous.participants as f64),
+                Some(current.participants as f64),
+            ),
+            drinks_per_head: change(previous.drinks_per_head, current.drinks_per_head),
+            crash_count: change(
+                Some(previous.crash_count as f64),
+                Some(current.crash_count as f64),
+            ),
+        };
+        games[index].trend = trend;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(game_id: i64, revenue: i64, participants: i64, beverages: i64) -> GameAnalytics {
+        GameAnalytics {
+            game_id,
+            name: format!("game {}", game_id),
+            start_time: Utc::now(),
+            close_time: Utc::now(),
+            revenue,
+            participants,
+            beverages,
+            drinks_per_head: drinks_per_head(beverages, participants),
+            crash_count: 2,
+            trend: Trend::default(),
+        }
+    }
+
+    #[test]
+    fn compare_games() {
+        let mut games = vec![
+            game(3, 1500, 10, 40),
+            game(2, 1000, 8, 16),
+            game(1, 0, 0, 0),
+        ];
+        compare(&mut games);
+
+        assert_eq!(games[0].trend.revenue, Some(0.5));
+        assert_eq!(games[0].trend.participants, Some(0.25));
+        assert_eq!(games[0].trend.drinks_per_head, Some(1.0));
+        assert_eq!(games[0].trend.crash_count, Some(0.0));
+
+        // nothing to compare with
+        assert_eq!(games[1].trend.revenue, None);
+        assert_eq!(games[1].trend.drinks_per_head, None);
+        assert_eq!(games[2].trend.revenue, None);
+    }
+}
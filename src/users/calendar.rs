@@ -0,0 +1,251 @@
+//! An iCalendar feed of the upcoming games of a user
+//!
+//! Calendar apps can't log in, so the feed is fetched with a secret token in the url.
+//! Every event has a reminder, so phones notify the players before the game starts.
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::invitations::State;
+
+/// the maximum length of a line in an iCalendar file, without the line break
+const MAX_LINE_OCTETS: usize = 75;
+/// how long before the start of a game the reminder goes off
+const REMINDER: &str = "-PT1H";
+
+/// The secret that gives read access to the calendar of a user
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CalendarToken {
+    pub token: String,
+    /// the path of the feed, relative to the api host
+    pub path: String,
+}
+
+impl CalendarToken {
+    fn new(token: String) -> Self {
+        CalendarToken {
+            path: format!("/api/users/me/calendar.ics?token={}", token),
+            token,
+        }
+    }
+
+    /// create a new token, the previous one stops working
+    #[tracing::instrument(name = "CalendarToken::rotate", skip(db))]
+    pub async fn rotate(user_id: i64, db: &Pool<Postgres>) -> Result<CalendarToken, sqlx::Error> {
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+
+        sqlx::query!(
+            r#"
+            INSERT INTO calendar_tokens (user_id, token)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET token = $2, created_at = NOW()
+            "#,
+            user_id,
+            token
+        )
+        .execute(db)
+        .await?;
+
+        Ok(CalendarToken::new(token))
+    }
+
+    #[tracing::instrument(name = "CalendarToken::revoke", skip(db))]
+    pub async fn revoke(user_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!("DELETE FROM calendar_tokens WHERE user_id = $1", user_id)
+            .execute(db)
+            .await?;
+
+        Ok(())
+    }
+
+    /// the user the token belongs to
+    #[tracing::instrument(name = "CalendarToken::find_user", skip(token, db))]
+    pub async fn find_user(token: &str, db: &Pool<Postgres>) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT user_id FROM calendar_tokens WHERE token = $1",
+            token
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(row.user_id)
+    }
+}
+
+/// A game in the calendar of a user
+#[derive(Debug)]
+pub struct CalendarEvent {
+    pub game_id: i64,
+    pub name: String,
+    pub start_time: DateTime<Utc>,
+    pub close_time: DateTime<Utc>,
+    pub beverage_count: i16,
+    pub owner: String,
+    pub venue_name: Option<String>,
+    pub venue_address: Option<String>,
+}
+
+impl CalendarEvent {
+    /// the games the user accepted that haven't finished yet
+    #[tracing::instrument(name = "CalendarEvent::upcoming", skip(db))]
+    pub async fn upcoming(
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<Vec<CalendarEvent>, sqlx::Error> {
+        sqlx::query_as!(
+            CalendarEvent,
+            r#"
+            SELECT games.id AS game_id, games.name, games.start_time, games.close_time, games.beverage_count, users.username AS owner, games.venue_name, games.venue_address
+            FROM games
+            INNER JOIN invitations ON invitations.game_id = games.id
+            INNER JOIN users ON users.id = games.owner_id
+            WHERE invitations.user_id = $1 AND invitations.state = $2 AND games.close_time > NOW()
+            ORDER BY games.start_time
+            "#,
+            user_id,
+            State::Accepted as _
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// the venue and its address, when they're known
+    fn location(&self) -> Option<String> {
+        match (&self.venue_name, &self.venue_address) {
+            (Some(name), Some(address)) => Some(format!("{}, {}", name, address)),
+            (Some(location), None) | (None, Some(location)) => Some(location.clone()),
+            (None, None) => None,
+        }
+    }
+
+    fn write(&self, calendar: &mut Vec<String>, now: DateTime<Utc>) {
+        calendar.push(String::from("BEGIN:VEVENT"));
+        calendar.push(format!("UID:game-{}@rustfuif", self.game_id));
+        calendar.push(format!("DTSTAMP:{}", format_time(now)));
+        calendar.push(format!("DTSTART:{}", format_time(self.start_time)));
+        calendar.push(format!("DTEND:{}", format_time(self.close_time)));
+        calendar.push(format!("SUMMARY:{}", escape(&self.name)));
+        if let Some(location) = self.location() {
+            calendar.push(format!("LOCATION:{}", escape(&location)));
+        }
+        calendar.push(format!(
+            "DESCRIPTION:{}",
+            escape(&format!(
+                "Organised by {}\n{} beverages on the market",
+                self.owner, self.beverage_count
+            ))
+        ));
+        calendar.push(String::from("BEGIN:VALARM"));
+        calendar.push(String::from("ACTION:DISPLAY"));
+        calendar.push(format!("TRIGGER:{}", REMINDER));
+        calendar.push(format!(
+            "DESCRIPTION:{}",
+            escape(&format!("{} starts soon", self.name))
+        ));
+        calendar.push(String::from("END:VALARM"));
+        calendar.push(String::from("END:VEVENT"));
+    }
+}
+
+/// Render the events as an iCalendar file
+pub fn render(events: &[CalendarEvent], now: DateTime<Utc>) -> String {
+    let mut calendar = vec![
+        String::from("BEGIN:VCALENDAR"),
+        String::from("VERSION:2.0"),
+        String::from("PRODID:-//rustfuif//games//EN"),
+        String::from("CALSCALE:GREGORIAN"),
+        String::from("X-WR-CALNAME:rustfuif"),
+    ];
+
+    for event in events {
+        event.write(&mut calendar, now);
+    }
+    calendar.push(String::from("END:VCALENDAR"));
+
+    calendar.iter().map(String::as_str).map(fold).collect()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// escape the characters that have a meaning in a text value
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// split a line in parts of at most 75 octets, the next parts start with a space
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut length = 0;
+
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE_OCTETS {
+            folded.push_str("\r\n ");
+            // the leading space counts as well
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> CalendarEvent {
+        CalendarEvent {
+            game_id: 7,
+            name: String::from("bar night, with friends"),
+            start_time: "2026-10-23T18:00:00Z".parse().unwrap(),
+            close_time: "2026-10-23T23:00:00Z".parse().unwrap(),
+            beverage_count: 8,
+            owner: String::from("bart"),
+            venue_name: Some(String::from("De Fuif")),
+            venue_address: None,
+        }
+    }
+
+    #[test]
+    fn render_calendar() {
+        let now = "2026-10-16T12:00:00Z".parse().unwrap();
+        let calendar = render(&[event()], now);
+
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert!(calendar.contains("UID:game-7@rustfuif\r\n"));
+        assert!(calendar.contains("DTSTAMP:20261016T120000Z\r\n"));
+        assert!(calendar.contains("DTSTART:20261023T180000Z\r\n"));
+        assert!(calendar.contains("DTEND:20261023T230000Z\r\n"));
+        assert!(calendar.contains("SUMMARY:bar night\\, with friends\r\n"));
+        assert!(calendar.contains("DESCRIPTION:Organised by bart\\n8 beverages on the market\r\n"));
+        assert!(calendar.contains("LOCATION:De Fuif\r\n"));
+        assert!(calendar.contains("TRIGGER:-PT1H\r\n"));
+    }
+
+    #[test]
+    fn escape_text() {
+        assert_eq!(escape("a;b,c\\d\ne"), "a\\;b\\,c\\\\d\\ne");
+    }
+
+    #[test]
+    fn fold_long_lines() {
+        let line = format!("SUMMARY:{}", "é".repeat(50));
+        let folded = fold(&line);
+
+        for part in folded.split("\r\n") {
+            assert!(part.len() <= MAX_LINE_OCTETS);
+        }
+        assert_eq!(folded.replace("\r\n ", ""), format!("{}\r\n", line));
+    }
+}
@@ -0,0 +1,711 @@
+//! An archive of everything that's stored about a user, for data access requests
+//!
+//! The archive is generated in the background: the request queues the export, a worker stores the archive
+//! in the database and the user downloads it from the path in the response. Without Redis the archive is
+//! generated right away in a background task. A user only keeps their latest export.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt};
+use serde::Serialize;
+use sqlx::{Pool, Postgres};
+
+use crate::cache::{CacheHandle, Job};
+use crate::db::{self, Operation};
+use crate::streaming;
+
+pub const QUEUE: &str = "exports";
+
+/// How many times an export is generated before it's moved to the dead letters
+const MAX_ATTEMPTS: u32 = 3;
+/// How long the worker waits for an export before checking the queue again
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+/// The pause after the queue couldn't be reached, or after a failed export
+const BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "export_format", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Json
+    }
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(sqlx::Type, Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[sqlx(rename = "export_status", rename_all = "UPPERCASE")]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ExportStatus {
+    Pending,
+    Ready,
+    Failed,
+}
+
+/// An export of a user, without the archive
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    pub id: i64,
+    pub format: ExportFormat,
+    pub status: ExportStatus,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// the path of the download, relative to the api host
+    pub path: String,
+}
+
+/// The item in the queue of the worker
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportJob {
+    export_id: i64,
+}
+
+/// Everything that's stored about a user
+///
+/// The archive is written while its rows are read from the database, so the orders and the transactions
+/// of a user with a long history never end up in memory twice.
+#[derive(Debug)]
+pub struct Archive {
+    format: ExportFormat,
+    content: Vec<u8>,
+    /// no row of the current table was written yet
+    first: bool,
+}
+
+/// The archive couldn't be written, a database error is retried by the worker
+#[derive(Debug)]
+enum ArchiveError {
+    Database(sqlx::Error),
+    Serialize(serde_json::Error),
+}
+
+impl From<sqlx::Error> for ArchiveError {
+    fn from(e: sqlx::Error) -> Self {
+        ArchiveError::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for ArchiveError {
+    fn from(e: serde_json::Error) -> Self {
+        ArchiveError::Serialize(e)
+    }
+}
+
+/// A part of the archive, a key in the JSON archive and a table in the CSV archive
+trait Table: Serialize {
+    const NAME: &'static str;
+    const HEADER: &'static str;
+
+    fn fields(&self) -> Vec<String>;
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    pub id: i64,
+    pub username: String,
+    pub is_admin: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedInvitation {
+    pub game_id: i64,
+    pub game_name: String,
+    pub state: crate::invitations::State,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedOrder {
+    pub id: i64,
+    pub game_id: i64,
+    /// where the order was placed, in the app or imported from the till
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportedTransaction {
+    pub id: i64,
+    pub order_id: i64,
+    pub game_id: i64,
+    pub slot_no: i16,
+    pub amount: i32,
+    pub price: i64,
+    pub ordered_at: DateTime<Utc>,
+    pub priced_at: DateTime<Utc>,
+}
+
+impl UserExport {
+    fn path(id: i64) -> String {
+        format!("/api/users/me/export/{}", id)
+    }
+
+    /// Start a new export, the previous exports of the user are removed
+    ///
+    /// An export that's still being generated is returned instead of starting another one,
+    /// unless it was started so long ago that it was lost.
+    #[tracing::instrument(name = "UserExport::create", skip(db))]
+    pub async fn create(
+        user_id: i64,
+        format: ExportFormat,
+        db: &Pool<Postgres>,
+    ) -> Result<(UserExport, bool), sqlx::Error> {
+        let mut tx = db::begin(Operation::ExportUser, db).await?;
+
+        // one export at a time per user
+        sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        let pending = sqlx::query!(
+            r#"
+            SELECT id, format AS "format: ExportFormat", status AS "status: ExportStatus", error, created_at, finished_at
+            FROM user_exports
+            WHERE user_id = $1 AND status = 'PENDING' AND created_at > NOW() - INTERVAL '1 hour'
+            "#,
+            user_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(export) = pending {
+            return Ok((
+                UserExport {
+                    id: export.id,
+                    format: export.format,
+                    status: export.status,
+                    error: export.error,
+                    created_at: export.created_at,
+                    finished_at: export.finished_at,
+                    path: UserExport::path(export.id),
+                },
+                false,
+            ));
+        }
+
+        sqlx::query!("DELETE FROM user_exports WHERE user_id = $1", user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let export = sqlx::query!(
+            r#"
+            INSERT INTO user_exports (user_id, format)
+            VALUES ($1, $2)
+            RETURNING id, status AS "status: ExportStatus", created_at
+            "#,
+            user_id,
+            format as ExportFormat
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok((
+            UserExport {
+                id: export.id,
+                format,
+                status: export.status,
+                error: None,
+                created_at: export.created_at,
+                finished_at: None,
+                path: UserExport::path(export.id),
+            },
+            true,
+        ))
+    }
+
+    /// The export of a user with its archive, the archive is empty until it's ready
+    #[tracing::instrument(name = "UserExport::find", skip(db))]
+    pub async fn find(
+        id: i64,
+        user_id: i64,
+        db: &Pool<Postgres>,
+    ) -> Result<(UserExport, Option<String>), sqlx::Error> {
+        let export = sqlx::query!(
+            r#"
+            SELECT id, format AS "format: ExportFormat", status AS "status: ExportStatus", content, error, created_at, finished_at
+            FROM user_exports
+            WHERE id = $1 AND user_id = $2
+            "#,
+            id,
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok((
+            UserExport {
+                id: export.id,
+                format: export.format,
+                status: export.status,
+                error: export.error,
+                created_at: export.created_at,
+                finished_at: export.finished_at,
+                path: UserExport::path(export.id),
+            },
+            export.content,
+        ))
+    }
+
+    /// Generate the archive and store it, the export fails when it's generated but can't be stored
+    #[tracing::instrument(name = "UserExport::generate", skip(db))]
+    async fn generate(export_id: i64, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        let export = match sqlx::query!(
+            r#"
+            SELECT user_id, format AS "format: ExportFormat"
+            FROM user_exports
+            WHERE id = $1 AND status = 'PENDING'
+            "#,
+            export_id
+        )
+        .fetch_optional(db)
+        .await?
+        {
+            Some(export) => export,
+            // replaced by a newer export, or generated already
+            None => return Ok(()),
+        };
+
+        match Archive::write(export.user_id, export.format, db).await {
+            Ok(content) => {
+                sqlx::query!(
+                    r#"
+                    UPDATE user_exports
+                    SET status = 'READY', content = $2, finished_at = NOW()
+                    WHERE id = $1
+                    "#,
+                    export_id,
+                    content
+                )
+                .execute(db)
+                .await?;
+            }
+            Err(ArchiveError::Serialize(e)) => {
+                UserExport::fail(export_id, &e.to_string(), db).await?
+            }
+            Err(ArchiveError::Database(e)) => return Err(e),
+        }
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "UserExport::fail", skip(db))]
+    async fn fail(export_id: i64, error: &str, db: &Pool<Postgres>) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            r#"
+            UPDATE user_exports
+            SET status = 'FAILED', error = $2, finished_at = NOW()
+            WHERE id = $1 AND status = 'PENDING'
+            "#,
+            export_id,
+            error
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn time(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.to_rfc3339()).unwrap_or_default()
+}
+
+impl Table for Profile {
+    const NAME: &'static str = "profile";
+    const HEADER: &'static str = "id,username,is_admin,created_at,updated_at";
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.username.clone(),
+            self.is_admin.to_string(),
+            time(self.created_at),
+            time(self.updated_at),
+        ]
+    }
+}
+
+impl Table for ExportedInvitation {
+    const NAME: &'static str = "invitations";
+    const HEADER: &'static str = "game_id,game_name,state,created_at,updated_at";
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.game_id.to_string(),
+            self.game_name.clone(),
+            format!("{:?}", self.state).to_uppercase(),
+            time(self.created_at),
+            time(self.updated_at),
+        ]
+    }
+}
+
+impl Table for ExportedOrder {
+    const NAME: &'static str = "orders";
+    const HEADER: &'static str = "id,game_id,source,created_at";
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.game_id.to_string(),
+            self.source.clone(),
+            time(Some(self.created_at)),
+        ]
+    }
+}
+
+impl Table for ExportedTransaction {
+    const NAME: &'static str = "transactions";
+    const HEADER: &'static str = "id,order_id,game_id,slot_no,amount,price,ordered_at,priced_at";
+
+    fn fields(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(),
+            self.order_id.to_string(),
+            self.game_id.to_string(),
+            self.slot_no.to_string(),
+            self.amount.to_string(),
+            self.price.to_string(),
+            time(Some(self.ordered_at)),
+            time(Some(self.priced_at)),
+        ]
+    }
+}
+
+impl Archive {
+    /// Write the archive of a user in the requested format
+    #[tracing::instrument(name = "Archive::write", skip(db))]
+    async fn write(
+        user_id: i64,
+        format: ExportFormat,
+        db: &Pool<Postgres>,
+    ) -> Result<String, ArchiveError> {
+        let profile = sqlx::query_as!(
+            Profile,
+            "SELECT id, username, is_admin, created_at, updated_at FROM users WHERE id = $1",
+            user_id
+        )
+        .fetch_one(db)
+        .await?;
+
+        let mut archive = Archive::new(format, Utc::now(), &profile)?;
+
+        archive
+            .write_table(sqlx::query_as!(
+                ExportedInvitation,
+                r#"
+                SELECT invitations.game_id, games.name AS game_name, invitations.state AS "state: crate::invitations::State",
+                    invitations.created_at, invitations.updated_at
+                FROM invitations
+                INNER JOIN games ON games.id = invitations.game_id
+                WHERE invitations.user_id = $1
+                ORDER BY invitations.id
+                "#,
+                user_id
+            )
+            .fetch(db))
+            .await?;
+
+        archive
+            .write_table(
+                sqlx::query_as!(
+                ExportedOrder,
+                "SELECT id, game_id, source, created_at FROM orders WHERE user_id = $1 ORDER BY id",
+                user_id
+            )
+                .fetch(db),
+            )
+            .await?;
+
+        archive
+            .write_table(
+                sqlx::query_as!(
+                    ExportedTransaction,
+                    r#"
+                SELECT transactions.id, transactions.order_id, orders.game_id, transactions.slot_no,
+                    transactions.amount, transactions.price, orders.created_at AS ordered_at,
+                    COALESCE(transactions.priced_at, games.start_time) AS "priced_at!"
+                FROM transactions
+                INNER JOIN orders ON orders.id = transactions.order_id
+                INNER JOIN games ON games.id = orders.game_id
+                WHERE orders.user_id = $1
+                ORDER BY transactions.id
+                "#,
+                    user_id
+                )
+                .fetch(db),
+            )
+            .await?;
+
+        Ok(archive.finish())
+    }
+
+    /// Start an archive with the time of the export and the profile of the user
+    fn new(
+        format: ExportFormat,
+        exported_at: DateTime<Utc>,
+        profile: &Profile,
+    ) -> Result<Archive, serde_json::Error> {
+        let mut archive = Archive {
+            format,
+            content: Vec::new(),
+            first: true,
+        };
+
+        match format {
+            ExportFormat::Json => {
+                archive.content.extend_from_slice(b"{\"exportedAt\":");
+                serde_json::to_writer(&mut archive.content, &exported_at)?;
+                archive.content.extend_from_slice(b",\"profile\":");
+                serde_json::to_writer(&mut archive.content, profile)?;
+            }
+            ExportFormat::Csv => {
+                archive.begin::<Profile>();
+                archive.row(profile)?;
+            }
+        }
+
+        Ok(archive)
+    }
+
+    /// Add a table with every row of a query
+    async fn write_table<T, S>(&mut self, rows: S) -> Result<(), ArchiveError>
+    where
+        T: Table,
+        S: Stream<Item = Result<T, sqlx::Error>>,
+    {
+        futures::pin_mut!(rows);
+
+        self.begin::<T>();
+        while let Some(row) = rows.next().await {
+            self.row(&row?)?;
+        }
+        self.end();
+
+        Ok(())
+    }
+
+    /// Start a table, preceded by a `# name` line in the CSV archive
+    fn begin<T: Table>(&mut self) {
+        self.first = true;
+
+        match self.format {
+            ExportFormat::Json => {
+                self.content
+                    .extend_from_slice(format!(",\"{}\":", T::NAME).as_bytes());
+            }
+            ExportFormat::Csv => {
+                if !self.content.is_empty() {
+                    self.content.push(b'\n');
+                }
+                self.content
+                    .extend_from_slice(format!("# {}\n{}\n", T::NAME, T::HEADER).as_bytes());
+            }
+        }
+    }
+
+    fn row<T: Table>(&mut self, row: &T) -> Result<(), serde_json::Error> {
+        match self.format {
+            ExportFormat::Json => streaming::append(&mut self.content, row, self.first)?,
+            ExportFormat::Csv => {
+                let fields: Vec<String> = row.fields().iter().map(|field| escape(field)).collect();
+                self.content.extend_from_slice(fields.join(",").as_bytes());
+                self.content.push(b'\n');
+            }
+        }
+        self.first = false;
+
+        Ok(())
+    }
+
+    fn end(&mut self) {
+        if self.format == ExportFormat::Json {
+            let end: &[u8] = if self.first { b"[]" } else { b"]" };
+            self.content.extend_from_slice(end);
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if self.format == ExportFormat::Json {
+            self.content.push(b'}');
+        }
+
+        String::from_utf8(self.content).expect("the archive is written from strings")
+    }
+}
+
+/// quote a CSV field when it contains a separator, a quote or a line break
+fn escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Queue the export, it's generated right away when the queue can't be reached
+pub async fn dispatch(export_id: i64, db: &Pool<Postgres>, cache: &CacheHandle) {
+    if cache.queue(QUEUE).push(&ExportJob { export_id }).await {
+        return;
+    }
+
+    let db = db.clone();
+    actix_rt::spawn(async move {
+        if let Err(e) = UserExport::generate(export_id, &db).await {
+            error!("unable to generate export {}: {}", export_id, e);
+            UserExport::fail(export_id, &e.to_string(), &db).await.ok();
+        }
+    });
+}
+
+/// Generate the queued exports until the server stops
+pub fn start(db: Pool<Postgres>, cache: CacheHandle) {
+    actix_rt::spawn(async move {
+        let queue = cache.queue(QUEUE);
+
+        loop {
+            if !cache.is_enabled().await {
+                actix_rt::time::delay_for(POLL_TIMEOUT).await;
+                continue;
+            }
+
+            let job: Job<ExportJob> = match queue.pop(POLL_TIMEOUT).await {
+                Some(job) => job,
+                None => {
+                    actix_rt::time::delay_for(BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let export_id = job.delivery.payload.export_id;
+            match UserExport::generate(export_id, &db).await {
+                Ok(()) => queue.ack(job).await,
+                Err(e) => {
+                    warn!("unable to generate export {}: {}", export_id, e);
+                    if job.delivery.attempts + 1 >= MAX_ATTEMPTS {
+                        UserExport::fail(export_id, &e.to_string(), &db).await.ok();
+                    }
+                    queue.fail(job, e.to_string(), MAX_ATTEMPTS).await;
+                    actix_rt::time::delay_for(BACKOFF).await;
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_fields() {
+        assert_eq!(escape("duvel"), "duvel");
+        assert_eq!(escape("duvel, tripel"), "\"duvel, tripel\"");
+        assert_eq!(escape("the \"best\""), "\"the \"\"best\"\"\"");
+    }
+
+    #[test]
+    fn csv_archive() {
+        let time = Utc::now();
+        let profile = Profile {
+            id: 1,
+            username: String::from("bart"),
+            is_admin: false,
+            created_at: Some(time),
+            updated_at: None,
+        };
+
+        let mut archive = Archive::new(ExportFormat::Csv, time, &profile).unwrap();
+        archive.begin::<ExportedInvitation>();
+        archive
+            .row(&ExportedInvitation {
+                game_id: 2,
+                game_name: String::from("cantus, night one"),
+                state: crate::invitations::State::Accepted,
+                created_at: None,
+                updated_at: None,
+            })
+            .unwrap();
+        archive.end();
+        archive.begin::<ExportedOrder>();
+        archive.end();
+        archive.begin::<ExportedTransaction>();
+        archive.end();
+
+        let csv = archive.finish();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "# profile");
+        assert_eq!(lines[2], format!("1,bart,false,{},", time.to_rfc3339()));
+        assert_eq!(lines[4], "# invitations");
+        assert_eq!(lines[6], "2,\"cantus, night one\",ACCEPTED,,");
+        assert_eq!(lines[8], "# orders");
+        assert_eq!(lines[11], "# transactions");
+        assert_eq!(lines.len(), 13);
+    }
+
+    #[test]
+    fn json_archive() {
+        let time = Utc::now();
+        let profile = Profile {
+            id: 1,
+            username: String::from("bart"),
+            is_admin: false,
+            created_at: None,
+            updated_at: None,
+        };
+
+        let mut archive = Archive::new(ExportFormat::Json, time, &profile).unwrap();
+        archive.begin::<ExportedInvitation>();
+        archive.end();
+        archive.begin::<ExportedOrder>();
+        for id in 1..=2 {
+            archive
+                .row(&ExportedOrder {
+                    id,
+                    game_id: 3,
+                    source: String::from("app"),
+                    created_at: time,
+                })
+                .unwrap();
+        }
+        archive.end();
+
+        let json: serde_json::Value = serde_json::from_str(&archive.finish()).unwrap();
+        assert_eq!(json["profile"]["username"], "bart");
+        assert_eq!(json["invitations"], serde_json::json!([]));
+        assert_eq!(json["orders"][1]["id"], 2);
+        assert_eq!(json["orders"][1]["gameId"], 3);
+    }
+}
@@ -1,4 +1,8 @@
+pub mod analytics;
+pub mod calendar;
+pub mod export;
 mod models;
+pub mod provisioning;
 pub mod routes;
 
 pub use models::{Credentials, Filter, User, UserResponse};
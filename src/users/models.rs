@@ -4,6 +4,8 @@ use rand::Rng;
 use regex::Regex;
 use sqlx::{Pool, Postgres};
 
+use crate::blocking;
+use crate::db::{self, Operation};
 use crate::errors::ServiceError;
 
 #[derive(Deserialize)]
@@ -82,7 +84,7 @@ impl User {
     /// Store the user in the database after hashing it's password
     #[tracing::instrument(name = "user::create")]
     pub async fn create(user: &mut Credentials, db: &Pool<Postgres>) -> Result<Self, ServiceError> {
-        user.hash_password()?;
+        user.password = hash_password(user.password.clone()).await?;
 
         let user = sqlx::query_as!(
             User,
@@ -96,6 +98,74 @@ impl User {
         Ok(user)
     }
 
+    /// Find the user linked to an identity of an external identity provider
+    #[tracing::instrument(name = "user::find_external")]
+    pub async fn find_external(
+        provider: &str,
+        subject: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            User,
+            r#"
+            SELECT users.* FROM users
+            INNER JOIN external_identities ON external_identities.user_id = users.id
+            WHERE external_identities.provider = $1 AND external_identities.subject = $2
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Store a user with a random password, so the account can't be used to log in with a password
+    pub(crate) async fn create_passwordless(
+        username: &str,
+        tx: &mut sqlx::Transaction<'_, Postgres>,
+    ) -> Result<Self, ServiceError> {
+        let password = hash_password(format!("{:032x}", rand::thread_rng().gen::<u128>())).await?;
+
+        let user = sqlx::query_as!(
+            User,
+            r"INSERT INTO users (username, password) VALUES ($1, $2) RETURNING *;",
+            username,
+            password
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        Ok(user)
+    }
+
+    /// Store a user of an external identity provider
+    ///
+    /// The user gets a random password, so the account can only be used through the identity provider.
+    #[tracing::instrument(name = "user::create_external")]
+    pub async fn create_external(
+        username: &str,
+        provider: &str,
+        subject: &str,
+        db: &Pool<Postgres>,
+    ) -> Result<Self, ServiceError> {
+        let mut tx = db::begin(Operation::CreateUser, db).await?;
+
+        let user = User::create_passwordless(username, &mut tx).await?;
+
+        sqlx::query!(
+            "INSERT INTO external_identities (provider, subject, user_id) VALUES ($1, $2, $3)",
+            provider,
+            subject,
+            user.id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user)
+    }
+
     /// Hash and store the user's changed password to the database
     #[tracing::instrument(name = "user::update_password", skip(password))]
     pub async fn update_password(
@@ -103,7 +173,7 @@ impl User {
         password: String,
         db: &Pool<Postgres>,
     ) -> Result<(), ServiceError> {
-        self.set_password(password).hash_password()?;
+        self.password = hash_password(password).await?;
 
         sqlx::query!(
             "UPDATE users SET password = $1 WHERE id = $2",
@@ -127,8 +197,12 @@ impl User {
     }
 
     /// Returns Ok(()) if the user's hashed password matches the given password
-    pub fn verify_password(&self, password: &[u8]) -> Result<(), ServiceError> {
-        let is_match = argon2::verify_encoded(&self.password, password)?;
+    pub async fn verify_password(&self, password: &str) -> Result<(), ServiceError> {
+        let hash = self.password.clone();
+        let password = password.to_string();
+
+        let is_match =
+            blocking::run(move || Ok(argon2::verify_encoded(&hash, password.as_bytes())?)).await?;
 
         if !is_match {
             return Err(ServiceError::Unauthorized);
@@ -144,40 +218,15 @@ impl std::fmt::Display for User {
     }
 }
 
-trait PasswordHash {
-    fn hash_password(&mut self) -> Result<(), ServiceError> {
-        let salt: [u8; 32] = rand::thread_rng().gen();
-        let config = Config::default();
-        let hash = argon2::hash_encoded(self.password().as_bytes(), &salt, &config)?;
-        self.set_password(hash);
-        Ok(())
-    }
-
-    /// Get the current password
-    fn password(&self) -> &str;
-    fn set_password(&mut self, password: String) -> &mut Self;
-}
-
-impl PasswordHash for User {
-    fn password(&self) -> &str {
-        &self.password
-    }
-
-    fn set_password(&mut self, password: String) -> &mut Self {
-        self.password = password;
-        self
-    }
+/// Hash a password on the blocking thread pool, argon2 is slow on purpose
+async fn hash_password(password: String) -> Result<String, ServiceError> {
+    blocking::run(move || hash(&password)).await
 }
 
-impl PasswordHash for Credentials {
-    fn password(&self) -> &str {
-        &self.password
-    }
-
-    fn set_password(&mut self, password: String) -> &mut Self {
-        self.password = password;
-        self
-    }
+fn hash(password: &str) -> Result<String, ServiceError> {
+    let salt: [u8; 32] = rand::thread_rng().gen();
+    let config = Config::default();
+    Ok(argon2::hash_encoded(password.as_bytes(), &salt, &config)?)
 }
 
 impl crate::validator::Validate<Credentials> for Credentials {
@@ -267,8 +316,8 @@ mod tests {
         assert!(Validator::new(user).validate().is_ok());
     }
 
-    #[test]
-    fn incorrect_password() {
+    #[actix_rt::test]
+    async fn incorrect_password() {
         let mut user = User {
             id: 1,
             is_admin: true,
@@ -278,10 +327,34 @@ mod tests {
             updated_at: None,
         };
 
-        user.hash_password().unwrap();
+        user.password = hash_password(user.password).await.unwrap();
+
+        assert!(user.verify_password("admin").await.is_ok());
+        assert!(user.verify_password("not-admin").await.is_err());
+        assert_ne!(user.password, "admin");
+    }
+
+    /// Registrations hash passwords, which used to stall every other request on the same worker
+    #[actix_rt::test]
+    async fn responsive_during_registrations() {
+        use std::sync::mpsc;
+        use std::time::Duration;
+
+        let (handled, request_handled) = mpsc::channel();
+
+        // the hash waits for a request that runs on the executor,
+        // which never runs when the hash blocks the executor
+        let registration = blocking::run(move || {
+            request_handled
+                .recv_timeout(Duration::from_secs(10))
+                .map_err(|_| ServiceError::InternalServerError)?;
+            hash("hunter2boogaloo")
+        });
+
+        // a request that doesn't hash anything, like checking a session
+        let request = async move { handled.send(()).unwrap() };
 
-        assert!(user.verify_password(b"admin").is_ok());
-        assert!(user.verify_password(b"not-admin").is_err());
-        assert_ne!(user.password(), "admin");
+        let (hash, ()) = futures::future::join(registration, request).await;
+        assert!(hash.is_ok());
     }
 }
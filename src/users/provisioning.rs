@@ -0,0 +1,195 @@
+//! Bulk creation of the members of an organisation
+//!
+//! An administrator uploads the member list before the party, as CSV `username,password` lines
+//! or as a JSON array of `{ "username", "password" }` objects.
+//! A member without a password gets a temporary one, which is only returned in the results.
+//! Every row is created on its own, so one taken username doesn't stop the rest of the list.
+
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::{Pool, Postgres};
+
+use crate::errors::ServiceError;
+use crate::users::{Credentials, User};
+use crate::validator::Validate;
+
+const MAX_ROWS: usize = 500;
+const TEMPORARY_PASSWORD_LENGTH: usize = 12;
+
+/// A row of the member list
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Member {
+    pub username: String,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RowStatus {
+    Created,
+    Failed,
+}
+
+/// The outcome of a row of the member list
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RowResult {
+    /// the position of the member in the list, starting at 1
+    pub row: usize,
+    pub username: String,
+    pub status: RowStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<i64>,
+    /// hand this to the member, it's not stored anywhere in plain text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temporary_password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Parse a CSV member list, the first line can be a header
+pub fn parse_csv(body: &str) -> Result<Vec<Member>, ServiceError> {
+    let mut members = Vec::new();
+
+    for (index, line) in body.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut columns = line.split(',').map(str::trim);
+        let member = match (columns.next(), columns.next(), columns.next()) {
+            (Some(username), password, None) => Member {
+                username: username.to_string(),
+                password: password
+                    .filter(|password| !password.is_empty())
+                    .map(String::from),
+            },
+            _ => bad_request!(format!(
+                "line {}: expected the columns username,password",
+                index + 1
+            )),
+        };
+
+        if index == 0 && member.username.eq_ignore_ascii_case("username") {
+            continue;
+        }
+
+        members.push(member);
+    }
+
+    Ok(members)
+}
+
+pub fn parse_json(body: &str) -> Result<Vec<Member>, ServiceError> {
+    serde_json::from_str(body)
+        .map_err(|e| ServiceError::BadRequest(format!("invalid member list: {}", e)))
+}
+
+fn temporary_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TEMPORARY_PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// the reason a row failed, without leaking database details
+fn reason(error: ServiceError) -> String {
+    match error {
+        ServiceError::BadRequest(message) => message,
+        ServiceError::Conflict(_) => "the username is already taken".to_string(),
+        _ => "unable to create the user".to_string(),
+    }
+}
+
+/// Create the users of the member list, and report the outcome of every row
+#[tracing::instrument(name = "provisioning::create", skip(members, db))]
+pub async fn create(
+    members: Vec<Member>,
+    db: &Pool<Postgres>,
+) -> Result<Vec<RowResult>, ServiceError> {
+    if members.is_empty() {
+        bad_request!("the member list is empty");
+    }
+    if members.len() > MAX_ROWS {
+        bad_request!(format!(
+            "a member list can contain at most {} rows",
+            MAX_ROWS
+        ));
+    }
+
+    let mut results = Vec::with_capacity(members.len());
+
+    for (index, member) in members.into_iter().enumerate() {
+        let temporary_password = match member.password {
+            Some(_) => None,
+            None => Some(temporary_password()),
+        };
+        let mut credentials = Credentials {
+            username: member.username.trim().to_string(),
+            password: member
+                .password
+                .or_else(|| temporary_password.clone())
+                .unwrap_or_default(),
+        };
+
+        let created = match credentials.validate() {
+            Ok(()) => User::create(&mut credentials, db).await,
+            Err(e) => Err(e),
+        };
+
+        results.push(match created {
+            Ok(user) => RowResult {
+                row: index + 1,
+                username: user.username,
+                status: RowStatus::Created,
+                user_id: Some(user.id),
+                temporary_password,
+                error: None,
+            },
+            Err(e) => RowResult {
+                row: index + 1,
+                username: member.username,
+                status: RowStatus::Failed,
+                user_id: None,
+                temporary_password: None,
+                error: Some(reason(e)),
+            },
+        });
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_csv_list() {
+        let members = parse_csv("username,password\nbart,supersecret\n\n jef \n").unwrap();
+        assert_eq!(
+            members,
+            vec![
+                Member {
+                    username: "bart".to_string(),
+                    password: Some("supersecret".to_string())
+                },
+                Member {
+                    username: "jef".to_string(),
+                    password: None
+                },
+            ]
+        );
+
+        assert!(parse_csv("bart,supersecret,admin").is_err());
+    }
+
+    #[test]
+    fn generated_password_is_valid() {
+        let password = temporary_password();
+        assert_eq!(password.len(), TEMPORARY_PASSWORD_LENGTH);
+        assert!(password.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+}
@@ -1,10 +1,16 @@
-use actix_identity::Identity;
-use actix_web::get;
 use actix_web::web;
-use actix_web::web::{Data, Query};
+use actix_web::web::{Data, HttpResponse, Path, Query};
+use actix_web::{delete, get, post};
 
 use crate::auth;
+use crate::auth::activity::LoginDevice;
+use crate::auth::Identity;
+use crate::errors::ServiceError;
+use crate::quota::Usage;
 use crate::server::{Response, State};
+use crate::users::analytics::{AnalyticsFilter, OwnerAnalytics};
+use crate::users::calendar::{self, CalendarEvent, CalendarToken};
+use crate::users::export::{self, ExportFormat, ExportStatus, UserExport};
 use crate::users::{Filter, User};
 
 #[get("/users")]
@@ -25,7 +31,133 @@ async fn find_me(state: Data<State>, id: Identity) -> Response {
     http_ok_json!(user);
 }
 
+/// The devices and addresses the current user logged in from
+#[get("/users/me/devices")]
+async fn devices(state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let devices = LoginDevice::find_by_user(user.id, &state.db).await?;
+
+    http_ok_json!(devices);
+}
+
+/// The requests the current user made today, and the daily quota
+#[get("/usage")]
+async fn usage(state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    http_ok_json!(Usage::load(&user, &state.cache).await);
+}
+
+/// Compare the finished games of the current user, most recent first
+#[get("/users/me/games/analytics")]
+async fn game_analytics(
+    filter: Query<AnalyticsFilter>,
+    state: Data<State>,
+    id: Identity,
+) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let analytics = OwnerAnalytics::load(user.id, &filter, &state.db, &state.cache).await?;
+
+    http_ok_json!(analytics);
+}
+
+/// Create the secret calendar url of the current user, the previous url stops working
+#[post("/users/me/calendar")]
+async fn create_calendar_token(state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let token = CalendarToken::rotate(user.id, &state.db).await?;
+
+    http_created_json!(token);
+}
+
+#[delete("/users/me/calendar")]
+async fn revoke_calendar_token(state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    CalendarToken::revoke(user.id, &state.db).await?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+struct CalendarQuery {
+    token: String,
+}
+
+/// The upcoming games of a user as an iCalendar feed, authenticated with the calendar token
+#[get("/users/me/calendar.ics")]
+async fn calendar_feed(query: Query<CalendarQuery>, state: Data<State>) -> Response {
+    let user_id = match CalendarToken::find_user(&query.token, &state.db).await {
+        Ok(user_id) => user_id,
+        Err(sqlx::Error::RowNotFound) => return Err(ServiceError::Unauthorized),
+        Err(e) => return Err(e.into()),
+    };
+
+    let events = CalendarEvent::upcoming(user_id, &state.db).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .body(calendar::render(&events, chrono::Utc::now())))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Export everything that's stored about the current user, the archive is generated in the background
+#[get("/users/me/export")]
+async fn create_export(query: Query<ExportQuery>, state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let (export, created) = UserExport::create(user.id, query.format, &state.db).await?;
+    if created {
+        export::dispatch(export.id, &state.db, &state.cache).await;
+    }
+
+    Ok(HttpResponse::Accepted().json(export))
+}
+
+/// Download an export of the current user, the export is returned with HTTP 202 until it's ready
+#[get("/users/me/export/{export_id}")]
+async fn download_export(export_id: Path<i64>, state: Data<State>, id: Identity) -> Response {
+    let user = auth::get_user(&id)?;
+
+    let (export, content) = UserExport::find(*export_id, user.id, &state.db).await?;
+
+    match (export.status, content) {
+        (ExportStatus::Ready, Some(content)) => Ok(HttpResponse::Ok()
+            .content_type(export.format.content_type())
+            .header(
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"rustfuif-export-{}.{}\"",
+                    export.id,
+                    export.format.extension()
+                ),
+            )
+            .body(content)),
+        (ExportStatus::Failed, _) => Err(ServiceError::Conflict(format!(
+            "the export failed, start a new one: {}",
+            export.error.unwrap_or_default()
+        ))),
+        _ => Ok(HttpResponse::Accepted().json(export)),
+    }
+}
+
 pub fn register(cfg: &mut web::ServiceConfig) {
     cfg.service(find_all);
     cfg.service(find_me);
+    cfg.service(devices);
+    cfg.service(game_analytics);
+    cfg.service(usage);
+    cfg.service(create_calendar_token);
+    cfg.service(revoke_calendar_token);
+    cfg.service(calendar_feed);
+    cfg.service(create_export);
+    cfg.service(download_export);
 }
@@ -6,6 +6,83 @@ pub struct Validator<T>(T);
 
 pub trait Validate<T> {
     fn validate(&self) -> Result<(), ServiceError>;
+
+    /// every rule the value breaks, not just the first one
+    fn violations(&self) -> Vec<Violation> {
+        match self.validate() {
+            Ok(()) => Vec::new(),
+            Err(ServiceError::BadRequest(message)) => vec![Violation::new(None, message)],
+            Err(e) => vec![Violation::new(None, e.to_string())],
+        }
+    }
+
+    /// values that are allowed but probably a mistake
+    fn warnings(&self) -> Vec<Violation> {
+        Vec::new()
+    }
+}
+
+/// A rule that's broken by a payload
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Violation {
+    /// the invalid field, empty when the rule involves the whole payload
+    pub field: Option<&'static str>,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn new<S: Into<String>>(field: Option<&'static str>, message: S) -> Self {
+        Violation {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+/// Collects the violations of a payload
+#[derive(Debug, Default)]
+pub struct Violations(Vec<Violation>);
+
+impl Violations {
+    /// add a violation of a field when the check fails
+    pub fn check<S: Into<String>>(&mut self, valid: bool, field: &'static str, message: S) {
+        if !valid {
+            self.0.push(Violation::new(Some(field), message));
+        }
+    }
+
+    pub fn into_inner(self) -> Vec<Violation> {
+        self.0
+    }
+}
+
+/// fail with the first violation
+pub fn first_violation(violations: Vec<Violation>) -> Result<(), ServiceError> {
+    match violations.into_iter().next() {
+        Some(violation) => Err(ServiceError::BadRequest(violation.message)),
+        None => Ok(()),
+    }
+}
+
+/// The result of validating a payload without storing it
+#[derive(Debug, Serialize)]
+pub struct Preview {
+    pub valid: bool,
+    pub violations: Vec<Violation>,
+    /// these don't make the payload invalid
+    pub warnings: Vec<Violation>,
+}
+
+impl Preview {
+    pub fn new<T: Validate<T>>(payload: &T) -> Self {
+        let violations = payload.violations();
+
+        Preview {
+            valid: violations.is_empty(),
+            violations,
+            warnings: payload.warnings(),
+        }
+    }
 }
 
 impl<T> Validator<T> {
@@ -52,4 +129,16 @@ mod tests {
 
         assert!(valid.validate().is_ok());
     }
+
+    #[test]
+    fn preview_violations() {
+        assert!(Preview::new(&true).valid);
+
+        let preview = Preview::new(&false);
+        assert!(!preview.valid);
+        assert_eq!(
+            preview.violations,
+            vec![Violation::new(None, "invalid input")]
+        );
+    }
 }
@@ -0,0 +1,102 @@
+//! Webhook delivery in the background
+//!
+//! The webhooks are queued in Redis and delivered by a worker on every instance, a webhook that fails
+//! is retried a few times before it ends up in the dead letters, where an administrator can retry it.
+//! Without Redis the webhooks are sent right away, without retries beyond the ones of the HTTP client.
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::cache::{CacheHandle, Job, Queue};
+use crate::http::HttpClient;
+
+pub const QUEUE: &str = "webhooks";
+
+pub fn queue(cache: &CacheHandle) -> Queue {
+    cache.queue(QUEUE)
+}
+
+/// How many times a webhook is sent before it's moved to the dead letters
+const MAX_ATTEMPTS: u32 = 5;
+/// How long the worker waits for a webhook before checking the queue again
+const POLL_TIMEOUT: Duration = Duration::from_secs(5);
+/// The pause after the queue couldn't be reached, or after a failed delivery
+const BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    pub body: serde_json::Value,
+}
+
+/// Queue a webhook, it's sent right away when the queue can't be reached
+pub async fn dispatch<T: Serialize>(url: String, body: &T, http: &HttpClient, cache: &CacheHandle) {
+    let webhook = match serde_json::to_value(body) {
+        Ok(body) => Webhook { url, body },
+        Err(e) => {
+            error!("unable to serialize the webhook for {}: {}", url, e);
+            return;
+        }
+    };
+
+    if queue(cache).push(&webhook).await {
+        return;
+    }
+
+    if let Err(e) = deliver(&webhook, http).await {
+        error!("unable to send the webhook to {}: {}", webhook.url, e);
+    }
+}
+
+async fn deliver(webhook: &Webhook, http: &HttpClient) -> Result<(), String> {
+    let response = http
+        .send(http.post(&webhook.url).json(&webhook.body))
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "{} responded with {}",
+            webhook.url,
+            response.status()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Deliver the queued webhooks until the server stops
+pub fn start(http: HttpClient, cache: CacheHandle) {
+    actix_rt::spawn(async move {
+        let queue = queue(&cache);
+
+        let recovered = queue.recover().await;
+        if recovered > 0 {
+            info!("queued {} unfinished webhooks again", recovered);
+        }
+
+        loop {
+            if !cache.is_enabled().await {
+                actix_rt::time::delay_for(POLL_TIMEOUT).await;
+                continue;
+            }
+
+            let job: Job<Webhook> = match queue.pop(POLL_TIMEOUT).await {
+                Some(job) => job,
+                None => {
+                    actix_rt::time::delay_for(BACKOFF).await;
+                    continue;
+                }
+            };
+
+            match deliver(&job.delivery.payload, &http).await {
+                Ok(()) => queue.ack(job).await,
+                Err(e) => {
+                    warn!("unable to deliver webhook {}: {}", job.delivery.id, e);
+                    queue.fail(job, e, MAX_ATTEMPTS).await;
+                    actix_rt::time::delay_for(BACKOFF).await;
+                }
+            }
+        }
+    });
+}
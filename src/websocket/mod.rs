@@ -1,5 +1,8 @@
+pub mod moderation;
+pub mod outbox;
+pub mod protocol;
 pub mod queries;
 pub mod routes;
 pub mod server;
 
-pub use server::{Notification, Sale};
+pub use server::Sale;
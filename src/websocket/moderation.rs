@@ -0,0 +1,212 @@
+//! Moderation tools for game owners
+//!
+//! Game owners can mute users in the chat, pin an announcement and clear the activity ticker.
+//! The moderation state is stored in the database, so clients receive it again when they reconnect.
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres};
+
+/// An action sent by a game owner over the websocket
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum ModerationAction {
+    #[serde(rename_all = "camelCase")]
+    Mute {
+        user_id: i64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Unmute {
+        user_id: i64,
+    },
+    Pin {
+        message: String,
+    },
+    Unpin,
+    ClearTicker,
+}
+
+/// The longest announcement that can be pinned
+const MAX_ANNOUNCEMENT_LENGTH: usize = 500;
+
+impl ModerationAction {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            ModerationAction::Pin { message } if message.trim().is_empty() => {
+                Err(String::from("the announcement can not be empty"))
+            }
+            ModerationAction::Pin { message } if message.len() > MAX_ANNOUNCEMENT_LENGTH => {
+                Err(format!(
+                    "the announcement can not be longer than {} characters",
+                    MAX_ANNOUNCEMENT_LENGTH
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The current moderation state of a game
+#[derive(Debug, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Moderation {
+    pub game_id: i64,
+    pub pinned_announcement: Option<String>,
+    /// the activity before this moment should no longer be shown
+    pub ticker_cleared_at: Option<DateTime<Utc>>,
+    /// the users who are not allowed to chat
+    pub muted_users: Vec<i64>,
+}
+
+impl Moderation {
+    /// returns true when nothing has been moderated in the game
+    pub fn is_empty(&self) -> bool {
+        self.pinned_announcement.is_none()
+            && self.ticker_cleared_at.is_none()
+            && self.muted_users.is_empty()
+    }
+
+    #[tracing::instrument(name = "Moderation::find", skip(db))]
+    pub async fn find(game_id: i64, db: &Pool<Postgres>) -> Result<Moderation, sqlx::Error> {
+        let row = sqlx::query!(
+            "SELECT pinned_announcement, ticker_cleared_at FROM game_moderation WHERE game_id = $1",
+            game_id
+        )
+        .fetch_optional(db)
+        .await?;
+
+        let muted_users = sqlx::query!(
+            "SELECT user_id FROM muted_users WHERE game_id = $1 ORDER BY created_at",
+            game_id
+        )
+        .fetch_all(db)
+        .await?
+        .into_iter()
+        .map(|row| row.user_id)
+        .collect();
+
+        let (pinned_announcement, ticker_cleared_at) = match row {
+            Some(row) => (row.pinned_announcement, row.ticker_cleared_at),
+            None => (None, None),
+        };
+
+        Ok(Moderation {
+            game_id,
+            pinned_announcement,
+            ticker_cleared_at,
+            muted_users,
+        })
+    }
+
+    /// Store the result of a moderation action and return the new moderation state
+    #[tracing::instrument(name = "Moderation::apply", skip(db))]
+    pub async fn apply(
+        game_id: i64,
+        moderator_id: i64,
+        action: &ModerationAction,
+        db: &Pool<Postgres>,
+    ) -> Result<Moderation, sqlx::Error> {
+        match action {
+            ModerationAction::Mute { user_id } => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO muted_users (game_id, user_id, muted_by)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (game_id, user_id) DO NOTHING
+                    "#,
+                    game_id,
+                    user_id,
+                    moderator_id
+                )
+                .execute(db)
+                .await?;
+            }
+            ModerationAction::Unmute { user_id } => {
+                sqlx::query!(
+                    "DELETE FROM muted_users WHERE game_id = $1 AND user_id = $2",
+                    game_id,
+                    user_id
+                )
+                .execute(db)
+                .await?;
+            }
+            ModerationAction::Pin { message } => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO game_moderation (game_id, pinned_announcement)
+                    VALUES ($1, $2)
+                    ON CONFLICT (game_id) DO UPDATE
+                    SET pinned_announcement = EXCLUDED.pinned_announcement, updated_at = NOW()
+                    "#,
+                    game_id,
+                    message
+                )
+                .execute(db)
+                .await?;
+            }
+            ModerationAction::Unpin => {
+                sqlx::query!(
+                    r#"
+                    UPDATE game_moderation
+                    SET pinned_announcement = NULL, updated_at = NOW()
+                    WHERE game_id = $1
+                    "#,
+                    game_id
+                )
+                .execute(db)
+                .await?;
+            }
+            ModerationAction::ClearTicker => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO game_moderation (game_id, ticker_cleared_at)
+                    VALUES ($1, NOW())
+                    ON CONFLICT (game_id) DO UPDATE
+                    SET ticker_cleared_at = EXCLUDED.ticker_cleared_at, updated_at = NOW()
+                    "#,
+                    game_id
+                )
+                .execute(db)
+                .await?;
+            }
+        }
+
+        Moderation::find(game_id, db).await
+    }
+}
+
+/// Sent to the users in a game when the moderation state changes, and when they connect
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationUpdate {
+    /// the action that changed the state, empty when sent on connect
+    pub action: Option<ModerationAction>,
+    pub moderation: Moderation,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_actions() {
+        let action: ModerationAction =
+            serde_json::from_str(r#"{"action": "mute", "userId": 3}"#).unwrap();
+        assert_eq!(action, ModerationAction::Mute { user_id: 3 });
+
+        let action: ModerationAction =
+            serde_json::from_str(r#"{"action": "clearTicker"}"#).unwrap();
+        assert_eq!(action, ModerationAction::ClearTicker);
+    }
+
+    #[test]
+    fn validate_announcement() {
+        let pin = |message: &str| ModerationAction::Pin {
+            message: message.to_string(),
+        };
+
+        assert!(pin("happy hour at 22h").validate().is_ok());
+        assert!(pin("  ").validate().is_err());
+        assert!(pin(&"a".repeat(MAX_ANNOUNCEMENT_LENGTH + 1))
+            .validate()
+            .is_err());
+    }
+}
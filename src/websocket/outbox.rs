@@ -0,0 +1,228 @@
+//! The notifications that wait for a slow websocket client
+//!
+//! A websocket actor only reads its mailbox as fast as its client reads the socket. When the mailbox is full,
+//! the notifications of the connection wait in its outbox instead of piling up in the mailbox.
+//! A price update replaces the price updates that are still waiting, as it supersedes them.
+//! A full outbox drops the oldest notification with the lowest priority to make room.
+//! A client that can't keep up is disconnected, when its outbox has to drop a critical notification
+//! or when it stays backed up too long.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use actix::prelude::*;
+
+use crate::websocket::protocol::Priority;
+use crate::websocket::server::Notification;
+
+/// the amount of notifications that can wait for a connection
+const CAPACITY: usize = 64;
+/// how long a connection can stay backed up before it's disconnected
+const MAX_BACKLOG: Duration = Duration::from_secs(30);
+
+/// the amount of notifications dropped for slow clients, since the server started
+static DROPPED_NOTIFICATIONS: AtomicUsize = AtomicUsize::new(0);
+/// the amount of slow clients that were disconnected, since the server started
+static SLOW_DISCONNECTS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: VecDeque<Notification>,
+    /// when the mailbox of the connection filled up, empty while the connection keeps up
+    backed_up_since: Option<Instant>,
+    /// a critical notification was dropped, the connection is disconnected
+    overflowed: bool,
+}
+
+impl Outbox {
+    /// Hand a notification to the connection, the notifications that are waiting go first
+    pub fn deliver(&mut self, recipient: &Recipient<Notification>, notification: Notification) {
+        self.flush(recipient);
+
+        if !self.queue.is_empty() {
+            self.push(notification, Instant::now());
+            return;
+        }
+
+        if let Err(SendError::Full(notification)) = recipient.try_send(notification) {
+            self.push(notification, Instant::now());
+        }
+    }
+
+    /// Hand the waiting notifications to the connection, as far as its mailbox allows
+    pub fn flush(&mut self, recipient: &Recipient<Notification>) {
+        while let Some(notification) = self.queue.pop_front() {
+            match recipient.try_send(notification) {
+                Ok(()) => (),
+                Err(SendError::Full(notification)) => {
+                    self.queue.push_front(notification);
+                    return;
+                }
+                // the sweep removes the session
+                Err(SendError::Closed(_)) => self.queue.clear(),
+            }
+        }
+
+        self.backed_up_since = None;
+    }
+
+    /// Queue a notification, it replaces the waiting price updates when it's a price update itself
+    fn push(&mut self, notification: Notification, now: Instant) {
+        let notification = match notification {
+            Notification::PriceUpdate(mut update) => {
+                let waiting = self.queue.len();
+                self.queue
+                    .retain(|notification| !matches!(notification, Notification::PriceUpdate(_)));
+
+                let superseded = waiting - self.queue.len();
+                if superseded > 0 {
+                    DROPPED_NOTIFICATIONS.fetch_add(superseded, Ordering::Relaxed);
+                    // the client missed the changes of the dropped updates
+                    update.snapshot = true;
+                }
+                Notification::PriceUpdate(update)
+            }
+            notification => notification,
+        };
+
+        if self.queue.len() >= CAPACITY {
+            DROPPED_NOTIFICATIONS.fetch_add(1, Ordering::Relaxed);
+
+            // the first of the least important notifications is the oldest one
+            let (index, lowest) = match self
+                .queue
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, waiting)| waiting.priority())
+            {
+                Some((index, waiting)) => (index, waiting.priority()),
+                None => return,
+            };
+            if notification.priority() < lowest {
+                return;
+            }
+            if lowest == Priority::Critical {
+                self.overflowed = true;
+            }
+            self.queue.remove(index);
+        }
+
+        self.backed_up_since.get_or_insert(now);
+        self.queue.push_back(notification);
+    }
+
+    /// true when the client can't keep up with its notifications
+    pub fn is_too_slow(&self, now: Instant) -> bool {
+        self.overflowed
+            || self
+                .backed_up_since
+                .map_or(false, |since| now.duration_since(since) > MAX_BACKLOG)
+    }
+
+    /// true when notifications are waiting
+    pub fn is_backed_up(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// count a slow client that was disconnected
+    pub fn disconnected() {
+        SLOW_DISCONNECTS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dropped_notifications() -> usize {
+        DROPPED_NOTIFICATIONS.load(Ordering::Relaxed)
+    }
+
+    pub fn slow_disconnects() -> usize {
+        SLOW_DISCONNECTS.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::MarketStatus;
+    use crate::websocket::server::{GameId, PriceUpdate};
+
+    fn price_update(tick: i64) -> Notification {
+        Notification::PriceUpdate(PriceUpdate {
+            market_status: MarketStatus::Regular,
+            game_id: GameId(1),
+            tick,
+            prices: Vec::new(),
+            snapshot: false,
+        })
+    }
+
+    #[test]
+    fn superseded_price_updates() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+
+        outbox.push(price_update(1), now);
+        outbox.push(Notification::ConnectionCount(2), now);
+        outbox.push(price_update(2), now);
+
+        assert_eq!(outbox.queue.len(), 2);
+        assert!(matches!(outbox.queue[0], Notification::ConnectionCount(2)));
+        match &outbox.queue[1] {
+            Notification::PriceUpdate(update) => {
+                assert_eq!(update.tick, 2);
+                assert!(update.snapshot);
+            }
+            notification => panic!("unexpected notification: {:?}", notification),
+        }
+    }
+
+    #[test]
+    fn slow_clients() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        assert!(!outbox.is_too_slow(now));
+
+        outbox.push(Notification::ConnectionCount(1), now);
+        assert!(outbox.is_backed_up());
+        assert!(!outbox.is_too_slow(now + Duration::from_secs(5)));
+        assert!(outbox.is_too_slow(now + MAX_BACKLOG + Duration::from_secs(1)));
+
+        let mut outbox = Outbox::default();
+        for device_id in 0..CAPACITY as i64 {
+            outbox.push(Notification::DeviceRevoked(device_id), now);
+        }
+        assert!(!outbox.is_too_slow(now));
+
+        outbox.push(Notification::DeviceRevoked(CAPACITY as i64), now);
+        assert_eq!(outbox.queue.len(), CAPACITY);
+        assert!(outbox.is_too_slow(now));
+    }
+
+    #[test]
+    fn low_priority_first() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+
+        outbox.push(Notification::DeviceRevoked(1), now);
+        for count in 1..CAPACITY {
+            outbox.push(Notification::ConnectionCount(count), now);
+        }
+
+        // the oldest presence notification makes room
+        outbox.push(price_update(1), now);
+        assert_eq!(outbox.queue.len(), CAPACITY);
+        assert!(matches!(outbox.queue[1], Notification::ConnectionCount(2)));
+        assert!(matches!(
+            outbox.queue[CAPACITY - 1],
+            Notification::PriceUpdate(_)
+        ));
+
+        // a presence notification only replaces an older one
+        outbox.push(Notification::ConnectionCount(CAPACITY), now);
+        assert!(matches!(outbox.queue[1], Notification::ConnectionCount(3)));
+        assert!(matches!(
+            outbox.queue[CAPACITY - 1],
+            Notification::ConnectionCount(CAPACITY)
+        ));
+        assert!(matches!(outbox.queue[0], Notification::DeviceRevoked(1)));
+        assert!(!outbox.is_too_slow(now));
+    }
+}
@@ -0,0 +1,229 @@
+//! Messages exchanged between the websocket clients and the server
+//!
+//! After connecting, a client can send a `hello` message declaring the protocol version
+//! it speaks and the features it would like to use. The server answers with a `Welcome`
+//! notification containing the granted features, or rejects the connection when the
+//! protocol version is unknown.
+//!
+//! Constrained displays can ask for a minimum priority in their `hello`, the notifications
+//! with a lower priority aren't sent to them.
+//!
+//! Players can place an order with a `purchase` message, the new sale is pushed to the game
+//! like any other order and a rejected order is answered with a `PurchaseRejected` notification.
+use std::collections::{HashMap, HashSet};
+
+use crate::websocket::moderation::ModerationAction;
+
+/// The protocol versions this server understands
+pub const SUPPORTED_PROTOCOL_VERSIONS: [u32; 1] = [1];
+
+/// Optional features a client can ask for
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Feature {
+    /// only receive the beverages with a new price in price updates
+    Deltas,
+    /// receive MessagePack encoded binary messages instead of JSON
+    Msgpack,
+    /// send and receive chat messages
+    Chat,
+    /// features from newer clients are ignored
+    #[serde(other)]
+    Unknown,
+}
+
+/// How important a notification is, the send queue of a slow client drops the least important ones first
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum Priority {
+    /// presence, like the amount of connected users
+    Low,
+    /// price updates, sales and the other notifications about the game
+    Normal,
+    /// market crashes, revoked devices and anything else a client shouldn't miss
+    Critical,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Low
+    }
+}
+
+/// The features this server can grant
+const SUPPORTED_FEATURES: [Feature; 1] = [Feature::Deltas];
+
+/// Messages sent by the websocket clients
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ClientMessage {
+    #[serde(rename_all = "camelCase")]
+    Hello {
+        protocol_version: u32,
+        #[serde(default)]
+        features: Vec<Feature>,
+        /// the notifications with a lower priority aren't sent to the client
+        #[serde(default)]
+        min_priority: Priority,
+    },
+    /// Moderate the game, only allowed for the game owner
+    Moderate(ModerationAction),
+    /// Place an order, like `POST /games/{id}/sales`
+    Purchase {
+        #[serde(deserialize_with = "slot_keys")]
+        slots: HashMap<i16, i32>,
+        /// on top of the beverages, in cents
+        #[serde(default)]
+        tip: i64,
+    },
+}
+
+/// The slots of a purchase are keyed by their number as a string, like in the body of a sale
+///
+/// A message with a type tag is buffered before it's parsed, and a buffered key isn't read as a number.
+fn slot_keys<'de, D>(deserializer: D) -> Result<HashMap<i16, i32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let slots: HashMap<String, i32> = serde::Deserialize::deserialize(deserializer)?;
+
+    slots
+        .into_iter()
+        .map(|(slot_no, amount)| match slot_no.parse() {
+            Ok(slot_no) => Ok((slot_no, amount)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "invalid beverage slot {}",
+                slot_no
+            ))),
+        })
+        .collect()
+}
+
+/// Reply to a successful `hello`
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Welcome {
+    pub protocol_version: u32,
+    pub features: HashSet<Feature>,
+    pub min_priority: Priority,
+    /// the amount of notifications the server has handled, used to detect missed messages
+    pub sequence: u64,
+}
+
+/// Reply to a `hello` with an unknown protocol version, the connection is closed afterwards
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HandshakeRejected {
+    pub reason: String,
+    pub supported_versions: Vec<u32>,
+}
+
+/// Returns the granted features, or the rejection when the protocol version isn't supported
+pub fn negotiate(
+    protocol_version: u32,
+    features: &[Feature],
+) -> Result<HashSet<Feature>, HandshakeRejected> {
+    if !SUPPORTED_PROTOCOL_VERSIONS.contains(&protocol_version) {
+        return Err(HandshakeRejected {
+            reason: format!("unsupported protocol version {}", protocol_version),
+            supported_versions: SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+        });
+    }
+
+    Ok(features
+        .iter()
+        .filter(|feature| SUPPORTED_FEATURES.contains(feature))
+        .copied()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hello() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type": "hello", "protocolVersion": 1, "features": ["deltas", "chat", "teleport"]}"#,
+        )
+        .unwrap();
+
+        match message {
+            ClientMessage::Hello {
+                protocol_version,
+                features,
+                min_priority,
+            } => {
+                assert_eq!(min_priority, Priority::Low);
+                assert_eq!(protocol_version, 1);
+                assert_eq!(
+                    features,
+                    vec![Feature::Deltas, Feature::Chat, Feature::Unknown]
+                );
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[test]
+    fn parse_min_priority() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type": "hello", "protocolVersion": 1, "minPriority": "critical"}"#,
+        )
+        .unwrap();
+
+        match message {
+            ClientMessage::Hello { min_priority, .. } => {
+                assert_eq!(min_priority, Priority::Critical)
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+        assert!(Priority::Low < Priority::Normal && Priority::Normal < Priority::Critical);
+    }
+
+    #[test]
+    fn parse_moderate() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type": "moderate", "action": "pin", "message": "last round at 2am"}"#,
+        )
+        .unwrap();
+
+        match message {
+            ClientMessage::Moderate(action) => assert_eq!(
+                action,
+                ModerationAction::Pin {
+                    message: String::from("last round at 2am")
+                }
+            ),
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[test]
+    fn parse_purchase() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"type": "purchase", "slots": {"0": 2, "3": 1}}"#).unwrap();
+
+        match message {
+            ClientMessage::Purchase { slots, tip } => {
+                assert_eq!(slots.len(), 2);
+                assert_eq!(slots.get(&0), Some(&2));
+                assert_eq!(slots.get(&3), Some(&1));
+                assert_eq!(tip, 0);
+            }
+            message => panic!("unexpected message: {:?}", message),
+        }
+    }
+
+    #[test]
+    fn negotiate_features() {
+        let granted = negotiate(1, &[Feature::Deltas, Feature::Msgpack, Feature::Unknown]).unwrap();
+        assert_eq!(granted.len(), 1);
+        assert!(granted.contains(&Feature::Deltas));
+
+        assert!(negotiate(1, &[]).unwrap().is_empty());
+
+        let rejection = negotiate(2, &[Feature::Deltas]).unwrap_err();
+        assert_eq!(rejection.supported_versions, vec![1]);
+    }
+}
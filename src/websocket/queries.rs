@@ -15,6 +15,46 @@ impl Handler<ActiveSessionCount> for NotificationServer {
     }
 }
 
+/// returns the amount of notifications handled by the server
+#[derive(Message)]
+#[rtype(u64)]
+pub struct CurrentSequence;
+
+impl Handler<CurrentSequence> for NotificationServer {
+    type Result = u64;
+
+    fn handle(&mut self, _: CurrentSequence, _: &mut Context<Self>) -> Self::Result {
+        self.sequence()
+    }
+}
+
+/// returns the state of the server, used by the readiness probe
+#[derive(Message)]
+#[rtype(NotifierHealth)]
+pub struct Health;
+
+#[derive(Serialize, Debug, MessageResponse)]
+#[serde(rename_all = "camelCase")]
+pub struct NotifierHealth {
+    pub sessions: usize,
+    /// the amount of handled notifications
+    pub sequence: u64,
+    /// the amount of times the server was restarted after it stopped
+    pub restarts: usize,
+}
+
+impl Handler<Health> for NotificationServer {
+    type Result = NotifierHealth;
+
+    fn handle(&mut self, _: Health, _: &mut Context<Self>) -> Self::Result {
+        NotifierHealth {
+            sessions: self.session_count(),
+            sequence: self.sequence(),
+            restarts: self.restarts(),
+        }
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "Result<Vec<User>, std::io::Error>")]
 pub struct ConnectedUsers;
@@ -1,15 +1,23 @@
+use std::collections::{HashMap, HashSet};
 use std::time::{Duration, Instant};
 
 use actix::prelude::*;
-use actix_identity::Identity;
 use actix_web::web::{Data, Path};
 use actix_web::{web, HttpRequest};
 use actix_web_actors::ws;
 
 use crate::auth;
+use crate::auth::Identity;
+use crate::games::devices::{self, Device};
 use crate::games::Game;
+use crate::market::BeveragePrice;
 use crate::server::State;
+use crate::transactions;
+use crate::transactions::models::NewSale;
 use crate::users::User;
+use crate::websocket::moderation::ModerationAction;
+use crate::websocket::protocol::{self, ClientMessage, Feature, Priority, Welcome};
+use crate::websocket::queries::CurrentSequence;
 use crate::websocket::server;
 use crate::websocket::server::{ConnectionType, GameId, SessionId};
 
@@ -19,6 +27,8 @@ const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// route used for game updates
+///
+/// Display devices connect with their device token instead of a session
 pub async fn game_route(
     req: HttpRequest,
     stream: web::Payload,
@@ -26,15 +36,28 @@ pub async fn game_route(
     id: Identity,
     state: Data<State>,
 ) -> crate::server::Response {
-    let mut user = auth::get_user(&id)?;
+    let mut printer = false;
+    let (user, device_id) = match devices::token(&req) {
+        Some(token) => {
+            let device = Device::authenticate(*game_id, &token, &state.db).await?;
+            printer = device.is_printer();
+            (device.as_user(), Some(device.device.id))
+        }
+        None => {
+            let mut user = auth::get_user(&id)?;
 
-    if !Game::verify_user_participation(*game_id, user.id, &state.db).await? && !user.is_admin {
-        forbidden!("you are not in this game");
-    }
+            if !Game::verify_user_participation(*game_id, user.id, &state.db).await?
+                && !user.is_admin
+            {
+                forbidden!("you are not in this game");
+            }
 
-    // When an administrator connects to this route, his admin flag is set to false so he only receives normal user notifications
-    // This is a temporary workaround and should be changed once the server knows if a connected user wants game updates or admin updates
-    user.is_admin = false;
+            // When an administrator connects to this route, his admin flag is set to false so he only receives normal user notifications
+            // This is a temporary workaround and should be changed once the server knows if a connected user wants game updates or admin updates
+            user.is_admin = false;
+            (user, None)
+        }
+    };
 
     ws::start(
         WebsocketConnection {
@@ -42,7 +65,12 @@ pub async fn game_route(
             hb: Instant::now(),
             connection_type: ConnectionType::GameConnection(GameId(*game_id)),
             user,
+            device_id,
+            printer,
             notifier: state.notifier.clone(),
+            state: state.clone(),
+            features: HashSet::new(),
+            min_priority: Priority::default(),
         },
         &req,
         stream,
@@ -69,7 +97,12 @@ pub async fn admin_route(
             hb: Instant::now(),
             connection_type: ConnectionType::AdminConnection,
             user,
+            device_id: None,
+            printer: false,
             notifier: state.notifier.clone(),
+            state: state.clone(),
+            features: HashSet::new(),
+            min_priority: Priority::default(),
         },
         &req,
         stream,
@@ -88,8 +121,18 @@ struct WebsocketConnection {
     connection_type: ConnectionType,
     /// Connected user
     user: User,
+    /// the display device, these only watch the game
+    device_id: Option<i64>,
+    /// the device prints the tickets of the new orders
+    printer: bool,
     /// notification server
     notifier: Addr<server::NotificationServer>,
+    /// used to place the orders of the player
+    state: Data<State>,
+    /// the features granted during the handshake
+    features: HashSet<Feature>,
+    /// the notifications with a lower priority aren't sent to the client
+    min_priority: Priority,
 }
 
 impl Actor for WebsocketConnection {
@@ -100,7 +143,19 @@ impl Actor for WebsocketConnection {
     fn started(&mut self, ctx: &mut Self::Context) {
         // we'll start heartbeat process on session start.
         self.hb(ctx);
+        self.register(ctx);
+        debug!("{} connected to the websocket", self.user.username);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.notifier.do_send(server::Disconnect { id: self.id });
+        Running::Stop
+    }
+}
 
+impl WebsocketConnection {
+    /// Register the connection with the notification server, this is done again when it restarted
+    fn register(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
         // register self in notification server. `AsyncContext::wait` register
         // future within context, but context waits until this future resolves
         // before processing any other events.
@@ -112,6 +167,7 @@ impl Actor for WebsocketConnection {
                 addr: addr.recipient(),
                 user: self.user.clone(),
                 connection_type: self.connection_type,
+                device_id: self.device_id,
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -126,12 +182,6 @@ impl Actor for WebsocketConnection {
                 fut::ready(())
             })
             .wait(ctx);
-        debug!("{} connected to the websocket", self.user.username);
-    }
-
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        self.notifier.do_send(server::Disconnect { id: self.id });
-        Running::Stop
     }
 }
 
@@ -140,6 +190,46 @@ impl Handler<server::Notification> for WebsocketConnection {
     type Result = ();
 
     fn handle(&mut self, notification: server::Notification, ctx: &mut Self::Context) {
+        if notification.priority() < self.min_priority {
+            return;
+        }
+        let shutdown = matches!(notification, server::Notification::ServerShutdown { .. });
+        let notification = match notification {
+            server::Notification::PriceUpdate(mut update) => {
+                if self.features.contains(&Feature::Deltas) && !update.snapshot {
+                    update.prices.retain(BeveragePrice::changed);
+                } else {
+                    update.snapshot = true;
+                }
+                server::Notification::PriceUpdate(update)
+            }
+            server::Notification::DeviceRevoked(_) => {
+                debug!("{} has been revoked", self.user);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some(String::from("this device has been revoked")),
+                }));
+                ctx.stop();
+                return;
+            }
+            server::Notification::TooSlow => {
+                debug!("{} can't keep up with the notifications", self.user);
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Again,
+                    description: Some(String::from("the connection is too slow")),
+                }));
+                ctx.stop();
+                return;
+            }
+            server::Notification::PrintTicket(_) if !self.printer => return,
+            server::Notification::Reconnect => {
+                debug!("{} registers again with the notification server", self.user);
+                self.register(ctx);
+                return;
+            }
+            notification => notification,
+        };
+
         let json = match serde_json::to_string(&notification) {
             Ok(json) => json,
             Err(error) => {
@@ -152,6 +242,14 @@ impl Handler<server::Notification> for WebsocketConnection {
             }
         };
         ctx.text(json);
+
+        if shutdown {
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Restart,
+                description: Some(String::from("the server is restarting")),
+            }));
+            ctx.stop();
+        }
     }
 }
 
@@ -177,11 +275,14 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(_) => {
-                debug!("ignoring incoming messages for now");
-                ctx.close(Some(ws::CloseReason::from(ws::CloseCode::Unsupported)));
-                ctx.stop();
-            }
+            ws::Message::Text(text) => match serde_json::from_str::<ClientMessage>(&text) {
+                Ok(message) => self.handle_message(message, ctx),
+                Err(error) => {
+                    debug!("unsupported websocket message: {}", error);
+                    ctx.close(Some(ws::CloseReason::from(ws::CloseCode::Unsupported)));
+                    ctx.stop();
+                }
+            },
             ws::Message::Binary(_) => {
                 debug!("Unexpected binary");
                 ctx.close(Some(ws::CloseReason::from(ws::CloseCode::Unsupported)));
@@ -200,6 +301,173 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketConnecti
 }
 
 impl WebsocketConnection {
+    fn handle_message(&mut self, message: ClientMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match message {
+            ClientMessage::Hello {
+                protocol_version,
+                features,
+                min_priority,
+            } => self.handshake(protocol_version, &features, min_priority, ctx),
+            ClientMessage::Moderate(action) => self.moderate(action, ctx),
+            ClientMessage::Purchase { slots, tip } => self.purchase(slots, tip, ctx),
+        }
+    }
+
+    /// place an order, the new sale reaches the player with the other notifications of the game
+    fn purchase(
+        &mut self,
+        slots: HashMap<i16, i32>,
+        tip: i64,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if self.device_id.is_some() {
+            Handler::handle(
+                self,
+                server::Notification::PurchaseRejected(String::from(
+                    "display devices can't place orders",
+                )),
+                ctx,
+            );
+            return;
+        }
+
+        let game_id = match self.connection_type {
+            ConnectionType::GameConnection(game_id) => game_id,
+            ConnectionType::AdminConnection => {
+                debug!("{} tried to purchase without joining a game", self.user);
+                return;
+            }
+        };
+
+        let sale = NewSale {
+            user_id: self.user.id,
+            game_id: game_id.0,
+            slots,
+            tip,
+        };
+        let user = self.user.clone();
+        let state = self.state.clone();
+
+        async move { transactions::routes::purchase(&sale, &user, &state).await }
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                if let Err(e) = res {
+                    debug!(
+                        "the order of {} over the websocket was rejected: {}",
+                        act.user, e
+                    );
+                    Handler::handle(
+                        act,
+                        server::Notification::PurchaseRejected(e.to_string()),
+                        ctx,
+                    );
+                }
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
+    /// let the notification server apply a moderation action of the game owner
+    fn moderate(&mut self, action: ModerationAction, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.device_id.is_some() {
+            Handler::handle(
+                self,
+                server::Notification::ModerationRejected(String::from(
+                    "display devices can't moderate a game",
+                )),
+                ctx,
+            );
+            return;
+        }
+
+        let game_id = match self.connection_type {
+            ConnectionType::GameConnection(game_id) => game_id,
+            ConnectionType::AdminConnection => {
+                debug!("{} tried to moderate without joining a game", self.user);
+                return;
+            }
+        };
+
+        self.notifier
+            .send(server::Moderate {
+                game_id,
+                user: self.user.clone(),
+                action,
+            })
+            .into_actor(self)
+            .then(|res, act, ctx| {
+                let reason = match res {
+                    Ok(Ok(())) => None,
+                    Ok(Err(e)) => Some(e.to_string()),
+                    Err(e) => {
+                        error!("unable to moderate the game: {}", e);
+                        Some(String::from("moderation is unavailable"))
+                    }
+                };
+
+                if let Some(reason) = reason {
+                    Handler::handle(act, server::Notification::ModerationRejected(reason), ctx);
+                }
+                fut::ready(())
+            })
+            .spawn(ctx);
+    }
+
+    /// grant the supported features and reply with the current sequence number
+    fn handshake(
+        &mut self,
+        protocol_version: u32,
+        features: &[Feature],
+        min_priority: Priority,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let features = match protocol::negotiate(protocol_version, features) {
+            Ok(features) => features,
+            Err(rejection) => {
+                debug!("{} uses an unsupported protocol", self.user);
+                let reason = rejection.reason.clone();
+                Handler::handle(
+                    self,
+                    server::Notification::HandshakeRejected(rejection),
+                    ctx,
+                );
+                ctx.close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Unsupported,
+                    description: Some(reason),
+                }));
+                ctx.stop();
+                return;
+            }
+        };
+
+        self.features = features.clone();
+        self.min_priority = min_priority;
+
+        self.notifier
+            .send(CurrentSequence)
+            .into_actor(self)
+            .then(move |res, act, ctx| {
+                match res {
+                    Ok(sequence) => Handler::handle(
+                        act,
+                        server::Notification::Welcome(Welcome {
+                            protocol_version,
+                            features,
+                            min_priority,
+                            sequence,
+                        }),
+                        ctx,
+                    ),
+                    Err(e) => {
+                        error!("unable to complete the websocket handshake: {}", e);
+                        ctx.stop();
+                    }
+                }
+                fut::ready(())
+            })
+            .wait(ctx);
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
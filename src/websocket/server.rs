@@ -1,14 +1,39 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
-use rand::{self, rngs::ThreadRng, Rng};
-
-use crate::market::MarketStatus;
+use sqlx::{Pool, Postgres};
+
+use tokio::sync::broadcast::RecvError;
+
+use crate::auth::activity::SuspiciousLogin;
+use crate::errors::ServiceError;
+use crate::events::{DomainEvent, EventBus};
+use crate::games::blackouts::Blackout;
+use crate::games::Game;
+use crate::market::{BeveragePrice, MarketStatus};
+use crate::teams::TeamLeaderboard;
+use crate::transactions::drink_limits::DrinkWarning;
+use crate::transactions::splits::OrderSplit;
 use crate::transactions::Transaction;
 use crate::users::User;
+use crate::websocket::moderation::{Moderation, ModerationAction, ModerationUpdate};
+use crate::websocket::outbox::Outbox;
+use crate::websocket::protocol::{HandshakeRejected, Priority, Welcome};
 use crate::websocket::queries::ActiveGamesResponse;
 
-#[derive(Debug, Copy, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// How often the sessions of vanished clients are swept
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the notifications waiting for slow clients are handed to their connections
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the amount of sessions removed by the sweep, since the server started
+static SWEPT_SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Copy, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GameId(pub i64);
 
 #[derive(Debug, Copy, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, MessageResponse)]
@@ -33,6 +58,8 @@ pub struct Connect {
     pub addr: Recipient<Notification>,
     pub user: User,
     pub connection_type: ConnectionType,
+    /// the registered display device, when the connection isn't made by a logged in user
+    pub device_id: Option<i64>,
 }
 
 #[derive(Message)]
@@ -41,28 +68,46 @@ pub struct Disconnect {
     pub id: SessionId,
 }
 
+/// Moderate a game, the user must be the game owner
+#[derive(Message)]
+#[rtype(result = "Result<(), ServiceError>")]
+pub struct Moderate {
+    pub game_id: GameId,
+    pub user: User,
+    pub action: ModerationAction,
+}
+
 #[derive(Debug)]
 struct ConnectedUser {
     recipient: Recipient<Notification>,
     user: User,
+    device_id: Option<i64>,
+    /// the notifications that don't fit in the mailbox of the connection
+    outbox: RefCell<Outbox>,
 }
 
 impl ConnectedUser {
-    fn new(recipient: Recipient<Notification>, user: User) -> Self {
-        ConnectedUser { recipient, user }
-    }
-
-    fn send(&self, message: Notification) -> Result<(), actix::prelude::SendError<Notification>> {
-        self.recipient.do_send(message)
+    fn new(recipient: Recipient<Notification>, user: User, device_id: Option<i64>) -> Self {
+        ConnectedUser {
+            recipient,
+            user,
+            device_id,
+            outbox: RefCell::new(Outbox::default()),
+        }
     }
 
-    fn user(&self) -> &User {
-        &self.user
+    fn send(&self, message: Notification) {
+        self.outbox.borrow_mut().deliver(&self.recipient, message);
     }
 
     fn is_admin(&self) -> bool {
         self.user.is_admin
     }
+
+    /// false when the websocket actor is gone
+    fn is_connected(&self) -> bool {
+        self.recipient.connected()
+    }
 }
 
 /// `NotificationServer` manages price updates/new sales
@@ -71,30 +116,33 @@ pub struct NotificationServer {
     /// The `ConnectedUser` contains the user and the actix  recipient address.
     sessions: HashMap<SessionId, ConnectedUser>,
     games: HashMap<GameId, HashSet<SessionId>>,
-    rng: ThreadRng,
+    /// the id of the next session, ids aren't reused while the server runs
+    next_session: usize,
+    /// the amount of handled notifications
+    sequence: u64,
+    /// used to store and load the moderation state of the games
+    db: Pool<Postgres>,
+    /// the amount of times the supervisor restarted the server
+    restarts: usize,
 }
 
 #[allow(dead_code)]
 impl NotificationServer {
-    pub fn new() -> Self {
+    pub fn new(db: Pool<Postgres>) -> Self {
         NotificationServer {
             sessions: HashMap::new(),
             games: HashMap::new(),
-            rng: rand::thread_rng(),
+            next_session: 1,
+            sequence: 0,
+            db,
+            restarts: 0,
         }
     }
 
     /// send a message to all connected users
     pub fn broadcast(&self, notification: Notification) {
         for (_, recipient) in self.sessions.iter() {
-            if let Err(error) = recipient.send(notification.clone()) {
-                error!(
-                    "Unable to notify {} about {:?}, error: {}",
-                    recipient.user(),
-                    notification,
-                    error
-                );
-            }
+            recipient.send(notification.clone());
         }
     }
 
@@ -103,7 +151,25 @@ impl NotificationServer {
         if let Some(sessions) = self.games.get(&game_id) {
             for id in sessions {
                 if let Some(addr) = self.sessions.get(id) {
-                    let _ = addr.send(notification.clone());
+                    addr.send(notification.clone());
+                }
+            }
+        }
+    }
+
+    /// send every user in a game the new prices of their beverages in a single message
+    pub fn notify_prices(&self, update: PriceUpdate) {
+        if let Some(sessions) = self.games.get(&update.game_id) {
+            for id in sessions {
+                if let Some(connection) = self.sessions.get(id) {
+                    let prices = update
+                        .prices
+                        .iter()
+                        .filter(|price| price.user_id == connection.user.id)
+                        .copied()
+                        .collect();
+
+                    connection.send(Notification::PriceUpdate(PriceUpdate { prices, ..update }));
                 }
             }
         }
@@ -114,20 +180,54 @@ impl NotificationServer {
         self.sessions
             .iter()
             .filter(|&(_, user)| user.is_admin())
-            .for_each(|(_, admin)| {
-                let _ = admin.send(notification.clone());
-            });
+            .for_each(|(_, admin)| admin.send(notification.clone()));
     }
 
+    /// send a message to a user, the display devices of their games don't receive it
     pub fn notify_user(&self, notification: Notification, user_id: i64) {
         self.sessions
             .iter()
-            .filter(|&(_, connection)| connection.user.id == user_id)
+            .filter(|&(_, connection)| {
+                connection.user.id == user_id && connection.device_id.is_none()
+            })
+            .for_each(|(_, connection)| {
+                connection.send(notification.clone());
+            });
+    }
+
+    /// send a message to every connection of a display device
+    pub fn notify_device(&self, notification: Notification, device_id: i64) {
+        self.sessions
+            .iter()
+            .filter(|&(_, connection)| connection.device_id == Some(device_id))
             .for_each(|(_, connection)| {
-                connection.send(notification.clone()).ok();
+                connection.send(notification.clone());
             });
     }
 
+    /// send a message to the devices in a game, the players don't receive it
+    pub fn notify_devices(&self, notification: Notification, game_id: GameId) {
+        if let Some(sessions) = self.games.get(&game_id) {
+            sessions
+                .iter()
+                .filter_map(|id| self.sessions.get(id))
+                .filter(|connection| connection.device_id.is_some())
+                .for_each(|connection| {
+                    connection.send(notification.clone());
+                });
+        }
+    }
+
+    /// returns the amount of handled notifications
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// returns the amount of times the server was restarted
+    pub fn restarts(&self) -> usize {
+        self.restarts
+    }
+
     /// returns the number of connected users
     pub fn session_count(&self) -> usize {
         self.sessions.len()
@@ -177,11 +277,211 @@ impl NotificationServer {
     }
 }
 
+impl NotificationServer {
+    /// Send the moderation state of a game to a user joining it, so they see the pinned announcement
+    fn send_moderation(&self, game_id: GameId, recipient: Recipient<Notification>) {
+        let db = self.db.clone();
+
+        actix_rt::spawn(async move {
+            match Moderation::find(game_id.0, &db).await {
+                Ok(moderation) if !moderation.is_empty() => {
+                    let _ = recipient.do_send(Notification::Moderation(ModerationUpdate {
+                        action: None,
+                        moderation,
+                    }));
+                }
+                Ok(_) => (),
+                Err(e) => error!("unable to load the moderation of game {}: {}", game_id.0, e),
+            }
+        });
+    }
+
+    /// A new session id, the ids that are still in use are skipped once the counter wraps around
+    ///
+    /// The default id is never handed out, it belongs to connections that didn't register yet.
+    fn next_session_id(&mut self) -> SessionId {
+        loop {
+            let id = SessionId(self.next_session);
+            self.next_session = self.next_session.wrapping_add(1);
+
+            if id == SessionId::default() {
+                continue;
+            }
+            if self.sessions.contains_key(&id) {
+                warn!("session id {} is still in use, skipping it", id.0);
+                continue;
+            }
+
+            return id;
+        }
+    }
+
+    /// Remove a session from the server and from its game
+    fn remove_session(&mut self, id: SessionId, ctx: &mut Context<Self>) {
+        if let Some(session) = self.sessions.remove(&id) {
+            // remove session from all games
+            for (game_id, game_sessions) in self.games.iter_mut() {
+                if game_sessions.remove(&id) {
+                    ctx.notify(Notification::UserDisconnected(
+                        ConnectionType::GameConnection(*game_id),
+                    ));
+                }
+            }
+
+            if session.is_admin() {
+                ctx.notify(Notification::UserDisconnected(
+                    ConnectionType::AdminConnection,
+                ));
+            }
+        }
+
+        // the games without players are removed from the list
+        self.games
+            .retain(|_, game_sessions| !game_sessions.is_empty());
+    }
+
+    /// Run a handler, the server stops when it panics so the supervisor restarts it
+    fn guard(
+        &mut self,
+        ctx: &mut Context<Self>,
+        handler: impl FnOnce(&mut Self, &mut Context<Self>),
+    ) {
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(self, ctx))).is_err() {
+            error!("the notification server panicked, stopping it");
+            ctx.stop();
+        }
+    }
+
+    /// Drop the sessions of clients that vanished without disconnecting
+    ///
+    /// This happens when the websocket actor dies without running `stopping`.
+    /// Returns the amount of removed sessions.
+    fn sweep(&mut self, ctx: &mut Context<Self>) -> usize {
+        let stale: Vec<SessionId> = self
+            .sessions
+            .iter()
+            .filter(|&(_, session)| !session.is_connected())
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in stale.iter() {
+            self.remove_session(*id, ctx);
+        }
+
+        // reconcile the games with the sessions that are left
+        let sessions = &self.sessions;
+        for game_sessions in self.games.values_mut() {
+            game_sessions.retain(|id| sessions.contains_key(id));
+        }
+        self.games
+            .retain(|_, game_sessions| !game_sessions.is_empty());
+
+        if !stale.is_empty() {
+            warn!("swept {} stale websocket sessions", stale.len());
+            SWEPT_SESSIONS.fetch_add(stale.len(), Ordering::Relaxed);
+        }
+
+        stale.len()
+    }
+
+    /// Tell every connection the server shuts down, this skips the outboxes as it's the last notification
+    fn notify_shutdown(&self, notification: Notification) {
+        for (_, session) in self.sessions.iter() {
+            let _ = session.recipient.do_send(notification.clone());
+        }
+    }
+
+    /// Hand the waiting notifications to the slow connections, the clients that can't keep up are disconnected
+    fn flush_outboxes(&mut self, ctx: &mut Context<Self>) {
+        let now = Instant::now();
+        let mut too_slow = Vec::new();
+
+        for (id, session) in self.sessions.iter() {
+            let mut outbox = session.outbox.borrow_mut();
+            if outbox.is_backed_up() {
+                outbox.flush(&session.recipient);
+            }
+            if outbox.is_too_slow(now) {
+                too_slow.push(*id);
+            }
+        }
+
+        for id in too_slow {
+            if let Some(session) = self.sessions.get(&id) {
+                warn!(
+                    "disconnecting {}, the client can't keep up with its notifications",
+                    session.user
+                );
+                let _ = session.recipient.do_send(Notification::TooSlow);
+            }
+            Outbox::disconnected();
+            self.remove_session(id, ctx);
+        }
+    }
+
+    /// the amount of sessions removed by the sweep
+    pub fn swept_sessions() -> usize {
+        SWEPT_SESSIONS.load(Ordering::Relaxed)
+    }
+
+    /// Forward the domain events the websocket users are interested in
+    pub fn subscribe(notifier: Addr<NotificationServer>, events: &EventBus) {
+        let mut receiver = events.subscribe();
+
+        actix_rt::spawn(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let Some(notification) = Notification::from_event(event) {
+                            notifier.do_send(notification);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("the notification server missed {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
 /// Make actor from `NotificationServer`
 impl Actor for NotificationServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(SWEEP_INTERVAL, |act, ctx| {
+            act.guard(ctx, |act, ctx| {
+                act.sweep(ctx);
+            });
+        });
+        ctx.run_interval(FLUSH_INTERVAL, |act, ctx| {
+            act.guard(ctx, |act, ctx| act.flush_outboxes(ctx));
+        });
+    }
+}
+
+/// The server is restarted by its supervisor when it stops
+///
+/// A panic can leave the sessions half updated, so they're dropped
+/// and the websocket connections that are still alive register again.
+impl Supervised for NotificationServer {
+    fn restarting(&mut self, _: &mut Context<Self>) {
+        self.restarts += 1;
+        warn!(
+            "restarting the notification server, {} sessions register again",
+            self.sessions.len()
+        );
+
+        self.games.clear();
+        for (_, session) in self.sessions.drain() {
+            // this can't wait in the outbox, the outbox is dropped with the session
+            let _ = session.recipient.do_send(Notification::Reconnect);
+        }
+    }
 }
 
 /// Handler for Connect message.
@@ -191,13 +491,15 @@ impl Handler<Connect> for NotificationServer {
     type Result = SessionId;
 
     fn handle(&mut self, msg: Connect, ctx: &mut Context<Self>) -> Self::Result {
-        // register session with random id
-        let session_id = SessionId(self.rng.gen::<usize>());
-        self.sessions
-            .insert(session_id, ConnectedUser::new(msg.addr, msg.user));
+        let session_id = self.next_session_id();
+        self.sessions.insert(
+            session_id,
+            ConnectedUser::new(msg.addr.clone(), msg.user, msg.device_id),
+        );
 
         match msg.connection_type {
             ConnectionType::GameConnection(game_id) => {
+                self.send_moderation(game_id, msg.addr);
                 self.games
                     .entry(game_id)
                     .or_insert_with(HashSet::new)
@@ -224,6 +526,8 @@ impl Handler<Connect> for NotificationServer {
 pub enum Notification {
     /// Notify users in a game when a new sale happened
     NewSale(Sale),
+    /// Notify users in a game that an order was voided, the transactions have negated amounts
+    SaleVoided(Sale),
     /// Notify all connected users that he prices are updated
     PriceUpdate(PriceUpdate),
     /// Notify users in a certain game that someone joined
@@ -239,6 +543,120 @@ pub enum Notification {
     ConnectionCount(usize),
     ConnectedUsers(Vec<User>),
     ActiveGames(Vec<ActiveGamesResponse>),
+    /// Notify the game owner and administrators about a user buying suspiciously much
+    SuspiciousPurchases(SuspiciousPurchases),
+    /// Reply to the handshake of a client
+    Welcome(Welcome),
+    /// Reply to a handshake with an unsupported protocol version
+    HandshakeRejected(HandshakeRejected),
+    /// Notify users in a game that the game owner changed the moderation state
+    Moderation(ModerationUpdate),
+    /// Notify a game owner that their moderation action failed
+    ModerationRejected(String),
+    /// Notify a player that the order they placed over the websocket was rejected
+    PurchaseRejected(String),
+    /// Notify users in a game that the price predictions are resolved, so the leaderboard changed
+    PredictionsResolved(GameId),
+    /// Ask a co-payer to accept their share of an order
+    SplitRequested(OrderSplit),
+    /// Notify the purchaser that a co-payer responded to their share, or that it expired
+    SplitResolved(OrderSplit),
+    /// Disconnect a display device after the game owner revoked it
+    DeviceRevoked(i64),
+    /// Warn a user and the administrators about unusual login activity on the account
+    SuspiciousLogin(SuspiciousLogin),
+    /// Ask the printer bridges of a game to print the ticket of a new order
+    PrintTicket(PrintJob),
+    /// Tell a waitlisted user they got a place in the game
+    WaitlistPromoted(WaitlistPromotion),
+    /// Notify users in a game that purchases are rejected until the blackout ends
+    BlackoutStarted(Blackout),
+    /// Notify users in a game that they can purchase again
+    BlackoutEnded(Blackout),
+    /// Notify users in a game about the new scoreboard of the teams
+    TeamLeaderboard(TeamLeaderboard),
+    /// Warn a player, and the game owner if they want to know, about exceeding the drink limit
+    DrinkLimitWarning(DrinkWarning),
+    /// Ask a websocket connection to register again after the server restarted,
+    /// this isn't sent to the client
+    Reconnect,
+    /// Disconnect a client that can't keep up with its notifications, this isn't sent to the client
+    TooSlow,
+    /// Tell the clients the server shuts down, they can reconnect after `reconnect_after` seconds
+    #[serde(rename_all = "camelCase")]
+    ServerShutdown {
+        reconnect_after: u64,
+    },
+}
+
+impl Notification {
+    /// how important the notification is, a crash is the price update nobody should miss
+    pub fn priority(&self) -> Priority {
+        match self {
+            Notification::PriceUpdate(update)
+                if matches!(update.market_status, MarketStatus::Crash) =>
+            {
+                Priority::Critical
+            }
+            Notification::HandshakeRejected(_)
+            | Notification::Welcome(_)
+            | Notification::Moderation(_)
+            | Notification::ModerationRejected(_)
+            | Notification::PurchaseRejected(_)
+            | Notification::DeviceRevoked(_)
+            | Notification::SuspiciousLogin(_)
+            | Notification::BlackoutStarted(_)
+            | Notification::BlackoutEnded(_)
+            | Notification::Reconnect
+            | Notification::TooSlow
+            | Notification::ServerShutdown { .. } => Priority::Critical,
+            Notification::UserConnected(_)
+            | Notification::UserDisconnected(_)
+            | Notification::ConnectionCount(_)
+            | Notification::ConnectedUsers(_)
+            | Notification::ActiveGames(_) => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+
+    /// the notification sent to the websocket users for a domain event, if any
+    fn from_event(event: DomainEvent) -> Option<Notification> {
+        match event {
+            DomainEvent::SaleCreated(sale) => Some(Notification::NewSale(sale)),
+            DomainEvent::SaleVoided(sale) => Some(Notification::SaleVoided(sale)),
+            DomainEvent::PricesUpdated(update) => Some(Notification::PriceUpdate(update)),
+            DomainEvent::SuspiciousPurchases(suspicion) => {
+                Some(Notification::SuspiciousPurchases(suspicion))
+            }
+            DomainEvent::PredictionsResolved(game_id) => {
+                Some(Notification::PredictionsResolved(game_id))
+            }
+            DomainEvent::SplitRequested(split) => Some(Notification::SplitRequested(split)),
+            DomainEvent::SplitResolved(split) => Some(Notification::SplitResolved(split)),
+            DomainEvent::DeviceRevoked { device_id, .. } => {
+                Some(Notification::DeviceRevoked(device_id))
+            }
+            DomainEvent::SuspiciousLogin(login) => Some(Notification::SuspiciousLogin(login)),
+            DomainEvent::OrderPlaced { game_id, order_id } => {
+                Some(Notification::PrintTicket(PrintJob { game_id, order_id }))
+            }
+            DomainEvent::BlackoutStarted(blackout) => Some(Notification::BlackoutStarted(blackout)),
+            DomainEvent::BlackoutEnded(blackout) => Some(Notification::BlackoutEnded(blackout)),
+            DomainEvent::TeamScoresUpdated(leaderboard) => {
+                Some(Notification::TeamLeaderboard(leaderboard))
+            }
+            DomainEvent::DrinkLimitExceeded(warning) => {
+                Some(Notification::DrinkLimitWarning(warning))
+            }
+            DomainEvent::WaitlistPromoted { game_id, user_id } => {
+                Some(Notification::WaitlistPromoted(WaitlistPromotion {
+                    game_id,
+                    user_id,
+                }))
+            }
+            _ => None,
+        }
+    }
 }
 
 #[derive(Message, Debug, Serialize, Clone)]
@@ -248,62 +666,172 @@ pub struct Sale {
     pub transactions: Vec<Transaction>,
 }
 
-#[derive(Debug, Serialize, Clone, Copy)]
+/// A new order, the printer bridge downloads its ticket from `/orders/{order_id}/ticket`
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PrintJob {
+    pub game_id: GameId,
+    pub order_id: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WaitlistPromotion {
+    pub game_id: GameId,
+    pub user_id: i64,
+}
+
+#[derive(Debug, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SuspiciousPurchases {
+    pub game_id: GameId,
+    pub owner_id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub z_score: f64,
+    /// wether the user is being throttled
+    pub throttled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PriceUpdate {
     pub market_status: MarketStatus,
     pub game_id: GameId,
+    /// increases by one with every price update of a game, so clients can detect missed updates
+    /// and load them from the price history
+    #[serde(default)]
+    pub tick: i64,
+    /// the prices of the beverages, each user only receives the prices of their own beverages
+    ///
+    /// Clients using the `deltas` feature only receive the changed prices
+    #[serde(default)]
+    pub prices: Vec<BeveragePrice>,
+    /// when true, `prices` contains every beverage instead of only the changed ones
+    #[serde(default)]
+    pub snapshot: bool,
 }
 
 impl Handler<Notification> for NotificationServer {
     type Result = ();
 
-    fn handle(&mut self, notification: Notification, _: &mut Context<Self>) {
+    fn handle(&mut self, notification: Notification, ctx: &mut Context<Self>) {
+        self.sequence += 1;
+
+        self.guard(ctx, |act, _| act.dispatch(notification));
+    }
+}
+
+impl NotificationServer {
+    fn dispatch(&mut self, notification: Notification) {
         match notification {
             Notification::NewSale(sale) => {
                 self.notify_game(Notification::NewSale(sale.clone()), sale.game_id)
             }
-            Notification::PriceUpdate(update) => self.notify_game(notification, update.game_id),
+            Notification::SaleVoided(ref sale) => {
+                let game_id = sale.game_id;
+                self.notify_game(notification, game_id)
+            }
+            Notification::PriceUpdate(update) => self.notify_prices(update),
             Notification::UserConnected(connection_type) => self.connection_change(connection_type),
             Notification::UserDisconnected(connection_type) => {
                 self.connection_change(connection_type)
             }
+            Notification::PredictionsResolved(game_id) => self.notify_game(notification, game_id),
+            Notification::SplitRequested(ref split) => {
+                let user_id = split.user_id;
+                self.notify_user(notification, user_id)
+            }
+            Notification::SplitResolved(ref split) => {
+                let user_id = split.purchaser_id;
+                self.notify_user(notification, user_id)
+            }
+            Notification::SuspiciousPurchases(ref suspicion) => {
+                self.notify_user(notification.clone(), suspicion.owner_id);
+                self.notify_administrators(notification);
+            }
+            Notification::DeviceRevoked(device_id) => self.notify_device(notification, device_id),
+            Notification::SuspiciousLogin(ref login) => {
+                self.notify_user(notification.clone(), login.user_id);
+                self.notify_administrators(notification);
+            }
+            Notification::PrintTicket(ref job) => {
+                let game_id = job.game_id;
+                self.notify_devices(notification, game_id)
+            }
+            Notification::BlackoutStarted(ref blackout)
+            | Notification::BlackoutEnded(ref blackout) => {
+                let game_id = GameId(blackout.game_id);
+                self.notify_game(notification, game_id)
+            }
+            Notification::WaitlistPromoted(ref promotion) => {
+                let user_id = promotion.user_id;
+                self.notify_user(notification, user_id)
+            }
+            Notification::TeamLeaderboard(ref leaderboard) => {
+                let game_id = leaderboard.game_id;
+                self.notify_game(notification, game_id)
+            }
+            Notification::DrinkLimitWarning(ref warning) => {
+                let user_id = warning.user_id;
+                if let Some(owner_id) = warning.owner_id.filter(|&owner_id| owner_id != user_id) {
+                    self.notify_user(notification.clone(), owner_id);
+                }
+                self.notify_user(notification, user_id)
+            }
+            Notification::ServerShutdown { .. } => self.notify_shutdown(notification),
             _ => (),
         }
     }
 }
 
-/// Handler for Disconnect message.
-impl Handler<Disconnect> for NotificationServer {
-    type Result = ();
+/// Handler for Moderate message.
+///
+/// Store the moderation action and notify the users in the game
+impl Handler<Moderate> for NotificationServer {
+    type Result = ResponseActFuture<Self, Result<(), ServiceError>>;
 
-    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
-        let mut stale_game: Option<GameId> = None;
-        // remove address
-        if let Some(session) = self.sessions.remove(&msg.id) {
-            // remove session from all games
-            for (game_id, game_sessions) in self.games.iter_mut() {
-                if game_sessions.remove(&msg.id) {
-                    ctx.notify(Notification::UserDisconnected(
-                        ConnectionType::GameConnection(*game_id),
-                    ));
-                    // this was the last user in the game
-                    if game_sessions.is_empty() {
-                        stale_game = Some(*game_id);
-                    }
-                }
+    fn handle(&mut self, msg: Moderate, _: &mut Context<Self>) -> Self::Result {
+        let db = self.db.clone();
+
+        let moderation = async move {
+            if let Err(reason) = msg.action.validate() {
+                bad_request!(reason);
             }
 
-            if session.is_admin() {
-                ctx.notify(Notification::UserDisconnected(
-                    ConnectionType::AdminConnection,
-                ));
+            let game = Game::find_by_id(msg.game_id.0, &db).await?;
+            if game.owner_id != msg.user.id {
+                forbidden!("only the game owner can moderate the game");
             }
-        }
 
-        // the game has no more players, remove it from the list
-        if let Some(game_id) = stale_game {
-            self.games.remove(&game_id);
-        }
+            let moderation = Moderation::apply(game.id, msg.user.id, &msg.action, &db).await?;
+
+            Ok((msg.game_id, msg.action, moderation))
+        };
+
+        Box::pin(
+            moderation
+                .into_actor(self)
+                .map(|res: Result<_, ServiceError>, act, _| {
+                    let (game_id, action, moderation) = res?;
+                    act.notify_game(
+                        Notification::Moderation(ModerationUpdate {
+                            action: Some(action),
+                            moderation,
+                        }),
+                        game_id,
+                    );
+                    Ok(())
+                }),
+        )
+    }
+}
+
+/// Handler for Disconnect message.
+impl Handler<Disconnect> for NotificationServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Context<Self>) {
+        self.remove_session(msg.id, ctx);
 
         debug!("user disconnected");
         debug!("sessions count: {}", self.sessions.len());
@@ -323,6 +851,29 @@ mod tests {
     #[rtype(usize)]
     pub struct InnerGamesCount;
 
+    #[derive(Message)]
+    #[rtype(usize)]
+    pub struct InnerSweep;
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    pub struct InnerPanic;
+
+    /// A websocket connection that can be stopped without disconnecting
+    struct Client;
+
+    impl Actor for Client {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Notification> for Client {
+        type Result = ();
+
+        fn handle(&mut self, _: Notification, ctx: &mut Context<Self>) {
+            ctx.stop();
+        }
+    }
+
     impl Handler<InnerSessions> for NotificationServer {
         type Result = Result<Vec<SessionId>, std::io::Error>;
 
@@ -340,6 +891,29 @@ mod tests {
         }
     }
 
+    impl Handler<InnerSweep> for NotificationServer {
+        type Result = usize;
+
+        fn handle(&mut self, _: InnerSweep, ctx: &mut Context<Self>) -> Self::Result {
+            self.sweep(ctx)
+        }
+    }
+
+    impl Handler<InnerPanic> for NotificationServer {
+        type Result = ();
+
+        fn handle(&mut self, _: InnerPanic, ctx: &mut Context<Self>) {
+            self.guard(ctx, |_, _| panic!("the notification server broke"));
+        }
+    }
+
+    fn db() -> Pool<Postgres> {
+        sqlx::postgres::PgPoolOptions::new()
+            .connect_timeout(std::time::Duration::from_millis(100))
+            .connect_lazy("postgres://localhost:1/rustfuif")
+            .unwrap()
+    }
+
     async fn add_user(
         server: &Addr<NotificationServer>,
         connection_type: ConnectionType,
@@ -358,6 +932,7 @@ mod tests {
                 addr: server.clone().recipient(),
                 user: user.clone(),
                 connection_type,
+                device_id: None,
             })
             .await
             .unwrap();
@@ -369,7 +944,7 @@ mod tests {
     /// session count reaches 0
     #[actix_rt::test]
     async fn session_cleanup() {
-        let server = NotificationServer::new().start();
+        let server = NotificationServer::new(db()).start();
 
         add_user(&server, ConnectionType::GameConnection(GameId(1)), true).await;
 
@@ -420,4 +995,123 @@ mod tests {
         let games_count: usize = server.send(InnerGamesCount).await.unwrap();
         assert_eq!(0, games_count);
     }
+
+    /// A client that vanished without sending `Disconnect` is removed by the sweep
+    #[actix_rt::test]
+    async fn sweep_stale_sessions() {
+        let server = NotificationServer::new(db()).start();
+
+        add_user(&server, ConnectionType::GameConnection(GameId(1)), false).await;
+
+        let client = Client.start();
+        server
+            .send(Connect {
+                addr: client.clone().recipient(),
+                user: User {
+                    id: 2,
+                    username: String::from("vanished"),
+                    is_admin: false,
+                    password: String::from("..."),
+                    created_at: None,
+                    updated_at: None,
+                },
+                connection_type: ConnectionType::GameConnection(GameId(2)),
+                device_id: None,
+            })
+            .await
+            .unwrap();
+
+        // the client stops when it receives the connection count, wait until it's gone
+        while client.connected() {
+            actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(1, server.send(InnerSweep).await.unwrap());
+        let users: Vec<SessionId> = server.send(InnerSessions).await.unwrap().unwrap();
+        assert_eq!(1, users.len());
+        let games_count: usize = server.send(InnerGamesCount).await.unwrap();
+        assert_eq!(1, games_count);
+    }
+
+    /// The supervisor restarts a server that panicked, and the server keeps handling messages
+    #[actix_rt::test]
+    async fn restart_after_panic() {
+        let server = Supervisor::start(|_| NotificationServer::new(db()));
+
+        add_user(&server, ConnectionType::GameConnection(GameId(1)), false).await;
+        server.send(InnerPanic).await.unwrap();
+
+        let health = server
+            .send(crate::websocket::queries::Health)
+            .await
+            .unwrap();
+        assert_eq!(health.restarts, 1);
+        // the test user can't register again, as it's the server itself
+        assert_eq!(health.sessions, 0);
+
+        add_user(&server, ConnectionType::GameConnection(GameId(1)), false).await;
+        let games_count: usize = server.send(InnerGamesCount).await.unwrap();
+        assert_eq!(1, games_count);
+    }
+
+    /// The ids that are still in use aren't handed out again after the counter wraps around
+    #[actix_rt::test]
+    async fn unique_session_ids() {
+        let mut server = NotificationServer::new(db());
+        let recipient = Client.start().recipient();
+        let user = User {
+            id: 1,
+            username: String::from("admin"),
+            is_admin: false,
+            password: String::from("..."),
+            created_at: None,
+            updated_at: None,
+        };
+
+        let first = server.next_session_id();
+        assert_eq!(first, SessionId(1));
+        server
+            .sessions
+            .insert(first, ConnectedUser::new(recipient, user, None));
+
+        server.next_session = usize::MAX;
+        assert_eq!(server.next_session_id(), SessionId(usize::MAX));
+        // zero is the default id and one is taken
+        assert_eq!(server.next_session_id(), SessionId(2));
+    }
+
+    /// Every connection gets its own session, and disconnecting cleans up the games
+    #[actix_rt::test]
+    async fn many_sessions() {
+        let server = NotificationServer::new(db()).start();
+
+        for i in 0..500 {
+            add_user(
+                &server,
+                ConnectionType::GameConnection(GameId(i % 10)),
+                false,
+            )
+            .await;
+        }
+
+        let users: Vec<SessionId> = server.send(InnerSessions).await.unwrap().unwrap();
+        assert_eq!(500, users.len());
+        let games_count: usize = server.send(InnerGamesCount).await.unwrap();
+        assert_eq!(10, games_count);
+
+        // the sessions are handed out in order, so this disconnects every user of the even games
+        for id in users.iter().filter(|id| id.0 % 2 == 1) {
+            server.send(Disconnect { id: *id }).await.unwrap();
+        }
+        let users: Vec<SessionId> = server.send(InnerSessions).await.unwrap().unwrap();
+        assert_eq!(250, users.len());
+        let games_count: usize = server.send(InnerGamesCount).await.unwrap();
+        assert_eq!(5, games_count);
+
+        // a session that's already gone is ignored
+        server.send(Disconnect { id: users[0] }).await.unwrap();
+        server.send(Disconnect { id: users[0] }).await.unwrap();
+        let users: Vec<SessionId> = server.send(InnerSessions).await.unwrap().unwrap();
+        assert_eq!(249, users.len());
+    }
 }